@@ -0,0 +1,175 @@
+use anyhow::anyhow;
+use chrono::{ DateTime, Utc };
+use serde::{ Deserialize, Serialize };
+use serde_json::json;
+
+use crate::context::audio::AudioManager;
+use crate::context::focus::focus_ages;
+use crate::context::ocr::engine_from_settings;
+use crate::context::render::{ render_audio, render_screen };
+use crate::context::screen::{ get_screen, rank_windows };
+use crate::context::screen_source::default_screen_source;
+use crate::context::timeline::{ with_timeline, TimelineQuery, TimelineResult };
+use crate::settings::Settings;
+use super::ToolRegistry;
+
+#[derive(Deserialize)]
+struct NoArguments {}
+
+#[derive(Serialize)]
+struct WindowSummary {
+    title: String,
+    process_name: String,
+    focused: bool,
+    visible_area: u64,
+    /// `None` if not seen focused
+    last_focused_secs_ago: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct ScreenArguments {
+    window: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TranscriptArguments {
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+}
+
+/// Read-only tools over what the app already captures: open windows, their
+/// text, the transcript and the timeline. Each refuses if its source is
+/// turned off in `settings`.
+pub fn builtin_tools(settings: &Settings) -> ToolRegistry {
+    let mut tools = ToolRegistry::new();
+
+    let enabled = settings.screen_context;
+    tools.register(
+        "list_windows",
+        "List the open windows, most relevant first: focused, then recently focused, then most visible. Cheap, no text.",
+        json!({ "type": "object", "properties": {} }),
+        move |_: NoArguments| async move {
+            if !enabled {
+                return Err(anyhow!("Screen context is turned off in settings"));
+            }
+            let windows = tokio::task
+                ::spawn_blocking(|| default_screen_source()?.list_windows()).await
+                .map_err(|err| anyhow!("Failed to list windows: {}", err))??;
+            let summaries: Vec<WindowSummary> = rank_windows(windows, &focus_ages())
+                .into_iter()
+                .map(|window| WindowSummary {
+                    title: window.info.title,
+                    process_name: window.info.process_name,
+                    focused: window.info.focused,
+                    visible_area: window.visible_area,
+                    last_focused_secs_ago: window.focus_age.map(|age| age.as_secs()),
+                })
+                .collect();
+            Ok(summaries)
+        }
+    );
+
+    let screen_settings = settings.clone();
+    tools.register(
+        "get_screen_context",
+        "Read the text in the open windows now, by OCR. Optionally only windows whose title or process name contains `window`.",
+        json!({
+            "type": "object",
+            "properties": {
+                "window": {
+                    "type": "string",
+                    "description": "Part of a window title or process name, case-insensitive",
+                },
+            },
+        }),
+        move |arguments: ScreenArguments| {
+            let settings = screen_settings.clone();
+            async move {
+                if !settings.screen_context {
+                    return Err(anyhow!("Screen context is turned off in settings"));
+                }
+                // ~4 chars per token: a quarter of the budget, like any tool
+                // result, unless one window is asked for
+                let max_chars = match arguments.window {
+                    Some(_) => settings.context_budget_tokens * 4,
+                    None => settings.context_budget_tokens,
+                };
+                let screen = tokio::task
+                    ::spawn_blocking(move || {
+                        let ocr = engine_from_settings(&settings)?;
+                        get_screen(max_chars, ocr, arguments.window.as_deref())
+                    }).await
+                    .map_err(|err| anyhow!("Failed to get screen: {}", err))??;
+                if screen.windows.is_empty() {
+                    return Ok("No matching windows with text".to_string());
+                }
+                Ok(render_screen(&screen))
+            }
+        }
+    );
+
+    let enabled = settings.audio_context;
+    let window_secs = settings.audio_context_window_secs as i64;
+    tools.register(
+        "get_transcript",
+        "Read what was said, from the microphone and system audio, between `since` and `until` (RFC 3339). Defaults to the last few minutes.",
+        json!({
+            "type": "object",
+            "properties": {
+                "since": { "type": "string", "format": "date-time" },
+                "until": { "type": "string", "format": "date-time" },
+            },
+        }),
+        move |arguments: TranscriptArguments| async move {
+            if !enabled {
+                return Err(anyhow!("Audio context is turned off in settings"));
+            }
+            // Cloned out so the lock isn't held across the await
+            let manager = AudioManager::get_instance()
+                .lock()
+                .map_err(|err| anyhow!("Failed to lock mutex: {}", err))?
+                .clone()
+                .ok_or_else(|| anyhow!("Audio is not running"))?;
+            let since = arguments.since.unwrap_or_else(|| {
+                Utc::now() - chrono::Duration::seconds(window_secs)
+            });
+            if arguments.until.is_none() {
+                manager.flush_live().await;
+            }
+            let transcript = manager.get_transcript(Some(since), arguments.until).await;
+            if transcript.segments.is_empty() && transcript.tentative.is_empty() {
+                return Ok("Nothing was said then".to_string());
+            }
+            Ok(render_audio(&transcript))
+        }
+    );
+
+    let enabled = settings.timeline_enabled;
+    tools.register(
+        "search_timeline",
+        "Search the recorded history of window text and speech. Use `at` for what was on screen at a time, `text` for words that must appear, `window` to limit to windows by title or process name.",
+        json!({
+            "type": "object",
+            "properties": {
+                "at": { "type": "string", "format": "date-time" },
+                "since": { "type": "string", "format": "date-time" },
+                "until": { "type": "string", "format": "date-time" },
+                "window": { "type": "string" },
+                "text": { "type": "string" },
+                "limit": { "type": "integer", "minimum": 1 },
+            },
+        }),
+        move |query: TimelineQuery| async move {
+            if !enabled {
+                return Err(anyhow!("The timeline is turned off in settings"));
+            }
+            let result: TimelineResult = tokio::task
+                ::spawn_blocking(move || with_timeline(|timeline| timeline.query(&query))).await
+                .map_err(|err| anyhow!("Failed to search timeline: {}", err))?
+                .map_err(|err| anyhow!(err))?;
+            Ok(result)
+        }
+    );
+
+    tools
+}
@@ -0,0 +1,585 @@
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{ Path, PathBuf };
+use std::process::Stdio;
+use std::sync::atomic::{ AtomicU64, Ordering };
+use std::sync::{ Arc, Mutex as SyncMutex };
+use std::time::{ Duration, Instant };
+use anyhow::{ anyhow, Result };
+use chrono::{ DateTime, Utc };
+use serde::{ Deserialize, Serialize };
+use serde_json::json;
+use tokio::io::{ AsyncRead, AsyncReadExt };
+use tokio::process::Command;
+use tokio::sync::oneshot;
+
+use crate::settings::Settings;
+#[cfg(windows)]
+use super::job_object::{ self, JobObject, CREATE_SUSPENDED };
+use super::ToolRegistry;
+
+pub const COMMAND_LOG_PATH: &str = "./commands.log";
+
+/// Unanswered approvals count as declined after this, so a forgotten prompt
+/// doesn't hold the answer forever
+const APPROVAL_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// Anything that makes one command line run more than one command, or
+/// redirects it, so `git status; rm -rf ~` can't pass as `git status`
+#[cfg(not(windows))]
+const SHELL_OPERATORS: &[char] = &[';', '&', '|', '<', '>', '`', '$', '(', ')', '\n', '\r'];
+/// cmd also expands `%VAR%` and `!VAR!` and escapes operators with `^`
+#[cfg(windows)]
+const SHELL_OPERATORS: &[char] = &[
+    ';', '&', '|', '<', '>', '`', '$', '(', ')', '\n', '\r', '%', '^', '!',
+];
+
+lazy_static! {
+    static ref PENDING_APPROVALS: SyncMutex<HashMap<u64, oneshot::Sender<bool>>> = SyncMutex::new(
+        HashMap::new()
+    );
+}
+
+static NEXT_APPROVAL_ID: AtomicU64 = AtomicU64::new(1);
+
+/// A command waiting for the user, sent to the UI as `command-approval`
+#[derive(Serialize, Clone, Debug)]
+pub struct CommandApproval {
+    pub id: u64,
+    pub command: String,
+    pub working_dir: String,
+}
+
+/// Shows an approval to the user; the answer comes back through
+/// `answer_approval`
+pub type ApprovalPrompt = Arc<dyn (Fn(&CommandApproval) -> Result<()>) + Send + Sync>;
+
+/// Settles approval `id`. Errors if it isn't waiting, e.g. it timed out.
+pub fn answer_approval(id: u64, approved: bool) -> Result<()> {
+    let sender = PENDING_APPROVALS.lock()
+        .map_err(|err| anyhow!("Failed to lock mutex: {}", err))?
+        .remove(&id)
+        .ok_or_else(|| anyhow!("No command is waiting for approval {}", id))?;
+    // The tool may have stopped waiting in the meantime
+    let _ = sender.send(approved);
+    Ok(())
+}
+
+fn forget_approval(id: u64) {
+    if let Ok(mut pending) = PENDING_APPROVALS.lock() {
+        pending.remove(&id);
+    }
+}
+
+async fn ask_approval(prompt: &ApprovalPrompt, command: &str, working_dir: &Path) -> Result<bool> {
+    let id = NEXT_APPROVAL_ID.fetch_add(1, Ordering::Relaxed);
+    let (sender, receiver) = oneshot::channel();
+    PENDING_APPROVALS.lock()
+        .map_err(|err| anyhow!("Failed to lock mutex: {}", err))?
+        .insert(id, sender);
+    let approval = CommandApproval {
+        id,
+        command: command.to_string(),
+        working_dir: working_dir.display().to_string(),
+    };
+    if let Err(err) = prompt(&approval) {
+        forget_approval(id);
+        return Err(err);
+    }
+    match tokio::time::timeout(APPROVAL_TIMEOUT, receiver).await {
+        Ok(answer) => Ok(answer.unwrap_or(false)),
+        Err(_) => {
+            forget_approval(id);
+            Ok(false)
+        }
+    }
+}
+
+/// Whether `command` runs without asking: it's an entry of `allowlist`, or
+/// one followed by arguments, and has no shell operators.
+pub fn is_allowlisted(command: &str, allowlist: &[String]) -> bool {
+    let command = command.trim();
+    if command.is_empty() || command.contains(SHELL_OPERATORS) {
+        return false;
+    }
+    allowlist.iter().any(|entry| {
+        let entry = entry.trim();
+        !entry.is_empty() &&
+            command
+                .strip_prefix(entry)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with(char::is_whitespace))
+    })
+}
+
+#[derive(Serialize, Debug)]
+pub struct CommandOutput {
+    /// `None` if killed, by the timeout or a signal
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+    pub timed_out: bool,
+    /// Output past `command_output_bytes` was dropped
+    pub truncated: bool,
+}
+
+/// Reads `pipe` to the end, keeping the first `max_bytes` in `kept` and
+/// counting all of them in `total`
+async fn read_capped<R: AsyncRead + Unpin>(
+    pipe: Option<R>,
+    kept: &mut Vec<u8>,
+    total: &mut usize,
+    max_bytes: usize
+) -> std::io::Result<()> {
+    let Some(mut pipe) = pipe else {
+        return Ok(());
+    };
+    let mut chunk = [0u8; 8192];
+    loop {
+        let read = pipe.read(&mut chunk).await?;
+        if read == 0 {
+            return Ok(());
+        }
+        *total += read;
+        let room = max_bytes.saturating_sub(kept.len());
+        kept.extend_from_slice(&chunk[..read.min(room)]);
+    }
+}
+
+/// Runs `command` with the platform shell in `working_dir`, killing it and
+/// whatever it started after `timeout`. Keeps the first `max_bytes` of stdout and of stderr.
+pub async fn run_shell_command(
+    command: &str,
+    working_dir: &Path,
+    timeout: Duration,
+    max_bytes: usize
+) -> Result<CommandOutput> {
+    #[cfg(windows)]
+    let mut process = {
+        let mut process = Command::new("cmd");
+        process.arg("/C").arg(command);
+        // No console window flashing up
+        process.creation_flags(0x08000000 | CREATE_SUSPENDED);
+        process
+    };
+    #[cfg(not(windows))]
+    let mut process = {
+        let mut process = Command::new("sh");
+        process.arg("-c").arg(command);
+        // Its own group, so the timeout reaches the commands it runs too
+        process.process_group(0);
+        process
+    };
+    let mut child = process
+        .current_dir(working_dir)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|err| anyhow!("Failed to start {}: {}", command, err))?;
+    #[cfg(windows)]
+    let job = JobObject::assign(&child)
+        .map_err(|err| eprintln!("Only {} itself is killed on timeout: {}", command, err))
+        .ok();
+    #[cfg(windows)]
+    if let Err(err) = job_object::resume(&child) {
+        let _ = child.kill().await;
+        return Err(anyhow!("Failed to start {}: {}", command, err));
+    }
+
+    let (stdout_pipe, stderr_pipe) = (child.stdout.take(), child.stderr.take());
+    let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+    let (mut stdout_total, mut stderr_total) = (0, 0);
+    // Kept outside the timeout so what was read before it is still returned
+    let finished = tokio::time::timeout(timeout, async {
+        let (stdout_read, stderr_read) = tokio::join!(
+            read_capped(stdout_pipe, &mut stdout, &mut stdout_total, max_bytes),
+            read_capped(stderr_pipe, &mut stderr, &mut stderr_total, max_bytes)
+        );
+        stdout_read?;
+        stderr_read?;
+        child.wait().await
+    }).await;
+
+    let (exit_code, timed_out) = match finished {
+        Ok(status) => {
+            let status = status.map_err(|err| anyhow!("Failed to run {}: {}", command, err))?;
+            (status.code(), false)
+        }
+        Err(_) => {
+            #[cfg(windows)]
+            if let Some(job) = &job {
+                job.terminate();
+            }
+            #[cfg(not(windows))]
+            if let Some(pid) = child.id() {
+                unsafe {
+                    libc::killpg(pid as libc::pid_t, libc::SIGKILL);
+                }
+            }
+            // Reaps it
+            let _ = child.kill().await;
+            (None, true)
+        }
+    };
+    Ok(CommandOutput {
+        exit_code,
+        stdout: String::from_utf8_lossy(&stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&stderr).into_owned(),
+        timed_out,
+        truncated: stdout_total > stdout.len() || stderr_total > stderr.len(),
+    })
+}
+
+/// How a command came to run, or not
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Approval {
+    Allowlist,
+    User,
+    Declined,
+}
+
+/// A line of the command log
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CommandLogEntry {
+    pub at: DateTime<Utc>,
+    pub command: String,
+    pub working_dir: String,
+    pub approval: Approval,
+    /// Unset if it didn't run or was killed
+    pub exit_code: Option<i32>,
+    pub timed_out: bool,
+    pub duration_ms: u64,
+    /// Why it couldn't be started, if it couldn't
+    pub error: Option<String>,
+}
+
+/// Appends `entry` to the JSON-lines log at `path`
+pub fn log_command(path: &Path, entry: &CommandLogEntry) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|err| anyhow!("Failed to open {}: {}", path.display(), err))?;
+    writeln!(file, "{}", serde_json::to_string(entry)?).map_err(|err|
+        anyhow!("Failed to write {}: {}", path.display(), err)
+    )?;
+    Ok(())
+}
+
+fn default_working_dir(settings: &Settings) -> PathBuf {
+    if !settings.command_working_dir.is_empty() {
+        return PathBuf::from(&settings.command_working_dir);
+    }
+    std::env
+        ::var_os(if cfg!(windows) { "USERPROFILE" } else { "HOME" })
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("."))
+}
+
+#[derive(Deserialize)]
+struct CommandArguments {
+    command: String,
+    working_dir: Option<String>,
+}
+
+/// Adds `run_command` to `tools`. Commands not on the allowlist are shown
+/// through `prompt` and only run once approved; every one, run or
+/// declined, is logged to `log_path`.
+pub fn register_command_tool(
+    tools: &mut ToolRegistry,
+    settings: &Settings,
+    prompt: ApprovalPrompt,
+    log_path: PathBuf
+) {
+    let allowlist = settings.command_allowlist.clone();
+    let timeout = Duration::from_secs(settings.command_timeout_secs.max(1));
+    let max_bytes = settings.command_output_bytes;
+    let default_dir = default_working_dir(settings);
+    tools.register(
+        "run_command",
+        "Run a shell command on the user's computer and get its exit code, stdout and stderr. The user sees the command and has to approve it, so prefer commands that only look, and explain why you need it.",
+        json!({
+            "type": "object",
+            "properties": {
+                "command": {
+                    "type": "string",
+                    "description": if cfg!(windows) { "Run with cmd /C" } else { "Run with sh -c" },
+                },
+                "working_dir": {
+                    "type": "string",
+                    "description": "Directory to run in, defaults to the user's home",
+                },
+            },
+            "required": ["command"],
+        }),
+        move |arguments: CommandArguments| {
+            let allowlist = allowlist.clone();
+            let prompt = Arc::clone(&prompt);
+            let log_path = log_path.clone();
+            let working_dir = arguments.working_dir
+                .filter(|dir| !dir.trim().is_empty())
+                .map(PathBuf::from)
+                .unwrap_or_else(|| default_dir.clone());
+            async move {
+                let command = arguments.command.trim().to_string();
+                if command.is_empty() {
+                    return Err(anyhow!("No command given"));
+                }
+                let mut entry = CommandLogEntry {
+                    at: Utc::now(),
+                    command: command.clone(),
+                    working_dir: working_dir.display().to_string(),
+                    approval: Approval::Declined,
+                    exit_code: None,
+                    timed_out: false,
+                    duration_ms: 0,
+                    error: None,
+                };
+                entry.approval = if is_allowlisted(&command, &allowlist) {
+                    Approval::Allowlist
+                } else {
+                    match ask_approval(&prompt, &command, &working_dir).await {
+                        Ok(true) => Approval::User,
+                        Ok(false) => Approval::Declined,
+                        // Not run, since nobody could approve it
+                        Err(err) => {
+                            entry.error = Some(err.to_string());
+                            log_command(&log_path, &entry)?;
+                            return Err(err);
+                        }
+                    }
+                };
+                if entry.approval == Approval::Declined {
+                    log_command(&log_path, &entry)?;
+                    return Err(anyhow!("The user declined to run {}", command));
+                }
+                let started = Instant::now();
+                let result = run_shell_command(&command, &working_dir, timeout, max_bytes).await;
+                entry.duration_ms = started.elapsed().as_millis() as u64;
+                match &result {
+                    Ok(output) => {
+                        entry.exit_code = output.exit_code;
+                        entry.timed_out = output.timed_out;
+                    }
+                    Err(err) => {
+                        entry.error = Some(err.to_string());
+                    }
+                }
+                log_command(&log_path, &entry)?;
+                result
+            }
+        }
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use std::sync::{ Arc, Mutex as SyncMutex };
+    use std::time::{ Duration, Instant };
+    use anyhow::Result;
+    use serde_json::{ json, Value };
+    use tempfile::TempDir;
+
+    use crate::settings::Settings;
+    use super::*;
+
+    #[cfg(windows)]
+    const SLOW_COMMAND: &str = "ping -n 11 127.0.0.1 > NUL";
+    #[cfg(not(windows))]
+    const SLOW_COMMAND: &str = "sleep 10";
+
+    /// Leaves a process behind that writes `late.txt` after 2 s
+    #[cfg(windows)]
+    const ORPHANING_COMMAND: &str = concat!(
+        "start /B cmd /C \"ping -n 3 127.0.0.1 > NUL & echo late > late.txt\" & ",
+        "ping -n 11 127.0.0.1 > NUL"
+    );
+    #[cfg(not(windows))]
+    const ORPHANING_COMMAND: &str = "(sleep 2; echo late > late.txt) & sleep 10";
+
+    #[cfg(windows)]
+    const LOUD_COMMAND: &str = "for /L %i in (1,1,2000) do @echo 0123456789";
+    #[cfg(not(windows))]
+    const LOUD_COMMAND: &str = "for i in $(seq 2000); do echo 0123456789; done";
+
+    /// A `run_command` tool allowing `echo`, with a 1 s timeout and 1000 bytes
+    /// of output, running in and logging to a temp directory. Its prompt
+    /// answers `approve` at once and records the commands it was shown.
+    struct Fixture {
+        tools: ToolRegistry,
+        prompted: Arc<SyncMutex<Vec<String>>>,
+        dir: TempDir,
+    }
+
+    impl Fixture {
+        fn new(approve: bool) -> Self {
+            Self::with_prompt(move |approval| answer_approval(approval.id, approve))
+        }
+
+        fn with_prompt(
+            answer: impl (Fn(&CommandApproval) -> Result<()>) + Send + Sync + 'static
+        ) -> Self {
+            let dir = tempfile::tempdir().unwrap();
+            let settings = Settings {
+                command_allowlist: vec!["echo".to_string()],
+                command_timeout_secs: 1,
+                command_output_bytes: 1000,
+                command_working_dir: dir.path().display().to_string(),
+                ..Settings::default()
+            };
+            let prompted = Arc::new(SyncMutex::new(Vec::new()));
+            let shown = Arc::clone(&prompted);
+            let mut tools = ToolRegistry::new();
+            register_command_tool(
+                &mut tools,
+                &settings,
+                Arc::new(move |approval: &CommandApproval| {
+                    shown.lock().unwrap().push(approval.command.clone());
+                    answer(approval)
+                }),
+                dir.path().join("commands.log")
+            );
+            Self { tools, prompted, dir }
+        }
+
+        /// Runs `command`, returning the tool's output as JSON
+        async fn run(&self, command: &str) -> Result<Value> {
+            let arguments = json!({ "command": command }).to_string();
+            let output = self.tools.call("run_command", &arguments).await?;
+            Ok(serde_json::from_str(&output)?)
+        }
+
+        fn prompted(&self) -> Vec<String> {
+            self.prompted.lock().unwrap().clone()
+        }
+
+        fn log(&self) -> Vec<CommandLogEntry> {
+            fs::read_to_string(self.dir.path().join("commands.log"))
+                .unwrap_or_default()
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect()
+        }
+
+        fn path(&self) -> &Path {
+            self.dir.path()
+        }
+    }
+
+    // The `run_command` tool end to end with real processes
+
+    #[test]
+    fn allowlist_matches_words_not_operators() {
+        let allowlist = vec!["git status".to_string(), "echo".to_string()];
+        let cases = [
+            ("git status", true),
+            ("git status --short", true),
+            ("  echo hi  ", true),
+            ("git statusx", false),
+            ("git stash", false),
+            ("echo hi; rm -rf ~", false),
+            ("echo hi && curl example.com", false),
+            ("echo $(whoami)", false),
+            ("echo hi > file", false),
+            ("", false),
+        ];
+        for (command, expected) in cases {
+            assert_eq!(is_allowlisted(command, &allowlist), expected, "{:?}", command);
+        }
+    }
+
+    #[tokio::test]
+    async fn allowlisted_command_runs_unasked() {
+        let fixture = Fixture::new(true);
+        let output = fixture.run("echo hello").await.unwrap();
+        assert_eq!(output["stdout"].as_str().map(str::trim), Some("hello"));
+        assert_eq!(output["exit_code"], 0);
+        assert!(fixture.prompted().is_empty());
+
+        let log = fixture.log();
+        assert_eq!(log.len(), 1);
+        assert_eq!(log[0].approval, Approval::Allowlist);
+        assert_eq!(log[0].exit_code, Some(0));
+    }
+
+    #[tokio::test]
+    async fn approved_command_reports_exit_and_stderr() {
+        let fixture = Fixture::new(true);
+        let output = fixture.run("echo oops 1>&2 && exit 3").await.unwrap();
+        assert_eq!(output["exit_code"], 3);
+        assert_eq!(output["stderr"].as_str().map(str::trim), Some("oops"));
+        assert_eq!(fixture.prompted(), ["echo oops 1>&2 && exit 3"]);
+
+        let log = fixture.log();
+        assert_eq!(log[0].approval, Approval::User);
+        assert_eq!(log[0].exit_code, Some(3));
+    }
+
+    #[tokio::test]
+    async fn slow_command_is_killed_at_the_timeout() {
+        let fixture = Fixture::new(true);
+        let started = Instant::now();
+        let output = fixture.run(SLOW_COMMAND).await.unwrap();
+        assert!(started.elapsed().as_secs() < 5, "took {:?}", started.elapsed());
+        assert_eq!(output["timed_out"], true);
+        assert!(output["exit_code"].is_null());
+        assert!(fixture.log()[0].timed_out);
+    }
+
+    #[tokio::test]
+    async fn timeout_kills_what_the_command_started() {
+        let fixture = Fixture::new(true);
+        let output = fixture.run(ORPHANING_COMMAND).await.unwrap();
+        assert_eq!(output["timed_out"], true);
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        assert!(!fixture.path().join("late.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn output_is_capped() {
+        let fixture = Fixture::new(true);
+        let output = fixture.run(LOUD_COMMAND).await.unwrap();
+        assert_eq!(output["stdout"].as_str().map(str::len), Some(1000));
+        assert_eq!(output["truncated"], true);
+    }
+
+    #[tokio::test]
+    async fn declined_command_does_not_run() {
+        let fixture = Fixture::new(false);
+        let marker = fixture.path().join("declined.txt");
+        let err = fixture.run(&format!("echo ran > {}", marker.display())).await.unwrap_err();
+        assert!(err.to_string().contains("declined"), "{}", err);
+        assert!(!marker.exists());
+        assert_eq!(fixture.prompted().len(), 1);
+
+        let log = fixture.log();
+        assert_eq!(log[0].approval, Approval::Declined);
+        assert!(log[0].exit_code.is_none());
+    }
+
+    #[tokio::test]
+    async fn failed_prompt_is_logged() {
+        let fixture = Fixture::with_prompt(|_| Err(anyhow!("No window to ask in")));
+        let err = fixture.run("whoami").await.unwrap_err();
+        assert!(err.to_string().contains("No window"), "{}", err);
+
+        let log = fixture.log();
+        assert_eq!(log.len(), 1);
+        assert_eq!(log[0].approval, Approval::Declined);
+        assert_eq!(log[0].error.as_deref(), Some("No window to ask in"));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn cmd_expansions_need_approval() {
+        let allowlist = vec!["echo".to_string()];
+        for command in ["echo %USERPROFILE%", "echo !PATH!", "echo hi ^& calc"] {
+            assert!(!is_allowlisted(command, &allowlist), "{:?}", command);
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use std::ffi::c_void;
+use anyhow::{ anyhow, Result };
+use tokio::process::Child;
+use windows::core::PCWSTR;
+use windows::Win32::Foundation::{ CloseHandle, HANDLE };
+use windows::Win32::System::Diagnostics::ToolHelp::{
+    CreateToolhelp32Snapshot,
+    Thread32First,
+    Thread32Next,
+    TH32CS_SNAPTHREAD,
+    THREADENTRY32,
+};
+use windows::Win32::System::JobObjects::{
+    AssignProcessToJobObject,
+    CreateJobObjectW,
+    JobObjectExtendedLimitInformation,
+    SetInformationJobObject,
+    TerminateJobObject,
+    JOBOBJECT_EXTENDED_LIMIT_INFORMATION,
+    JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+};
+use windows::Win32::System::Threading::{ OpenThread, ResumeThread, THREAD_SUSPEND_RESUME };
+
+/// Process creation flag to spawn a command with, so it can't start
+/// anything before it's in its job; `resume` lets it run.
+pub const CREATE_SUSPENDED: u32 = 0x00000004;
+
+/// A job holding a command and every process it starts. Everything still
+/// running in it is killed when it's dropped.
+pub struct JobObject(HANDLE);
+
+// A job handle may be used from any thread
+unsafe impl Send for JobObject {}
+
+impl JobObject {
+    /// Puts `child` in a new job.
+    pub fn assign(child: &Child) -> Result<Self> {
+        let process = child.raw_handle().ok_or_else(|| anyhow!("The process already exited"))?;
+        let job = Self(unsafe { CreateJobObjectW(None, PCWSTR::null())? });
+        let mut limits = JOBOBJECT_EXTENDED_LIMIT_INFORMATION::default();
+        limits.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+        unsafe {
+            SetInformationJobObject(
+                job.0,
+                JobObjectExtendedLimitInformation,
+                (&limits as *const JOBOBJECT_EXTENDED_LIMIT_INFORMATION).cast::<c_void>(),
+                std::mem::size_of_val(&limits) as u32
+            )?;
+            AssignProcessToJobObject(job.0, HANDLE(process))?;
+        }
+        Ok(job)
+    }
+
+    /// Kills every process in the job.
+    pub fn terminate(&self) {
+        let _ = unsafe { TerminateJobObject(self.0, 1) };
+    }
+}
+
+impl Drop for JobObject {
+    fn drop(&mut self) {
+        let _ = unsafe { CloseHandle(self.0) };
+    }
+}
+
+/// Starts a process spawned with `CREATE_SUSPENDED`. Its threads are looked
+/// up by process id, as the child doesn't keep a handle to its main thread.
+pub fn resume(child: &Child) -> Result<()> {
+    let process_id = child.id().ok_or_else(|| anyhow!("The process already exited"))?;
+    let snapshot = unsafe { CreateToolhelp32Snapshot(TH32CS_SNAPTHREAD, 0)? };
+    let mut entry = THREADENTRY32 {
+        dwSize: std::mem::size_of::<THREADENTRY32>() as u32,
+        ..Default::default()
+    };
+    let mut resumed = 0;
+    let mut listed = unsafe { Thread32First(snapshot, &mut entry) };
+    while listed.is_ok() {
+        if entry.th32OwnerProcessID == process_id {
+            let thread = unsafe { OpenThread(THREAD_SUSPEND_RESUME, false, entry.th32ThreadID) };
+            if let Ok(thread) = thread {
+                if unsafe { ResumeThread(thread) } != u32::MAX {
+                    resumed += 1;
+                }
+                let _ = unsafe { CloseHandle(thread) };
+            }
+        }
+        listed = unsafe { Thread32Next(snapshot, &mut entry) };
+    }
+    let _ = unsafe { CloseHandle(snapshot) };
+    if resumed == 0 {
+        return Err(anyhow!("Failed to resume process {}", process_id));
+    }
+    Ok(())
+}
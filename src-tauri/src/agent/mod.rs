@@ -0,0 +1,657 @@
+use std::future::Future;
+use std::sync::Arc;
+use anyhow::{ anyhow, Result };
+use futures::future::BoxFuture;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::context::render::TRUNCATION_MARKER;
+use crate::llm::sse::StreamEvent;
+use crate::llm::tokenizer::TokenCounter;
+use crate::llm::tools::{ FunctionDefinition, ToolDefinition };
+use crate::llm::{ ChatMessage, ChatRequest, LlmProvider };
+use crate::settings::Settings;
+
+pub mod builtin;
+pub mod command;
+#[cfg(windows)]
+mod job_object;
+
+type Handler = Arc<dyn (Fn(Value) -> BoxFuture<'static, Result<String>>) + Send + Sync>;
+
+#[derive(Clone)]
+struct Tool {
+    definition: ToolDefinition,
+    handler: Handler,
+}
+
+/// Tools the model can call by name, each with a JSON Schema of its
+/// arguments and a handler taking them deserialized.
+#[derive(Clone, Default)]
+pub struct ToolRegistry {
+    tools: Vec<Tool>,
+}
+
+impl ToolRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a tool, replacing one of the same name. The model's arguments
+    /// are deserialized into `A` for `handler`; a string result is passed
+    /// back as-is, anything else as JSON.
+    pub fn register<A, R, F, Fut>(
+        &mut self,
+        name: &str,
+        description: &str,
+        parameters: Value,
+        handler: F
+    )
+        where
+            A: DeserializeOwned + Send + 'static,
+            R: Serialize,
+            F: (Fn(A) -> Fut) + Send + Sync + 'static,
+            Fut: Future<Output = Result<R>> + Send + 'static
+    {
+        let tool_name = name.to_string();
+        let handler = Arc::new(handler);
+        let handler: Handler = Arc::new(move |arguments: Value| {
+            let handler = Arc::clone(&handler);
+            let tool_name = tool_name.clone();
+            Box::pin(async move {
+                let arguments: A = serde_json
+                    ::from_value(arguments)
+                    .map_err(|err| anyhow!("Invalid arguments for {}: {}", tool_name, err))?;
+                match serde_json::to_value(handler(arguments).await?)? {
+                    Value::String(text) => Ok(text),
+                    value => Ok(value.to_string()),
+                }
+            })
+        });
+        let definition = ToolDefinition {
+            kind: "function".to_string(),
+            function: FunctionDefinition {
+                name: name.to_string(),
+                description: description.to_string(),
+                parameters,
+            },
+        };
+        self.tools.retain(|tool| tool.definition.function.name != name);
+        self.tools.push(Tool { definition, handler });
+    }
+
+    /// What's sent to the model as `tools`, in the order registered
+    pub fn definitions(&self) -> Vec<ToolDefinition> {
+        self.tools
+            .iter()
+            .map(|tool| tool.definition.clone())
+            .collect()
+    }
+
+    /// Runs tool `name` on `arguments`, the JSON text the model sent;
+    /// empty text means no arguments.
+    pub async fn call(&self, name: &str, arguments: &str) -> Result<String> {
+        let tool = self.tools
+            .iter()
+            .find(|tool| tool.definition.function.name == name)
+            .ok_or_else(|| anyhow!("Unknown tool {}", name))?;
+        let arguments: Value = if arguments.trim().is_empty() {
+            Value::Object(Default::default())
+        } else {
+            serde_json
+                ::from_str(arguments)
+                .map_err(|err| anyhow!("Arguments for {} aren't JSON: {}", name, err))?
+        };
+        (tool.handler)(arguments).await
+    }
+}
+
+/// Answers the chat, letting the model call `tools` on the way: each round
+/// its calls are run and their results appended, until it answers without
+/// calling any. After `agent_max_steps` rounds it's asked again without
+/// tools, so it has to answer.
+///
+/// Content streams to `on_event` as `Delta`s, each call as a `ToolCall`
+/// then a `ToolResult`. A failed call is reported to the model as its
+/// result, so it can recover. Results are cut to a quarter of the context
+/// budget, and once the conversation outgrows the budget the oldest results
+/// are dropped. Resolves with the reply text of all rounds.
+pub async fn run_agent(
+    provider: &dyn LlmProvider,
+    settings: &Settings,
+    tools: &ToolRegistry,
+    counter: &dyn TokenCounter,
+    mut messages: Vec<ChatMessage>,
+    on_event: &mut (dyn FnMut(StreamEvent) + Send)
+) -> Result<String> {
+    let max_steps = settings.agent_max_steps as usize;
+    let max_result_tokens = settings.context_budget_tokens / 4;
+    let mut reply = String::new();
+    // Messages come in budgeted, only what the loop adds can grow past it
+    let first_added = messages.len();
+    let mut used: usize = messages
+        .iter()
+        .map(|message| message_tokens(counter, message))
+        .sum();
+
+    for step in 0..=max_steps {
+        let mut oldest = first_added;
+        while used > settings.context_budget_tokens {
+            let Some(index) = (oldest..messages.len()).find(|&index| {
+                messages[index].tool_call_id.is_some() && messages[index].content != DROPPED_RESULT
+            }) else {
+                break;
+            };
+            used -= counter.count(&messages[index].content);
+            messages[index].content = DROPPED_RESULT.to_string();
+            used += counter.count(DROPPED_RESULT);
+            oldest = index + 1;
+        }
+
+        let offered = if step < max_steps { tools.definitions() } else { Vec::new() };
+        let answer = {
+            let mut request = ChatRequest::from_settings(settings, &messages);
+            request.tools = &offered;
+            // Each round's text starts a new paragraph
+            let mut separate = !reply.is_empty();
+            let mut on_token = |token: &str| {
+                if separate {
+                    separate = false;
+                    on_event(StreamEvent::Delta { content: "\n\n".to_string() });
+                }
+                on_event(StreamEvent::Delta { content: token.to_string() });
+            };
+            provider.stream_chat(&request, &mut on_token).await?
+        };
+        if !answer.content.is_empty() {
+            if !reply.is_empty() {
+                reply.push_str("\n\n");
+            }
+            reply.push_str(&answer.content);
+        }
+        if answer.tool_calls.is_empty() || offered.is_empty() {
+            return Ok(reply);
+        }
+
+        messages.push(ChatMessage::tool_calls(&answer.content, answer.tool_calls.clone()));
+        used += message_tokens(counter, &messages[messages.len() - 1]);
+        for call in &answer.tool_calls {
+            on_event(StreamEvent::ToolCall {
+                id: call.id.clone(),
+                name: call.function.name.clone(),
+                arguments: call.function.arguments.clone(),
+            });
+            let result = tools.call(&call.function.name, &call.function.arguments).await;
+            on_event(StreamEvent::ToolResult {
+                id: call.id.clone(),
+                name: call.function.name.clone(),
+                error: result.as_ref().err().map(|err| err.to_string()),
+            });
+            let content = match result {
+                Ok(output) => {
+                    let cut = counter.truncate_start(&output, max_result_tokens);
+                    if cut.len() < output.len() {
+                        format!("{}{}", cut, TRUNCATION_MARKER)
+                    } else {
+                        output
+                    }
+                }
+                Err(err) => format!("Error: {}", err),
+            };
+            messages.push(ChatMessage::tool_result(&call.id, &content));
+            used += counter.count(&content);
+        }
+    }
+    Ok(reply)
+}
+
+/// Stands in for a tool result dropped to stay within the context budget
+const DROPPED_RESULT: &str = "[Result dropped to fit the context budget]";
+
+fn message_tokens(counter: &dyn TokenCounter, message: &ChatMessage) -> usize {
+    let arguments: usize = message.tool_calls
+        .iter()
+        .flatten()
+        .map(|call| counter.count(&call.function.arguments))
+        .sum();
+    counter.count(&message.content) + arguments
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::sync::Mutex as SyncMutex;
+    use anyhow::{ anyhow, Result };
+    use futures::future::BoxFuture;
+    use serde::Deserialize;
+    use serde_json::{ json, Value };
+
+    use crate::context::render::TRUNCATION_MARKER;
+    use crate::llm::sse::{ tool_call_deltas, SseDecoder, StreamEvent };
+    use crate::llm::tokenizer::{ TiktokenCounter, TokenCounter };
+    use crate::llm::tools::{ ToolCall, ToolCallAccumulator };
+    use crate::llm::{ ChatMessage, ChatReply, ChatRequest, LlmProvider, TokenCallback };
+    use crate::settings::Settings;
+    use super::builtin::builtin_tools;
+    use super::*;
+
+    /// Replays canned replies and records what it was asked
+    struct ScriptedProvider {
+        replies: SyncMutex<VecDeque<ChatReply>>,
+        /// Messages and offered tool names of every request
+        requests: SyncMutex<Vec<(Vec<ChatMessage>, Vec<String>)>>,
+    }
+
+    impl LlmProvider for ScriptedProvider {
+        fn stream_chat<'a>(
+            &'a self,
+            request: &'a ChatRequest<'a>,
+            on_token: TokenCallback<'a>
+        ) -> BoxFuture<'a, Result<ChatReply>> {
+            Box::pin(async move {
+                let offered = request.tools
+                    .iter()
+                    .map(|tool| tool.function.name.clone())
+                    .collect();
+                self.requests
+                    .lock()
+                    .map_err(|err| anyhow!("Failed to lock mutex: {}", err))?
+                    .push((request.messages.to_vec(), offered));
+                let reply = self.replies
+                    .lock()
+                    .map_err(|err| anyhow!("Failed to lock mutex: {}", err))?
+                    .pop_front()
+                    .ok_or_else(|| anyhow!("Connection refused"))?;
+                for piece in reply.content.split_inclusive(' ') {
+                    on_token(piece);
+                }
+                Ok(reply)
+            })
+        }
+    }
+
+    struct Run {
+        reply: Result<String>,
+        requests: Vec<(Vec<ChatMessage>, Vec<String>)>,
+        events: Vec<StreamEvent>,
+    }
+
+    impl Run {
+        fn tool_messages(&self) -> Vec<ChatMessage> {
+            self.requests
+                .last()
+                .map(|(messages, _)| messages.iter().filter(|message| message.role == "tool"))
+                .into_iter()
+                .flatten()
+                .cloned()
+                .collect()
+        }
+
+        fn streamed(&self) -> String {
+            self.events
+                .iter()
+                .filter_map(|event| match event {
+                    StreamEvent::Delta { content } => Some(content.as_str()),
+                    _ => None,
+                })
+                .collect()
+        }
+    }
+
+    fn reply(content: &str, calls: &[(&str, &str, &str)]) -> ChatReply {
+        ChatReply {
+            content: content.to_string(),
+            tool_calls: calls
+                .iter()
+                .map(|(id, name, arguments)| ToolCall::new(id, name, arguments))
+                .collect(),
+        }
+    }
+
+    #[derive(Deserialize)]
+    struct AddArguments {
+        a: i64,
+        b: i64,
+    }
+
+    #[derive(Deserialize)]
+    struct EchoArguments {
+        text: String,
+    }
+
+    fn fixture_tools() -> ToolRegistry {
+        let object = json!({ "type": "object" });
+        let mut tools = ToolRegistry::new();
+        tools.register("add", "Add a and b", object.clone(), |arguments: AddArguments| async move {
+            Ok(arguments.a + arguments.b)
+        });
+        tools.register("echo", "Say text back", object.clone(), |arguments: EchoArguments| {
+            async move { Ok(arguments.text) }
+        });
+        tools.register("fail", "Always fails", object.clone(), |_: Value| async move {
+            Err::<String, _>(anyhow!("disk on fire"))
+        });
+        tools.register("long", "Too much text", object, |_: Value| async move {
+            Ok("all work and no play ".repeat(2000))
+        });
+        tools
+    }
+
+    const RESULT_TOKENS: usize = 100;
+
+    async fn run(
+        counter: &dyn TokenCounter,
+        tools: &ToolRegistry,
+        max_steps: u32,
+        script: Vec<ChatReply>
+    ) -> Run {
+        let provider = ScriptedProvider {
+            replies: SyncMutex::new(script.into()),
+            requests: SyncMutex::new(Vec::new()),
+        };
+        let settings = Settings {
+            agent_max_steps: max_steps,
+            context_budget_tokens: RESULT_TOKENS * 4,
+            ..Settings::default()
+        };
+        let messages = vec![
+            ChatMessage::new("system", "You are a test."),
+            ChatMessage::new("user", "What is 2 + 3?")
+        ];
+
+        let mut events = Vec::new();
+        let mut on_event = |event: StreamEvent| events.push(event);
+        let reply = run_agent(&provider, &settings, tools, counter, messages, &mut on_event).await;
+        let requests = provider.requests.into_inner().unwrap();
+        Run { reply, requests, events }
+    }
+
+    fn counter() -> TiktokenCounter {
+        TiktokenCounter::for_model("gpt-4o").unwrap()
+    }
+
+    // The agent loop against a scripted provider
+
+    /// Two calls in one round, then the answer
+    async fn answered() -> Run {
+        run(&counter(), &fixture_tools(), 5, vec![
+            reply("Let me check.", &[
+                ("call_1", "add", r#"{"a": 2, "b": 3}"#),
+                ("call_2", "echo", r#"{"text": "hi"}"#),
+            ]),
+            reply("It is 5.", &[])
+        ]).await
+    }
+
+    #[tokio::test]
+    async fn rounds_join_into_one_reply() {
+        let answered = answered().await;
+        let text = answered.reply.as_deref().unwrap();
+        assert_eq!(text, "Let me check.\n\nIt is 5.");
+        assert_eq!(answered.streamed(), text);
+    }
+
+    #[tokio::test]
+    async fn results_follow_their_call() {
+        let answered = answered().await;
+        let (messages, _) = answered.requests.last().unwrap();
+        let roles: Vec<&str> = messages
+            .iter()
+            .map(|message| message.role.as_str())
+            .collect();
+        assert_eq!(roles, ["system", "user", "assistant", "tool", "tool"]);
+        let results: Vec<(Option<String>, String)> = answered
+            .tool_messages()
+            .into_iter()
+            .map(|message| (message.tool_call_id, message.content))
+            .collect();
+        assert_eq!(results, [
+            (Some("call_1".to_string()), "5".to_string()),
+            (Some("call_2".to_string()), "hi".to_string()),
+        ]);
+        // The assistant turn keeps its calls
+        assert_eq!(messages[2].tool_calls.as_ref().map(Vec::len), Some(2));
+    }
+
+    #[tokio::test]
+    async fn tools_are_offered_every_round() {
+        let answered = answered().await;
+        assert_eq!(answered.requests.len(), 2);
+        for (_, offered) in &answered.requests {
+            assert_eq!(offered, &["add", "echo", "fail", "long"]);
+        }
+    }
+
+    #[tokio::test]
+    async fn each_call_is_reported_then_its_result() {
+        let answered = answered().await;
+        let tool_events: Vec<String> = answered.events
+            .iter()
+            .filter_map(|event| match event {
+                StreamEvent::ToolCall { id, name, .. } => Some(format!("call {} {}", id, name)),
+                StreamEvent::ToolResult { id, error, .. } =>
+                    Some(format!("result {} {}", id, if error.is_none() { "ok" } else { "error" })),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(tool_events, [
+            "call call_1 add",
+            "result call_1 ok",
+            "call call_2 echo",
+            "result call_2 ok",
+        ]);
+    }
+
+    #[tokio::test]
+    async fn failed_calls_are_reported_to_the_model() {
+        let recovered = run(&counter(), &fixture_tools(), 5, vec![
+            reply("", &[
+                ("call_1", "nope", "{}"),
+                ("call_2", "add", r#"{"a": "two", "b": 3}"#),
+                ("call_3", "add", "a=2"),
+                ("call_4", "fail", ""),
+            ]),
+            reply("Sorry, I couldn't.", &[])
+        ]).await;
+        let errors: Vec<String> = recovered
+            .tool_messages()
+            .into_iter()
+            .map(|message| message.content)
+            .collect();
+        let expected = [
+            "Unknown tool nope",
+            "Invalid arguments for add",
+            "aren't JSON",
+            "disk on fire",
+        ];
+        assert_eq!(errors.len(), expected.len(), "{:?}", errors);
+        for (error, expected) in errors.iter().zip(expected) {
+            assert!(error.starts_with("Error: ") && error.contains(expected), "{}", error);
+        }
+
+        // And reach the UI
+        let failed = recovered.events
+            .iter()
+            .filter(|event| matches!(event, StreamEvent::ToolResult { error: Some(_), .. }))
+            .count();
+        assert_eq!(failed, 4);
+        assert_eq!(recovered.reply.as_deref().unwrap(), "Sorry, I couldn't.");
+    }
+
+    #[tokio::test]
+    async fn long_results_are_cut() {
+        let counter = counter();
+        let cut = run(&counter, &fixture_tools(), 5, vec![
+            reply("", &[("call_1", "long", "{}")]),
+            reply("Too long.", &[])
+        ]).await;
+        let result = cut.tool_messages().pop().unwrap().content;
+        assert!(result.ends_with(TRUNCATION_MARKER));
+        assert!(counter.count(result.trim_end_matches(TRUNCATION_MARKER)) <= RESULT_TOKENS);
+    }
+
+    #[tokio::test]
+    async fn oldest_results_are_dropped_past_the_budget() {
+        let counter = counter();
+        let calls: Vec<String> = (1..=5).map(|i| format!("call_{}", i)).collect();
+        let mut script: Vec<ChatReply> = calls
+            .iter()
+            .map(|id| reply("", &[(id, "long", "{}")]))
+            .collect();
+        script.push(reply("Read it all.", &[]));
+        let long = run(&counter, &fixture_tools(), 5, script).await;
+        assert_eq!(long.reply.as_deref().unwrap(), "Read it all.");
+
+        // Every request fits, the results left are the newest
+        for (messages, _) in &long.requests {
+            let used: usize = messages
+                .iter()
+                .map(|message| message_tokens(&counter, message))
+                .sum();
+            assert!(used <= RESULT_TOKENS * 4, "{} tokens", used);
+        }
+        let results: Vec<bool> = long
+            .tool_messages()
+            .iter()
+            .map(|message| message.content == DROPPED_RESULT)
+            .collect();
+        assert_eq!(results, [true, true, false, false, false]);
+    }
+
+    #[tokio::test]
+    async fn step_limit_forces_an_answer() {
+        // A model that never stops calling tools
+        let looping = run(&counter(), &fixture_tools(), 2, vec![
+            reply("", &[("call_1", "echo", r#"{"text": "again"}"#)]),
+            reply("", &[("call_2", "echo", r#"{"text": "again"}"#)]),
+            reply("Done.", &[("call_3", "echo", r#"{"text": "again"}"#)])
+        ]).await;
+        let offered: Vec<usize> = looping.requests
+            .iter()
+            .map(|(_, offered)| offered.len())
+            .collect();
+        assert_eq!(offered, [4, 4, 0]);
+        assert_eq!(looping.reply.as_deref().unwrap(), "Done.");
+    }
+
+    #[tokio::test]
+    async fn no_tools_is_a_plain_chat() {
+        let plain = run(&counter(), &ToolRegistry::new(), 5, vec![reply("Hello.", &[])]).await;
+        assert_eq!(plain.requests.len(), 1);
+        assert!(plain.requests[0].1.is_empty());
+        assert_eq!(plain.reply.as_deref().unwrap(), "Hello.");
+    }
+
+    #[tokio::test]
+    async fn provider_errors_end_the_run() {
+        let failing = run(&counter(), &fixture_tools(), 5, Vec::new()).await;
+        assert!(failing.reply.is_err());
+    }
+
+    #[test]
+    fn streamed_calls_reassemble() {
+        // Two calls streamed in pieces, the second starting before the first ends
+        let chunks = [
+            json!([{ "index": 0, "id": "call_a", "type": "function",
+                "function": { "name": "get_transcript", "arguments": "" } }]),
+            json!([{ "index": 0, "function": { "arguments": "{\"since\": \"2024-" } }]),
+            json!([{ "index": 1, "id": "call_b", "type": "function",
+                "function": { "name": "list_", "arguments": "{" } }]),
+            json!([{ "index": 1, "function": { "name": "windows", "arguments": "}" } }]),
+            json!([{ "index": 0, "function": { "arguments": "05-07T09:00:00Z\"}" } }]),
+        ];
+        let mut body = String::new();
+        for chunk in &chunks {
+            let event = json!({ "choices": [{ "index": 0, "delta": { "tool_calls": chunk } }] });
+            body.push_str(&format!("data: {}\n\n", event));
+        }
+        body.push_str("data: [DONE]\n\n");
+        let mut decoder = SseDecoder::new();
+        let mut accumulator = ToolCallAccumulator::default();
+        // Network reads split events anywhere
+        for piece in body.as_bytes().chunks(7) {
+            for event in decoder.feed(piece) {
+                if let Some(deltas) = tool_call_deltas(&event) {
+                    accumulator.feed(&deltas);
+                }
+            }
+        }
+        assert_eq!(accumulator.finish(), [
+            ToolCall::new("call_a", "get_transcript", "{\"since\": \"2024-05-07T09:00:00Z\"}"),
+            ToolCall::new("call_b", "list_windows", "{}"),
+        ]);
+    }
+
+    #[test]
+    fn calls_without_index_are_told_apart() {
+        // Servers that leave out `index`, or send arguments as an object
+        let mut accumulator = ToolCallAccumulator::default();
+        accumulator.feed(&json!([{ "id": "x", "function": { "name": "echo", "arguments": "{" } }]));
+        accumulator.feed(&json!([{ "function": { "arguments": "}" } }]));
+        accumulator.feed(&json!([{
+            "id": "y", "function": { "name": "add", "arguments": { "a": 1 } }
+        }]));
+        assert_eq!(accumulator.finish(), [
+            ToolCall::new("x", "echo", "{}"),
+            ToolCall::new("y", "add", r#"{"a":1}"#),
+        ]);
+    }
+
+    #[test]
+    fn malformed_calls_are_dropped() {
+        let mut accumulator = ToolCallAccumulator::default();
+        accumulator.feed(&json!([{ "index": 0, "id": "a", "function": { "name": "echo" } }]));
+        // Skips index 1
+        accumulator.feed(&json!([{ "index": 2, "id": "c", "function": { "name": "add" } }]));
+        accumulator.feed(&json!([{ "index": 1, "function": { "name": "add" } }]));
+        accumulator.feed(&json!([{ "index": 2, "id": "d", "function": { "arguments": "{}" } }]));
+        assert_eq!(accumulator.finish(), [ToolCall::new("a", "echo", "")]);
+    }
+
+    #[test]
+    fn messages_keep_the_api_wire_format() {
+        // Plain messages stay as they were saved before tools existed
+        let plain = serde_json::to_value(ChatMessage::new("user", "hi")).unwrap();
+        assert_eq!(plain, json!({ "role": "user", "content": "hi" }));
+        let calls = serde_json
+            ::to_value(ChatMessage::tool_calls("", vec![ToolCall::new("call_1", "echo", "{}")]))
+            .unwrap();
+        assert_eq!(calls["tool_calls"][0]["type"], "function");
+        assert_eq!(calls["tool_calls"][0]["function"]["name"], "echo");
+        let result = serde_json::to_value(ChatMessage::tool_result("call_1", "hi")).unwrap();
+        assert_eq!(result["role"], "tool");
+        assert_eq!(result["tool_call_id"], "call_1");
+    }
+
+    #[tokio::test]
+    async fn builtin_tools_respect_settings() {
+        let builtin = builtin_tools(
+            &(Settings {
+                screen_context: false,
+                audio_context: false,
+                timeline_enabled: false,
+                ..Settings::default()
+            })
+        );
+        let definitions = serde_json::to_value(builtin.definitions()).unwrap();
+        let definitions = definitions.as_array().unwrap();
+        let names: Vec<&str> = definitions
+            .iter()
+            .filter_map(|definition| definition["function"]["name"].as_str())
+            .collect();
+        assert_eq!(
+            names,
+            ["list_windows", "get_screen_context", "get_transcript", "search_timeline"]
+        );
+        for definition in definitions {
+            assert_eq!(definition["type"], "function");
+            assert_eq!(definition["function"]["parameters"]["type"], "object");
+        }
+
+        for name in names {
+            let err = builtin.call(name, "{}").await.unwrap_err().to_string();
+            assert!(err.contains("turned off"), "{}: {}", name, err);
+        }
+    }
+}
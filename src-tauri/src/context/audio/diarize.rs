@@ -0,0 +1,510 @@
+use std::f32::consts::PI;
+use realfft::RealFftPlanner;
+
+use super::resample::WHISPER_SAMPLE_RATE;
+
+/// 25 ms analysis frames every 10 ms, at 16 kHz
+const FRAME_LEN: usize = 400;
+const HOP_LEN: usize = 160;
+const FFT_LEN: usize = 512;
+const MEL_FILTERS: usize = 26;
+/// Cepstral coefficients kept, c0 (loudness) excluded
+const CEPSTRA: usize = 19;
+const LIFTER: f32 = 22.0;
+/// Frames this far below the loudest one are pauses and breath, not voice
+const VOICED_RANGE_DB: f32 = 30.0;
+/// Speaking pitch range searched, Hz
+const MIN_PITCH: f32 = 70.0;
+const MAX_PITCH: f32 = 400.0;
+/// Normalized autocorrelation a frame needs to have a pitch
+const MIN_PERIODICITY: f32 = 0.5;
+
+/// Tuning for `Diarizer`.
+#[derive(Debug, Clone, Copy)]
+pub struct DiarizerConfig {
+    /// Similarity a voiceprint needs to count as a known voice: cosine of
+    /// the cepstra, less `pitch_penalty` per semitone of pitch difference
+    pub min_similarity: f32,
+    pub pitch_penalty: f32,
+    /// Past this, new voices go to the closest known one
+    pub max_voices: usize,
+    /// Voiced audio a voiceprint needs to start a new voice or refine one
+    pub min_voiced_ms: u32,
+}
+
+impl Default for DiarizerConfig {
+    fn default() -> Self {
+        Self {
+            min_similarity: 0.85,
+            pitch_penalty: 0.05,
+            max_voices: 8,
+            min_voiced_ms: 1000,
+        }
+    }
+}
+
+/// Summary of how a stretch of speech sounds: mean and spread of its
+/// cepstrum (vocal tract) over the voiced frames, and its median pitch.
+#[derive(Debug, Clone)]
+pub struct Voiceprint {
+    vector: Vec<f32>,
+    /// Semitones above 100 Hz, `None` if no frame had a clear pitch
+    pitch: Option<f32>,
+    voiced_ms: u32,
+}
+
+/// Voiceprint of 16 kHz mono speech, `None` if nothing in it is voiced.
+pub fn voiceprint(samples: &[f32]) -> Option<Voiceprint> {
+    if samples.len() < FRAME_LEN {
+        return None;
+    }
+    let mut planner = RealFftPlanner::<f32>::new();
+    let fft = planner.plan_fft_forward(FFT_LEN);
+    let mut input = fft.make_input_vec();
+    let mut spectrum = fft.make_output_vec();
+    let window: Vec<f32> = (0..FRAME_LEN)
+        .map(|i| 0.54 - 0.46 * ((2.0 * PI * (i as f32)) / ((FRAME_LEN - 1) as f32)).cos())
+        .collect();
+    let filters = mel_filters();
+
+    // Log mel energies, loudness and pitch of every frame
+    let mut frames: Vec<(f32, Vec<f32>, Option<f32>)> = Vec::new();
+    for start in (0..=samples.len() - FRAME_LEN).step_by(HOP_LEN) {
+        let frame = &samples[start..start + FRAME_LEN];
+        input.iter_mut().for_each(|value| {
+            *value = 0.0;
+        });
+        for i in 0..FRAME_LEN {
+            // Pre-emphasis lifts the high formants that tell voices apart
+            let previous = if i > 0 { frame[i - 1] } else { 0.0 };
+            input[i] = (frame[i] - 0.97 * previous) * window[i];
+        }
+        if fft.process(&mut input, &mut spectrum).is_err() {
+            return None;
+        }
+        let power: Vec<f32> = spectrum
+            .iter()
+            .map(|bin| bin.norm_sqr())
+            .collect();
+        let energy_db = 10.0 * (power.iter().sum::<f32>() + 1e-10).log10();
+        let mel: Vec<f32> = filters
+            .iter()
+            .map(|filter| {
+                let energy: f32 = filter
+                    .iter()
+                    .map(|(bin, weight)| power[*bin] * weight)
+                    .sum();
+                (energy + 1e-10).ln()
+            })
+            .collect();
+        frames.push((energy_db, mel, pitch(frame)));
+    }
+
+    let loudest = frames
+        .iter()
+        .map(|(energy_db, _, _)| *energy_db)
+        .fold(f32::MIN, f32::max);
+    let voiced: Vec<&(f32, Vec<f32>, Option<f32>)> = frames
+        .iter()
+        .filter(|(energy_db, _, _)| *energy_db >= loudest - VOICED_RANGE_DB)
+        .collect();
+    if voiced.is_empty() {
+        return None;
+    }
+    let cepstra: Vec<Vec<f32>> = voiced
+        .iter()
+        .map(|(_, mel, _)| cepstrum(mel))
+        .collect();
+    let mut pitches: Vec<f32> = voiced
+        .iter()
+        .filter_map(|(_, _, pitch)| *pitch)
+        .collect();
+    pitches.sort_by(f32::total_cmp);
+
+    let count = cepstra.len() as f32;
+    let mean: Vec<f32> = (0..CEPSTRA)
+        .map(|k| cepstra.iter().map(|c| c[k]).sum::<f32>() / count)
+        .collect();
+    let spread: Vec<f32> = (0..CEPSTRA)
+        .map(|k| {
+            let variance = cepstra
+                .iter()
+                .map(|c| (c[k] - mean[k]).powi(2))
+                .sum::<f32>() / count;
+            variance.sqrt()
+        })
+        .collect();
+
+    Some(Voiceprint {
+        vector: normalize(mean.into_iter().chain(spread).collect()),
+        pitch: pitches.get(pitches.len() / 2).copied(),
+        voiced_ms: ((cepstra.len() * HOP_LEN * 1000) / (WHISPER_SAMPLE_RATE as usize)) as u32,
+    })
+}
+
+/// Pitch of a frame in semitones above 100 Hz, from the strongest
+/// autocorrelation peak in the speaking range
+fn pitch(frame: &[f32]) -> Option<f32> {
+    let rate = WHISPER_SAMPLE_RATE as f32;
+    let min_lag = (rate / MAX_PITCH) as usize;
+    let max_lag = ((rate / MIN_PITCH) as usize).min(frame.len() - 1);
+    let energy: f32 = frame
+        .iter()
+        .map(|s| s * s)
+        .sum();
+    if energy <= 0.0 {
+        return None;
+    }
+    let (lag, correlation) = (min_lag..=max_lag)
+        .map(|lag| {
+            let correlation: f32 = frame[..frame.len() - lag]
+                .iter()
+                .zip(&frame[lag..])
+                .map(|(a, b)| a * b)
+                .sum();
+            (lag, correlation / energy)
+        })
+        .max_by(|a, b| a.1.total_cmp(&b.1))?;
+    (correlation >= MIN_PERIODICITY).then(|| 12.0 * (rate / (lag as f32) / 100.0).log2())
+}
+
+/// Triangular filters on the mel scale as (FFT bin, weight) lists
+fn mel_filters() -> Vec<Vec<(usize, f32)>> {
+    let to_mel = |hz: f32| 2595.0 * (1.0 + hz / 700.0).log10();
+    let to_hz = |mel: f32| 700.0 * ((10.0f32).powf(mel / 2595.0) - 1.0);
+    let (low, high) = (to_mel(64.0), to_mel(7600.0));
+    let edges: Vec<f32> = (0..MEL_FILTERS + 2)
+        .map(|i| {
+            let hz = to_hz(low + ((high - low) * (i as f32)) / ((MEL_FILTERS + 1) as f32));
+            (hz * (FFT_LEN as f32)) / (WHISPER_SAMPLE_RATE as f32)
+        })
+        .collect();
+    (0..MEL_FILTERS)
+        .map(|m| {
+            let (left, center, right) = (edges[m], edges[m + 1], edges[m + 2]);
+            ((left.floor() as usize)..=(right.ceil() as usize).min(FFT_LEN / 2))
+                .filter_map(|bin| {
+                    let position = bin as f32;
+                    let weight = if position <= center {
+                        (position - left) / (center - left)
+                    } else {
+                        (right - position) / (right - center)
+                    };
+                    (weight > 0.0).then_some((bin, weight))
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Liftered DCT-II of log mel energies, c1..=CEPSTRA
+fn cepstrum(mel: &[f32]) -> Vec<f32> {
+    let n = mel.len() as f32;
+    (1..=CEPSTRA)
+        .map(|k| {
+            let coefficient: f32 = mel
+                .iter()
+                .enumerate()
+                .map(|(i, energy)| energy * ((PI * (k as f32) * ((i as f32) + 0.5)) / n).cos())
+                .sum();
+            // Evens out the naturally decaying higher coefficients
+            let lifter = 1.0 + (LIFTER / 2.0) * ((PI * (k as f32)) / LIFTER).sin();
+            coefficient * lifter
+        })
+        .collect()
+}
+
+fn normalize(mut vector: Vec<f32>) -> Vec<f32> {
+    let norm = vector
+        .iter()
+        .map(|value| value * value)
+        .sum::<f32>()
+        .sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|value| {
+            *value /= norm;
+        });
+    }
+    vector
+}
+
+struct Voice {
+    /// Normalized mean of the voiceprints assigned to it
+    centroid: Vec<f32>,
+    pitch: Option<f32>,
+    voiced_ms: u64,
+}
+
+/// Online clustering of voiceprints into voices, numbered from 1 in the
+/// order they're first heard.
+///
+/// Each voiceprint joins the most similar voice if it's similar enough,
+/// otherwise it starts a new one. Joining moves the voice's centroid towards
+/// it, weighted by how much speech each has.
+pub struct Diarizer {
+    config: DiarizerConfig,
+    voices: Vec<Voice>,
+}
+
+impl Diarizer {
+    pub fn new(config: DiarizerConfig) -> Self {
+        Self { config, voices: Vec::new() }
+    }
+
+    pub fn reset(&mut self) {
+        self.voices.clear();
+    }
+
+    fn similarity(&self, voice: &Voice, print: &Voiceprint) -> f32 {
+        let cosine: f32 = voice.centroid
+            .iter()
+            .zip(&print.vector)
+            .map(|(a, b)| a * b)
+            .sum();
+        let pitch_difference = match (voice.pitch, print.pitch) {
+            (Some(a), Some(b)) => (a - b).abs(),
+            _ => 0.0,
+        };
+        cosine - self.config.pitch_penalty * pitch_difference
+    }
+
+    fn closest(&self, print: &Voiceprint) -> Option<(usize, f32)> {
+        self.voices
+            .iter()
+            .enumerate()
+            .map(|(index, voice)| (index, self.similarity(voice, print)))
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+    }
+
+    /// The known voice `print` sounds like, without learning from it.
+    pub fn identify(&self, print: &Voiceprint) -> Option<usize> {
+        self.closest(print)
+            .filter(|(_, similarity)| *similarity >= self.config.min_similarity)
+            .map(|(index, _)| index + 1)
+    }
+
+    /// The voice `print` belongs to, new or known, refining it. Too little
+    /// speech to trust only gets `identify`'d.
+    pub fn assign(&mut self, print: &Voiceprint) -> Option<usize> {
+        if print.voiced_ms < self.config.min_voiced_ms {
+            return self.identify(print);
+        }
+        let closest = self.closest(print);
+        let index = match closest {
+            Some((index, similarity)) if similarity >= self.config.min_similarity => index,
+            Some((index, _)) if self.voices.len() >= self.config.max_voices => index,
+            _ => {
+                self.voices.push(Voice {
+                    centroid: print.vector.clone(),
+                    pitch: print.pitch,
+                    voiced_ms: 0,
+                });
+                self.voices.len() - 1
+            }
+        };
+
+        let voice = &mut self.voices[index];
+        let total = (voice.voiced_ms + (print.voiced_ms as u64)) as f32;
+        let (old, new) = ((voice.voiced_ms as f32) / total, (print.voiced_ms as f32) / total);
+        voice.centroid = normalize(
+            voice.centroid
+                .iter()
+                .zip(&print.vector)
+                .map(|(a, b)| a * old + b * new)
+                .collect()
+        );
+        voice.pitch = match (voice.pitch, print.pitch) {
+            (Some(a), Some(b)) => Some(a * old + b * new),
+            (a, b) => a.or(b),
+        };
+        voice.voiced_ms += print.voiced_ms as u64;
+        Some(index + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{ BTreeSet, HashMap };
+    use std::fs;
+    use std::path::Path;
+    use anyhow::{ anyhow, Result };
+
+    use crate::test_support::{ load_wav_fixtures, noise, write_wav, WavFixture };
+    use super::super::vad::{ detect, VadConfig };
+    use super::*;
+
+    /// Labeled speech that must end up in the voice mostly holding its speaker
+    const MIN_ACCURACY: f32 = 0.85;
+
+    /// Runs every `<name>.wav` in `dir` through the VAD and the diarizer, each
+    /// with a fresh diarizer, and scores the voices against the turns labeled in
+    /// `<name>.json`, a list of `[start_seconds, end_seconds, "speaker"]`.
+    ///
+    /// Each voice stands for the labeled speaker it holds most speech of;
+    /// accuracy is the share of labeled speech in its speaker's voice. Fails if
+    /// any fixture is below `MIN_ACCURACY` or finds a different number of voices
+    /// than there are speakers.
+    fn run_diarization_eval(dir: &Path, config: DiarizerConfig) -> Result<()> {
+        let fixtures = load_wav_fixtures::<Vec<(f32, f32, String)>>(dir)?;
+
+        let mut failures = Vec::new();
+        println!(
+            "{:<32} {:>8} {:>8} {:>8} {:>10}",
+            "fixture",
+            "chunks",
+            "speakers",
+            "voices",
+            "accuracy"
+        );
+        for WavFixture { name, samples, labels } in &fixtures {
+            let rate = WHISPER_SAMPLE_RATE as f32;
+            let turns: Vec<(u64, u64, &str)> = labels
+                .iter()
+                .map(|(start, end, speaker)| {
+                    ((start * rate) as u64, (end * rate) as u64, speaker.as_str())
+                })
+                .collect();
+
+            // Like capture: each VAD chunk gets one voice
+            let chunks = detect(samples, WHISPER_SAMPLE_RATE, VadConfig::default());
+            let mut diarizer = Diarizer::new(config);
+            // Samples of each labeled speaker that went to each voice
+            let mut heard: HashMap<Option<usize>, HashMap<&str, u64>> = HashMap::new();
+            for chunk in &chunks {
+                let voice = voiceprint(&chunk.samples).and_then(|print| diarizer.assign(&print));
+                for (start, end, speaker) in &turns {
+                    let overlap = chunk
+                        .end_sample()
+                        .min(*end)
+                        .saturating_sub(chunk.start_sample.max(*start));
+                    *heard.entry(voice).or_default().entry(speaker).or_default() += overlap;
+                }
+            }
+
+            let total: u64 = heard
+                .values()
+                .flat_map(|speakers| speakers.values())
+                .sum();
+            // Speech no voice was found for counts as wrong
+            let correct: u64 = heard
+                .iter()
+                .filter(|(voice, _)| voice.is_some())
+                .map(|(_, speakers)| speakers.values().copied().max().unwrap_or(0))
+                .sum();
+            let accuracy = if total == 0 { 0.0 } else { (correct as f32) / (total as f32) };
+            let speakers: BTreeSet<&str> = turns
+                .iter()
+                .map(|(_, _, speaker)| *speaker)
+                .collect();
+            let voices = heard
+                .iter()
+                .filter(|(voice, speakers)| {
+                    voice.is_some() && speakers.values().any(|samples| *samples > 0)
+                })
+                .count();
+
+            println!(
+                "{:<32} {:>8} {:>8} {:>8} {:>9.0}%",
+                name,
+                chunks.len(),
+                speakers.len(),
+                voices,
+                accuracy * 100.0
+            );
+            if accuracy < MIN_ACCURACY || voices != speakers.len() {
+                failures.push(name.as_str());
+            }
+        }
+
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!("Diarization failed on {}", failures.join(", ")))
+        }
+    }
+
+    const RATE: u32 = 44100;
+
+    /// `secs` of a vowel-like sound: harmonics of `pitch` Hz falling off by
+    /// `rolloff` each, so voices differ in pitch and brightness
+    fn voice(pitch: f32, rolloff: f32, secs: f32) -> Vec<f32> {
+        let len = (secs * (RATE as f32)) as usize;
+        (0..len)
+            .map(|i| {
+                let t = (i as f32) / (RATE as f32);
+                (1..=20)
+                    .filter(|harmonic| pitch * (*harmonic as f32) < 7000.0)
+                    .map(|harmonic| {
+                        let k = harmonic as f32;
+                        0.2 * rolloff.powf(k - 1.0) * (std::f32::consts::TAU * pitch * k * t).sin()
+                    })
+                    .sum()
+            })
+            .collect()
+    }
+
+    /// Turns of `speakers` ("low" or "high" voice), 2 s each with a second
+    /// of quiet noise before and after each, as a 44.1 kHz mono capture
+    fn write_fixture(dir: &Path, name: &str, speakers: &[&str], labels: &[&str]) {
+        let mut samples = noise(0.001, 1.0, RATE);
+        let mut turns = Vec::new();
+        for (speaker, label) in speakers.iter().zip(labels) {
+            let start = (samples.len() as f32) / (RATE as f32);
+            samples.extend(match *speaker {
+                "low" => voice(110.0, 0.8, 2.0),
+                _ => voice(240.0, 0.5, 2.0),
+            });
+            turns.push((start, start + 2.0, label.to_string()));
+            samples.extend(noise(0.001, 1.0, RATE));
+        }
+        write_wav(&dir.join(format!("{}.wav", name)), RATE, 1, &samples);
+        let labels = serde_json::to_string(&turns).unwrap();
+        fs::write(dir.join(format!("{}.json", name)), labels).unwrap();
+    }
+
+    #[test]
+    fn synthetic_fixture_passes() {
+        let dir = tempfile::tempdir().unwrap();
+        let turns = ["low", "high", "low", "high"];
+        write_fixture(dir.path(), "two-voices", &turns, &["a", "b", "a", "b"]);
+        run_diarization_eval(dir.path(), DiarizerConfig::default()).unwrap();
+    }
+
+    #[test]
+    fn mislabeled_turns_fail() {
+        let dir = tempfile::tempdir().unwrap();
+        let turns = ["low", "high", "low", "high"];
+        write_fixture(dir.path(), "labeled", &turns, &["a", "b", "a", "b"]);
+        // Labels one voice as two speakers and two voices as one
+        write_fixture(dir.path(), "mislabeled", &turns, &["a", "b", "c", "a"]);
+        let err = run_diarization_eval(dir.path(), DiarizerConfig::default())
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("mislabeled.wav") && !err.contains(" labeled.wav"), "{}", err);
+    }
+
+    #[test]
+    fn empty_directory_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(run_diarization_eval(dir.path(), DiarizerConfig::default()).is_err());
+    }
+
+    #[test]
+    fn checked_in_fixtures_pass() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/diarization");
+        run_diarization_eval(&dir, DiarizerConfig::default()).unwrap();
+    }
+
+    // Run with `DIARIZATION_EVAL_DIR=<dir> cargo test diarization_eval -- --ignored --nocapture`,
+    // `DIARIZATION_MIN_SIMILARITY` overrides the diarizer's threshold
+    #[test]
+    #[ignore = "needs labeled WAV fixtures in DIARIZATION_EVAL_DIR"]
+    fn diarization_eval() {
+        let dir = std::env::var("DIARIZATION_EVAL_DIR").expect("DIARIZATION_EVAL_DIR is not set");
+        let mut config = DiarizerConfig::default();
+        if let Ok(value) = std::env::var("DIARIZATION_MIN_SIMILARITY") {
+            config.min_similarity = value.parse().expect("DIARIZATION_MIN_SIMILARITY is a number");
+        }
+        run_diarization_eval(Path::new(&dir), config).unwrap();
+    }
+}
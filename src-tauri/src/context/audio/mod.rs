@@ -0,0 +1,801 @@
+use whisper_rs::{
+    get_lang_id,
+    get_lang_str,
+    WhisperContext,
+    WhisperContextParameters,
+    WhisperState,
+    FullParams,
+    SamplingStrategy,
+};
+use std::path::PathBuf;
+use std::thread;
+use std::sync::Arc;
+use std::sync::Mutex as SyncMutex;
+use tokio::sync::Mutex;
+use anyhow::Result;
+use tokio::runtime::Runtime;
+use tokio::task;
+use chrono::{ DateTime, Duration, Utc };
+
+pub mod diarize;
+pub mod model;
+#[cfg(target_os = "linux")]
+mod pulse;
+pub mod resample;
+pub mod ring;
+pub mod source;
+pub mod streaming;
+pub mod transcript;
+pub mod vad;
+
+use crate::settings::{ Settings, WhisperDecoding, WhisperModelSize };
+use diarize::{ voiceprint, Diarizer, DiarizerConfig };
+use model::{ find_model, InstalledModel, ModelStatus };
+use resample::{ channels_to_mono, StreamResampler, WHISPER_SAMPLE_RATE };
+use ring::{ ring_buffer, RingConsumer };
+use source::{ open_source, AudioFormat, AudioSource, AudioSourceKind, CaptureSource };
+use streaming::{ LiveSpeech, LiveWindow };
+use transcript::{ AudioContext, Speaker, Transcript, TranscriptSegment };
+use vad::{ SpeechChunk, Vad, VadConfig };
+
+/// How audio is buffered and how and how often whisper runs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TranscriptionOptions {
+    /// Captured audio kept for the transcriber before the oldest is dropped,
+    /// applies to sources opened after it changes
+    pub max_buffered: std::time::Duration,
+    /// New speech between runs over speech in progress, `None` to only
+    /// transcribe at pauses
+    pub streaming_step: Option<std::time::Duration>,
+    /// ISO 639-1 code or "auto"
+    pub language: String,
+    pub translate: bool,
+    pub threads: u32,
+    /// `None` for greedy decoding
+    pub beam_size: Option<u32>,
+    pub initial_prompt: String,
+    /// Label system audio segments with the voice they're in
+    pub diarization: bool,
+    pub speaker_names: Vec<String>,
+}
+
+impl TranscriptionOptions {
+    pub fn from_settings(settings: &Settings) -> Self {
+        Self {
+            max_buffered: std::time::Duration::from_secs(settings.audio_buffer_secs.max(1).into()),
+            streaming_step: settings.audio_streaming.then(||
+                std::time::Duration::from_millis(settings.audio_streaming_step_ms.max(200).into())
+            ),
+            language: settings.whisper_language.trim().to_lowercase(),
+            translate: settings.whisper_translate,
+            threads: settings.whisper_threads.max(1),
+            beam_size: match settings.whisper_decoding {
+                WhisperDecoding::Greedy => None,
+                WhisperDecoding::BeamSearch => Some(settings.whisper_beam_size.max(1)),
+            },
+            initial_prompt: settings.whisper_initial_prompt.clone(),
+            diarization: settings.audio_diarization,
+            speaker_names: settings.speaker_names.clone(),
+        }
+    }
+}
+
+impl Default for TranscriptionOptions {
+    fn default() -> Self {
+        Self::from_settings(&Settings::default())
+    }
+}
+
+/// How far back `get_transcript` can look
+const TRANSCRIPT_RETENTION: Duration = Duration::hours(1);
+
+#[derive(Clone)]
+pub struct AudioManager {
+    shared_context: Arc<SharedWhisperContext>,
+}
+
+impl AudioManager {
+    /// Nothing is transcribed until `set_model` finds a model.
+    pub fn new(sources: Vec<CaptureSource>) -> Self {
+        let shared_context = Arc::new(SharedWhisperContext::new(sources));
+        Self { shared_context }
+    }
+
+    pub fn start_audio_capture(&self) -> Result<()> {
+        let shared_context = Arc::clone(&self.shared_context);
+        thread::spawn(move || {
+            if let Err(e) = run_audio_capture_and_transcription(shared_context) {
+                eprintln!("Audio capture and transcription error: {:?}", e);
+            }
+        });
+        Ok(())
+    }
+    pub async fn reset_transcript(&self) {
+        self.shared_context.reset_transcript().await;
+    }
+    /// Swaps the capture sources; the capture loop picks them up within a
+    /// couple of seconds. Replay runs keep their sources.
+    pub fn set_sources(&self, sources: Vec<CaptureSource>) {
+        let mut current = self.shared_context.sources.lock().unwrap();
+        let replaying = current
+            .iter()
+            .any(|source| matches!(source.kind, AudioSourceKind::Replay(_)));
+        if !replaying {
+            *current = sources;
+        }
+    }
+    /// Looks for the `size` model in `dir` in the background (hashing takes
+    /// a while); transcription switches to it at the next speech chunk.
+    /// Repeated calls are cheap, except after an error, which is retried.
+    pub fn set_model(&self, dir: PathBuf, size: WhisperModelSize) {
+        let shared_context = Arc::clone(&self.shared_context);
+        let request = (dir, size);
+        {
+            let mut last_request = shared_context.model_request.lock().unwrap();
+            let failed = matches!(*shared_context.model_status.lock().unwrap(), ModelStatus::Error {
+                ..
+            });
+            if last_request.as_ref() == Some(&request) && !failed {
+                return;
+            }
+            *last_request = Some(request.clone());
+        }
+        shared_context.set_model_status(ModelStatus::Checking);
+
+        thread::spawn(move || {
+            let result = find_model(&request.0, request.1);
+            // Settings may have moved on while hashing
+            if shared_context.model_request.lock().unwrap().as_ref() != Some(&request) {
+                return;
+            }
+            let mut slot = shared_context.model.blocking_lock();
+            match result {
+                Ok(model) => {
+                    println!("whisper model: {} ({:?})", model.path.display(), model.verification);
+                    let loaded = slot.loaded.as_ref().is_some_and(|(loaded, _)| *loaded == model);
+                    shared_context.set_model_status(if loaded {
+                        ModelStatus::Ready { model: model.clone() }
+                    } else {
+                        ModelStatus::Found { model: model.clone() }
+                    });
+                    slot.wanted = Some(model);
+                }
+                Err(e) => {
+                    eprintln!("No usable whisper model: {}", e);
+                    slot.wanted = None;
+                    slot.loaded = None;
+                    shared_context.set_model_status(ModelStatus::Error { message: e.to_string() });
+                }
+            }
+        });
+    }
+    /// Takes effect from the next speech chunk
+    pub fn set_options(&self, options: TranscriptionOptions) {
+        *self.shared_context.options.lock().unwrap() = options;
+    }
+    pub fn model_status(&self) -> ModelStatus {
+        self.shared_context.model_status.lock().unwrap().clone()
+    }
+    pub fn set_enabled(&self, set: bool) {
+        println!("Attempting to set enabled to: {}", set);
+        match self.shared_context.enabled.lock() {
+            Ok(mut guard) => {
+                println!("Lock acquired. Current value: {}", *guard);
+                *guard = set;
+                println!("Value updated. New value: {}", *guard);
+            }
+            Err(_) => println!("Failed to acquire lock"),
+        }
+    }
+
+    /// Segments overlapping `[since, until]`, either end open if `None`,
+    /// and the tentative tail of speech still going on.
+    pub async fn get_transcript(
+        &self,
+        since: Option<DateTime<Utc>>,
+        until: Option<DateTime<Utc>>
+    ) -> AudioContext {
+        let mut context = self.shared_context.transcript.lock().await.window(since, until);
+        let live = self.shared_context.live.lock().unwrap().clone();
+        context.tentative = live
+            .iter()
+            .flat_map(|live| live.lock().unwrap().tentative().to_vec())
+            .filter(|segment| since.map_or(true, |since| segment.end >= since))
+            .filter(|segment| until.map_or(true, |until| segment.start <= until))
+            .collect();
+        context.tentative.sort_by_key(|segment| segment.start);
+        context.voice_names = self.shared_context.options.lock().unwrap().speaker_names.clone();
+        context
+    }
+
+    /// Transcribes speech still in progress up to now, so the next
+    /// `get_transcript` has it without waiting for a pause or the next
+    /// streaming step. Sources with a streaming run already going are left
+    /// to it.
+    pub async fn flush_live(&self) {
+        let live = self.shared_context.live.lock().unwrap().clone();
+        for live in live {
+            let (window, speaker) = {
+                let mut live = live.lock().unwrap();
+                (live.snapshot(), live.speaker)
+            };
+            let Some(window) = window else {
+                continue;
+            };
+            if let Err(e) = self.shared_context.run_live(&live, &window, speaker).await {
+                eprintln!("Transcription error: {:?}", e);
+            }
+            live.lock().unwrap().run_finished();
+        }
+    }
+}
+
+// pub fn start_audio_context_capture() -> Result<()> {
+//     thread::spawn(|| {
+//         if let Err(e) = run_audio_capture_and_transcription() {
+//             eprintln!("Audio capture and transcription error: {:?}", e);
+//         }
+//     });
+//     Ok(())
+// }
+
+fn run_audio_capture_and_transcription(shared_context: Arc<SharedWhisperContext>) -> Result<()> {
+    let runtime = Runtime::new()?;
+    runtime.block_on(async { capture_and_transcribe(shared_context).await })
+}
+
+/// The model transcription should use and, once loaded, its context.
+#[derive(Default)]
+struct ModelSlot {
+    wanted: Option<InstalledModel>,
+    loaded: Option<(InstalledModel, WhisperContext)>,
+}
+
+struct SharedWhisperContext {
+    model: Arc<Mutex<ModelSlot>>,
+    model_status: Arc<SyncMutex<ModelStatus>>,
+    /// Last `set_model` arguments
+    model_request: Arc<SyncMutex<Option<(PathBuf, WhisperModelSize)>>>,
+    options: Arc<SyncMutex<TranscriptionOptions>>,
+    /// All sources merged by start time, the last `TRANSCRIPT_RETENTION`
+    transcript: Arc<Mutex<Transcript>>,
+    /// Speech in progress of each capture slot
+    live: SyncMutex<Vec<Arc<SyncMutex<LiveSpeech>>>>,
+    /// Voices heard in system audio since the transcript was last reset
+    diarizer: SyncMutex<Diarizer>,
+    enabled: Arc<SyncMutex<bool>>,
+    /// What the capture loop should be recording
+    sources: Arc<SyncMutex<Vec<CaptureSource>>>,
+}
+
+impl SharedWhisperContext {
+    fn new(sources: Vec<CaptureSource>) -> Self {
+        Self {
+            model: Arc::new(Mutex::new(ModelSlot::default())),
+            model_status: Arc::new(SyncMutex::new(ModelStatus::Checking)),
+            model_request: Arc::new(SyncMutex::new(None)),
+            options: Arc::new(SyncMutex::new(TranscriptionOptions::default())),
+            enabled: Arc::new(SyncMutex::new(true)),
+            transcript: Arc::new(Mutex::new(Transcript::default())),
+            live: SyncMutex::new(Vec::new()),
+            diarizer: SyncMutex::new(Diarizer::new(DiarizerConfig::default())),
+            sources: Arc::new(SyncMutex::new(sources)),
+        }
+    }
+    fn set_model_status(&self, status: ModelStatus) {
+        *self.model_status.lock().unwrap() = status;
+    }
+    pub async fn reset_transcript(&self) {
+        self.transcript.lock().await.clear();
+        for live in self.live.lock().unwrap().iter() {
+            live.lock().unwrap().discard();
+        }
+        self.diarizer.lock().unwrap().reset();
+    }
+    /// Transcribes and adds the segments to the transcript.
+    /// `audio_data_16khz` is mono at `WHISPER_SAMPLE_RATE`, `started_at` the
+    /// wall-clock time of its first sample
+    pub async fn transcribe(
+        &self,
+        audio_data_16khz: &[f32],
+        started_at: DateTime<Utc>,
+        speaker: Speaker
+    ) -> Result<Vec<TranscriptSegment>> {
+        let mut segments = self.run_whisper(audio_data_16khz, started_at, speaker).await?;
+        if !segments.is_empty() {
+            let voice = self.voice_of(audio_data_16khz, speaker, true);
+            segments.iter_mut().for_each(|segment| {
+                segment.voice = voice;
+            });
+        }
+        self.commit(&segments).await;
+        Ok(segments)
+    }
+    /// Transcribes a window of speech in progress, committing what it
+    /// settles.
+    async fn run_live(
+        &self,
+        live: &SyncMutex<LiveSpeech>,
+        window: &LiveWindow,
+        speaker: Speaker
+    ) -> Result<()> {
+        let mut segments = self.run_whisper(&window.samples, window.started_at, speaker).await?;
+        if !segments.is_empty() {
+            // Only a guess for the tentative tail, the voice isn't learned
+            // from speech that may still change
+            let voice = self.voice_of(&window.samples, speaker, false);
+            segments.iter_mut().for_each(|segment| {
+                segment.voice = voice;
+            });
+        }
+        let mut committed = live.lock().unwrap().accept(window, segments);
+        if let Some(last) = committed.last() {
+            let end_ms = (last.end - window.started_at).num_milliseconds().max(0) as usize;
+            let end = ((end_ms * (WHISPER_SAMPLE_RATE as usize)) / 1000).min(window.samples.len());
+            let voice = self.voice_of(&window.samples[..end], speaker, true);
+            committed.iter_mut().for_each(|segment| {
+                segment.voice = voice;
+            });
+        }
+        self.commit(&committed).await;
+        Ok(())
+    }
+    /// The voice in a stretch of system audio, if diarization is on.
+    /// `learn` lets it start a new voice or refine a known one.
+    fn voice_of(&self, audio_data_16khz: &[f32], speaker: Speaker, learn: bool) -> Option<usize> {
+        if speaker != Speaker::Others || !self.options.lock().unwrap().diarization {
+            return None;
+        }
+        let print = voiceprint(audio_data_16khz)?;
+        let mut diarizer = self.diarizer.lock().unwrap();
+        if learn {
+            diarizer.assign(&print)
+        } else {
+            diarizer.identify(&print)
+        }
+    }
+    async fn commit(&self, segments: &[TranscriptSegment]) {
+        if segments.is_empty() {
+            return;
+        }
+        let mut transcript = self.transcript.lock().await;
+        for segment in segments {
+            transcript.insert(segment.clone());
+        }
+        transcript.prune(Utc::now() - TRANSCRIPT_RETENTION);
+    }
+    /// A fresh inference state of the wanted model, loading the model
+    /// first if it changed. `None` while there's no usable model.
+    async fn whisper_state(&self) -> Result<Option<WhisperState>> {
+        let mut slot = self.model.lock().await;
+        let Some(wanted) = slot.wanted.clone() else {
+            // No usable model, the status says why
+            return Ok(None);
+        };
+        if slot.loaded.as_ref().map(|(loaded, _)| loaded) != Some(&wanted) {
+            // Free the old model before the new one takes its memory
+            slot.loaded = None;
+            self.set_model_status(ModelStatus::Loading { model: wanted.clone() });
+            let model = wanted.clone();
+            match task::spawn_blocking(move || load_model(&model)).await? {
+                Ok(context) => {
+                    slot.loaded = Some((wanted.clone(), context));
+                    self.set_model_status(ModelStatus::Ready { model: wanted });
+                }
+                Err(e) => {
+                    slot.wanted = None;
+                    self.set_model_status(ModelStatus::Error {
+                        message: format!("Failed to load {}: {}", wanted.name, e),
+                    });
+                    return Err(e);
+                }
+            }
+        }
+        let Some((_, context)) = &slot.loaded else {
+            return Ok(None);
+        };
+        // The state holds on to its model, even if another one is loaded
+        // while it's in use
+        Ok(Some(context.create_state()?))
+    }
+    async fn run_whisper(
+        &self,
+        audio_data_16khz: &[f32],
+        started_at: DateTime<Utc>,
+        speaker: Speaker
+    ) -> Result<Vec<TranscriptSegment>> {
+        let Some(mut state) = self.whisper_state().await? else {
+            return Ok(Vec::new());
+        };
+        let options = self.options.lock().unwrap().clone();
+        let audio = audio_data_16khz.to_vec();
+        // Inference takes seconds, keep it off the async workers and out of
+        // the model lock so sources can transcribe at the same time
+        task::spawn_blocking(move || {
+            run_inference(&mut state, &options, &audio, started_at, speaker)
+        }).await?
+    }
+}
+
+/// Transcribes `audio_data_16khz` with `state` into segments starting at
+/// `started_at`.
+fn run_inference(
+    state: &mut WhisperState,
+    options: &TranscriptionOptions,
+    audio_data_16khz: &[f32],
+    started_at: DateTime<Utc>,
+    speaker: Speaker
+) -> Result<Vec<TranscriptSegment>> {
+    let strategy = match options.beam_size {
+        Some(beam_size) =>
+            SamplingStrategy::BeamSearch {
+                beam_size: beam_size as i32,
+                // whisper.cpp's default
+                patience: -1.0,
+            },
+        None => SamplingStrategy::Greedy { best_of: 1 },
+    };
+    let mut params = FullParams::new(strategy);
+
+    // whisper.cpp fails the whole chunk on a language it doesn't know
+    let language = if options.language == "auto" || get_lang_id(&options.language).is_some() {
+        options.language.as_str()
+    } else {
+        eprintln!("Unknown whisper language {:?}, detecting instead", options.language);
+        "auto"
+    };
+    params.set_n_threads(options.threads as i32);
+    params.set_translate(options.translate);
+    params.set_language(Some(language));
+    if !options.initial_prompt.is_empty() {
+        params.set_initial_prompt(&options.initial_prompt);
+    }
+    params.set_print_special(false);
+    params.set_print_progress(false);
+    params.set_print_realtime(false);
+    params.set_print_timestamps(false);
+
+    state.full(params, audio_data_16khz)?;
+    let detected_language = get_lang_str(state.full_lang_id_from_state()?).map(str::to_string);
+
+    let num_segments = state.full_n_segments()?;
+    let mut result = Vec::new();
+    for i in 0..num_segments {
+        let segment = state.full_get_segment_text(i)?;
+        if segment.trim() != "[BLANK_AUDIO]" && !segment.trim().is_empty() {
+            // Segment times are in 10 ms units from the start of the chunk
+            let t0 = state.full_get_segment_t0(i)?;
+            let t1 = state.full_get_segment_t1(i)?;
+            result.push(TranscriptSegment {
+                start: started_at + Duration::milliseconds(t0 * 10),
+                end: started_at + Duration::milliseconds(t1 * 10),
+                speaker,
+                voice: None,
+                text: segment,
+                language: detected_language.clone(),
+            });
+        }
+    }
+
+    Ok(result)
+}
+
+fn load_model(model: &InstalledModel) -> Result<WhisperContext> {
+    let path = model.path
+        .to_str()
+        .ok_or_else(|| anyhow::anyhow!("Model path is not UTF-8: {}", model.path.display()))?;
+    println!("loading whisper model {}", path);
+    Ok(WhisperContext::new_with_params(path, WhisperContextParameters::default())?)
+}
+
+async fn transcribe_audio(
+    shared_context: &SharedWhisperContext,
+    audio_data: Vec<f32>,
+    started_at: DateTime<Utc>,
+    speaker: Speaker
+) -> Result<()> {
+    shared_context.transcribe(&audio_data, started_at, speaker).await?;
+    // println!("Transcription: {}", transcription);
+    Ok(())
+}
+
+/// A started capture source and its processing state: downmix, resample to
+/// 16 kHz, VAD.
+struct ActiveSource {
+    speaker: Speaker,
+    format: AudioFormat,
+    ring: RingConsumer,
+    /// `ring`'s dropped count when last reported
+    reported_dropped: u64,
+    resampler: StreamResampler,
+    vad: Vad,
+    /// 16 kHz samples fed to `vad`, the last one captured at about the last poll
+    samples_fed: u64,
+    /// Must stay alive (cpal streams stop on drop) while capturing
+    source: Box<dyn AudioSource>,
+}
+
+impl ActiveSource {
+    fn start(capture: &CaptureSource, max_buffered: std::time::Duration) -> Result<Self> {
+        let mut source = open_source(&capture.kind)?;
+        let format = source.format();
+        let capacity = ((format.sample_rate as f64) *
+            (format.channels as f64) *
+            max_buffered.as_secs_f64()) as usize;
+        let (producer, ring) = ring_buffer(capacity);
+        source.start(producer)?;
+        Ok(Self {
+            speaker: capture.speaker,
+            format,
+            ring,
+            reported_dropped: 0,
+            resampler: StreamResampler::new(format.sample_rate, WHISPER_SAMPLE_RATE)?,
+            vad: Vad::new(WHISPER_SAMPLE_RATE, VadConfig::default()),
+            samples_fed: 0,
+            source,
+        })
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        self.resampler = StreamResampler::new(self.format.sample_rate, WHISPER_SAMPLE_RATE)?;
+        self.vad = Vad::new(WHISPER_SAMPLE_RATE, VadConfig::default());
+        self.samples_fed = 0;
+        Ok(())
+    }
+
+    /// Takes what was captured since the last call, reporting audio lost
+    /// because the loop fell too far behind.
+    fn take_captured(&mut self) -> Vec<f32> {
+        let samples = self.ring.drain();
+        let stats = self.ring.stats();
+        if stats.dropped > self.reported_dropped {
+            let samples_per_second = (self.format.sample_rate as f64) * (self.format.channels as f64);
+            eprintln!(
+                "Audio buffer overrun: dropped {:.1}s of {} audio ({} overruns, {:.1}s total)",
+                ((stats.dropped - self.reported_dropped) as f64) / samples_per_second,
+                self.speaker.label(),
+                stats.overruns,
+                (stats.dropped as f64) / samples_per_second
+            );
+            self.reported_dropped = stats.dropped;
+        }
+        samples
+    }
+
+    /// Runs newly captured interleaved samples through the pipeline,
+    /// returning the speech chunks they completed.
+    fn process(&mut self, audio_data: Vec<f32>) -> Result<Vec<SpeechChunk>> {
+        let mono = channels_to_mono(audio_data, self.format.channels.into());
+        let resampled = self.resampler.process(&mono)?;
+        self.samples_fed += resampled.len() as u64;
+        Ok(self.vad.push(&resampled))
+    }
+
+    /// Ends the stream before the source is dropped: processes what's left
+    /// in the ring and the resampler, then cuts off speech still going on.
+    fn finish(&mut self) -> Result<Vec<SpeechChunk>> {
+        let audio_data = self.take_captured();
+        let mut chunks = self.process(audio_data)?;
+        let tail = self.resampler.flush()?;
+        self.samples_fed += tail.len() as u64;
+        chunks.extend(self.vad.push(&tail));
+        chunks.extend(self.vad.flush());
+        Ok(chunks)
+    }
+
+    /// Wall-clock time of a 16 kHz sample, given the last fed sample was
+    /// captured `now`
+    fn sample_time(&self, sample: u64, now: DateTime<Utc>) -> DateTime<Utc> {
+        now -
+            Duration::milliseconds(
+                (((self.samples_fed.saturating_sub(sample)) * 1000) /
+                    (WHISPER_SAMPLE_RATE as u64)) as i64
+            )
+    }
+}
+
+/// One wanted source and, while its device is there, the running capture.
+struct SourceSlot {
+    capture: CaptureSource,
+    active: Option<ActiveSource>,
+    /// Last open error, so a missing device is reported once, not every retry
+    last_error: Option<String>,
+    live: Arc<SyncMutex<LiveSpeech>>,
+}
+
+impl SourceSlot {
+    fn new(capture: CaptureSource) -> Self {
+        let live = Arc::new(SyncMutex::new(LiveSpeech::new(capture.speaker)));
+        Self { capture, active: None, last_error: None, live }
+    }
+
+    /// (Re)opens the source if it isn't running or its device went away.
+    /// Returns speech the old stream still held.
+    fn check(&mut self, max_buffered: std::time::Duration) -> Vec<(SpeechChunk, DateTime<Utc>)> {
+        let mut flushed = Vec::new();
+        if let Some(active) = &mut self.active {
+            if !active.source.needs_restart() {
+                return flushed;
+            }
+            println!("audio device changed, restarting {:?} capture", self.capture.kind);
+            let chunks = active.finish().unwrap_or_else(|e| {
+                eprintln!("Failed to process audio: {:?}", e);
+                Vec::new()
+            });
+            let now = Utc::now();
+            let mut live = self.live.lock().unwrap();
+            for chunk in chunks {
+                if let Some(chunk) = live.finish(chunk) {
+                    let started_at = active.sample_time(chunk.start_sample, now);
+                    flushed.push((chunk, started_at));
+                }
+            }
+            // The new stream's VAD counts from zero
+            live.reset();
+            drop(live);
+            self.active = None;
+        }
+
+        match ActiveSource::start(&self.capture, max_buffered) {
+            Ok(active) => {
+                self.active = Some(active);
+                self.last_error = None;
+            }
+            Err(e) => {
+                let message = e.to_string();
+                if self.last_error.as_ref() != Some(&message) {
+                    eprintln!("Failed to start {:?} capture: {}", self.capture.kind, message);
+                }
+                self.last_error = Some(message);
+            }
+        }
+        flushed
+    }
+}
+
+/// How often sources are checked for lost or changed devices
+const DEVICE_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(2);
+
+async fn capture_and_transcribe(shared_context: Arc<SharedWhisperContext>) -> Result<()> {
+    let mut wanted = shared_context.sources.lock().unwrap().clone();
+    let mut slots: Vec<SourceSlot> = wanted.iter().cloned().map(SourceSlot::new).collect();
+    *shared_context.live.lock().unwrap() = slots
+        .iter()
+        .map(|slot| Arc::clone(&slot.live))
+        .collect();
+    let mut last_device_check: Option<std::time::Instant> = None;
+
+    let mut was_enabled = true;
+    loop {
+        if last_device_check.map_or(true, |checked| checked.elapsed() >= DEVICE_CHECK_INTERVAL) {
+            last_device_check = Some(std::time::Instant::now());
+
+            let sources = shared_context.sources.lock().unwrap().clone();
+            if sources != wanted {
+                println!("audio sources changed");
+                wanted = sources;
+                slots = wanted.iter().cloned().map(SourceSlot::new).collect();
+                *shared_context.live.lock().unwrap() = slots
+                    .iter()
+                    .map(|slot| Arc::clone(&slot.live))
+                    .collect();
+            }
+            let max_buffered = shared_context.options.lock().unwrap().max_buffered;
+            for slot in &mut slots {
+                for (chunk, started_at) in slot.check(max_buffered) {
+                    spawn_transcription(
+                        &shared_context,
+                        &slot.live,
+                        chunk,
+                        started_at,
+                        slot.capture.speaker
+                    );
+                }
+            }
+        }
+
+        tokio::time::sleep(tokio::time::Duration::from_millis(200)).await;
+        let is_enabled = *shared_context.enabled.lock().unwrap();
+        let streaming_step = shared_context.options.lock().unwrap().streaming_step;
+        let now = Utc::now();
+
+        for slot in &mut slots {
+            let Some(source) = slot.active.as_mut() else {
+                continue;
+            };
+            // Drained even while disabled, so nothing stale is left to
+            // transcribe when it's turned back on
+            let audio_data = source.take_captured();
+            if !is_enabled {
+                if was_enabled {
+                    if let Err(e) = source.reset() {
+                        eprintln!("Failed to reset audio pipeline: {:?}", e);
+                    }
+                    slot.live.lock().unwrap().reset();
+                }
+                continue;
+            }
+
+            let chunks = match source.process(audio_data) {
+                Ok(chunks) => chunks,
+                Err(e) => {
+                    eprintln!("Failed to process audio: {:?}", e);
+                    continue;
+                }
+            };
+            for chunk in chunks {
+                // Only what streaming hasn't committed yet
+                let Some(chunk) = slot.live.lock().unwrap().finish(chunk) else {
+                    continue;
+                };
+                let started_at = source.sample_time(chunk.start_sample, now);
+                spawn_transcription(&shared_context, &slot.live, chunk, started_at, source.speaker);
+            }
+
+            let window = {
+                let mut live = slot.live.lock().unwrap();
+                live.update(source.vad.in_progress(), |sample| source.sample_time(sample, now));
+                streaming_step.and_then(|step| {
+                    let step_samples = (step.as_millis() as u64) * (WHISPER_SAMPLE_RATE as u64) / 1000;
+                    live.next_window(step_samples)
+                })
+            };
+            if let Some(window) = window {
+                spawn_live_transcription(&shared_context, &slot.live, window, source.speaker);
+            }
+        }
+
+        if was_enabled && !is_enabled {
+            println!("not transcribing");
+        }
+        was_enabled = is_enabled;
+    }
+}
+
+/// Final transcription of a chunk the VAD closed
+fn spawn_transcription(
+    shared_context: &Arc<SharedWhisperContext>,
+    live: &Arc<SyncMutex<LiveSpeech>>,
+    chunk: SpeechChunk,
+    started_at: DateTime<Utc>,
+    speaker: Speaker
+) {
+    println!(
+        "transcribing {:.1}s of speech from {}",
+        (chunk.samples.len() as f32) / (WHISPER_SAMPLE_RATE as f32),
+        speaker.label()
+    );
+    let shared_context_clone = Arc::clone(shared_context);
+    let live = Arc::clone(live);
+    let end_sample = chunk.end_sample();
+    tokio::spawn(async move {
+        if
+            let Err(e) = transcribe_audio(
+                &shared_context_clone,
+                chunk.samples,
+                started_at,
+                speaker
+            ).await
+        {
+            eprintln!("Transcription error: {:?}", e);
+        }
+        live.lock().unwrap().finished(end_sample);
+    });
+}
+
+/// Streaming run over speech still in progress
+fn spawn_live_transcription(
+    shared_context: &Arc<SharedWhisperContext>,
+    live: &Arc<SyncMutex<LiveSpeech>>,
+    window: LiveWindow,
+    speaker: Speaker
+) {
+    let shared_context = Arc::clone(shared_context);
+    let live = Arc::clone(live);
+    tokio::spawn(async move {
+        if let Err(e) = shared_context.run_live(&live, &window, speaker).await {
+            eprintln!("Transcription error: {:?}", e);
+        }
+        live.lock().unwrap().run_finished();
+    });
+}
@@ -0,0 +1,281 @@
+use std::collections::HashMap;
+use std::fs::{ self, File };
+use std::io::Read;
+use std::path::{ Path, PathBuf };
+use std::sync::Mutex;
+use std::time::SystemTime;
+use anyhow::{ anyhow, Result };
+use serde::Serialize;
+use sha2::{ Digest, Sha256 };
+
+use crate::settings::WhisperModelSize;
+
+/// Expected hashes of other models, `sha256sum` output kept next to them
+const CHECKSUM_FILE: &str = "SHA256SUMS";
+
+/// Hashes of the models huggingface.co/ggerganov/whisper.cpp publishes for
+/// each size the settings offer
+const KNOWN_MODELS: [(&str, &str); 4] = [
+    ("ggml-tiny.bin", "be07e048e1e599ad46341c8d2a135645097a538221678b7acdd1b1919c6e1b21"),
+    ("ggml-base.bin", "60ed5bc3dd14eea856493d334349b405782ddcaf0028d4b5df4088345fba2efe"),
+    ("ggml-small.bin", "1be3a9b2063867b937e64e2ec7483364a79917e157fa98c5d94b5c1fffea987b"),
+    ("ggml-medium.bin", "6c14d5adee5f86394037b4e4e8b59f1673b6cee10e3cf0b11bbdbee79c156208"),
+];
+
+lazy_static! {
+    /// Hashes by path, reused while length and mtime are unchanged: hashing
+    /// a medium model takes seconds
+    static ref HASH_CACHE: Mutex<HashMap<PathBuf, (u64, SystemTime, String)>> = Mutex::new(
+        HashMap::new()
+    );
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Verification {
+    /// Matches its known hash
+    Verified,
+    /// Differs from its known hash: truncated or corrupt download
+    Mismatch,
+    /// Neither built in nor in `SHA256SUMS`, nothing to check against
+    Unlisted,
+}
+
+/// A ggml model file found in the models directory.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct InstalledModel {
+    pub name: String,
+    pub path: PathBuf,
+    /// `None` for sizes the settings don't offer (large, ...)
+    pub size: Option<WhisperModelSize>,
+    pub bytes: u64,
+    pub sha256: String,
+    pub verification: Verification,
+}
+
+/// Where the Whisper model is at, for the UI.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "state", rename_all = "lowercase")]
+pub enum ModelStatus {
+    /// Looking for and hashing the selected model
+    Checking,
+    /// Found, loads when the first speech comes in
+    Found {
+        model: InstalledModel,
+    },
+    Loading {
+        model: InstalledModel,
+    },
+    Ready {
+        model: InstalledModel,
+    },
+    /// No usable model, nothing is transcribed
+    Error {
+        message: String,
+    },
+}
+
+/// Size named by a whisper.cpp file name: `ggml-<size>[.en][-q5_1].bin`
+pub fn model_size(file_name: &str) -> Option<WhisperModelSize> {
+    let stem = file_name.strip_prefix("ggml-")?.strip_suffix(".bin")?;
+    match stem.split(['.', '-']).next()? {
+        "tiny" => Some(WhisperModelSize::Tiny),
+        "base" => Some(WhisperModelSize::Base),
+        "small" => Some(WhisperModelSize::Small),
+        "medium" => Some(WhisperModelSize::Medium),
+        _ => None,
+    }
+}
+
+/// Every `ggml-*.bin` in `dir`, hashed and checked.
+pub fn list_models(dir: &Path) -> Result<Vec<InstalledModel>> {
+    let checksums = read_checksums(dir);
+    model_files(dir)?
+        .into_iter()
+        .map(|(name, path)| inspect(name, path, &checksums))
+        .collect()
+}
+
+/// The installed model to use for `size`. The official `ggml-<size>.bin`
+/// comes first, then files listed in `SHA256SUMS`, then any other. Only
+/// that file is hashed, and a mismatch is an error.
+pub fn find_model(dir: &Path, size: WhisperModelSize) -> Result<InstalledModel> {
+    let checksums = read_checksums(dir);
+    let official = format!("ggml-{}.bin", size.name());
+    let (name, path) = model_files(dir)?
+        .into_iter()
+        .filter(|(name, _)| model_size(name) == Some(size))
+        .min_by_key(|(name, _)| (*name != official, expected_hash(name, &checksums).is_none()))
+        .ok_or_else(|| {
+            anyhow!(
+                "No {} Whisper model in {}, download {} from huggingface.co/ggerganov/whisper.cpp",
+                size.name(),
+                dir.display(),
+                official
+            )
+        })?;
+
+    let model = inspect(name, path, &checksums)?;
+    if model.verification == Verification::Mismatch {
+        return Err(anyhow!("{} failed the SHA-256 check, download again", model.name));
+    }
+    Ok(model)
+}
+
+/// `ggml-*.bin` files in `dir` by name, sorted
+fn model_files(dir: &Path) -> Result<Vec<(String, PathBuf)>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir).map_err(|e| anyhow!("Failed to read {}: {}", dir.display(), e))? {
+        let path = entry?.path();
+        let Some(name) = path.file_name().and_then(|name| name.to_str()).map(str::to_string) else {
+            continue;
+        };
+        if path.is_file() && name.starts_with("ggml-") && name.ends_with(".bin") {
+            files.push((name, path));
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Hashes the model at `path` and checks it
+fn inspect(
+    name: String,
+    path: PathBuf,
+    checksums: &HashMap<String, String>
+) -> Result<InstalledModel> {
+    let sha256 = sha256_file(&path)?;
+    let verification = match expected_hash(&name, checksums) {
+        Some(expected) if expected.eq_ignore_ascii_case(&sha256) => Verification::Verified,
+        Some(_) => Verification::Mismatch,
+        None => Verification::Unlisted,
+    };
+    Ok(InstalledModel {
+        size: model_size(&name),
+        bytes: fs::metadata(&path)?.len(),
+        name,
+        path,
+        sha256,
+        verification,
+    })
+}
+
+/// The built-in hash of an official model, otherwise its `SHA256SUMS` entry
+fn expected_hash<'a>(name: &str, checksums: &'a HashMap<String, String>) -> Option<&'a str> {
+    KNOWN_MODELS.iter()
+        .find(|(known, _)| *known == name)
+        .map(|(_, hash)| *hash)
+        .or_else(|| checksums.get(name).map(String::as_str))
+}
+
+/// `<hex>  <file name>` lines; a `*` before the name marks binary mode
+fn read_checksums(dir: &Path) -> HashMap<String, String> {
+    let Ok(contents) = fs::read_to_string(dir.join(CHECKSUM_FILE)) else {
+        return HashMap::new();
+    };
+    contents
+        .lines()
+        .filter_map(|line| {
+            let (hash, name) = line.trim().split_once(char::is_whitespace)?;
+            let name = name.trim_start();
+            Some((name.strip_prefix('*').unwrap_or(name).to_string(), hash.to_string()))
+        })
+        .collect()
+}
+
+fn sha256_file(path: &Path) -> Result<String> {
+    let metadata = fs::metadata(path)?;
+    let modified = metadata.modified()?;
+    if let Some((len, cached_modified, hash)) = HASH_CACHE.lock().unwrap().get(path) {
+        if *len == metadata.len() && *cached_modified == modified {
+            return Ok(hash.clone());
+        }
+    }
+
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 1 << 20];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    let hash = format!("{:x}", hasher.finalize());
+
+    HASH_CACHE.lock()
+        .unwrap()
+        .insert(path.to_path_buf(), (metadata.len(), modified, hash.clone()));
+    Ok(hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn model_size_reads_whisper_cpp_names() {
+        assert_eq!(model_size("ggml-tiny.bin"), Some(WhisperModelSize::Tiny));
+        assert_eq!(model_size("ggml-base.en.bin"), Some(WhisperModelSize::Base));
+        assert_eq!(model_size("ggml-small-q5_1.bin"), Some(WhisperModelSize::Small));
+        assert_eq!(model_size("ggml-large-v3.bin"), None);
+        assert_eq!(model_size("tiny.bin"), None);
+    }
+
+    #[test]
+    fn official_model_failing_its_builtin_hash_is_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("ggml-tiny.bin"), b"truncated").unwrap();
+
+        let err = find_model(dir.path(), WhisperModelSize::Tiny).unwrap_err();
+        assert!(err.to_string().contains("ggml-tiny.bin failed the SHA-256 check"), "{}", err);
+        let models = list_models(dir.path()).unwrap();
+        assert_eq!(models[0].verification, Verification::Mismatch);
+    }
+
+    #[test]
+    fn checksum_file_covers_other_models() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("ggml-base-q5_1.bin"), b"model").unwrap();
+        fs::write(dir.path().join("ggml-base.en.bin"), b"model").unwrap();
+        let hash = format!("{:x}", Sha256::digest(b"model"));
+        let checksums = dir.path().join(CHECKSUM_FILE);
+        fs::write(&checksums, format!("{}  ggml-base.en.bin\n", hash)).unwrap();
+
+        let model = find_model(dir.path(), WhisperModelSize::Base).unwrap();
+        assert_eq!(model.name, "ggml-base.en.bin");
+        assert_eq!(model.verification, Verification::Verified);
+
+        fs::write(&checksums, format!("{}  *ggml-base.en.bin\n", "0".repeat(64))).unwrap();
+        assert!(find_model(dir.path(), WhisperModelSize::Base).is_err());
+    }
+
+    #[test]
+    fn unlisted_model_is_used() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("ggml-small-q5_1.bin"), b"model").unwrap();
+
+        let model = find_model(dir.path(), WhisperModelSize::Small).unwrap();
+        assert_eq!(model.verification, Verification::Unlisted);
+        assert!(find_model(dir.path(), WhisperModelSize::Medium).is_err());
+    }
+
+    #[test]
+    fn find_model_hashes_only_the_selected_file() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["ggml-medium.en.bin", "ggml-medium-q5_0.bin", "ggml-tiny.bin"] {
+            fs::write(dir.path().join(name), name).unwrap();
+        }
+        let hash = format!("{:x}", Sha256::digest(b"ggml-medium.en.bin"));
+        let checksums = format!("{}  ggml-medium.en.bin\n", hash);
+        fs::write(dir.path().join(CHECKSUM_FILE), checksums).unwrap();
+
+        let model = find_model(dir.path(), WhisperModelSize::Medium).unwrap();
+        assert_eq!(model.name, "ggml-medium.en.bin");
+        let cache = HASH_CACHE.lock().unwrap();
+        assert!(cache.contains_key(&dir.path().join("ggml-medium.en.bin")));
+        assert!(!cache.contains_key(&dir.path().join("ggml-medium-q5_0.bin")));
+        assert!(!cache.contains_key(&dir.path().join("ggml-tiny.bin")));
+    }
+}
@@ -0,0 +1,264 @@
+use std::ffi::{ c_char, c_int, c_void, CStr, CString };
+use std::process::Command;
+use std::ptr::{ self, NonNull };
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::sync::Arc;
+use std::thread;
+use anyhow::{ anyhow, Result };
+use libloading::Library;
+
+use super::ring::RingProducer;
+use super::source::{ AudioFormat, AudioSource };
+
+const PA_STREAM_RECORD: c_int = 2;
+const PA_SAMPLE_FLOAT32LE: c_int = 5;
+
+/// `pa_sample_spec`
+#[repr(C)]
+struct SampleSpec {
+    format: c_int,
+    rate: u32,
+    channels: u8,
+}
+
+type NewFn = unsafe extern "C" fn(
+    server: *const c_char,
+    name: *const c_char,
+    direction: c_int,
+    device: *const c_char,
+    stream_name: *const c_char,
+    spec: *const SampleSpec,
+    channel_map: *const c_void,
+    buffer_attr: *const c_void,
+    error: *mut c_int
+) -> *mut c_void;
+type ReadFn = unsafe extern "C" fn(
+    stream: *mut c_void,
+    data: *mut c_void,
+    bytes: usize,
+    error: *mut c_int
+) -> c_int;
+type FreeFn = unsafe extern "C" fn(stream: *mut c_void);
+type StrErrorFn = unsafe extern "C" fn(error: c_int) -> *const c_char;
+
+/// A blocking record stream of libpulse-simple. The library is loaded at
+/// runtime, so the app still starts where PulseAudio isn't installed.
+struct SimpleStream {
+    handle: NonNull<c_void>,
+    read: ReadFn,
+    free: FreeFn,
+    strerror: Option<StrErrorFn>,
+    // Keeps the functions above loaded, dropped last
+    _library: Library,
+}
+
+// A pa_simple stream may be used from any thread, one at a time
+unsafe impl Send for SimpleStream {}
+
+impl SimpleStream {
+    /// Connects a record stream to the Pulse source `device`, taking
+    /// interleaved f32 samples in `format`.
+    fn record(device: &str, format: AudioFormat) -> Result<Self> {
+        let library = unsafe { Library::new("libpulse-simple.so.0") }.map_err(|err| {
+            anyhow!("libpulse-simple not found, is PulseAudio or PipeWire installed? {}", err)
+        })?;
+        let (new, read, free, strerror) = unsafe {
+            (
+                *library.get::<NewFn>(b"pa_simple_new\0")?,
+                *library.get::<ReadFn>(b"pa_simple_read\0")?,
+                *library.get::<FreeFn>(b"pa_simple_free\0")?,
+                // From libpulse, which libpulse-simple loads
+                library.get::<StrErrorFn>(b"pa_strerror\0").ok().map(|symbol| *symbol),
+            )
+        };
+
+        let spec = SampleSpec {
+            format: PA_SAMPLE_FLOAT32LE,
+            rate: format.sample_rate,
+            channels: format.channels as u8,
+        };
+        let app_name = CString::new("PCAgent")?;
+        let stream_name = CString::new("System audio")?;
+        let device_name = CString::new(device)?;
+        let mut error = 0;
+        let handle = unsafe {
+            new(
+                ptr::null(),
+                app_name.as_ptr(),
+                PA_STREAM_RECORD,
+                device_name.as_ptr(),
+                stream_name.as_ptr(),
+                &spec,
+                ptr::null(),
+                ptr::null(),
+                &mut error
+            )
+        };
+        let Some(handle) = NonNull::new(handle) else {
+            return Err(
+                anyhow!("Failed to record from {}: {}", device, describe(strerror, error))
+            );
+        };
+        Ok(Self { handle, read, free, strerror, _library: library })
+    }
+
+    /// Blocks until `samples` is full.
+    fn read(&self, samples: &mut [f32]) -> Result<()> {
+        let mut error = 0;
+        let result = unsafe {
+            (self.read)(
+                self.handle.as_ptr(),
+                samples.as_mut_ptr().cast(),
+                std::mem::size_of_val(samples),
+                &mut error
+            )
+        };
+        if result < 0 {
+            let message = describe(self.strerror, error);
+            return Err(anyhow!("Failed to read from PulseAudio: {}", message));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for SimpleStream {
+    fn drop(&mut self) {
+        unsafe { (self.free)(self.handle.as_ptr()) }
+    }
+}
+
+fn describe(strerror: Option<StrErrorFn>, error: c_int) -> String {
+    let message = strerror
+        .map(|strerror| unsafe { strerror(error) })
+        .filter(|message| !message.is_null())
+        .map(|message| unsafe { CStr::from_ptr(message) }.to_string_lossy().into_owned());
+    message.unwrap_or_else(|| format!("error {}", error))
+}
+
+/// The server's default sink, from `pactl info`, which both PulseAudio and
+/// PipeWire ship. `None` if pactl isn't there or doesn't say.
+fn default_sink() -> Option<String> {
+    // pactl translates its field names
+    let output = Command::new("pactl").arg("info").env("LC_ALL", "C").output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_default_sink(&String::from_utf8_lossy(&output.stdout))
+}
+
+fn parse_default_sink(info: &str) -> Option<String> {
+    info.lines()
+        .find_map(|line| line.strip_prefix("Default Sink: "))
+        .map(|sink| sink.trim().to_string())
+}
+
+/// Records the default sink's monitor from the Pulse server, PulseAudio's
+/// or PipeWire's, without touching any other stream of the process.
+pub struct PulseMonitorSource {
+    /// Moved to the capture thread by `start`
+    stream: Option<SimpleStream>,
+    format: AudioFormat,
+    /// The sink `@DEFAULT_MONITOR@` resolved to, to notice the default
+    /// changing; the stream stays on the old one
+    sink: Option<String>,
+    running: Arc<AtomicBool>,
+    failed: Arc<AtomicBool>,
+}
+
+impl PulseMonitorSource {
+    pub fn open() -> Result<Self> {
+        // Pulse converts from whatever the sink runs at
+        let format = AudioFormat { sample_rate: 48000, channels: 2 };
+        let sink = default_sink();
+        let stream = SimpleStream::record("@DEFAULT_MONITOR@", format)?;
+        let sink_name = sink.as_deref().unwrap_or("the default sink");
+        println!("Capturing the monitor of {} with {:?}", sink_name, format);
+        Ok(Self {
+            stream: Some(stream),
+            format,
+            sink,
+            running: Arc::new(AtomicBool::new(true)),
+            failed: Arc::new(AtomicBool::new(false)),
+        })
+    }
+}
+
+impl AudioSource for PulseMonitorSource {
+    fn format(&self) -> AudioFormat {
+        self.format
+    }
+
+    fn start(&mut self, mut ring: RingProducer) -> Result<()> {
+        let stream = self.stream.take().ok_or_else(|| anyhow!("Monitor capture already started"))?;
+        let running = Arc::clone(&self.running);
+        let failed = Arc::clone(&self.failed);
+        // 100 ms blocks, like a sound card would deliver them
+        let block = ((self.format.sample_rate / 10) as usize) * (self.format.channels as usize);
+        thread::spawn(move || {
+            let mut samples = vec![0.0; block];
+            while running.load(Ordering::SeqCst) {
+                if let Err(err) = stream.read(&mut samples) {
+                    eprintln!("{}", err);
+                    failed.store(true, Ordering::SeqCst);
+                    return;
+                }
+                ring.push(samples.iter().copied());
+            }
+        });
+        Ok(())
+    }
+
+    fn needs_restart(&self) -> bool {
+        if self.failed.load(Ordering::SeqCst) {
+            return true;
+        }
+        // Without pactl the monitor can't follow the default
+        match (&self.sink, default_sink()) {
+            (Some(sink), Some(default)) => *sink != default,
+            _ => false,
+        }
+    }
+}
+
+impl Drop for PulseMonitorSource {
+    fn drop(&mut self) {
+        // The capture thread closes the stream after its current block
+        self.running.store(false, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{ Duration, Instant };
+
+    use super::super::ring::ring_buffer;
+    use super::*;
+
+    #[test]
+    fn default_sink_is_read_from_pactl_info() {
+        let info = "Server Name: PulseAudio (on PipeWire 1.0.5)\n\
+            Default Sink: alsa_output.pci-0000_00_1f.3.analog-stereo\n\
+            Default Source: alsa_input.pci-0000_00_1f.3.analog-stereo\n";
+        assert_eq!(
+            parse_default_sink(info).as_deref(),
+            Some("alsa_output.pci-0000_00_1f.3.analog-stereo")
+        );
+        assert_eq!(parse_default_sink("Server Name: pulseaudio\n"), None);
+    }
+
+    // Run with `cargo test -- --ignored pulse`
+    #[test]
+    #[ignore = "needs a PulseAudio or PipeWire server"]
+    fn pulse_monitor_delivers_samples() {
+        let mut source = PulseMonitorSource::open().unwrap();
+        let (producer, consumer) = ring_buffer(48000 * 2);
+        source.start(producer).unwrap();
+
+        let started = Instant::now();
+        while consumer.stats().written == 0 && started.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(50));
+        }
+        assert!(consumer.stats().written > 0);
+        assert!(!source.needs_restart());
+    }
+}
@@ -0,0 +1,354 @@
+use anyhow::Result;
+use rubato::{
+    Resampler,
+    SincFixedIn,
+    SincInterpolationParameters,
+    SincInterpolationType,
+    WindowFunction,
+};
+
+/// Whisper only takes 16 kHz mono
+pub const WHISPER_SAMPLE_RATE: u32 = 16000;
+
+/// Input frames per rubato call
+const CHUNK_SIZE: usize = 1024;
+
+/// Band-limited mono resampler that keeps its filter state between calls,
+/// so a stream can be fed in blocks of any size without clicks at the seams.
+/// Output is aligned with input: the filter delay is trimmed off the start.
+pub struct StreamResampler {
+    /// `None` when input and output rates match
+    resampler: Option<SincFixedIn<f32>>,
+    /// Output frames per input frame, for `flush`
+    ratio: f64,
+    /// Input not yet making up a whole chunk
+    pending: Vec<f32>,
+    /// Output frames still owed to the filter delay
+    delay_left: usize,
+    frames_in: u64,
+    frames_out: u64,
+}
+
+impl StreamResampler {
+    pub fn new(input_rate: u32, output_rate: u32) -> Result<Self> {
+        let ratio = (output_rate as f64) / (input_rate as f64);
+        if input_rate == output_rate {
+            return Ok(Self {
+                resampler: None,
+                ratio,
+                pending: Vec::new(),
+                delay_left: 0,
+                frames_in: 0,
+                frames_out: 0,
+            });
+        }
+
+        let parameters = SincInterpolationParameters {
+            sinc_len: 256,
+            // Cut just below the lower Nyquist so nothing above 8 kHz folds
+            // back into the speech band when going to 16 kHz
+            f_cutoff: 0.95,
+            interpolation: SincInterpolationType::Linear,
+            oversampling_factor: 128,
+            window: WindowFunction::BlackmanHarris2,
+        };
+        let resampler = SincFixedIn::<f32>::new(ratio, 1.0, parameters, CHUNK_SIZE, 1)?;
+        Ok(Self {
+            delay_left: resampler.output_delay(),
+            resampler: Some(resampler),
+            ratio,
+            pending: Vec::new(),
+            frames_in: 0,
+            frames_out: 0,
+        })
+    }
+
+    /// Feeds mono samples, returns whatever output they completed.
+    pub fn process(&mut self, input: &[f32]) -> Result<Vec<f32>> {
+        self.frames_in += input.len() as u64;
+        let Some(resampler) = &mut self.resampler else {
+            self.frames_out += input.len() as u64;
+            return Ok(input.to_vec());
+        };
+
+        self.pending.extend_from_slice(input);
+        let mut output = Vec::new();
+        while self.pending.len() >= resampler.input_frames_next() {
+            let chunk: Vec<f32> = self.pending.drain(..resampler.input_frames_next()).collect();
+            let mut resampled = resampler.process(&[chunk], None)?;
+            output.append(&mut resampled[0]);
+        }
+        Ok(self.emit(output))
+    }
+
+    /// Ends the stream: pushes the remaining input through the filter and
+    /// returns the tail, so the total output matches the input duration.
+    pub fn flush(&mut self) -> Result<Vec<f32>> {
+        let Some(resampler) = &mut self.resampler else {
+            return Ok(Vec::new());
+        };
+
+        let expected = ((self.frames_in as f64) * self.ratio).round() as u64;
+        let mut output = Vec::new();
+        let pending = std::mem::take(&mut self.pending);
+        let mut resampled = resampler.process_partial(Some(&[pending][..]), None)?;
+        output.append(&mut resampled[0]);
+        // Zero input drains the delay line
+        while
+            self.frames_out + (output.len().saturating_sub(self.delay_left) as u64) < expected
+        {
+            let mut resampled = resampler.process_partial::<Vec<f32>>(None, None)?;
+            if resampled[0].is_empty() {
+                break;
+            }
+            output.append(&mut resampled[0]);
+        }
+
+        let frames_before = self.frames_out;
+        let mut output = self.emit(output);
+        output.truncate(expected.saturating_sub(frames_before) as usize);
+        self.frames_out = frames_before + (output.len() as u64);
+        Ok(output)
+    }
+
+    /// Drops the leading filter delay and counts what goes out.
+    fn emit(&mut self, mut output: Vec<f32>) -> Vec<f32> {
+        let skip = self.delay_left.min(output.len());
+        output.drain(..skip);
+        self.delay_left -= skip;
+        self.frames_out += output.len() as u64;
+        output
+    }
+}
+
+/// Averages interleaved channels into one. Done before resampling: filtering
+/// interleaved data would smear neighbouring channels into each other.
+pub fn channels_to_mono(audio_data: Vec<f32>, num_channels: usize) -> Vec<f32> {
+    if num_channels <= 1 {
+        return audio_data;
+    }
+    audio_data
+        .chunks_exact(num_channels)
+        .map(|frame| frame.iter().sum::<f32>() / (num_channels as f32))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+    use std::fs;
+    use std::path::{ Path, PathBuf };
+
+    use crate::test_support::to_whisper_format;
+    use super::super::source::read_wav;
+    use super::*;
+
+    /// Device rates the checks run at
+    const INPUT_RATES: [u32; 2] = [44100, 48000];
+    /// A tone this far above 8 kHz folds back to 16000 - 11000 = 5 kHz if the
+    /// resampler doesn't filter it out
+    const ALIAS_TONE_HZ: f32 = 11000.0;
+    const MAX_ALIAS_DB: f32 = -60.0;
+    /// Passband tone level may move this much
+    const MAX_PASSBAND_ERROR_DB: f32 = 0.5;
+    /// Phase-inverted channels must cancel to below this
+    const MAX_CANCELLATION_DB: f32 = -80.0;
+    const SECONDS: f32 = 2.0;
+
+    // The capture pipeline's downmix + resample stage, on synthetic stereo
+    // signals at common device rates
+
+    #[test]
+    fn passband_keeps_its_level() {
+        for rate in INPUT_RATES {
+            let tone_1k = tone(1000.0, 0.5, rate, SECONDS);
+            let output = to_whisper_format(interleave(&tone_1k, &tone_1k), rate, 2).unwrap();
+            let level = tone_level(trim_edges(&output), 1000.0, WHISPER_SAMPLE_RATE);
+            let error_db = to_db(level / 0.5).abs();
+            assert!(
+                error_db <= MAX_PASSBAND_ERROR_DB,
+                "{} Hz: 1 kHz is {:.2} dB off",
+                rate,
+                error_db
+            );
+        }
+    }
+
+    #[test]
+    fn content_above_8k_does_not_alias() {
+        for rate in INPUT_RATES {
+            let high = tone(ALIAS_TONE_HZ, 0.5, rate, SECONDS);
+            let output = to_whisper_format(interleave(&high, &high), rate, 2).unwrap();
+            let alias_db = to_db(rms(trim_edges(&output)) / rms_of_sine(0.5));
+            assert!(alias_db <= MAX_ALIAS_DB, "{} Hz: alias at {:.1} dB", rate, alias_db);
+        }
+    }
+
+    #[test]
+    fn inverted_channels_cancel() {
+        // Only if frames are averaged before filtering
+        for rate in INPUT_RATES {
+            let left = tone(1000.0, 0.5, rate, SECONDS);
+            let right: Vec<f32> = left
+                .iter()
+                .map(|s| -s)
+                .collect();
+            let output = to_whisper_format(interleave(&left, &right), rate, 2).unwrap();
+            let cancellation_db = to_db(rms(&output) / rms_of_sine(0.5));
+            assert!(
+                cancellation_db <= MAX_CANCELLATION_DB,
+                "{} Hz: {:.1} dB left",
+                rate,
+                cancellation_db
+            );
+        }
+    }
+
+    #[test]
+    fn output_covers_the_input_duration() {
+        for rate in INPUT_RATES {
+            let mono = tone(1000.0, 0.5, rate, SECONDS);
+            let frames = mono.len() as f64;
+            let expected = (frames * (WHISPER_SAMPLE_RATE as f64)) / (rate as f64);
+            let expected = expected.round() as usize;
+            let output = to_whisper_format(interleave(&mono, &mono), rate, 2).unwrap();
+            assert_eq!(output.len(), expected, "{} Hz", rate);
+        }
+    }
+
+    #[test]
+    fn streaming_matches_one_shot() {
+        // Odd-sized blocks give the same stream as one call
+        for rate in INPUT_RATES {
+            let mono = tone(1000.0, 0.5, rate, SECONDS);
+            let mut resampler = StreamResampler::new(rate, WHISPER_SAMPLE_RATE).unwrap();
+            let mut streamed = Vec::new();
+            let mut offset = 0;
+            let mut block = 1;
+            while offset < mono.len() {
+                let end = (offset + block).min(mono.len());
+                streamed.extend(resampler.process(&mono[offset..end]).unwrap());
+                offset = end;
+                block = (block * 7 + 13) % 997;
+            }
+            streamed.extend(resampler.flush().unwrap());
+            let whole = to_whisper_format(mono, rate, 1).unwrap();
+            assert_eq!(streamed.len(), whole.len(), "{} Hz", rate);
+            let max_difference = streamed
+                .iter()
+                .zip(&whole)
+                .map(|(a, b)| (a - b).abs())
+                .fold(0.0, f32::max);
+            assert!(max_difference < 1e-5, "{} Hz: max difference {:.1e}", rate, max_difference);
+        }
+    }
+
+    /// Converts every `.wav` in `dir` and checks it keeps its duration.
+    fn check_wav_durations(dir: &Path) {
+        let mut fixtures: Vec<PathBuf> = fs
+            ::read_dir(dir)
+            .unwrap()
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("wav"))
+            })
+            .collect();
+        fixtures.sort();
+        assert!(!fixtures.is_empty(), "No .wav files in {}", dir.display());
+
+        let mut failures = Vec::new();
+        for path in fixtures {
+            let spec = hound::WavReader::open(&path).unwrap().spec();
+            let samples = read_wav(&path).unwrap();
+            let frames = samples.len() / (spec.channels as usize);
+            let output = to_whisper_format(samples, spec.sample_rate, spec.channels).unwrap();
+            let input_seconds = (frames as f32) / (spec.sample_rate as f32);
+            let output_seconds = (output.len() as f32) / (WHISPER_SAMPLE_RATE as f32);
+            println!(
+                "{} ({} Hz, {} ch): {:.3}s -> {:.3}s",
+                path.display(),
+                spec.sample_rate,
+                spec.channels,
+                input_seconds,
+                output_seconds
+            );
+            if (input_seconds - output_seconds).abs() > 1.0 / (WHISPER_SAMPLE_RATE as f32) {
+                failures.push(path.display().to_string());
+            }
+        }
+        assert!(failures.is_empty(), "Duration changed: {}", failures.join(", "));
+    }
+
+    #[test]
+    fn wav_files_keep_their_duration() {
+        let dir = tempfile::tempdir().unwrap();
+        for (rate, channels) in [(44100, 2), (48000, 2), (22050, 1)] {
+            let mono = tone(440.0, 0.5, rate, 1.3);
+            let samples = if channels == 2 { interleave(&mono, &mono) } else { mono };
+            let path = dir.path().join(format!("{}-{}.wav", rate, channels));
+            crate::test_support::write_wav(&path, rate, channels, &samples);
+        }
+        check_wav_durations(dir.path());
+    }
+
+    // Run with `RESAMPLER_CHECK_DIR=<dir> cargo test recorded_wav_files -- --ignored --nocapture`
+    #[test]
+    #[ignore = "needs .wav files in RESAMPLER_CHECK_DIR"]
+    fn recorded_wav_files_keep_their_duration() {
+        let dir = std::env::var("RESAMPLER_CHECK_DIR").expect("RESAMPLER_CHECK_DIR is not set");
+        check_wav_durations(Path::new(&dir));
+    }
+
+    fn tone(frequency: f32, amplitude: f32, rate: u32, seconds: f32) -> Vec<f32> {
+        let frames = ((rate as f32) * seconds) as usize;
+        // Phase in f64: in f32 it loses enough precision over a few seconds to
+        // add broadband noise that would read as aliasing
+        (0..frames)
+            .map(|i| {
+                let phase = (2.0 * PI * (frequency as f64) * (i as f64)) / (rate as f64);
+                amplitude * (phase.sin() as f32)
+            })
+            .collect()
+    }
+
+    fn interleave(left: &[f32], right: &[f32]) -> Vec<f32> {
+        left.iter()
+            .zip(right)
+            .flat_map(|(l, r)| [*l, *r])
+            .collect()
+    }
+
+    /// Drops the first and last 10% where the filter ramps in and out
+    fn trim_edges(samples: &[f32]) -> &[f32] {
+        let edge = samples.len() / 10;
+        &samples[edge..samples.len() - edge]
+    }
+
+    fn rms(samples: &[f32]) -> f32 {
+        let sum: f64 = samples
+            .iter()
+            .map(|s| (*s as f64) * (*s as f64))
+            .sum();
+        ((sum / (samples.len().max(1) as f64)).sqrt()) as f32
+    }
+
+    fn rms_of_sine(amplitude: f32) -> f32 {
+        amplitude / (2.0f32).sqrt()
+    }
+
+    /// Amplitude of the `frequency` component, by correlating with a sine and
+    /// cosine at that frequency (a single DFT bin)
+    fn tone_level(samples: &[f32], frequency: f32, rate: u32) -> f32 {
+        let (mut re, mut im) = (0.0f64, 0.0f64);
+        for (i, s) in samples.iter().enumerate() {
+            let phase = (2.0 * PI * (frequency as f64) * (i as f64)) / (rate as f64);
+            re += (*s as f64) * phase.cos();
+            im += (*s as f64) * phase.sin();
+        }
+        ((2.0 * (re * re + im * im).sqrt()) / (samples.len() as f64)) as f32
+    }
+
+    fn to_db(ratio: f32) -> f32 {
+        20.0 * ratio.max(1e-12).log10()
+    }
+}
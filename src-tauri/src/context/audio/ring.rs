@@ -0,0 +1,219 @@
+use std::sync::atomic::{ fence, AtomicU32, AtomicU64, Ordering };
+use std::sync::Arc;
+
+/// Fixed-size sample store shared by one producer and one consumer.
+///
+/// Positions count samples ever written; position `p` lives in slot
+/// `p % capacity`. The producer never waits: when the consumer falls behind,
+/// the oldest samples are overwritten and the consumer counts them as lost.
+/// Samples are stored as `f32` bits in atomics, so a slot being overwritten
+/// while it's copied is detected (`claimed`) instead of being a data race.
+struct Ring {
+    slots: Box<[AtomicU32]>,
+    /// End of the samples the producer has finished writing
+    written: AtomicU64,
+    /// End of the samples the producer has started writing
+    claimed: AtomicU64,
+    /// Samples the consumer lost to overwrites
+    dropped: AtomicU64,
+    /// Drains that found samples lost
+    overruns: AtomicU64,
+}
+
+impl Ring {
+    fn capacity(&self) -> u64 {
+        self.slots.len() as u64
+    }
+}
+
+/// Counters of one ring, in samples (interleaved, all channels).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RingStats {
+    pub written: u64,
+    pub dropped: u64,
+    pub overruns: u64,
+}
+
+/// Writing end, owned by the audio callback.
+pub struct RingProducer {
+    ring: Arc<Ring>,
+    position: u64,
+}
+
+/// Reading end, owned by the capture loop.
+pub struct RingConsumer {
+    ring: Arc<Ring>,
+    position: u64,
+}
+
+/// A ring holding the last `capacity` samples.
+pub fn ring_buffer(capacity: usize) -> (RingProducer, RingConsumer) {
+    let ring = Arc::new(Ring {
+        slots: (0..capacity.max(1)).map(|_| AtomicU32::new(0)).collect(),
+        written: AtomicU64::new(0),
+        claimed: AtomicU64::new(0),
+        dropped: AtomicU64::new(0),
+        overruns: AtomicU64::new(0),
+    });
+    (RingProducer { ring: Arc::clone(&ring), position: 0 }, RingConsumer { ring, position: 0 })
+}
+
+impl RingProducer {
+    /// Appends samples, overwriting the oldest ones if the ring is full.
+    /// Never blocks or allocates, safe to call from an audio callback.
+    pub fn push<I: ExactSizeIterator<Item = f32>>(&mut self, samples: I) {
+        let ring = &self.ring;
+        let capacity = ring.capacity();
+        let end = self.position + (samples.len() as u64);
+
+        // Announce the overwrite before doing it, see `RingConsumer::drain`
+        ring.claimed.store(end, Ordering::Relaxed);
+        fence(Ordering::Release);
+        for (offset, sample) in samples.enumerate() {
+            let slot = ((self.position + (offset as u64)) % capacity) as usize;
+            ring.slots[slot].store(sample.to_bits(), Ordering::Relaxed);
+        }
+        ring.written.store(end, Ordering::Release);
+        self.position = end;
+    }
+}
+
+impl RingConsumer {
+    /// Takes every sample written since the last drain that is still there.
+    pub fn drain(&mut self) -> Vec<f32> {
+        let ring = &self.ring;
+        let capacity = ring.capacity();
+        let written = ring.written.load(Ordering::Acquire);
+        let mut start = self.position.max(written.saturating_sub(capacity));
+
+        let mut samples: Vec<f32> = (start..written)
+            .map(|position| {
+                f32::from_bits(ring.slots[(position % capacity) as usize].load(Ordering::Relaxed))
+            })
+            .collect();
+
+        // If a copied slot was already overwritten, this sees the claim made
+        // before the overwrite; drop everything the claim covers
+        fence(Ordering::Acquire);
+        let valid_from = ring.claimed.load(Ordering::Relaxed).saturating_sub(capacity).min(written);
+        if valid_from > start {
+            samples.drain(..(valid_from - start) as usize);
+            start = valid_from;
+        }
+
+        if start > self.position {
+            ring.dropped.fetch_add(start - self.position, Ordering::Relaxed);
+            ring.overruns.fetch_add(1, Ordering::Relaxed);
+        }
+        self.position = written;
+        samples
+    }
+
+    pub fn stats(&self) -> RingStats {
+        RingStats {
+            written: self.ring.written.load(Ordering::Relaxed),
+            dropped: self.ring.dropped.load(Ordering::Relaxed),
+            overruns: self.ring.overruns.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+
+    /// 48 kHz stereo, what most devices capture
+    const SAMPLES_PER_SECOND: usize = 48000 * 2;
+    /// Callback block size, 10 ms
+    const BLOCK: usize = SAMPLES_PER_SECOND / 100;
+
+    /// The sample written at `position`; exact in f32 up to 2^24
+    fn sample_at(position: u64) -> f32 {
+        (position % (1 << 24)) as f32
+    }
+
+    /// One callback's worth of samples starting at `position`
+    fn block(position: u64) -> impl ExactSizeIterator<Item = f32> {
+        (0..BLOCK).map(move |offset| sample_at(position + (offset as u64)))
+    }
+
+    // The capture ring buffer: an hour with transcription disabled (nothing
+    // drained) keeps memory at the configured duration and loses exactly the
+    // oldest samples; a producer thread racing the consumer never hands out a
+    // torn or out-of-order sample and every sample is either delivered or
+    // counted as dropped.
+
+    #[test]
+    fn disabled_hour_keeps_the_newest_samples() {
+        // The callback keeps pushing, nobody drains
+        let capacity = SAMPLES_PER_SECOND * 10;
+        let (mut producer, mut consumer) = ring_buffer(capacity);
+        let total = (SAMPLES_PER_SECOND * 3600) as u64;
+        let mut position = 0u64;
+        while position < total {
+            producer.push(block(position));
+            position += BLOCK as u64;
+        }
+        let samples = consumer.drain();
+        let stats = consumer.stats();
+        assert_eq!(samples.len(), capacity, "memory stays at the last 10 s");
+        let first = total - (capacity as u64);
+        for (i, sample) in samples.iter().enumerate() {
+            assert_eq!(*sample, sample_at(first + (i as u64)), "sample {} of the last 10 s", i);
+        }
+        assert_eq!(stats.dropped, first);
+        assert_eq!(stats.overruns, 1);
+
+        // Re-enabled: drained every 200 ms, nothing is lost any more
+        for _ in 0..20 {
+            producer.push(block(position));
+            position += BLOCK as u64;
+        }
+        let samples = consumer.drain();
+        assert_eq!(samples.len(), 20 * BLOCK);
+        assert_eq!(consumer.stats().dropped, stats.dropped);
+    }
+
+    #[test]
+    fn concurrent_samples_are_delivered_or_dropped() {
+        // The consumer too slow now and then
+        let capacity = BLOCK * 8;
+        let (mut producer, mut consumer) = ring_buffer(capacity);
+        let total = (BLOCK as u64) * 20_000;
+        let writer = thread::spawn(move || {
+            let mut position = 0u64;
+            while position < total {
+                producer.push(block(position));
+                position += BLOCK as u64;
+            }
+        });
+        let mut expected = 0u64;
+        let mut delivered = 0u64;
+        let mut drains = 0u64;
+        loop {
+            let finished = writer.is_finished();
+            let dropped_before = consumer.stats().dropped;
+            let samples = consumer.drain();
+            expected += consumer.stats().dropped - dropped_before;
+            for sample in &samples {
+                assert_eq!(*sample, sample_at(expected), "torn or out of order, drain {}", drains);
+                expected += 1;
+            }
+            delivered += samples.len() as u64;
+            drains += 1;
+            if finished && samples.is_empty() {
+                break;
+            }
+            if drains % 64 == 0 {
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+        writer.join().unwrap();
+        let stats = consumer.stats();
+        assert_eq!(stats.written, total);
+        assert_eq!(delivered + stats.dropped, stats.written, "{} overruns", stats.overruns);
+    }
+}
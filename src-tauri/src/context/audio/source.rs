@@ -0,0 +1,465 @@
+use std::fs;
+use std::path::{ Path, PathBuf };
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use anyhow::{ anyhow, Result };
+use cpal::traits::{ DeviceTrait, HostTrait, StreamTrait };
+use cpal::{ SampleFormat, StreamError, SupportedStreamConfig };
+use serde::Serialize;
+
+#[cfg(target_os = "linux")]
+use super::pulse::PulseMonitorSource;
+use super::ring::RingProducer;
+use super::transcript::Speaker;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioFormat {
+    pub sample_rate: u32,
+    pub channels: u16,
+}
+
+/// A device pinned in settings. `None` follows the system default, and
+/// the source is rebuilt whenever that default changes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeviceChoice {
+    pub host: Option<String>,
+    pub device: Option<String>,
+}
+
+/// Where one of `AudioManager`'s capture sources gets its samples from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AudioSourceKind {
+    /// Loopback of an output device (WASAPI)
+    Loopback(DeviceChoice),
+    /// PulseAudio/PipeWire monitor of the default sink
+    Monitor,
+    Microphone(DeviceChoice),
+    /// WAV files from a directory (`--replay-audio <dir>`)
+    Replay(PathBuf),
+}
+
+impl AudioSourceKind {
+    /// Whatever the platform offers for capturing what the PC plays
+    pub fn system(output: DeviceChoice) -> Self {
+        if cfg!(target_os = "linux") {
+            AudioSourceKind::Monitor
+        } else {
+            AudioSourceKind::Loopback(output)
+        }
+    }
+}
+
+/// A source to capture and who is talking on it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaptureSource {
+    pub kind: AudioSourceKind,
+    pub speaker: Speaker,
+}
+
+/// Producer of interleaved f32 samples. `start` begins pushing into `ring`
+/// and must keep doing so for as long as the source is alive.
+pub trait AudioSource {
+    fn format(&self) -> AudioFormat;
+    fn start(&mut self, ring: RingProducer) -> Result<()>;
+
+    /// Whether the source should be dropped and opened again, because its
+    /// device went away or the default device it follows changed.
+    fn needs_restart(&self) -> bool {
+        false
+    }
+}
+
+pub fn open_source(kind: &AudioSourceKind) -> Result<Box<dyn AudioSource>> {
+    match kind {
+        AudioSourceKind::Loopback(choice) => Ok(Box::new(CpalSource::output(choice)?)),
+        AudioSourceKind::Monitor => open_monitor(),
+        AudioSourceKind::Microphone(choice) => Ok(Box::new(CpalSource::input(choice)?)),
+        AudioSourceKind::Replay(dir) => Ok(Box::new(WavReplaySource::open(dir)?)),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn open_monitor() -> Result<Box<dyn AudioSource>> {
+    Ok(Box::new(PulseMonitorSource::open()?))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn open_monitor() -> Result<Box<dyn AudioSource>> {
+    Err(anyhow!("Monitor capture is only available on Linux"))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeviceDirection {
+    Input,
+    Output,
+}
+
+/// A device as offered to the user for pinning.
+#[derive(Debug, Clone, Serialize)]
+pub struct AudioDeviceInfo {
+    pub host: String,
+    pub name: String,
+    pub direction: DeviceDirection,
+    pub is_default: bool,
+    /// Default config, absent if the device wouldn't report one
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u16>,
+}
+
+/// Every input and output device of every available cpal host.
+pub fn list_devices() -> Result<Vec<AudioDeviceInfo>> {
+    let mut devices = Vec::new();
+    for host_id in cpal::available_hosts() {
+        let host = match cpal::host_from_id(host_id) {
+            Ok(host) => host,
+            Err(e) => {
+                eprintln!("Failed to open audio host {}: {}", host_id.name(), e);
+                continue;
+            }
+        };
+        let default_input = host.default_input_device().and_then(|device| device.name().ok());
+        let default_output = host.default_output_device().and_then(|device| device.name().ok());
+
+        for device in host.input_devices()? {
+            let Ok(name) = device.name() else {
+                continue;
+            };
+            let config = device.default_input_config().ok();
+            devices.push(AudioDeviceInfo {
+                host: host_id.name().to_string(),
+                is_default: default_input.as_deref() == Some(name.as_str()),
+                name,
+                direction: DeviceDirection::Input,
+                sample_rate: config.as_ref().map(|config| config.sample_rate().0),
+                channels: config.as_ref().map(|config| config.channels()),
+            });
+        }
+        for device in host.output_devices()? {
+            let Ok(name) = device.name() else {
+                continue;
+            };
+            let config = device.default_output_config().ok();
+            devices.push(AudioDeviceInfo {
+                host: host_id.name().to_string(),
+                is_default: default_output.as_deref() == Some(name.as_str()),
+                name,
+                direction: DeviceDirection::Output,
+                sample_rate: config.as_ref().map(|config| config.sample_rate().0),
+                channels: config.as_ref().map(|config| config.channels()),
+            });
+        }
+    }
+    Ok(devices)
+}
+
+fn host_for(choice: &DeviceChoice) -> Result<cpal::Host> {
+    match &choice.host {
+        Some(name) => {
+            let host_id = cpal
+                ::available_hosts()
+                .into_iter()
+                .find(|host_id| host_id.name() == name)
+                .ok_or_else(|| anyhow!("Audio host not available: {}", name))?;
+            Ok(cpal::host_from_id(host_id)?)
+        }
+        None => Ok(cpal::default_host()),
+    }
+}
+
+fn default_device_name(choice: &DeviceChoice, direction: DeviceDirection) -> Option<String> {
+    let host = host_for(choice).ok()?;
+    let device = match direction {
+        DeviceDirection::Input => host.default_input_device(),
+        DeviceDirection::Output => host.default_output_device(),
+    };
+    device.and_then(|device| device.name().ok())
+}
+
+fn find_device(
+    choice: &DeviceChoice,
+    direction: DeviceDirection
+) -> Result<cpal::Device> {
+    let host = host_for(choice)?;
+    match (&choice.device, direction) {
+        (Some(name), DeviceDirection::Input) =>
+            host
+                .input_devices()?
+                .find(|device| device.name().map(|n| &n == name).unwrap_or(false))
+                .ok_or_else(|| anyhow!("Input device not found: {}", name)),
+        (Some(name), DeviceDirection::Output) =>
+            host
+                .output_devices()?
+                .find(|device| device.name().map(|n| &n == name).unwrap_or(false))
+                .ok_or_else(|| anyhow!("Output device not found: {}", name)),
+        (None, DeviceDirection::Input) =>
+            host.default_input_device().ok_or_else(|| anyhow!("No default input device")),
+        (None, DeviceDirection::Output) =>
+            host.default_output_device().ok_or_else(|| anyhow!("No default output device")),
+    }
+}
+
+pub struct CpalSource {
+    device: cpal::Device,
+    device_name: String,
+    config: SupportedStreamConfig,
+    /// Set when following the default device, to notice it changing
+    follows_default: Option<(DeviceChoice, DeviceDirection)>,
+    /// Raised by the stream's error callback
+    failed: Arc<AtomicBool>,
+    stream: Option<cpal::Stream>,
+}
+
+impl CpalSource {
+    fn open(
+        device: cpal::Device,
+        config: SupportedStreamConfig,
+        follows_default: Option<(DeviceChoice, DeviceDirection)>
+    ) -> Result<Self> {
+        let device_name = device.name()?;
+        println!("Capturing {} with {:?}", device_name, config);
+        Ok(Self {
+            device,
+            device_name,
+            config,
+            follows_default,
+            failed: Arc::new(AtomicBool::new(false)),
+            stream: None,
+        })
+    }
+
+    /// Loopback capture of an output device
+    pub fn output(choice: &DeviceChoice) -> Result<Self> {
+        let device = find_device(choice, DeviceDirection::Output)?;
+        let config = device.default_output_config()?;
+        let follows_default = choice.device
+            .is_none()
+            .then(|| (choice.clone(), DeviceDirection::Output));
+        Self::open(device, config, follows_default)
+    }
+
+    pub fn input(choice: &DeviceChoice) -> Result<Self> {
+        let device = find_device(choice, DeviceDirection::Input)?;
+        let config = device.default_input_config()?;
+        let follows_default = choice.device
+            .is_none()
+            .then(|| (choice.clone(), DeviceDirection::Input));
+        Self::open(device, config, follows_default)
+    }
+
+    fn build_stream(&self, mut ring: RingProducer) -> Result<cpal::Stream> {
+        let config = self.config.clone();
+        let failed = Arc::clone(&self.failed);
+        let on_error = move |err: StreamError| {
+            eprintln!("An error occurred on the input audio stream: {}", err);
+            // Unplugging shows up as DeviceNotAvailable on WASAPI but as
+            // backend errors on ALSA, reopen on either
+            failed.store(true, Ordering::SeqCst);
+        };
+        let stream = match config.sample_format() {
+            SampleFormat::F32 =>
+                self.device.build_input_stream(
+                    &config.into(),
+                    move |data: &[f32], _: &cpal::InputCallbackInfo| {
+                        ring.push(data.iter().copied());
+                    },
+                    on_error,
+                    None
+                )?,
+            SampleFormat::I16 =>
+                self.device.build_input_stream(
+                    &config.into(),
+                    move |data: &[i16], _: &cpal::InputCallbackInfo| {
+                        ring.push(data.iter().map(|&s| (s as f32) / 32768.0));
+                    },
+                    on_error,
+                    None
+                )?,
+            SampleFormat::U16 =>
+                self.device.build_input_stream(
+                    &config.into(),
+                    move |data: &[u16], _: &cpal::InputCallbackInfo| {
+                        ring.push(data.iter().map(|&s| (s as f32) / 65535.0 - 0.5));
+                    },
+                    on_error,
+                    None
+                )?,
+            format => {
+                return Err(anyhow!("Unsupported sample format: {:?}", format));
+            }
+        };
+        Ok(stream)
+    }
+}
+
+impl AudioSource for CpalSource {
+    fn format(&self) -> AudioFormat {
+        AudioFormat {
+            sample_rate: self.config.sample_rate().0,
+            channels: self.config.channels(),
+        }
+    }
+
+    fn start(&mut self, ring: RingProducer) -> Result<()> {
+        let stream = self.build_stream(ring)?;
+        stream.play()?;
+        self.stream = Some(stream);
+        Ok(())
+    }
+
+    fn needs_restart(&self) -> bool {
+        if self.failed.load(Ordering::SeqCst) {
+            return true;
+        }
+        match &self.follows_default {
+            Some((choice, direction)) =>
+                default_device_name(choice, *direction).as_deref() != Some(self.device_name.as_str()),
+            None => false,
+        }
+    }
+}
+
+/// Plays the `.wav` files of a directory, in file name order, into the
+/// ring at real-time pace. All files must share one sample rate and
+/// channel count.
+pub struct WavReplaySource {
+    files: Vec<PathBuf>,
+    format: AudioFormat,
+}
+
+impl WavReplaySource {
+    pub fn open(dir: &Path) -> Result<Self> {
+        let mut files: Vec<PathBuf> = fs
+            ::read_dir(dir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.extension().and_then(|ext| ext.to_str()).map(|ext| ext.eq_ignore_ascii_case("wav")) ==
+                    Some(true)
+            })
+            .collect();
+        files.sort();
+
+        let first = files.first().ok_or_else(|| anyhow!("No .wav files in {}", dir.display()))?;
+        let spec = hound::WavReader::open(first)?.spec();
+        let format = AudioFormat {
+            sample_rate: spec.sample_rate,
+            channels: spec.channels,
+        };
+        for file in &files {
+            let spec = hound::WavReader::open(file)?.spec();
+            if spec.sample_rate != format.sample_rate || spec.channels != format.channels {
+                return Err(
+                    anyhow!(
+                        "{} is {} Hz/{} ch, expected {} Hz/{} ch",
+                        file.display(),
+                        spec.sample_rate,
+                        spec.channels,
+                        format.sample_rate,
+                        format.channels
+                    )
+                );
+            }
+        }
+
+        Ok(Self { files, format })
+    }
+}
+
+pub fn read_wav(path: &Path) -> Result<Vec<f32>> {
+    let mut reader = hound::WavReader::open(path)?;
+    let spec = reader.spec();
+    let samples = match spec.sample_format {
+        hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<Vec<_>, _>>()?,
+        hound::SampleFormat::Int => {
+            let scale = (1u64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .samples::<i32>()
+                .map(|s| s.map(|s| (s as f32) / scale))
+                .collect::<Result<Vec<_>, _>>()?
+        }
+    };
+    Ok(samples)
+}
+
+impl AudioSource for WavReplaySource {
+    fn format(&self) -> AudioFormat {
+        self.format
+    }
+
+    fn start(&mut self, mut ring: RingProducer) -> Result<()> {
+        let files = self.files.clone();
+        let format = self.format;
+        thread::spawn(move || {
+            // 100 ms blocks, like a sound card would deliver them
+            let block = ((format.sample_rate / 10) as usize) * (format.channels as usize);
+            for file in files {
+                let samples = match read_wav(&file) {
+                    Ok(samples) => samples,
+                    Err(e) => {
+                        eprintln!("Failed to read {}: {:?}", file.display(), e);
+                        continue;
+                    }
+                };
+                println!("replaying {}", file.display());
+                for chunk in samples.chunks(block) {
+                    ring.push(chunk.iter().copied());
+                    thread::sleep(Duration::from_millis(100));
+                }
+            }
+            println!("audio replay finished");
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use crate::test_support::write_wav;
+    use super::super::ring::ring_buffer;
+    use super::*;
+
+    #[test]
+    fn wav_replay_plays_files_in_name_order() {
+        let dir = tempfile::tempdir().unwrap();
+        // 0.2 s each at 16 kHz mono
+        write_wav(&dir.path().join("2-second.wav"), 16000, 1, &[-0.5; 3200]);
+        write_wav(&dir.path().join("1-first.wav"), 16000, 1, &[0.25; 3200]);
+        fs::write(dir.path().join("notes.txt"), "not audio").unwrap();
+
+        let mut source = WavReplaySource::open(dir.path()).unwrap();
+        assert_eq!(source.format(), AudioFormat { sample_rate: 16000, channels: 1 });
+        let (producer, mut consumer) = ring_buffer(16000);
+        source.start(producer).unwrap();
+
+        let mut samples = Vec::new();
+        let started = Instant::now();
+        while samples.len() < 6400 && started.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(50));
+            samples.extend(consumer.drain());
+        }
+        assert_eq!(samples.len(), 6400);
+        assert!(samples[..3200].iter().all(|&sample| sample == 0.25));
+        assert!(samples[3200..].iter().all(|&sample| sample == -0.5));
+        assert_eq!(consumer.stats().dropped, 0);
+    }
+
+    #[test]
+    fn wav_replay_rejects_mixed_formats() {
+        let dir = tempfile::tempdir().unwrap();
+        write_wav(&dir.path().join("a.wav"), 16000, 1, &[0.0; 160]);
+        write_wav(&dir.path().join("b.wav"), 44100, 2, &[0.0; 882]);
+
+        let err = WavReplaySource::open(dir.path()).err().unwrap();
+        assert!(err.to_string().contains("44100 Hz/2 ch, expected 16000 Hz/1 ch"), "{}", err);
+    }
+
+    #[test]
+    fn wav_replay_needs_a_wav_file() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(WavReplaySource::open(dir.path()).is_err());
+    }
+}
@@ -0,0 +1,371 @@
+use chrono::{ DateTime, Utc };
+
+use super::resample::WHISPER_SAMPLE_RATE;
+use super::transcript::{ Speaker, TranscriptSegment };
+use super::vad::SpeechChunk;
+
+/// Audio one whisper run covers.
+#[derive(Debug, Clone)]
+pub struct LiveWindow {
+    /// VAD sample index of `samples[0]`
+    pub start_sample: u64,
+    pub started_at: DateTime<Utc>,
+    pub samples: Vec<f32>,
+}
+
+impl LiveWindow {
+    pub fn end_sample(&self) -> u64 {
+        self.start_sample + (self.samples.len() as u64)
+    }
+}
+
+/// Speech a source is still in the middle of, transcribed again and again
+/// on a window that slides forward as its text settles.
+///
+/// Each run covers the uncommitted audio up to now, so consecutive windows
+/// overlap. Segments two runs in a row agree on are committed and the next
+/// window starts after them; the newest segment is never committed this way,
+/// its last words may still change. What isn't committed is the tentative
+/// tail. When the VAD closes the chunk, only the uncommitted rest is
+/// transcribed for good.
+pub struct LiveSpeech {
+    pub speaker: Speaker,
+    /// VAD sample index up to which text is in the transcript
+    committed_until: u64,
+    /// The VAD's speech in progress from `committed_until` on, empty in silence
+    pending: LiveWindow,
+    /// Segments of the latest run after the committed ones
+    tentative: Vec<TranscriptSegment>,
+    /// Where the run that produced `tentative` ended
+    tentative_end: u64,
+    /// Where the last periodic run ended
+    last_run_end: u64,
+    running: bool,
+}
+
+impl LiveSpeech {
+    pub fn new(speaker: Speaker) -> Self {
+        Self {
+            speaker,
+            committed_until: 0,
+            pending: LiveWindow { start_sample: 0, started_at: Utc::now(), samples: Vec::new() },
+            tentative: Vec::new(),
+            tentative_end: 0,
+            last_run_end: 0,
+            running: false,
+        }
+    }
+
+    /// For a restarted source, whose VAD counts samples from zero again
+    pub fn reset(&mut self) {
+        *self = Self::new(self.speaker);
+    }
+
+    /// Mirrors the VAD's speech in progress, `None` in silence. `time_of`
+    /// gives the wall-clock time of a sample index.
+    pub fn update(
+        &mut self,
+        in_progress: Option<(u64, &[f32])>,
+        time_of: impl FnOnce(u64) -> DateTime<Utc>
+    ) {
+        self.pending.samples.clear();
+        let Some((start_sample, samples)) = in_progress else {
+            return;
+        };
+        let from = self.committed_until.max(start_sample);
+        let skip = ((from - start_sample) as usize).min(samples.len());
+        self.pending.start_sample = from;
+        self.pending.started_at = time_of(from);
+        self.pending.samples.extend_from_slice(&samples[skip..]);
+    }
+
+    /// The window for the next periodic run, once `step` samples came in
+    /// since the last one and it has finished.
+    pub fn next_window(&mut self, step: u64) -> Option<LiveWindow> {
+        if self.running || self.pending.samples.is_empty() {
+            return None;
+        }
+        if self.pending.end_sample() < self.last_run_end + step {
+            return None;
+        }
+        self.running = true;
+        self.last_run_end = self.pending.end_sample();
+        Some(self.pending.clone())
+    }
+
+    /// Everything uncommitted right now, for an on-demand flush. Like
+    /// `next_window`, `None` while a run is going and `run_finished` must
+    /// follow.
+    pub fn snapshot(&mut self) -> Option<LiveWindow> {
+        if self.running || self.pending.samples.is_empty() {
+            return None;
+        }
+        self.running = true;
+        self.last_run_end = self.pending.end_sample();
+        Some(self.pending.clone())
+    }
+
+    pub fn run_finished(&mut self) {
+        self.running = false;
+    }
+
+    /// Takes a run's segments, returning those agreed on to be committed.
+    /// Runs over audio committed or closed in the meantime, or older than
+    /// the current tentative tail, change nothing.
+    pub fn accept(
+        &mut self,
+        window: &LiveWindow,
+        mut segments: Vec<TranscriptSegment>
+    ) -> Vec<TranscriptSegment> {
+        if window.start_sample < self.committed_until || window.end_sample() < self.tentative_end {
+            return Vec::new();
+        }
+
+        // A run that doesn't overlap the last one (new speech) has nothing
+        // to agree with yet
+        let overlaps = window.start_sample < self.tentative_end;
+        let settled = if overlaps { segments.len().saturating_sub(1) } else { 0 };
+        let agreed = self.tentative
+            .iter()
+            .zip(&segments[..settled])
+            .take_while(|(previous, current)| same_text(&previous.text, &current.text))
+            .count();
+        let committed: Vec<TranscriptSegment> = segments.drain(..agreed).collect();
+        if let Some(last) = committed.last() {
+            // The next window starts where the committed text ends
+            let end_ms = (last.end - window.started_at).num_milliseconds().max(0) as u64;
+            let end_sample = window.start_sample + (end_ms * (WHISPER_SAMPLE_RATE as u64)) / 1000;
+            self.committed_until = end_sample.min(window.end_sample());
+        }
+        self.tentative = segments;
+        self.tentative_end = window.end_sample();
+        committed
+    }
+
+    /// Forgets the speech heard so far, for a cleared transcript: the
+    /// tentative tail goes, and runs still going over it or its final
+    /// transcription add nothing.
+    pub fn discard(&mut self) {
+        self.committed_until = self.committed_until
+            .max(self.tentative_end)
+            .max(self.pending.end_sample());
+        self.pending.samples.clear();
+        self.tentative.clear();
+        self.tentative_end = self.committed_until;
+    }
+
+    /// A chunk the VAD closed: returns the part not committed yet for the
+    /// final transcription. Runs still going over it will be ignored.
+    pub fn finish(&mut self, chunk: SpeechChunk) -> Option<SpeechChunk> {
+        let from = self.committed_until.max(chunk.start_sample);
+        let end_sample = chunk.end_sample();
+        self.committed_until = end_sample;
+        self.pending.samples.clear();
+        if from >= end_sample {
+            return None;
+        }
+        let skip = (from - chunk.start_sample) as usize;
+        Some(SpeechChunk { start_sample: from, samples: chunk.samples[skip..].to_vec() })
+    }
+
+    /// The final transcription of a chunk ending at `end_sample` is in the
+    /// transcript, its tentative tail can go.
+    pub fn finished(&mut self, end_sample: u64) {
+        if self.committed_until == end_sample && self.tentative_end <= end_sample {
+            self.tentative.clear();
+        }
+    }
+
+    pub fn tentative(&self) -> &[TranscriptSegment] {
+        &self.tentative
+    }
+}
+
+/// Runs may differ in case, punctuation and spacing for the same words
+fn same_text(a: &str, b: &str) -> bool {
+    let normalize = |text: &str| {
+        text.chars()
+            .filter(|c| c.is_alphanumeric() || c.is_whitespace())
+            .flat_map(char::to_lowercase)
+            .collect::<String>()
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    normalize(a) == normalize(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{ Duration, TimeZone };
+
+    use super::*;
+
+    const RATE: u64 = WHISPER_SAMPLE_RATE as u64;
+
+    fn origin() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap()
+    }
+
+    fn time_of(sample: u64) -> DateTime<Utc> {
+        origin() + Duration::milliseconds(((sample * 1000) / RATE) as i64)
+    }
+
+    fn segment(start_secs: u64, end_secs: u64, text: &str) -> TranscriptSegment {
+        TranscriptSegment {
+            start: time_of(start_secs * RATE),
+            end: time_of(end_secs * RATE),
+            speaker: Speaker::Me,
+            voice: None,
+            text: text.to_string(),
+            language: None,
+        }
+    }
+
+    fn texts(segments: &[TranscriptSegment]) -> Vec<&str> {
+        segments
+            .iter()
+            .map(|segment| segment.text.as_str())
+            .collect()
+    }
+
+    /// Lets the VAD have `secs` of speech from sample 0 on and takes the
+    /// next periodic window
+    fn run(live: &mut LiveSpeech, speech: &[f32], secs: u64) -> LiveWindow {
+        live.update(Some((0, &speech[..(secs * RATE) as usize])), time_of);
+        let window = live.next_window(RATE).expect("a window is due");
+        live.run_finished();
+        window
+    }
+
+    #[test]
+    fn agreed_prefix_is_committed_once() {
+        let speech = vec![0.1; (10 * RATE) as usize];
+        let mut live = LiveSpeech::new(Speaker::Me);
+
+        let first = run(&mut live, &speech, 3);
+        let committed = live.accept(&first, vec![
+            segment(0, 1, "One."),
+            segment(1, 2, "Two."),
+            segment(2, 3, "Thr")
+        ]);
+        assert!(committed.is_empty(), "nothing to agree with yet");
+
+        let second = run(&mut live, &speech, 5);
+        assert_eq!(second.start_sample, 0);
+        let committed = live.accept(&second, vec![
+            segment(0, 1, "one"),
+            segment(1, 2, "Two!"),
+            segment(2, 3, "Three."),
+            segment(3, 5, "Four")
+        ]);
+        assert_eq!(texts(&committed), ["one", "Two!"]);
+
+        // The next window starts after the committed text
+        let third = run(&mut live, &speech, 7);
+        assert_eq!(third.start_sample, 2 * RATE);
+        assert_eq!(third.started_at, time_of(2 * RATE));
+        let committed = live.accept(&third, vec![
+            segment(2, 3, "Three."),
+            segment(3, 5, "Four."),
+            segment(5, 7, "Fi")
+        ]);
+        assert_eq!(texts(&committed), ["Three.", "Four."]);
+        assert_eq!(texts(live.tentative()), ["Fi"]);
+
+        // A late run over audio committed since changes nothing
+        let late = vec![segment(0, 1, "One."), segment(1, 2, "Two.")];
+        assert!(live.accept(&second, late).is_empty());
+        assert_eq!(texts(live.tentative()), ["Fi"]);
+    }
+
+    #[test]
+    fn tentative_text_is_replaced() {
+        let speech = vec![0.1; (10 * RATE) as usize];
+        let mut live = LiveSpeech::new(Speaker::Me);
+
+        let first = run(&mut live, &speech, 2);
+        live.accept(&first, vec![segment(0, 2, "Hello wor")]);
+        assert_eq!(texts(live.tentative()), ["Hello wor"]);
+
+        let second = run(&mut live, &speech, 4);
+        let committed = live.accept(&second, vec![
+            segment(0, 2, "Hello world,"),
+            segment(2, 4, "how are")
+        ]);
+        assert!(committed.is_empty());
+        assert_eq!(texts(live.tentative()), ["Hello world,", "how are"]);
+
+        // A run that ended before the tentative tail doesn't bring it back
+        assert!(live.accept(&first, vec![segment(0, 2, "Hello wor")]).is_empty());
+        assert_eq!(texts(live.tentative()), ["Hello world,", "how are"]);
+    }
+
+    #[test]
+    fn flush_commits_the_remainder() {
+        let speech = vec![0.1; (6 * RATE) as usize];
+        let mut live = LiveSpeech::new(Speaker::Me);
+
+        let first = run(&mut live, &speech, 3);
+        live.accept(&first, vec![segment(0, 2, "One two."), segment(2, 3, "Three")]);
+        let second = run(&mut live, &speech, 5);
+        live.accept(&second, vec![segment(0, 2, "One two."), segment(2, 5, "Three four")]);
+        assert_eq!(texts(live.tentative()), ["Three four"]);
+
+        // The VAD closes the chunk: only what isn't committed is left
+        let chunk = SpeechChunk { start_sample: 0, samples: speech.clone() };
+        let rest = live.finish(chunk).expect("an uncommitted rest");
+        assert_eq!(rest.start_sample, 2 * RATE);
+        assert_eq!(rest.samples.len(), (4 * RATE) as usize);
+        assert!(live.snapshot().is_none());
+
+        // A run still going over the chunk is ignored
+        assert!(live.accept(&second, vec![segment(0, 2, "One two.")]).is_empty());
+        // Its final transcription replaces the tentative tail
+        live.finished(rest.end_sample());
+        assert!(live.tentative().is_empty());
+
+        // Nothing left once everything is committed
+        let chunk = SpeechChunk { start_sample: 0, samples: speech };
+        assert!(live.finish(chunk).is_none());
+    }
+
+    #[test]
+    fn discarded_speech_is_not_transcribed() {
+        let speech = vec![0.1; (8 * RATE) as usize];
+        let mut live = LiveSpeech::new(Speaker::Me);
+
+        let first = run(&mut live, &speech, 3);
+        live.accept(&first, vec![segment(0, 3, "Old chat")]);
+        live.update(Some((0, &speech[..(4 * RATE) as usize])), time_of);
+        live.discard();
+        assert!(live.tentative().is_empty());
+        assert!(live.snapshot().is_none());
+        // A run still going over it is ignored
+        assert!(live.accept(&first, vec![segment(0, 3, "Old chat")]).is_empty());
+
+        // Only speech after the discard reaches the transcript
+        let second = run(&mut live, &speech, 6);
+        assert_eq!(second.start_sample, 4 * RATE);
+        let chunk = SpeechChunk { start_sample: 0, samples: speech };
+        let rest = live.finish(chunk).expect("speech after the discard");
+        assert_eq!(rest.start_sample, 4 * RATE);
+    }
+
+    #[test]
+    fn flush_waits_for_the_run_in_progress() {
+        let speech = vec![0.1; (4 * RATE) as usize];
+        let mut live = LiveSpeech::new(Speaker::Me);
+        live.update(Some((0, &speech[..(2 * RATE) as usize])), time_of);
+        assert!(live.next_window(RATE).is_some());
+        assert!(live.snapshot().is_none(), "a periodic run is going");
+        live.run_finished();
+
+        let window = live.snapshot().expect("nothing running");
+        assert_eq!(window.end_sample(), 2 * RATE);
+        // Neither kind of run starts while a flush is going
+        live.update(Some((0, &speech)), time_of);
+        assert!(live.next_window(RATE).is_none());
+        assert!(live.snapshot().is_none());
+    }
+}
@@ -0,0 +1,173 @@
+use std::collections::VecDeque;
+use chrono::{ DateTime, Utc };
+use serde::Serialize;
+
+/// Who a segment was heard from: the microphone is the user, everything the
+/// PC plays is someone else.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Speaker {
+    Me,
+    Others,
+}
+
+impl Speaker {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Speaker::Me => "me",
+            Speaker::Others => "others",
+        }
+    }
+}
+
+/// One whisper segment placed on the wall clock.
+#[derive(Debug, Clone, Serialize)]
+pub struct TranscriptSegment {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub speaker: Speaker,
+    /// Which of the voices told apart in system audio, from 1
+    pub voice: Option<usize>,
+    pub text: String,
+    /// Language whisper heard (or was told), ISO 639-1
+    pub language: Option<String>,
+}
+
+/// Speech from all sources within a time window, oldest segment first.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AudioContext {
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub segments: Vec<TranscriptSegment>,
+    /// Latest guess at speech still going on, may change before it's final
+    pub tentative: Vec<TranscriptSegment>,
+    /// Names given to voices, `voice_names[0]` is voice 1
+    pub voice_names: Vec<String>,
+}
+
+impl AudioContext {
+    /// The name given to a segment's voice, "Speaker N" if it has none, or
+    /// just the source if voices weren't told apart.
+    pub fn speaker_label(&self, segment: &TranscriptSegment) -> String {
+        match segment.voice {
+            Some(voice) =>
+                self.voice_names
+                    .get(voice - 1)
+                    .map(|name| name.trim())
+                    .filter(|name| !name.is_empty())
+                    .map_or_else(|| format!("Speaker {}", voice), str::to_string),
+            None => segment.speaker.label().to_string(),
+        }
+    }
+}
+
+/// Segments from all sources, kept sorted by start time.
+#[derive(Debug, Default)]
+pub struct Transcript {
+    segments: VecDeque<TranscriptSegment>,
+}
+
+impl Transcript {
+    /// Sources finish chunks out of order, so segments are inserted in place
+    pub fn insert(&mut self, segment: TranscriptSegment) {
+        let index = self.segments.partition_point(|other| other.start <= segment.start);
+        self.segments.insert(index, segment);
+    }
+
+    /// Drops segments that ended before `cutoff`.
+    pub fn prune(&mut self, cutoff: DateTime<Utc>) {
+        // Sorted by start, not end: a long segment may end after later ones
+        self.segments.retain(|segment| segment.end >= cutoff);
+    }
+
+    pub fn clear(&mut self) {
+        self.segments.clear();
+    }
+
+    /// Segments overlapping `[since, until]`; an open end is unbounded.
+    pub fn window(&self, since: Option<DateTime<Utc>>, until: Option<DateTime<Utc>>) -> AudioContext {
+        let segments = self.segments
+            .iter()
+            .filter(|segment| since.map_or(true, |since| segment.end >= since))
+            .filter(|segment| until.map_or(true, |until| segment.start <= until))
+            .cloned()
+            .collect();
+        AudioContext { since, until, segments, tentative: Vec::new(), voice_names: Vec::new() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{ Duration, TimeZone };
+
+    use super::*;
+
+    /// 2024-01-01 12:00:00 plus `secs`
+    fn at(secs: i64) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap() + Duration::seconds(secs)
+    }
+
+    fn segment(start: i64, end: i64, text: &str) -> TranscriptSegment {
+        TranscriptSegment {
+            start: at(start),
+            end: at(end),
+            speaker: Speaker::Me,
+            voice: None,
+            text: text.to_string(),
+            language: None,
+        }
+    }
+
+    fn texts(audio: &AudioContext) -> Vec<&str> {
+        audio.segments
+            .iter()
+            .map(|segment| segment.text.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn out_of_order_segments_are_sorted_by_start() {
+        let mut transcript = Transcript::default();
+        transcript.insert(segment(10, 12, "b"));
+        transcript.insert(segment(0, 3, "a"));
+        transcript.insert(segment(20, 25, "d"));
+        // Same start as "b", after it
+        transcript.insert(segment(10, 11, "c"));
+        assert_eq!(texts(&transcript.window(None, None)), ["a", "b", "c", "d"]);
+    }
+
+    #[test]
+    fn window_takes_segments_overlapping_either_end() {
+        let mut transcript = Transcript::default();
+        transcript.insert(segment(0, 4, "before"));
+        transcript.insert(segment(3, 6, "across start"));
+        transcript.insert(segment(7, 8, "inside"));
+        transcript.insert(segment(9, 12, "across end"));
+        transcript.insert(segment(11, 14, "after"));
+
+        let audio = transcript.window(Some(at(5)), Some(at(10)));
+        assert_eq!(texts(&audio), ["across start", "inside", "across end"]);
+        assert_eq!((audio.since, audio.until), (Some(at(5)), Some(at(10))));
+
+        // Touching an end counts
+        assert_eq!(texts(&transcript.window(Some(at(4)), Some(at(4)))), ["before", "across start"]);
+        // Open ends
+        assert_eq!(texts(&transcript.window(Some(at(13)), None)), ["after"]);
+        assert_eq!(texts(&transcript.window(None, Some(at(2)))), ["before"]);
+    }
+
+    #[test]
+    fn prune_drops_every_segment_ended_before_the_cutoff() {
+        let mut transcript = Transcript::default();
+        // Starts first but runs past the cutoff
+        transcript.insert(segment(0, 30, "long"));
+        transcript.insert(segment(5, 8, "short"));
+        transcript.insert(segment(9, 20, "ends at cutoff"));
+        transcript.insert(segment(25, 27, "later"));
+
+        transcript.prune(at(20));
+        assert_eq!(texts(&transcript.window(None, None)), ["long", "ends at cutoff", "later"]);
+        transcript.prune(at(31));
+        assert!(transcript.window(None, None).segments.is_empty());
+    }
+}
@@ -0,0 +1,459 @@
+use std::collections::VecDeque;
+
+/// Tuning for `Vad`. Durations are in milliseconds, levels in dBFS.
+#[derive(Debug, Clone, Copy)]
+pub struct VadConfig {
+    pub frame_ms: u32,
+    /// How far above the noise floor a frame must be to count as speech
+    pub margin_db: f32,
+    /// Frames quieter than this are never speech, however quiet the room
+    pub min_energy_db: f32,
+    /// Broadband hiss crosses zero on most samples, voiced speech doesn't
+    pub max_zero_crossing_rate: f32,
+    /// Shorter bursts (clicks, keyboard) are dropped
+    pub min_speech_ms: u32,
+    /// A pause this long ends the chunk
+    pub min_silence_ms: u32,
+    /// Audio kept before and after the speech so words aren't clipped
+    pub padding_ms: u32,
+    /// Chunks are cut here even without a pause, Whisper sees 30 s at most
+    pub max_chunk_ms: u32,
+}
+
+impl Default for VadConfig {
+    fn default() -> Self {
+        Self {
+            frame_ms: 30,
+            margin_db: 9.0,
+            min_energy_db: -55.0,
+            max_zero_crossing_rate: 0.35,
+            min_speech_ms: 200,
+            min_silence_ms: 600,
+            padding_ms: 200,
+            max_chunk_ms: 30_000,
+        }
+    }
+}
+
+/// A run of speech, mono, at the rate the `Vad` was created with.
+#[derive(Debug, Clone)]
+pub struct SpeechChunk {
+    /// Index of the first sample since the `Vad` was created
+    pub start_sample: u64,
+    pub samples: Vec<f32>,
+}
+
+impl SpeechChunk {
+    pub fn end_sample(&self) -> u64 {
+        self.start_sample + (self.samples.len() as u64)
+    }
+}
+
+enum State {
+    Silence,
+    Speech {
+        start_sample: u64,
+        samples: Vec<f32>,
+        speech_frames: usize,
+        /// Consecutive non-speech frames at the end of `samples`
+        silent_frames: usize,
+    },
+}
+
+/// Streaming energy/zero-crossing voice activity detector.
+///
+/// Audio is split into fixed frames; a frame is speech when it's louder than
+/// an adaptive noise floor by `margin_db` and not hiss-like. Speech frames
+/// are grouped into chunks that end at the first pause of `min_silence_ms`,
+/// so silence never reaches Whisper and chunks end between words.
+pub struct Vad {
+    config: VadConfig,
+    frame_len: usize,
+    padding_len: usize,
+    /// Samples not yet making up a whole frame
+    pending: Vec<f32>,
+    /// Samples consumed into frames so far
+    position: u64,
+    noise_floor_db: Option<f32>,
+    /// The last `padding_ms` of silence, prepended when speech starts
+    pre_roll: VecDeque<f32>,
+    state: State,
+}
+
+impl Vad {
+    pub fn new(sample_rate: u32, config: VadConfig) -> Self {
+        let samples_per_ms = |ms: u32| (((sample_rate as u64) * (ms as u64)) / 1000) as usize;
+        Self {
+            frame_len: samples_per_ms(config.frame_ms).max(1),
+            padding_len: samples_per_ms(config.padding_ms),
+            config,
+            pending: Vec::new(),
+            position: 0,
+            noise_floor_db: None,
+            pre_roll: VecDeque::new(),
+            state: State::Silence,
+        }
+    }
+
+    /// Feeds mono samples, returns the chunks they completed.
+    pub fn push(&mut self, samples: &[f32]) -> Vec<SpeechChunk> {
+        self.pending.extend_from_slice(samples);
+        let mut chunks = Vec::new();
+        let whole_frames = (self.pending.len() / self.frame_len) * self.frame_len;
+        let frames: Vec<f32> = self.pending.drain(..whole_frames).collect();
+        for frame in frames.chunks(self.frame_len) {
+            if let Some(chunk) = self.process_frame(frame) {
+                chunks.push(chunk);
+            }
+        }
+        chunks
+    }
+
+    /// Ends the stream, returning speech still in progress.
+    pub fn flush(&mut self) -> Option<SpeechChunk> {
+        let pending = std::mem::take(&mut self.pending);
+        if let State::Speech { samples, .. } = &mut self.state {
+            samples.extend_from_slice(&pending);
+        }
+        self.position += pending.len() as u64;
+        self.finish_chunk()
+    }
+
+    /// Speech still going on, once it's long enough to count: the index of
+    /// its first sample and the samples so far.
+    pub fn in_progress(&self) -> Option<(u64, &[f32])> {
+        match &self.state {
+            State::Speech { start_sample, samples, speech_frames, .. } if
+                (*speech_frames as u32) * self.config.frame_ms >= self.config.min_speech_ms
+            => {
+                Some((*start_sample, samples.as_slice()))
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether `frame` is speech, updating the noise floor either way.
+    fn classify(&mut self, frame: &[f32]) -> bool {
+        let energy = frame
+            .iter()
+            .map(|s| s * s)
+            .sum::<f32>() / (frame.len() as f32);
+        let energy_db = 10.0 * (energy + 1e-10).log10();
+        let zero_crossings = frame
+            .windows(2)
+            .filter(|pair| pair[0].signum() != pair[1].signum())
+            .count();
+        let zero_crossing_rate = (zero_crossings as f32) / (frame.len() as f32);
+
+        let noise_floor_db = *self.noise_floor_db.get_or_insert(energy_db);
+        let is_speech =
+            energy_db > (noise_floor_db + self.config.margin_db).max(self.config.min_energy_db) &&
+            zero_crossing_rate <= self.config.max_zero_crossing_rate;
+
+        // Follow the floor down at once, back up slowly (~3 s) so a long
+        // stretch of speech barely lifts it
+        let updated = if energy_db < noise_floor_db {
+            energy_db
+        } else {
+            noise_floor_db + (energy_db - noise_floor_db) * 0.01
+        };
+        self.noise_floor_db = Some(updated);
+
+        is_speech
+    }
+
+    fn process_frame(&mut self, frame: &[f32]) -> Option<SpeechChunk> {
+        let is_speech = self.classify(frame);
+        let frame_start = self.position;
+        self.position += frame.len() as u64;
+
+        match &mut self.state {
+            State::Silence => {
+                if is_speech {
+                    let mut samples: Vec<f32> = self.pre_roll.drain(..).collect();
+                    let start_sample = frame_start - (samples.len() as u64);
+                    samples.extend_from_slice(frame);
+                    self.state = State::Speech {
+                        start_sample,
+                        samples,
+                        speech_frames: 1,
+                        silent_frames: 0,
+                    };
+                } else {
+                    self.pre_roll.extend(frame.iter().copied());
+                    while self.pre_roll.len() > self.padding_len {
+                        self.pre_roll.pop_front();
+                    }
+                }
+                None
+            }
+            State::Speech { samples, speech_frames, silent_frames, .. } => {
+                samples.extend_from_slice(frame);
+                if is_speech {
+                    *speech_frames += 1;
+                    *silent_frames = 0;
+                } else {
+                    *silent_frames += 1;
+                }
+
+                let silence_ms = (*silent_frames as u32) * self.config.frame_ms;
+                let chunk_ms = (((samples.len() as u64) * (self.config.frame_ms as u64)) /
+                    (self.frame_len as u64)) as u32;
+                if silence_ms >= self.config.min_silence_ms {
+                    self.finish_chunk()
+                } else if chunk_ms >= self.config.max_chunk_ms {
+                    // No pause in sight, cut here and carry on in a new chunk
+                    let chunk = self.finish_chunk();
+                    self.state = State::Speech {
+                        start_sample: self.position,
+                        samples: Vec::new(),
+                        speech_frames: 0,
+                        silent_frames: 0,
+                    };
+                    chunk
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    /// Closes the current chunk, trimming trailing silence down to the
+    /// padding. Returns `None` if it was too short to be speech.
+    fn finish_chunk(&mut self) -> Option<SpeechChunk> {
+        let state = std::mem::replace(&mut self.state, State::Silence);
+        let State::Speech { start_sample, mut samples, speech_frames, silent_frames } = state else {
+            return None;
+        };
+
+        let trailing_silence = silent_frames * self.frame_len;
+        let keep = samples.len() - trailing_silence.min(samples.len()) + self.padding_len;
+        if keep < samples.len() {
+            // The cut-off silence is the next chunk's pre-roll
+            self.pre_roll = samples[samples.len() - self.padding_len.min(samples.len())..]
+                .iter()
+                .copied()
+                .collect();
+            samples.truncate(keep);
+        }
+
+        let speech_ms = (speech_frames as u32) * self.config.frame_ms;
+        if speech_ms < self.config.min_speech_ms {
+            return None;
+        }
+        Some(SpeechChunk { start_sample, samples })
+    }
+}
+
+/// Streams `samples` through a fresh VAD in 100 ms blocks, like capture does.
+#[cfg(test)]
+pub(super) fn detect(samples: &[f32], sample_rate: u32, config: VadConfig) -> Vec<SpeechChunk> {
+    let mut vad = Vad::new(sample_rate, config);
+    let block = ((sample_rate / 10) as usize).max(1);
+    let mut chunks: Vec<SpeechChunk> = samples
+        .chunks(block)
+        .flat_map(|block| vad.push(block))
+        .collect();
+    chunks.extend(vad.flush());
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use anyhow::{ anyhow, Result };
+
+    use crate::test_support::{ load_wav_fixtures, noise, tone, write_wav, WavFixture };
+    use super::super::resample::WHISPER_SAMPLE_RATE;
+    use super::*;
+
+    const RATE: u32 = 16000;
+
+    fn quiet(secs: f32) -> Vec<f32> {
+        noise(0.001, secs, RATE)
+    }
+
+    #[test]
+    fn clicks_and_hiss_are_not_speech() {
+        let samples = [
+            quiet(1.0),
+            // Shorter than `min_speech_ms`
+            tone(220.0, 0.3, 0.1, RATE),
+            quiet(1.0),
+            // Loud, but crossing zero all the time
+            noise(0.3, 1.0, RATE),
+            quiet(1.0),
+        ].concat();
+        assert!(detect(&samples, RATE, VadConfig::default()).is_empty());
+    }
+
+    #[test]
+    fn speech_is_padded_and_ends_at_a_pause() {
+        let samples = [quiet(1.0), tone(220.0, 0.3, 1.0, RATE), quiet(1.0)].concat();
+        let chunks = detect(&samples, RATE, VadConfig::default());
+        assert_eq!(chunks.len(), 1);
+        // 200 ms of padding either side, give or take a frame
+        let (start, end) = (chunks[0].start_sample as i64, chunks[0].end_sample() as i64);
+        assert!((start - 12800).abs() <= 480, "starts at {}", start);
+        assert!((end - 35200).abs() <= 480, "ends at {}", end);
+    }
+
+    #[test]
+    fn speech_without_pauses_is_cut_at_max_chunk() {
+        let config = VadConfig { max_chunk_ms: 1000, ..VadConfig::default() };
+        let samples = [quiet(1.0), tone(220.0, 0.3, 2.5, RATE), quiet(1.0)].concat();
+        let chunks = detect(&samples, RATE, config);
+        assert_eq!(chunks.len(), 3);
+        for pair in chunks.windows(2) {
+            assert_eq!(pair[0].end_sample(), pair[1].start_sample);
+        }
+        // Cut at the first frame reaching the limit
+        assert!(chunks.iter().all(|chunk| chunk.samples.len() <= 16000 + 480));
+    }
+
+    /// Labeled speech must be covered at least this much
+    const MIN_RECALL: f32 = 0.9;
+    /// Labeled silence must be skipped at least this much
+    const MIN_SILENCE_SKIPPED: f32 = 0.8;
+
+    /// Runs every `<name>.wav` in `dir` through the VAD and scores the chunks
+    /// against the speech regions labeled in `<name>.json`, a list of
+    /// `[start_seconds, end_seconds]` pairs. Fails if any fixture misses speech,
+    /// keeps too much silence or cuts a chunk in the middle of a labeled region.
+    fn run_vad_eval(dir: &Path, config: VadConfig) -> Result<()> {
+        let fixtures = load_wav_fixtures::<Vec<[f32; 2]>>(dir)?;
+
+        let mut failures = Vec::new();
+        println!(
+            "{:<32} {:>8} {:>8} {:>10} {:>6}",
+            "fixture",
+            "chunks",
+            "recall",
+            "skipped",
+            "cuts"
+        );
+        for WavFixture { name, samples, labels } in fixtures {
+            let rate = WHISPER_SAMPLE_RATE as f32;
+            let total = samples.len() as u64;
+            let regions: Vec<(u64, u64)> = labels
+                .iter()
+                .map(|[start, end]| {
+                    (((start * rate) as u64).min(total), ((end * rate) as u64).min(total))
+                })
+                .collect();
+
+            let chunks = detect(&samples, WHISPER_SAMPLE_RATE, config);
+            let spans: Vec<(u64, u64)> = chunks
+                .iter()
+                .map(|chunk| (chunk.start_sample, chunk.end_sample()))
+                .collect();
+
+            let speech: u64 = regions
+                .iter()
+                .map(|(start, end)| end - start)
+                .sum();
+            let covered = overlap(&regions, &spans);
+            let kept_silence = spans
+                .iter()
+                .map(|(start, end)| end - start)
+                .sum::<u64>() - covered;
+            let silence = total - speech;
+
+            let recall = if speech == 0 { 1.0 } else { (covered as f32) / (speech as f32) };
+            let skipped = if silence == 0 {
+                1.0
+            } else {
+                1.0 - (kept_silence as f32) / (silence as f32)
+            };
+            // Chunk boundaries that fall strictly inside a labeled region
+            let cuts = spans
+                .iter()
+                .flat_map(|(start, end)| [*start, *end])
+                .filter(|&boundary| {
+                    regions.iter().any(|(start, end)| *start < boundary && boundary < *end)
+                })
+                .count();
+
+            println!(
+                "{:<32} {:>8} {:>7.0}% {:>9.0}% {:>6}",
+                name,
+                chunks.len(),
+                recall * 100.0,
+                skipped * 100.0,
+                cuts
+            );
+            if recall < MIN_RECALL || skipped < MIN_SILENCE_SKIPPED || cuts > 0 {
+                failures.push(name);
+            }
+        }
+
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!("VAD failed on {}", failures.join(", ")))
+        }
+    }
+
+    /// Total length of the intersection of two sets of non-overlapping spans.
+    fn overlap(a: &[(u64, u64)], b: &[(u64, u64)]) -> u64 {
+        a.iter()
+            .flat_map(|(a_start, a_end)| {
+                b.iter().map(move |(b_start, b_end)| {
+                    a_end.min(b_end).saturating_sub(*a_start.max(b_start))
+                })
+            })
+            .sum()
+    }
+
+    /// Two tones over quiet noise, as a 44.1 kHz stereo capture would
+    /// record them: 3.0-4.5 s and 7.5-8.5 s
+    fn write_fixture(dir: &Path, name: &str, labels: &[[f32; 2]]) {
+        let rate = 44100;
+        let mut mono = noise(0.001, 10.5, rate);
+        for (start, secs) in [(3.0, 1.5), (7.5, 1.0)] {
+            let offset = ((start * (rate as f32)) as usize).min(mono.len());
+            for (sample, speech) in mono[offset..].iter_mut().zip(tone(220.0, 0.3, secs, rate)) {
+                *sample += speech;
+            }
+        }
+        let stereo: Vec<f32> = mono
+            .iter()
+            .flat_map(|&sample| [sample, sample])
+            .collect();
+        write_wav(&dir.join(format!("{}.wav", name)), rate, 2, &stereo);
+        let labels = serde_json::to_string(labels).unwrap();
+        fs::write(dir.join(format!("{}.json", name)), labels).unwrap();
+    }
+
+    #[test]
+    fn synthetic_fixture_passes() {
+        let dir = tempfile::tempdir().unwrap();
+        write_fixture(dir.path(), "two-tones", &[[3.0, 4.5], [7.5, 8.5]]);
+        run_vad_eval(dir.path(), VadConfig::default()).unwrap();
+    }
+
+    #[test]
+    fn missed_speech_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        write_fixture(dir.path(), "labeled", &[[3.0, 4.5], [7.5, 8.5]]);
+        // Labels speech where there is only noise
+        write_fixture(dir.path(), "mislabeled", &[[1.0, 2.0], [3.0, 4.5], [7.5, 8.5]]);
+        let err = run_vad_eval(dir.path(), VadConfig::default()).unwrap_err().to_string();
+        assert!(err.contains("mislabeled.wav") && !err.contains(" labeled.wav"), "{}", err);
+    }
+
+    #[test]
+    fn empty_directory_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(run_vad_eval(dir.path(), VadConfig::default()).is_err());
+    }
+
+    // Run with `VAD_EVAL_DIR=<dir> cargo test vad_eval -- --ignored --nocapture`
+    #[test]
+    #[ignore = "needs labeled WAV fixtures in VAD_EVAL_DIR"]
+    fn vad_eval() {
+        let dir = std::env::var("VAD_EVAL_DIR").expect("VAD_EVAL_DIR is not set");
+        run_vad_eval(Path::new(&dir), VadConfig::default()).unwrap();
+    }
+}
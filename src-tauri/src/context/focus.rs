@@ -0,0 +1,57 @@
+use std::collections::HashMap;
+use std::sync::Mutex as SyncMutex;
+use std::thread;
+use std::time::{ Duration, Instant };
+
+use super::screen_source::{ default_screen_source, WindowId };
+
+/// Windows not focused for this long are forgotten
+const FOCUS_HISTORY_TTL: Duration = Duration::from_secs(30 * 60);
+
+lazy_static! {
+    static ref FOCUS_HISTORY: SyncMutex<HashMap<WindowId, Instant>> = SyncMutex::new(
+        HashMap::new()
+    );
+}
+
+pub fn record_focus(id: WindowId) {
+    if let Ok(mut history) = FOCUS_HISTORY.lock() {
+        let now = Instant::now();
+        history.insert(id, now);
+        history.retain(|_, seen| now.duration_since(*seen) < FOCUS_HISTORY_TTL);
+    }
+}
+
+/// How long ago each remembered window last had focus.
+pub fn focus_ages() -> HashMap<WindowId, Duration> {
+    match FOCUS_HISTORY.lock() {
+        Ok(history) =>
+            history
+                .iter()
+                .map(|(id, seen)| (*id, seen.elapsed()))
+                .collect(),
+        Err(_) => HashMap::new(),
+    }
+}
+
+/// Polls the foreground window in the background so `get_screen` knows what
+/// the user was looking at before opening PC Agent, which steals focus.
+pub fn start_focus_tracker(interval: Duration) {
+    thread::spawn(move || {
+        let source = match default_screen_source() {
+            Ok(source) => source,
+            Err(e) => {
+                println!("Focus tracker disabled: {}", e);
+                return;
+            }
+        };
+        loop {
+            match source.focused_window() {
+                Ok(Some(id)) => record_focus(id),
+                Ok(None) => {}
+                Err(e) => println!("Error getting focused window: {}", e),
+            }
+            thread::sleep(interval);
+        }
+    });
+}
@@ -1,2 +1,23 @@
 pub mod screen;
+pub mod focus;
+pub mod screen_source;
+pub mod ocr;
 pub mod audio;
+pub mod render;
+pub mod timeline;
+pub mod retrieval;
+
+use serde::Serialize;
+
+use audio::transcript::AudioContext;
+use retrieval::RecalledSnippet;
+use screen::ScreenContext;
+
+/// Everything gathered from the PC for one request, `None` where disabled.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PcContext {
+    pub screen: Option<ScreenContext>,
+    pub audio: Option<AudioContext>,
+    /// Past screen text, speech and chat turns relevant to the question
+    pub recalled: Option<Vec<RecalledSnippet>>,
+}
@@ -0,0 +1,201 @@
+use std::sync::{ Arc, Mutex as SyncMutex };
+use anyhow::{ anyhow, Result };
+use image::RgbaImage;
+
+use crate::settings::{ OcrBackend, Settings };
+
+#[cfg(windows)]
+mod win32;
+#[cfg(target_os = "linux")]
+mod tess;
+
+#[cfg(windows)]
+pub use win32::WindowsOcrEngine;
+#[cfg(target_os = "linux")]
+pub use tess::TesseractOcrEngine;
+
+/// Lines read with less confidence than this are left out of the text;
+/// they're mostly icons and window chrome misread as letters.
+const MIN_LINE_CONFIDENCE: f32 = 0.3;
+
+#[derive(Debug, Clone)]
+pub struct OcrLine {
+    pub text: String,
+    /// 0.0..=1.0, `None` when the engine does not report one (WinRT)
+    pub confidence: Option<f32>,
+}
+
+pub trait OcrEngine: Send + Sync {
+    fn recognize(&self, image: &RgbaImage) -> Result<Vec<OcrLine>>;
+}
+
+pub fn lines_to_text(lines: &[OcrLine]) -> String {
+    let mut result = String::new();
+    for line in lines {
+        if line.confidence.is_some_and(|confidence| confidence < MIN_LINE_CONFIDENCE) {
+            continue;
+        }
+        result.push_str(&line.text);
+        result.push('\n');
+    }
+    result
+}
+
+#[derive(Clone, PartialEq)]
+struct EngineKey {
+    backend: OcrBackend,
+    languages: Vec<String>,
+    tessdata_dir: String,
+}
+
+lazy_static! {
+    static ref OCR_ENGINE: SyncMutex<Option<(EngineKey, Arc<dyn OcrEngine>)>> = SyncMutex::new(None);
+}
+
+fn create_engine(key: &EngineKey) -> Result<Arc<dyn OcrEngine>> {
+    match key.backend {
+        #[cfg(windows)]
+        OcrBackend::Windows => Ok(Arc::new(WindowsOcrEngine::new(&key.languages)?)),
+        #[cfg(target_os = "linux")]
+        OcrBackend::Tesseract => {
+            let datapath = if key.tessdata_dir.is_empty() {
+                None
+            } else {
+                Some(key.tessdata_dir.clone())
+            };
+            Ok(Arc::new(TesseractOcrEngine::new(datapath, &key.languages)?))
+        }
+        #[allow(unreachable_patterns)]
+        backend => Err(anyhow!("OCR engine {:?} is not available on this platform", backend)),
+    }
+}
+
+/// Returns the engine selected in `settings`, reusing the previous one while
+/// the OCR settings are unchanged.
+pub fn engine_from_settings(settings: &Settings) -> Result<Arc<dyn OcrEngine>> {
+    let key = EngineKey {
+        backend: settings.ocr_engine,
+        languages: settings.ocr_languages.clone(),
+        tessdata_dir: settings.tessdata_dir.clone(),
+    };
+
+    let mut cached = OCR_ENGINE.lock().map_err(|err| anyhow!("Failed to lock mutex: {}", err))?;
+    if let Some((cached_key, engine)) = cached.as_ref() {
+        if *cached_key == key {
+            return Ok(Arc::clone(engine));
+        }
+    }
+
+    let engine = create_engine(&key)?;
+    *cached = Some((key, Arc::clone(&engine)));
+    Ok(engine)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_to_text_drops_unsure_lines() {
+        let line = |text: &str, confidence: Option<f32>| OcrLine {
+            text: text.to_string(),
+            confidence,
+        };
+        let lines = [
+            line("File Edit View", Some(0.9)),
+            line("~%| =", Some(0.1)),
+            line("cargo build", None),
+        ];
+        assert_eq!(lines_to_text(&lines), "File Edit View\ncargo build\n");
+    }
+
+    /// Only WinRT OCR ever read captures from disk, so only it has a
+    /// before and after to compare.
+    #[cfg(windows)]
+    mod benchmark {
+        use std::fs;
+        use std::path::{ Path, PathBuf };
+        use std::time::{ Duration, Instant };
+
+        use super::super::*;
+
+        /// Replays every PNG/JPEG in `dir` through `engine` twice: once the way
+        /// captures used to go, through a PNG temp file read back by
+        /// `StorageFile` and `BitmapDecoder`, once straight from memory, and
+        /// prints the per-window cost of each.
+        fn run_ocr_benchmark(dir: &Path, engine: &WindowsOcrEngine, iterations: u32) -> Result<()> {
+            let mut fixtures: Vec<PathBuf> = fs
+                ::read_dir(dir)?
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| {
+                    let extension = path.extension().and_then(|ext| ext.to_str());
+                    let extension = extension.map(|ext| ext.to_lowercase());
+                    matches!(extension.as_deref(), Some("png" | "jpg" | "jpeg"))
+                })
+                .collect();
+            fixtures.sort();
+            if fixtures.is_empty() {
+                return Err(anyhow!("No fixture images found in {}", dir.display()));
+            }
+
+            let images = fixtures
+                .iter()
+                .map(|path| Ok((path, image::open(path)?.to_rgba8())))
+                .collect::<Result<Vec<_>>>()?;
+
+            let mut total_from_file = Duration::ZERO;
+            let mut total_in_memory = Duration::ZERO;
+
+            println!("{:<40} {:>12} {:>12} {:>8}", "window", "file+ocr", "memory+ocr", "lines");
+            for (path, image) in &images {
+                let mut from_file = Duration::ZERO;
+                let mut in_memory = Duration::ZERO;
+                let mut lines = 0;
+
+                for _ in 0..iterations {
+                    let start_time = Instant::now();
+                    engine.recognize_via_png_file(image)?;
+                    from_file += start_time.elapsed();
+
+                    let start_time = Instant::now();
+                    lines = engine.recognize(image)?.len();
+                    in_memory += start_time.elapsed();
+                }
+
+                let from_file = from_file / iterations;
+                let in_memory = in_memory / iterations;
+                total_from_file += from_file;
+                total_in_memory += in_memory;
+
+                let name = path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                println!("{:<40} {:>12?} {:>12?} {:>8}", name, from_file, in_memory, lines);
+            }
+
+            let count = images.len() as u32;
+            println!(
+                "{:<40} {:>12?} {:>12?}",
+                format!("mean over {} windows", count),
+                total_from_file / count,
+                total_in_memory / count
+            );
+            Ok(())
+        }
+
+        // Run with `OCR_BENCH_DIR=<dir> cargo test ocr_benchmark -- --ignored --nocapture`,
+        // `OCR_BENCH_ITERATIONS` to change the default of 3
+        #[test]
+        #[ignore = "needs fixture images in OCR_BENCH_DIR and an installed OCR language"]
+        fn ocr_benchmark() {
+            let dir = std::env::var("OCR_BENCH_DIR").expect("OCR_BENCH_DIR is not set");
+            let iterations = std::env
+                ::var("OCR_BENCH_ITERATIONS")
+                .map(|value| value.parse().expect("Invalid OCR_BENCH_ITERATIONS"))
+                .unwrap_or(3);
+            let engine = WindowsOcrEngine::new(&Settings::default().ocr_languages).unwrap();
+            run_ocr_benchmark(Path::new(&dir), &engine, iterations).unwrap();
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex as SyncMutex;
+use anyhow::{ anyhow, Result };
+use image::RgbaImage;
+use tesseract::Tesseract;
+
+use super::{ OcrEngine, OcrLine };
+
+/// Tesseract/leptonica backend.
+///
+/// A `Tesseract` handle is single-threaded and slow to initialise, so idle
+/// handles are pooled and windows are recognised in parallel on separate
+/// handles.
+pub struct TesseractOcrEngine {
+    datapath: Option<String>,
+    language: String,
+    pool: SyncMutex<Vec<Tesseract>>,
+}
+
+impl TesseractOcrEngine {
+    pub fn new(datapath: Option<String>, languages: &[String]) -> Result<Self> {
+        let language = if languages.is_empty() { "eng".to_string() } else { languages.join("+") };
+        let engine = Self {
+            datapath,
+            language,
+            pool: SyncMutex::new(Vec::new()),
+        };
+        // Fail early on missing traineddata instead of on the first capture
+        let tess = engine.create()?;
+        engine.release(tess);
+        Ok(engine)
+    }
+
+    fn create(&self) -> Result<Tesseract> {
+        Tesseract::new(self.datapath.as_deref(), Some(&self.language)).map_err(|e|
+            anyhow!("Failed to initialise tesseract for '{}': {}", self.language, e)
+        )
+    }
+
+    fn acquire(&self) -> Result<Tesseract> {
+        let pooled = self.pool
+            .lock()
+            .map_err(|err| anyhow!("Failed to lock mutex: {}", err))?
+            .pop();
+        match pooled {
+            Some(tess) => Ok(tess),
+            None => self.create(),
+        }
+    }
+
+    fn release(&self, tess: Tesseract) {
+        if let Ok(mut pool) = self.pool.lock() {
+            pool.push(tess);
+        }
+    }
+}
+
+impl OcrEngine for TesseractOcrEngine {
+    fn recognize(&self, image: &RgbaImage) -> Result<Vec<OcrLine>> {
+        let (width, height) = image.dimensions();
+        let tess = self.acquire()?;
+        let mut tess = tess
+            .set_frame(image.as_raw(), width as i32, height as i32, 4, (width * 4) as i32)?
+            .recognize()?;
+        let tsv = tess.get_tsv_text(0)?;
+        self.release(tess);
+
+        Ok(parse_tsv(&tsv))
+    }
+}
+
+/// Groups word rows (level 5) of Tesseract's TSV output into lines.
+///
+/// Columns: level page block par line word left top width height conf text
+fn parse_tsv(tsv: &str) -> Vec<OcrLine> {
+    struct LineAccumulator {
+        words: Vec<String>,
+        confidence_sum: f32,
+    }
+
+    let mut lines: BTreeMap<(u32, u32, u32, u32), LineAccumulator> = BTreeMap::new();
+
+    for row in tsv.lines() {
+        let columns: Vec<&str> = row.splitn(12, '\t').collect();
+        if columns.len() < 12 || columns[0] != "5" {
+            continue;
+        }
+        let text = columns[11].trim();
+        if text.is_empty() {
+            continue;
+        }
+        let number = |i: usize| columns[i].parse::<u32>().unwrap_or(0);
+        let key = (number(1), number(2), number(3), number(4));
+        let confidence = columns[10].parse::<f32>().unwrap_or(0.0).max(0.0) / 100.0;
+
+        let line = lines.entry(key).or_insert(LineAccumulator {
+            words: Vec::new(),
+            confidence_sum: 0.0,
+        });
+        line.words.push(text.to_string());
+        line.confidence_sum += confidence;
+    }
+
+    lines
+        .into_values()
+        .map(|line| OcrLine {
+            confidence: Some(line.confidence_sum / (line.words.len() as f32)),
+            text: line.words.join(" "),
+        })
+        .collect()
+}
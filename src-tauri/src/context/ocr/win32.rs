@@ -0,0 +1,120 @@
+use anyhow::{ anyhow, Result };
+use image::RgbaImage;
+use windows::{
+    core::HSTRING,
+    Globalization::Language,
+    Graphics::Imaging::{ BitmapPixelFormat, SoftwareBitmap },
+    Media::Ocr::OcrEngine as WinRtOcrEngine,
+    Storage::Streams::DataWriter,
+};
+
+use super::{ OcrEngine, OcrLine };
+
+/// `Windows.Media.Ocr` backend. WinRT recognises one language per engine, so
+/// the first configured language that has an installed recognizer is used.
+pub struct WindowsOcrEngine {
+    language_tag: Option<String>,
+}
+
+impl WindowsOcrEngine {
+    pub fn new(languages: &[String]) -> Result<Self> {
+        for tag in languages {
+            let language = Language::CreateLanguage(&HSTRING::from(tag.as_str()))?;
+            if WinRtOcrEngine::IsLanguageSupported(&language)? {
+                return Ok(Self { language_tag: Some(tag.clone()) });
+            }
+            println!("OCR language {} has no installed recognizer, skipping", tag);
+        }
+        if !languages.is_empty() {
+            return Err(anyhow!("None of the OCR languages {:?} are installed", languages));
+        }
+        Ok(Self { language_tag: None })
+    }
+
+    fn create_engine(&self) -> windows::core::Result<WinRtOcrEngine> {
+        match &self.language_tag {
+            Some(tag) => {
+                let lang = Language::CreateLanguage(&HSTRING::from(tag.as_str()))?;
+                WinRtOcrEngine::TryCreateFromLanguage(&lang)
+            }
+            None => {
+                let lang = &WinRtOcrEngine::AvailableRecognizerLanguages()?
+                    .First()?
+                    .Current()?
+                    .LanguageTag()?;
+
+                let lang = Language::CreateLanguage(lang)?;
+                WinRtOcrEngine::TryCreateFromLanguage(&lang)
+            }
+        }
+    }
+
+    fn ocr_from_bitmap(&self, bitmap: SoftwareBitmap) -> windows::core::Result<Vec<OcrLine>> {
+        let engine = self.create_engine()?;
+
+        let lines = engine.RecognizeAsync(&bitmap)?.get()?.Lines()?;
+        let mut result = Vec::new();
+
+        for line in lines {
+            result.push(OcrLine {
+                text: line.Text()?.to_string_lossy(),
+                confidence: None,
+            });
+        }
+        Ok(result)
+    }
+}
+
+/// Copies the capture straight into a `SoftwareBitmap`, swapping RGBA to the
+/// BGRA layout the OCR engine expects. No encode/decode round trip.
+fn bitmap_from_image(image: &RgbaImage) -> windows::core::Result<SoftwareBitmap> {
+    let mut bgra = image.as_raw().clone();
+    for pixel in bgra.chunks_exact_mut(4) {
+        pixel.swap(0, 2);
+    }
+
+    let writer = DataWriter::new()?;
+    writer.WriteBytes(&bgra)?;
+    let buffer = writer.DetachBuffer()?;
+
+    SoftwareBitmap::CreateCopyFromBuffer(
+        &buffer,
+        BitmapPixelFormat::Bgra8,
+        image.width() as i32,
+        image.height() as i32
+    )
+}
+
+#[cfg(test)]
+impl WindowsOcrEngine {
+    /// How captures reached the engine before `recognize` took them from
+    /// memory: a PNG temp file that `StorageFile` and `BitmapDecoder` read
+    /// back. Kept as the OCR benchmark's baseline.
+    pub fn recognize_via_png_file(&self, image: &RgbaImage) -> Result<Vec<OcrLine>> {
+        use std::fs;
+        use image::DynamicImage;
+        use windows::Graphics::Imaging::BitmapDecoder;
+        use windows::Storage::{ FileAccessMode, StorageFile };
+
+        let mut temp_file = tempfile::NamedTempFile::new()?;
+        DynamicImage::ImageRgba8(image.clone())
+            .to_rgb8()
+            .write_to(&mut temp_file, image::ImageFormat::Png)?;
+
+        let path = fs::canonicalize(temp_file.path())?.to_string_lossy().replace("\\\\?\\", "");
+        let file = StorageFile::GetFileFromPathAsync(&HSTRING::from(path))?.get()?;
+        let decoder = BitmapDecoder::CreateWithIdAsync(
+            BitmapDecoder::PngDecoderId()?,
+            &file.OpenAsync(FileAccessMode::Read)?.get()?
+        )?.get()?;
+        let bitmap = decoder.GetSoftwareBitmapAsync()?.get()?;
+        Ok(self.ocr_from_bitmap(bitmap)?)
+    }
+}
+
+impl OcrEngine for WindowsOcrEngine {
+    fn recognize(&self, image: &RgbaImage) -> Result<Vec<OcrLine>> {
+        let bitmap = bitmap_from_image(image)?;
+        Ok(self.ocr_from_bitmap(bitmap)?)
+    }
+}
@@ -0,0 +1,228 @@
+use chrono::Local;
+
+use super::audio::transcript::AudioContext;
+use super::retrieval::RecalledSnippet;
+use super::screen::ScreenContext;
+
+/// Appended to windows whose text was cut
+pub const TRUNCATION_MARKER: &str = " [...]";
+
+pub fn window_header(title: &str, process_name: &str, focused: bool) -> String {
+    let mut tags = Vec::new();
+    if !process_name.is_empty() {
+        tags.push(process_name);
+    }
+    if focused {
+        tags.push("focused");
+    }
+    if tags.is_empty() {
+        format!("{}:\n", title)
+    } else {
+        format!("{} ({}):\n", title, tags.join(", "))
+    }
+}
+
+pub fn render_screen(screen: &ScreenContext) -> String {
+    let mut combined_content = String::new();
+    for window in &screen.windows {
+        combined_content.push_str(
+            &window_header(&window.title, &window.process_name, window.focused)
+        );
+        combined_content.push_str(&window.text);
+        if window.truncated {
+            combined_content.push_str(TRUNCATION_MARKER);
+        }
+        combined_content.push_str("\n\n");
+    }
+    combined_content
+}
+
+/// One line per segment, prefixed with its local start time and speaker.
+pub fn render_audio(audio: &AudioContext) -> String {
+    let mut transcript = String::new();
+    for segment in &audio.segments {
+        let start = segment.start.with_timezone(&Local);
+        transcript.push_str(
+            &format!(
+                "[{}] {}: {}\n",
+                start.format("%H:%M:%S"),
+                audio.speaker_label(segment),
+                segment.text.trim()
+            )
+        );
+    }
+    for segment in &audio.tentative {
+        let start = segment.start.with_timezone(&Local);
+        transcript.push_str(
+            &format!(
+                "[{}] {} (still speaking): {}\n",
+                start.format("%H:%M:%S"),
+                audio.speaker_label(segment),
+                segment.text.trim()
+            )
+        );
+    }
+    transcript
+}
+
+/// Most relevant first, each headed by its local date and time and where it
+/// came from.
+pub fn render_recalled(snippets: &[RecalledSnippet]) -> String {
+    let mut recalled = String::new();
+    for snippet in snippets {
+        let at = snippet.at.with_timezone(&Local);
+        recalled.push_str(
+            &format!(
+                "[{}] {} {}:\n{}\n\n",
+                at.format("%Y-%m-%d %H:%M"),
+                snippet.kind.as_str(),
+                snippet.source,
+                snippet.text
+            )
+        );
+    }
+    recalled
+}
+
+/// The system message: prompt, then whatever screen, audio and recalled
+/// text is left after budgeting under a PC CONTEXT heading.
+pub fn render_system_message(
+    system_prompt: &str,
+    screen: &str,
+    audio: &str,
+    recalled: &str
+) -> String {
+    let mut context = system_prompt.to_string();
+    if screen.is_empty() && audio.is_empty() && recalled.is_empty() {
+        return context;
+    }
+    if !context.is_empty() {
+        context.push_str("\n\n");
+    }
+    context.push_str("PC CONTEXT\n");
+    if !screen.is_empty() {
+        context.push_str("SCREEN:\n");
+        context.push_str(screen);
+    }
+    if !audio.is_empty() {
+        context.push_str("AUDIO:\n");
+        context.push_str(audio);
+    }
+    if !recalled.is_empty() {
+        context.push_str("RECALLED FROM EARLIER:\n");
+        context.push_str(recalled);
+    }
+    context
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{ DateTime, TimeZone, Utc };
+
+    use crate::test_support::window;
+    use super::super::audio::transcript::{ Speaker, TranscriptSegment };
+    use super::super::retrieval::SourceKind;
+    use super::*;
+
+    /// `hour:minute` on 2024-05-06 local time, so rendered times don't
+    /// depend on the machine's zone
+    fn local(hour: u32, minute: u32) -> DateTime<Utc> {
+        Local.with_ymd_and_hms(2024, 5, 6, hour, minute, 0).unwrap().with_timezone(&Utc)
+    }
+
+    fn segment(
+        at: DateTime<Utc>,
+        speaker: Speaker,
+        voice: Option<usize>,
+        text: &str
+    ) -> TranscriptSegment {
+        TranscriptSegment {
+            start: at,
+            end: at,
+            speaker,
+            voice,
+            text: text.to_string(),
+            language: None,
+        }
+    }
+
+    #[test]
+    fn screen_lists_windows_with_their_tags() {
+        let mut editor = window("main.rs", "code", "fn main() {}", local(9, 30));
+        editor.focused = true;
+        let mut browser = window("Docs", "", "Lorem ipsum", local(9, 30));
+        browser.truncated = true;
+        let screen = ScreenContext { captured_at: local(9, 30), windows: vec![editor, browser] };
+        assert_eq!(
+            render_screen(&screen),
+            "main.rs (code, focused):\nfn main() {}\n\nDocs:\nLorem ipsum [...]\n\n"
+        );
+        let empty = ScreenContext { captured_at: local(9, 30), windows: vec![] };
+        assert_eq!(render_screen(&empty), "");
+    }
+
+    #[test]
+    fn audio_puts_tentative_speech_last() {
+        let audio = AudioContext {
+            segments: vec![
+                segment(local(9, 30), Speaker::Me, None, " Can you hear me? "),
+                segment(local(9, 31), Speaker::Others, Some(1), "Yes."),
+                segment(local(9, 32), Speaker::Others, Some(2), "Me too.")
+            ],
+            tentative: vec![segment(local(9, 33), Speaker::Me, None, "Then let's")],
+            voice_names: vec!["Alex".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            render_audio(&audio),
+            "[09:30:00] me: Can you hear me?\n\
+             [09:31:00] Alex: Yes.\n\
+             [09:32:00] Speaker 2: Me too.\n\
+             [09:33:00] me (still speaking): Then let's\n"
+        );
+        assert_eq!(render_audio(&AudioContext::default()), "");
+    }
+
+    #[test]
+    fn recalled_snippets_are_headed_by_date_and_source() {
+        let snippets = [
+            RecalledSnippet {
+                kind: SourceKind::Window,
+                source_id: 1,
+                source: "Invoice (excel)".to_string(),
+                at: local(14, 5),
+                text: "Total: 42".to_string(),
+                score: 0.9,
+            },
+            RecalledSnippet {
+                kind: SourceKind::Segment,
+                source_id: 2,
+                source: "others".to_string(),
+                at: local(15, 0),
+                text: "Ship it on Friday".to_string(),
+                score: 0.5,
+            },
+        ];
+        assert_eq!(
+            render_recalled(&snippets),
+            "[2024-05-06 14:05] window Invoice (excel):\nTotal: 42\n\n\
+             [2024-05-06 15:00] segment others:\nShip it on Friday\n\n"
+        );
+        assert_eq!(render_recalled(&[]), "");
+    }
+
+    #[test]
+    fn system_message_leaves_out_empty_sections() {
+        assert_eq!(
+            render_system_message("Be brief.", "Editor:\ntext\n\n", "", "[..] note\n\n"),
+            "Be brief.\n\nPC CONTEXT\nSCREEN:\nEditor:\ntext\n\n\
+             RECALLED FROM EARLIER:\n[..] note\n\n"
+        );
+        assert_eq!(
+            render_system_message("", "", "[09:30:00] me: hi\n", ""),
+            "PC CONTEXT\nAUDIO:\n[09:30:00] me: hi\n"
+        );
+        // No heading without context
+        assert_eq!(render_system_message("Be brief.", "", "", ""), "Be brief.");
+    }
+}
@@ -0,0 +1,183 @@
+use std::path::Path;
+use std::sync::{ Arc, Mutex as SyncMutex };
+use anyhow::{ anyhow, Result };
+use tokenizers::{ Tokenizer, TruncationParams };
+use tract_onnx::prelude::*;
+
+use crate::settings::Settings;
+
+/// Text is cut to this many tokens, and shorter text padded to it, so the
+/// model is optimized once for a fixed shape
+pub const MAX_TOKENS: usize = 128;
+
+/// What each model input is fed, told apart by name
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ModelInput {
+    TokenIds,
+    AttentionMask,
+    TokenTypeIds,
+}
+
+impl ModelInput {
+    fn from_name(name: &str) -> Self {
+        let name = name.to_lowercase();
+        if name.contains("mask") {
+            ModelInput::AttentionMask
+        } else if name.contains("type") {
+            ModelInput::TokenTypeIds
+        } else {
+            ModelInput::TokenIds
+        }
+    }
+}
+
+/// A sentence encoder run on the CPU with tract: BERT-style ONNX exports
+/// (all-MiniLM-L6-v2, bge-small, e5-small, ...) taking token ids, attention
+/// mask and optionally token type ids.
+///
+/// Per-token outputs are mean-pooled over the real tokens; a model that
+/// already pools returns its vector as-is. Vectors are L2-normalized, so a
+/// dot product is their cosine similarity.
+pub struct Embedder {
+    model: TypedRunnableModel<TypedModel>,
+    inputs: Vec<ModelInput>,
+    tokenizer: Tokenizer,
+    /// Tells vectors of different models apart in the index
+    pub id: String,
+}
+
+impl Embedder {
+    /// Loads `model.onnx` and `tokenizer.json` from `dir`.
+    pub fn load(dir: &Path) -> Result<Self> {
+        let model_path = dir.join("model.onnx");
+        let model = tract_onnx
+            ::onnx()
+            .model_for_path(&model_path)
+            .map_err(|err| anyhow!("Failed to load {}: {}", model_path.display(), err))?;
+        let tokenizer_path = dir.join("tokenizer.json");
+        let tokenizer = Tokenizer::from_file(&tokenizer_path).map_err(|err|
+            anyhow!("Failed to load {}: {}", tokenizer_path.display(), err)
+        )?;
+        // Replacing the model file re-indexes everything
+        let size = std::fs::metadata(&model_path)?.len();
+        let name = dir
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Self::from_parts(model, tokenizer, format!("{}-{}", name, size))
+    }
+
+    pub fn from_parts(model: InferenceModel, mut tokenizer: Tokenizer, id: String) -> Result<Self> {
+        let inputs = model
+            .input_outlets()?
+            .iter()
+            .map(|outlet| ModelInput::from_name(&model.node(outlet.node).name))
+            .collect::<Vec<_>>();
+        if !inputs.contains(&ModelInput::TokenIds) {
+            return Err(anyhow!("Embedding model has no token id input"));
+        }
+        let mut model = model;
+        for i in 0..inputs.len() {
+            model.set_input_fact(i, i64::fact([1, MAX_TOKENS]).into())?;
+        }
+        // Exports name their dimensions ("batch", "sequence"), which would
+        // clash with the fixed ones; outputs are inferred instead
+        for i in 0..model.output_outlets()?.len() {
+            model.set_output_fact(i, InferenceFact::default())?;
+        }
+        let model = model.into_optimized()?.into_runnable()?;
+
+        // Padding is done here, and a tokenizer.json may ask for its own
+        tokenizer.with_padding(None);
+        tokenizer
+            .with_truncation(
+                Some(TruncationParams {
+                    max_length: MAX_TOKENS,
+                    ..Default::default()
+                })
+            )
+            .map_err(|err| anyhow!("Failed to configure tokenizer: {}", err))?;
+
+        Ok(Self { model, inputs, tokenizer, id })
+    }
+
+    /// The unit-length vector for `text`.
+    pub fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        let encoding = self.tokenizer
+            .encode(text, true)
+            .map_err(|err| anyhow!("Failed to tokenize: {}", err))?;
+        let length = encoding.get_ids().len().min(MAX_TOKENS);
+        let mut ids = vec![0i64; MAX_TOKENS];
+        let mut mask = vec![0i64; MAX_TOKENS];
+        for i in 0..length {
+            ids[i] = encoding.get_ids()[i] as i64;
+            mask[i] = 1;
+        }
+
+        let tensor = |values: &[i64]| -> Result<TValue> {
+            Ok(tract_ndarray::Array2::from_shape_vec((1, MAX_TOKENS), values.to_vec())?.into_tvalue())
+        };
+        let zeros = vec![0i64; MAX_TOKENS];
+        let inputs = self.inputs
+            .iter()
+            .map(|input| {
+                match input {
+                    ModelInput::TokenIds => tensor(&ids),
+                    ModelInput::AttentionMask => tensor(&mask),
+                    ModelInput::TokenTypeIds => tensor(&zeros),
+                }
+            })
+            .collect::<Result<TVec<_>>>()?;
+        let outputs = self.model.run(inputs)?;
+        let output = outputs[0].to_array_view::<f32>()?;
+
+        let mut vector = match output.shape() {
+            // Per token: mean over the real ones
+            [1, tokens, dim] if *tokens == MAX_TOKENS => {
+                let mut sum = vec![0f32; *dim];
+                for token in 0..length.max(1) {
+                    for (d, value) in sum.iter_mut().enumerate() {
+                        *value += output[[0, token, d]];
+                    }
+                }
+                sum.iter()
+                    .map(|value| value / (length.max(1) as f32))
+                    .collect::<Vec<_>>()
+            }
+            [1, _] => output.iter().copied().collect(),
+            shape => {
+                return Err(anyhow!("Unexpected embedding output shape {:?}", shape));
+            }
+        };
+        let norm = vector
+            .iter()
+            .map(|value| value * value)
+            .sum::<f32>()
+            .sqrt();
+        if norm > 0.0 {
+            vector.iter_mut().for_each(|value| {
+                *value /= norm;
+            });
+        }
+        Ok(vector)
+    }
+}
+
+lazy_static! {
+    static ref EMBEDDER: SyncMutex<Option<(String, Arc<Embedder>)>> = SyncMutex::new(None);
+}
+
+/// The embedding model in `settings.embedding_model_dir`, loaded on first
+/// use and reused while the setting is unchanged.
+pub fn embedder_from_settings(settings: &Settings) -> Result<Arc<Embedder>> {
+    let mut cached = EMBEDDER.lock().map_err(|err| anyhow!("Failed to lock mutex: {}", err))?;
+    if let Some((dir, embedder)) = cached.as_ref() {
+        if *dir == settings.embedding_model_dir {
+            return Ok(Arc::clone(embedder));
+        }
+    }
+
+    let embedder = Arc::new(Embedder::load(Path::new(&settings.embedding_model_dir))?);
+    *cached = Some((settings.embedding_model_dir.clone(), Arc::clone(&embedder)));
+    Ok(embedder)
+}
@@ -0,0 +1,47 @@
+use std::sync::Mutex as SyncMutex;
+use std::thread;
+use std::time::Duration;
+
+use crate::settings::Settings;
+use super::embedder::embedder_from_settings;
+use super::{ catch_up, SharedStores };
+
+/// How often new timeline records and chat messages are indexed
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+lazy_static! {
+    static ref INDEXER_SETTINGS: SyncMutex<Option<Settings>> = SyncMutex::new(None);
+}
+
+/// Applies settings to the indexer: whether it runs and with which model.
+/// Takes effect at its next check.
+pub fn configure_indexer(settings: &Settings) {
+    if let Ok(mut current) = INDEXER_SETTINGS.lock() {
+        *current = Some(settings.clone());
+    }
+}
+
+/// Embeds new timeline records and chat messages in the background while
+/// `retrieval_enabled` is on, so questions can recall them.
+pub fn start_indexer() {
+    thread::spawn(|| {
+        loop {
+            thread::sleep(POLL_INTERVAL);
+            let settings = INDEXER_SETTINGS.lock()
+                .ok()
+                .and_then(|settings| settings.clone());
+            let Some(settings) = settings else {
+                continue;
+            };
+            if !settings.retrieval_enabled {
+                continue;
+            }
+            let result = embedder_from_settings(&settings).and_then(|embedder|
+                catch_up(&embedder, &SharedStores)
+            );
+            if let Err(e) = result {
+                eprintln!("Indexing failed: {}", e);
+            }
+        }
+    });
+}
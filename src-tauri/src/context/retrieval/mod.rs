@@ -0,0 +1,928 @@
+use std::collections::{ HashMap, HashSet };
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Mutex as SyncMutex;
+use anyhow::{ anyhow, Result };
+use chrono::{ DateTime, Utc };
+use rusqlite::{ params, Connection, OptionalExtension };
+use serde::Serialize;
+
+use crate::history::migrations::migrate;
+use crate::history::{ with_store, ChatStore };
+use super::timeline::{ with_timeline, TimelineStore };
+use embedder::Embedder;
+
+pub mod embedder;
+pub mod indexer;
+
+pub const INDEX_DB_PATH: &str = "./embeddings.db";
+
+/// Longer text is embedded in pieces of about this many characters, which
+/// fit in `embedder::MAX_TOKENS` for English text
+const CHUNK_CHARS: usize = 500;
+/// Records of each kind read and embedded at a time
+const BATCH: usize = 32;
+/// Candidates searched per snippet wanted, since some turn out to be
+/// already in the context
+const CANDIDATES_PER_RESULT: usize = 4;
+
+/// Embedding index schema changes, see `history::migrations::MIGRATIONS`.
+pub const MIGRATIONS: &[&str] = &[
+    // 1: a vector per piece of a stored record, and how far each kind of
+    // record has been indexed
+    "CREATE TABLE chunks (
+        id INTEGER PRIMARY KEY,
+        model TEXT NOT NULL,
+        kind TEXT NOT NULL,
+        source_id INTEGER NOT NULL,
+        start INTEGER NOT NULL,
+        end INTEGER NOT NULL,
+        vector BLOB NOT NULL,
+        UNIQUE (model, kind, source_id, start)
+    );
+    CREATE INDEX chunks_by_source ON chunks (kind, source_id);
+    CREATE TABLE progress (
+        model TEXT NOT NULL,
+        kind TEXT NOT NULL,
+        last_source_id INTEGER NOT NULL,
+        PRIMARY KEY (model, kind)
+    );",
+];
+
+/// Where an indexed piece of text is stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceKind {
+    /// Window text in the timeline
+    Window,
+    /// Speech in the timeline
+    Segment,
+    /// A chat message in the history
+    Message,
+}
+
+impl SourceKind {
+    pub const ALL: [SourceKind; 3] = [SourceKind::Window, SourceKind::Segment, SourceKind::Message];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SourceKind::Window => "window",
+            SourceKind::Segment => "segment",
+            SourceKind::Message => "message",
+        }
+    }
+
+    fn parse(kind: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|known| known.as_str() == kind)
+    }
+}
+
+/// A piece of a stored record's text, by byte range. Only vectors are
+/// indexed; the text stays where it's stored and goes when that's pruned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ChunkRef {
+    pub kind: SourceKind,
+    pub source_id: i64,
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Hit {
+    pub chunk: ChunkRef,
+    /// Cosine similarity to the query
+    pub score: f32,
+}
+
+/// Something from the past relevant to the question.
+#[derive(Debug, Clone, Serialize)]
+pub struct RecalledSnippet {
+    pub kind: SourceKind,
+    pub source_id: i64,
+    /// Window title and process, speaker, or chat session and role
+    pub source: String,
+    /// When it was last on screen, said or written
+    pub at: DateTime<Utc>,
+    pub text: String,
+    /// Cosine similarity to the question
+    pub score: f32,
+}
+
+/// What the request already has, which recalling would only repeat.
+#[derive(Debug, Clone, Default)]
+pub struct Present {
+    /// The chat being answered, whose messages are its history
+    pub session_id: Option<i64>,
+    /// Windows seen since then are on screen, or were moments ago
+    pub screen_since: Option<DateTime<Utc>>,
+    /// Speech since then is in the audio transcript
+    pub audio_since: Option<DateTime<Utc>>,
+}
+
+/// A model's name and every vector it made
+type ModelVectors = (String, Vec<(ChunkRef, Vec<f32>)>);
+
+/// Vectors of timeline and chat history text, in SQLite, searched by brute
+/// force. Each model's vectors are kept apart, so changing the model
+/// indexes everything again instead of comparing vectors that don't match.
+pub struct EmbeddingIndex {
+    connection: Connection,
+    /// One model's vectors, loaded on first search and kept in step after
+    cache: Option<ModelVectors>,
+}
+
+impl EmbeddingIndex {
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut connection = Connection::open(path)?;
+        migrate(&mut connection, MIGRATIONS)?;
+        Ok(Self { connection, cache: None })
+    }
+
+    /// The last record of `kind` indexed with `model`, 0 if none was.
+    pub fn indexed_up_to(&self, model: &str, kind: SourceKind) -> Result<i64> {
+        let last = self.connection
+            .query_row(
+                "SELECT last_source_id FROM progress WHERE model = ?1 AND kind = ?2",
+                params![model, kind.as_str()],
+                |row| row.get(0)
+            )
+            .optional()?;
+        Ok(last.unwrap_or(0))
+    }
+
+    /// Adds the vectors of records of `kind` up to `up_to`, all at once so
+    /// an interrupted run redoes the whole batch.
+    pub fn add(
+        &mut self,
+        model: &str,
+        kind: SourceKind,
+        up_to: i64,
+        chunks: &[(ChunkRef, Vec<f32>)]
+    ) -> Result<()> {
+        let transaction = self.connection.transaction()?;
+        for (chunk, vector) in chunks {
+            transaction.execute(
+                "INSERT OR REPLACE INTO chunks (model, kind, source_id, start, end, vector)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    model,
+                    chunk.kind.as_str(),
+                    chunk.source_id,
+                    chunk.start as i64,
+                    chunk.end as i64,
+                    vector_to_blob(vector)
+                ]
+            )?;
+        }
+        transaction.execute(
+            "INSERT OR REPLACE INTO progress (model, kind, last_source_id) VALUES (?1, ?2, ?3)",
+            params![model, kind.as_str(), up_to]
+        )?;
+        transaction.commit()?;
+
+        if let Some((cached_model, cached)) = self.cache.as_mut() {
+            if cached_model == model {
+                let replaced: HashSet<ChunkRef> = chunks
+                    .iter()
+                    .map(|(chunk, _)| *chunk)
+                    .collect();
+                cached.retain(|(cached, _)| !replaced.contains(cached));
+                cached.extend(chunks.iter().cloned());
+            }
+        }
+        Ok(())
+    }
+
+    /// Forgets vectors of records of `kind` that aren't in `existing`, for
+    /// every model. Returns how many records were forgotten.
+    pub fn retain(&mut self, kind: SourceKind, existing: &HashSet<i64>) -> Result<usize> {
+        let mut statement = self.connection.prepare(
+            "SELECT DISTINCT source_id FROM chunks WHERE kind = ?1"
+        )?;
+        let missing = statement
+            .query_map([kind.as_str()], |row| row.get::<_, i64>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?
+            .into_iter()
+            .filter(|id| !existing.contains(id))
+            .collect::<Vec<_>>();
+        drop(statement);
+        if missing.is_empty() {
+            return Ok(0);
+        }
+
+        let transaction = self.connection.transaction()?;
+        for id in &missing {
+            transaction.execute(
+                "DELETE FROM chunks WHERE kind = ?1 AND source_id = ?2",
+                params![kind.as_str(), id]
+            )?;
+        }
+        transaction.commit()?;
+        if let Some((_, cached)) = self.cache.as_mut() {
+            let missing: HashSet<i64> = missing.iter().copied().collect();
+            cached.retain(|(chunk, _)| chunk.kind != kind || !missing.contains(&chunk.source_id));
+        }
+        Ok(missing.len())
+    }
+
+    /// The `limit` records closest to `query`, each by its closest piece,
+    /// closest first.
+    pub fn search(&mut self, model: &str, query: &[f32], limit: usize) -> Result<Vec<Hit>> {
+        if self.cache.as_ref().map_or(true, |(cached, _)| cached != model) {
+            let mut statement = self.connection.prepare(
+                "SELECT kind, source_id, start, end, vector FROM chunks WHERE model = ?1"
+            )?;
+            let chunks = statement
+                .query_map([model], |row| {
+                    let kind: String = row.get(0)?;
+                    let source_id: i64 = row.get(1)?;
+                    let start: i64 = row.get(2)?;
+                    let end: i64 = row.get(3)?;
+                    let vector: Vec<u8> = row.get(4)?;
+                    Ok(
+                        SourceKind::parse(&kind).map(|kind| {
+                            let chunk = ChunkRef {
+                                kind,
+                                source_id,
+                                start: start as usize,
+                                end: end as usize,
+                            };
+                            (chunk, vector_from_blob(&vector))
+                        })
+                    )
+                })?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            self.cache = Some((model.to_string(), chunks.into_iter().flatten().collect()));
+        }
+        let (_, chunks) = self.cache.as_ref().unwrap();
+
+        let mut best: HashMap<(SourceKind, i64), Hit> = HashMap::new();
+        for (chunk, vector) in chunks {
+            if vector.len() != query.len() {
+                continue;
+            }
+            let score: f32 = vector
+                .iter()
+                .zip(query)
+                .map(|(a, b)| a * b)
+                .sum();
+            let entry = best.entry((chunk.kind, chunk.source_id)).or_insert(Hit {
+                chunk: *chunk,
+                score,
+            });
+            if score > entry.score {
+                *entry = Hit { chunk: *chunk, score };
+            }
+        }
+        let mut hits: Vec<Hit> = best.into_values().collect();
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+        hits.truncate(limit);
+        Ok(hits)
+    }
+}
+
+fn vector_to_blob(vector: &[f32]) -> Vec<u8> {
+    vector
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect()
+}
+
+fn vector_from_blob(blob: &[u8]) -> Vec<f32> {
+    blob.chunks_exact(4)
+        .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .collect()
+}
+
+/// Byte ranges of `text` of about `max_chars` each, cut at line ends where
+/// possible, else at a space. Blank pieces are left out.
+pub fn chunk_ranges(text: &str, max_chars: usize) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    while start < text.len() {
+        let rest = &text[start..];
+        let limit = rest
+            .char_indices()
+            .nth(max_chars)
+            .map_or(rest.len(), |(index, _)| index);
+        let end = if limit == rest.len() {
+            limit
+        } else {
+            let head = &rest[..limit];
+            head.rfind('\n')
+                .or_else(|| head.rfind(' '))
+                .filter(|&index| index > 0)
+                .map_or(limit, |index| index + 1)
+        };
+        if !rest[..end].trim().is_empty() {
+            ranges.push(start..start + end);
+        }
+        start += end;
+    }
+    ranges
+}
+
+/// A record's text split for embedding: what each piece is, and the text
+/// the model sees for it
+pub type Piece = (ChunkRef, String);
+
+/// Up to `BATCH` records of `kind` after `after` as pieces to embed, and
+/// the id of the last record read, `None` if there were none.
+pub fn pending_pieces(
+    kind: SourceKind,
+    after: i64,
+    timeline: &TimelineStore,
+    chats: &ChatStore
+) -> Result<(Vec<Piece>, Option<i64>)> {
+    let split = |source_id: i64, text: &str, prefix: &str| -> Vec<Piece> {
+        chunk_ranges(text, CHUNK_CHARS)
+            .into_iter()
+            .map(|range| {
+                let chunk = ChunkRef { kind, source_id, start: range.start, end: range.end };
+                (chunk, format!("{}{}", prefix, text[range].trim()))
+            })
+            .collect()
+    };
+    match kind {
+        SourceKind::Window => {
+            let windows = timeline.windows_after(after, BATCH)?;
+            let pieces = windows
+                .iter()
+                .flat_map(|window| split(window.id, &window.text, &format!("{}\n", window.title)))
+                .collect();
+            Ok((pieces, windows.last().map(|window| window.id)))
+        }
+        SourceKind::Segment => {
+            let segments = timeline.segments_after(after, BATCH)?;
+            let pieces = segments
+                .iter()
+                .flat_map(|segment| split(segment.id, &segment.text, ""))
+                .collect();
+            Ok((pieces, segments.last().map(|segment| segment.id)))
+        }
+        SourceKind::Message => {
+            let messages = chats.messages_after(after, BATCH)?;
+            let pieces = messages
+                .iter()
+                .flat_map(|message| split(message.id, &message.content, ""))
+                .collect();
+            Ok((pieces, messages.last().map(|message| message.id)))
+        }
+    }
+}
+
+/// Where the index and what it indexes are kept: the shared databases, or
+/// the check's temporary ones.
+pub trait Stores {
+    fn index<T>(&self, f: impl FnOnce(&mut EmbeddingIndex) -> Result<T>) -> Result<T>;
+    fn sources<T>(&self, f: impl FnOnce(&TimelineStore, &ChatStore) -> Result<T>) -> Result<T>;
+}
+
+/// `INDEX_DB_PATH`, the timeline and the chat history
+pub struct SharedStores;
+
+impl Stores for SharedStores {
+    fn index<T>(&self, f: impl FnOnce(&mut EmbeddingIndex) -> Result<T>) -> Result<T> {
+        with_index(f).map_err(|err| anyhow!(err))
+    }
+
+    // Always timeline, then chat store, so nothing waits on them the other
+    // way round
+    fn sources<T>(&self, f: impl FnOnce(&TimelineStore, &ChatStore) -> Result<T>) -> Result<T> {
+        with_timeline(|timeline| {
+            with_store(|chats| f(timeline, chats)).map_err(|err| anyhow!(err))
+        }).map_err(|err| anyhow!(err))
+    }
+}
+
+/// Embeds every record the index hasn't seen yet, after forgetting ones
+/// that were pruned or deleted. Store locks aren't held while embedding.
+/// Returns how many pieces were embedded.
+pub fn catch_up(embedder: &Embedder, stores: &impl Stores) -> Result<usize> {
+    let (windows, segments, messages) = stores.sources(|timeline, chats| {
+        Ok((timeline.window_ids()?, timeline.segment_ids()?, chats.message_ids()?))
+    })?;
+    stores.index(|index| {
+        index.retain(SourceKind::Window, &windows.into_iter().collect())?;
+        index.retain(SourceKind::Segment, &segments.into_iter().collect())?;
+        index.retain(SourceKind::Message, &messages.into_iter().collect())?;
+        Ok(())
+    })?;
+
+    let mut embedded = 0;
+    for kind in SourceKind::ALL {
+        loop {
+            let after = stores.index(|index| index.indexed_up_to(&embedder.id, kind))?;
+            let (pieces, last) = stores.sources(|timeline, chats| {
+                pending_pieces(kind, after, timeline, chats)
+            })?;
+            let Some(last) = last else {
+                break;
+            };
+            let chunks = pieces
+                .into_iter()
+                .map(|(chunk, text)| Ok((chunk, embedder.embed(&text)?)))
+                .collect::<Result<Vec<_>>>()?;
+            embedded += chunks.len();
+            stores.index(|index| index.add(&embedder.id, kind, last, &chunks))?;
+        }
+    }
+    Ok(embedded)
+}
+
+/// The `top_k` stored snippets closest to `question`, leaving out what
+/// `present` says the request already has.
+pub fn recall(
+    embedder: &Embedder,
+    stores: &impl Stores,
+    question: &str,
+    top_k: usize,
+    present: &Present
+) -> Result<Vec<RecalledSnippet>> {
+    if question.trim().is_empty() || top_k == 0 {
+        return Ok(Vec::new());
+    }
+    let query = embedder.embed(question)?;
+    let hits = stores.index(|index| {
+        index.search(&embedder.id, &query, top_k * CANDIDATES_PER_RESULT)
+    })?;
+    stores.sources(|timeline, chats| {
+        let mut snippets = Vec::new();
+        for hit in hits {
+            if let Some(snippet) = resolve(&hit, timeline, chats, present)? {
+                snippets.push(snippet);
+                if snippets.len() == top_k {
+                    break;
+                }
+            }
+        }
+        Ok(snippets)
+    })
+}
+
+/// The hit's text where it's stored, `None` if it's gone since or already
+/// in the request.
+fn resolve(
+    hit: &Hit,
+    timeline: &TimelineStore,
+    chats: &ChatStore,
+    present: &Present
+) -> Result<Option<RecalledSnippet>> {
+    let chunk = hit.chunk;
+    let piece = |text: &str| text.get(chunk.start..chunk.end).map(|piece| piece.trim().to_string());
+    let snippet = |source: String, at: DateTime<Utc>, text: Option<String>| {
+        text.map(|text| RecalledSnippet {
+            kind: chunk.kind,
+            source_id: chunk.source_id,
+            source,
+            at,
+            text,
+            score: hit.score,
+        })
+    };
+    let snippet = match chunk.kind {
+        SourceKind::Window => {
+            let Some(window) = timeline.window(chunk.source_id)? else {
+                return Ok(None);
+            };
+            if present.screen_since.is_some_and(|since| window.last_seen >= since) {
+                return Ok(None);
+            }
+            let source = if window.process_name.is_empty() {
+                window.title.clone()
+            } else {
+                format!("{} ({})", window.title, window.process_name)
+            };
+            snippet(source, window.last_seen, piece(&window.text))
+        }
+        SourceKind::Segment => {
+            let Some(segment) = timeline.segment(chunk.source_id)? else {
+                return Ok(None);
+            };
+            if present.audio_since.is_some_and(|since| segment.end >= since) {
+                return Ok(None);
+            }
+            // Voice numbers start over with each chat, so they only say
+            // the speaker differed from the others of the time
+            let source = match segment.voice {
+                Some(voice) => format!("Speaker {}", voice),
+                None => segment.speaker.clone(),
+            };
+            snippet(source, segment.start, piece(&segment.text))
+        }
+        SourceKind::Message => {
+            let Some((session, message)) = chats.message(chunk.source_id)? else {
+                return Ok(None);
+            };
+            if present.session_id == Some(session.id) {
+                return Ok(None);
+            }
+            let source = format!("chat \"{}\", {}", session.title, message.role);
+            snippet(source, message.created_at, piece(&message.content))
+        }
+    };
+    Ok(snippet)
+}
+
+lazy_static! {
+    static ref EMBEDDING_INDEX: SyncMutex<Option<EmbeddingIndex>> = SyncMutex::new(None);
+}
+
+/// Runs `f` on the index at `INDEX_DB_PATH`, opening it on first use.
+pub fn with_index<T>(f: impl FnOnce(&mut EmbeddingIndex) -> Result<T>) -> Result<T, String> {
+    let mut index = EMBEDDING_INDEX.lock().map_err(|err|
+        format!("Failed to lock embedding index: {}", err)
+    )?;
+    if index.is_none() {
+        *index = Some(
+            EmbeddingIndex::open(Path::new(INDEX_DB_PATH)).map_err(|err|
+                format!("Failed to open embedding index: {}", err)
+            )?
+        );
+    }
+    f(index.as_mut().unwrap()).map_err(|err| err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use anyhow::{ anyhow, Result };
+    use chrono::{ DateTime, Duration, TimeZone, Utc };
+    use tokenizers::models::wordlevel::WordLevel;
+    use tokenizers::normalizers::Lowercase;
+    use tokenizers::pre_tokenizers::whitespace::Whitespace;
+    use tokenizers::Tokenizer;
+    use tract_onnx::pb::{
+        tensor_shape_proto,
+        type_proto,
+        GraphProto,
+        ModelProto,
+        NodeProto,
+        OperatorSetIdProto,
+        TensorProto,
+        TensorShapeProto,
+        TypeProto,
+        ValueInfoProto,
+    };
+    use tract_onnx::pb::tensor_shape_proto::dimension::Value::DimParam;
+    use tract_onnx::prelude::*;
+
+    use crate::context::audio::transcript::{ Speaker, TranscriptSegment };
+    use crate::context::timeline::TimelineStore;
+    use crate::history::ChatStore;
+    use crate::llm::ChatMessage;
+    use crate::test_support::{ window, TempDatabase };
+    use super::*;
+
+    /// Words of the fixture vocabulary by the topic dimension they point along.
+    /// Filler words point weakly along the last one, anything else is unknown
+    /// and embeds to zero.
+    const TOPICS: [&[&str]; 4] = [
+        &["cargo", "build", "error", "borrow", "checker", "compile", "rust", "terminal"],
+        &["deadline", "friday", "thursday", "ship", "release", "schedule"],
+        &["lunch", "pizza", "salad", "menu", "hungry"],
+        &[
+            "the", "a", "to", "of", "what", "did", "they", "say", "about", "that", "in", "my",
+            "how", "do", "i", "fix", "this", "then", "we", "moved", "value", "from", "earlier",
+            "docs",
+        ],
+    ];
+    const FILLER_WEIGHT: f32 = 0.3;
+
+    /// A sentence encoder small enough to write down: a word-level tokenizer
+    /// and an ONNX graph that looks token vectors up in a table (`Gather`),
+    /// shaped like a BERT export with `input_ids` and `attention_mask` in and
+    /// per-token `last_hidden_state` out.
+    fn fixture_embedder(id: &str) -> Result<Embedder> {
+        let mut vocab = HashMap::from([("[PAD]".to_string(), 0u32), ("[UNK]".to_string(), 1)]);
+        let mut table = vec![0f32; 2 * TOPICS.len()];
+        for (dimension, words) in TOPICS.iter().enumerate() {
+            for word in words.iter() {
+                vocab.insert(word.to_string(), vocab.len() as u32);
+                let mut row = vec![0f32; TOPICS.len()];
+                row[dimension] = if dimension == TOPICS.len() - 1 { FILLER_WEIGHT } else { 1.0 };
+                table.extend(row);
+            }
+        }
+        let model = WordLevel::builder()
+            .vocab(vocab.clone())
+            .unk_token("[UNK]".to_string())
+            .build()
+            .map_err(|err| anyhow!("Failed to build fixture tokenizer: {}", err))?;
+        let mut tokenizer = Tokenizer::new(model);
+        tokenizer.with_normalizer(Lowercase);
+        tokenizer.with_pre_tokenizer(Whitespace {});
+
+        let value_info = |name: &str, elem_type: i32, dims: &[&str]| ValueInfoProto {
+            name: name.to_string(),
+            r#type: Some(TypeProto {
+                value: Some(
+                    type_proto::Value::TensorType(type_proto::Tensor {
+                        elem_type,
+                        shape: Some(TensorShapeProto {
+                            dim: dims
+                                .iter()
+                                .map(|dim| tensor_shape_proto::Dimension {
+                                    value: Some(DimParam(dim.to_string())),
+                                    ..Default::default()
+                                })
+                                .collect(),
+                        }),
+                    })
+                ),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        const FLOAT: i32 = 1;
+        const INT64: i32 = 7;
+        let proto = ModelProto {
+            ir_version: 8,
+            opset_import: vec![OperatorSetIdProto { domain: String::new(), version: 13 }],
+            graph: Some(GraphProto {
+                name: "fixture".to_string(),
+                node: vec![NodeProto {
+                    name: "lookup".to_string(),
+                    op_type: "Gather".to_string(),
+                    input: vec!["embeddings".to_string(), "input_ids".to_string()],
+                    output: vec!["last_hidden_state".to_string()],
+                    ..Default::default()
+                }],
+                initializer: vec![TensorProto {
+                    name: "embeddings".to_string(),
+                    dims: vec![vocab.len() as i64, TOPICS.len() as i64],
+                    data_type: FLOAT,
+                    float_data: table,
+                    ..Default::default()
+                }],
+                input: vec![
+                    value_info("input_ids", INT64, &["batch", "sequence"]),
+                    value_info("attention_mask", INT64, &["batch", "sequence"])
+                ],
+                output: vec![
+                    value_info("last_hidden_state", FLOAT, &["batch", "sequence", "hidden"])
+                ],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let model = tract_onnx::onnx().model_for_proto_model(&proto)?;
+        Embedder::from_parts(model, tokenizer, id.to_string())
+    }
+
+    /// The tests' own databases
+    struct LocalStores {
+        index: RefCell<EmbeddingIndex>,
+        timeline: RefCell<TimelineStore>,
+        chats: RefCell<ChatStore>,
+    }
+
+    impl Stores for LocalStores {
+        fn index<T>(&self, f: impl FnOnce(&mut EmbeddingIndex) -> Result<T>) -> Result<T> {
+            f(&mut self.index.borrow_mut())
+        }
+
+        fn sources<T>(&self, f: impl FnOnce(&TimelineStore, &ChatStore) -> Result<T>) -> Result<T> {
+            f(&self.timeline.borrow(), &self.chats.borrow())
+        }
+    }
+
+    fn segment(start: DateTime<Utc>, speaker: Speaker, text: &str) -> TranscriptSegment {
+        TranscriptSegment {
+            start,
+            end: start + Duration::seconds(4),
+            speaker,
+            voice: None,
+            text: text.to_string(),
+            language: Some("en".to_string()),
+        }
+    }
+
+    fn describe(snippets: &[RecalledSnippet]) -> String {
+        format!(
+            "{:?}",
+            snippets
+                .iter()
+                .map(|snippet| {
+                    format!("{} {:.2} {}", snippet.kind.as_str(), snippet.score, snippet.text)
+                })
+                .collect::<Vec<_>>()
+        )
+    }
+
+    fn yesterday(m: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 5, 6, 14, m, 0).unwrap()
+    }
+
+    fn today(m: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 5, 7, 9, m, 0).unwrap()
+    }
+
+    /// Yesterday: a build error on screen, a lunch menu, a meeting and a chat,
+    /// indexed with the fixture model
+    struct Fixture {
+        embedder: Embedder,
+        stores: LocalStores,
+        /// The chat about the borrow checker
+        session: i64,
+        /// Pieces the first catch-up indexed
+        indexed: usize,
+        index_database: TempDatabase,
+        _timeline_database: TempDatabase,
+        _chat_database: TempDatabase,
+    }
+
+    impl Fixture {
+        fn new() -> Self {
+            let timeline_database = TempDatabase::new("retrieval-timeline");
+            let mut timeline = TimelineStore::open(timeline_database.path()).unwrap();
+            timeline
+                .record_screen(yesterday(0), &[
+                    window(
+                        "Terminal",
+                        "WindowsTerminal.exe",
+                        "$ cargo build\nerror[E0382]: borrow of moved value",
+                        yesterday(0)
+                    ),
+                    window("Docs - Browser", "firefox", "Lunch menu: pizza, salad", yesterday(0)),
+                ])
+                .unwrap();
+            timeline
+                .record_segments(
+                    &[
+                        segment(yesterday(5), Speaker::Others, " The deadline moved to Friday."),
+                        segment(yesterday(6), Speaker::Me, " Then we ship Thursday."),
+                    ]
+                )
+                .unwrap();
+            let chat_database = TempDatabase::new("retrieval-chats");
+            let mut chats = ChatStore::open(chat_database.path()).unwrap();
+            let session = chats.create_session("How do I fix this borrow checker error?").unwrap();
+            let question = ChatMessage::new("user", "How do I fix this borrow checker error?");
+            chats.add_message(session, &question, None).unwrap();
+            let index_database = TempDatabase::new("retrieval-index");
+            let stores = LocalStores {
+                index: RefCell::new(EmbeddingIndex::open(index_database.path()).unwrap()),
+                timeline: RefCell::new(timeline),
+                chats: RefCell::new(chats),
+            };
+
+            let embedder = fixture_embedder("fixture").unwrap();
+            let indexed = catch_up(&embedder, &stores).unwrap();
+            Self {
+                embedder,
+                stores,
+                session,
+                indexed,
+                index_database,
+                _timeline_database: timeline_database,
+                _chat_database: chat_database,
+            }
+        }
+
+        fn recall(&self, question: &str, limit: usize, present: &Present) -> Vec<RecalledSnippet> {
+            recall(&self.embedder, &self.stores, question, limit, present).unwrap()
+        }
+
+        fn catch_up(&self) -> usize {
+            catch_up(&self.embedder, &self.stores).unwrap()
+        }
+
+        /// Yesterday's screen and speech are forgotten
+        fn prune_yesterday(&self) {
+            self.stores.timeline.borrow().prune(today(0)).unwrap();
+            self.catch_up();
+        }
+    }
+
+    // Retrieval end to end with a tiny fixture model over temporary databases
+
+    #[test]
+    fn vectors_are_unit_length_and_closer_for_related_text() {
+        let embedder = fixture_embedder("fixture").unwrap();
+        let build = embedder.embed("error: cargo build failed").unwrap();
+        let borrow = embedder.embed("How do I fix this borrow checker error?").unwrap();
+        let lunch = embedder.embed("Lunch menu: pizza, salad").unwrap();
+        let dot = |a: &[f32], b: &[f32]| a.iter().zip(b).map(|(a, b)| a * b).sum::<f32>();
+        let norm = dot(&build, &build).sqrt();
+        assert!((norm - 1.0).abs() < 1e-4, "norm {:.5}", norm);
+        assert!(
+            dot(&build, &borrow) > dot(&build, &lunch) + 0.5,
+            "{:.2} vs {:.2}",
+            dot(&build, &borrow),
+            dot(&build, &lunch)
+        );
+    }
+
+    #[test]
+    fn long_text_splits_at_line_ends() {
+        let long = "rustc output line with ümlauts and more words\n".repeat(60);
+        let ranges = chunk_ranges(&long, CHUNK_CHARS);
+        assert!(ranges.len() > 1);
+        assert_eq!(ranges.first().map(|range| range.start), Some(0));
+        assert_eq!(ranges.last().map(|range| range.end), Some(long.len()));
+        assert!(ranges.windows(2).all(|pair| pair[0].end == pair[1].start), "{:?}", ranges);
+        for range in ranges {
+            assert!(long[range.clone()].chars().count() <= CHUNK_CHARS, "{:?}", range);
+            assert!(long[range.clone()].ends_with('\n'), "{:?}", range);
+        }
+    }
+
+    #[test]
+    fn records_are_indexed_once() {
+        let fixture = Fixture::new();
+        assert_eq!(fixture.indexed, 5);
+        assert_eq!(fixture.catch_up(), 0);
+    }
+
+    #[test]
+    fn questions_recall_speech_screen_text_and_chat_turns() {
+        let fixture = Fixture::new();
+        let question = "what did they say about the deadline";
+        let recalled = fixture.recall(question, 1, &Present::default());
+        assert_eq!(recalled.len(), 1, "{}", describe(&recalled));
+        assert_eq!(recalled[0].kind, SourceKind::Segment);
+        assert_eq!(recalled[0].text, "The deadline moved to Friday.");
+        assert_eq!(recalled[0].source, "others");
+
+        let recalled = fixture.recall("that borrow error in my terminal", 2, &Present::default());
+        let kinds: Vec<SourceKind> = recalled
+            .iter()
+            .map(|snippet| snippet.kind)
+            .collect();
+        assert!(kinds.contains(&SourceKind::Window), "{}", describe(&recalled));
+        assert!(kinds.contains(&SourceKind::Message), "{}", describe(&recalled));
+        for snippet in &recalled {
+            assert!(snippet.text.contains("borrow"), "{}", describe(&recalled));
+        }
+    }
+
+    #[test]
+    fn what_the_request_has_is_left_out() {
+        let fixture = Fixture::new();
+        let present = Present {
+            session_id: Some(fixture.session),
+            screen_since: Some(yesterday(0)),
+            audio_since: None,
+        };
+        let recalled = fixture.recall("that borrow error in my terminal", 2, &present);
+        for snippet in &recalled {
+            assert_ne!(snippet.text, "How do I fix this borrow checker error?");
+            assert_ne!(snippet.kind, SourceKind::Window);
+        }
+    }
+
+    #[test]
+    fn pruned_records_are_forgotten() {
+        let fixture = Fixture::new();
+        fixture.prune_yesterday();
+        let recalled = fixture.recall("lunch pizza", 3, &Present::default());
+        assert!(
+            recalled.iter().all(|snippet| snippet.kind == SourceKind::Message),
+            "{}",
+            describe(&recalled)
+        );
+    }
+
+    #[test]
+    fn reopened_index_keeps_up() {
+        let mut fixture = Fixture::new();
+        fixture.prune_yesterday();
+        // Today: new speech, found after the index is reopened
+        let hungry = [segment(today(1), Speaker::Others, " Anyone hungry?")];
+        fixture.stores.timeline.borrow_mut().record_segments(&hungry).unwrap();
+        let reopened = EmbeddingIndex::open(fixture.index_database.path()).unwrap();
+        fixture.stores.index = RefCell::new(reopened);
+        assert_eq!(fixture.catch_up(), 1);
+        let recalled = fixture.recall("lunch", 1, &Present::default());
+        assert_eq!(recalled.first().map(|snippet| snippet.text.as_str()), Some("Anyone hungry?"));
+    }
+
+    #[test]
+    fn deleted_chats_are_forgotten() {
+        let fixture = Fixture::new();
+        // The only chat is deleted and another started: its message must not
+        // pass for the deleted one
+        fixture.stores.chats.borrow().delete_session(fixture.session).unwrap();
+        {
+            let mut chats = fixture.stores.chats.borrow_mut();
+            let later = chats.create_session("Pizza for lunch?").unwrap();
+            chats.add_message(later, &ChatMessage::new("user", "Pizza for lunch?"), None).unwrap();
+        }
+        assert_eq!(fixture.catch_up(), 1);
+        let recalled = fixture.recall("pizza", 3, &Present::default());
+        let found = recalled.iter().any(|snippet| snippet.text == "Pizza for lunch?");
+        assert!(found, "{}", describe(&recalled));
+        for snippet in recalled.iter().filter(|snippet| snippet.kind == SourceKind::Message) {
+            assert!(!snippet.text.contains("borrow"), "{}", describe(&recalled));
+        }
+    }
+
+    #[test]
+    fn another_model_indexes_everything_anew() {
+        let fixture = Fixture::new();
+        let other = fixture_embedder("fixture-2").unwrap();
+        assert_eq!(catch_up(&other, &fixture.stores).unwrap(), fixture.indexed);
+    }
+}
@@ -1,235 +1,231 @@
-use win_screenshot::prelude::*;
-use std::time::Instant;
-use windows::Win32::Foundation::{ BOOL, HWND, LPARAM, RECT };
-use windows::Win32::UI::WindowsAndMessaging::{
-    EnumWindows,
-    GetClassNameW,
-    GetWindowTextLengthW,
-    GetWindowTextW,
-    IsWindowVisible,
-    GetClientRect,
-    GetWindowThreadProcessId,
-};
-use windows::Win32::System::Threading::GetCurrentProcessId;
-use std::fs;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::time::{ Duration, Instant };
+use std::sync::Arc;
 
 use tokio::runtime::Runtime;
 use anyhow::{ anyhow, Result, Error };
-use image::{ DynamicImage, RgbaImage };
-use tempfile::NamedTempFile;
+use chrono::{ DateTime, Utc };
+use serde::Serialize;
 use tokio::task;
-use windows::{
-    core::HSTRING,
-    Globalization::Language,
-    Graphics::Imaging::{ BitmapDecoder, SoftwareBitmap },
-    Media::Ocr::OcrEngine,
-    Storage::{ FileAccessMode, StorageFile },
-};
-
-use windows::core::HRESULT;
-
-const E_ACCESSDENIED: HRESULT = HRESULT(0x80070005u32 as i32);
-fn ocr(path: &str) -> windows::core::Result<String> {
-    let bitmap = open_image_as_bitmap(path)?;
-    let ocr_result = ocr_from_bitmap(bitmap)?;
-    Ok(ocr_result)
-}
-fn open_image_as_bitmap(path: &str) -> windows::core::Result<SoftwareBitmap> {
-    let path = fs::canonicalize(path);
-    let path = match path {
-        Ok(path) => path.to_string_lossy().replace("\\\\?\\", ""),
-        Err(_) => {
-            return Err(windows::core::Error::new(E_ACCESSDENIED, "Could not open file"));
-        }
-    };
 
-    let file = StorageFile::GetFileFromPathAsync(&HSTRING::from(path))?.get()?;
-
-    let bitmap = BitmapDecoder::CreateWithIdAsync(
-        BitmapDecoder::PngDecoderId()?,
-        &file.OpenAsync(FileAccessMode::Read)?.get()?
-    )?.get()?;
-
-    bitmap.GetSoftwareBitmapAsync()?.get()
+use crate::llm::budget::allocate;
+use super::render::{ window_header, TRUNCATION_MARKER };
+use super::focus::{ focus_ages, record_focus };
+use super::ocr::{ lines_to_text, OcrEngine };
+use super::screen_source::{ default_screen_source, ScreenSource, WindowBounds, WindowId, WindowInfo };
+
+/// A window with everything `rank_windows` ordered it by.
+#[derive(Debug, Clone, Serialize)]
+pub struct RankedWindow {
+    pub info: WindowInfo,
+    /// Pixels not covered by windows above it
+    pub visible_area: u64,
+    /// Time since the window last had focus, `None` if not seen focused
+    pub focus_age: Option<Duration>,
 }
 
-fn ocr_from_bitmap(bitmap: SoftwareBitmap) -> windows::core::Result<String> {
-    let lang = &OcrEngine::AvailableRecognizerLanguages()?.First()?.Current()?.LanguageTag()?;
-
-    let lang = Language::CreateLanguage(lang)?;
-    let engine = OcrEngine::TryCreateFromLanguage(&lang)?;
-
-    let lines = engine.RecognizeAsync(&bitmap)?.get()?.Lines()?;
-    let mut result = String::new();
-
-    for line in lines {
-        let line_text = line.Text()?.to_string_lossy();
-        result.push_str(&line_text);
-        result.push_str("\n");
-    }
-    Ok(result)
+/// What was on screen in one window, as handed to the model.
+#[derive(Debug, Clone, Serialize)]
+pub struct WindowContext {
+    pub title: String,
+    pub process_name: String,
+    pub bounds: WindowBounds,
+    pub focused: bool,
+    pub visible_area: u64,
+    /// Milliseconds since the window last had focus, `None` if not seen focused
+    pub focus_age_ms: Option<u64>,
+    /// OCR text, cut to the window's share of `max_chars`
+    pub text: String,
+    pub truncated: bool,
+    pub captured_at: DateTime<Utc>,
 }
 
-#[derive(Debug)]
-pub struct HwndName {
-    pub hwnd: isize,
-    pub window_name: String,
+/// Every window that produced text, most relevant first.
+#[derive(Debug, Clone, Serialize)]
+pub struct ScreenContext {
+    pub captured_at: DateTime<Utc>,
+    pub windows: Vec<WindowContext>,
 }
 
-#[derive(Debug)]
-pub enum WLError {
-    EnumWindowsError,
+/// OCR output of a window before it's fit to the budget.
+#[derive(Debug, Clone)]
+pub struct CapturedWindow {
+    pub window: RankedWindow,
+    pub text: String,
+    pub captured_at: DateTime<Utc>,
 }
 
-unsafe extern "system" fn wl_callback(hwnd: HWND, lparam: LPARAM) -> BOOL {
-    let vec = lparam.0 as *mut Vec<HwndName>;
-    if IsWindowVisible(hwnd) == false {
-        return BOOL::from(true);
-    }
-
-    let gwtl = GetWindowTextLengthW(hwnd);
-    if gwtl == 0 {
-        return BOOL::from(true);
-    }
-
-    let max_count = 256; // Example maximum count, adjust based on your needs
-
-    // Allocate memory for the class name buffer
-    let mut class_name_buffer: Vec<u16> = vec![0; max_count as usize];
-
-    // Pass the buffer as a mutable slice
-    let class_name_length = GetClassNameW(hwnd, &mut class_name_buffer[..]);
-    let class_name = String::from_utf16_lossy(&class_name_buffer[..class_name_length as usize])
-        .trim_end_matches('\0')
-        .to_string();
-    let ignore_class_names = vec!["Progman", "TaskManagerWindow", "Windows.UI.Core.CoreWindow"];
-    if ignore_class_names.contains(&class_name.as_str()) {
-        return BOOL::from(true);
-    }
-    if class_name.contains("HwndWrapper") {
-        return BOOL::from(true);
-    }
-    // println!("className: {}", class_name);
+/// Rendered length of a window besides its text
+fn window_overhead(info: &WindowInfo) -> usize {
+    window_header(&info.title, &info.process_name, info.focused).len() +
+        TRUNCATION_MARKER.len() +
+        2
+}
 
-    let gwtl = GetWindowTextLengthW(hwnd);
-    if gwtl == 0 {
-        return BOOL::from(true);
-    }
+/// Orders `windows` (topmost first) by focus, then how recently they had
+/// focus, then how much of them is visible. Ties keep z-order.
+pub fn rank_windows(
+    windows: Vec<WindowInfo>,
+    focus_ages: &HashMap<WindowId, Duration>
+) -> Vec<RankedWindow> {
+    let visible_areas = visible_areas(&windows);
+    let mut ranked: Vec<RankedWindow> = windows
+        .into_iter()
+        .zip(visible_areas)
+        .map(|(info, visible_area)| RankedWindow {
+            focus_age: focus_ages.get(&info.id).copied(),
+            info,
+            visible_area,
+        })
+        .collect();
+
+    ranked.sort_by_key(|window| (
+        !window.info.focused,
+        // Windows never seen focused sort after all remembered ones
+        window.focus_age.unwrap_or(Duration::MAX),
+        Reverse(window.visible_area),
+    ));
+    ranked
+}
 
-    let mut name_buf: Vec<u16> = vec![0; (gwtl + 1) as usize];
+/// Area of each window left after subtracting every window above it.
+fn visible_areas(windows: &[WindowInfo]) -> Vec<u64> {
+    windows
+        .iter()
+        .enumerate()
+        .map(|(i, window)| {
+            let covering: Vec<WindowBounds> = windows[..i]
+                .iter()
+                .filter_map(|above| intersect(&window.bounds, &above.bounds))
+                .collect();
+            window.bounds.area() - union_area(&covering)
+        })
+        .collect()
+}
 
-    let gwt = GetWindowTextW(hwnd, &mut name_buf);
-    if gwt == 0 {
-        return BOOL::from(true);
+fn intersect(a: &WindowBounds, b: &WindowBounds) -> Option<WindowBounds> {
+    let left = a.x.max(b.x);
+    let top = a.y.max(b.y);
+    let right = (a.x + (a.width as i32)).min(b.x + (b.width as i32));
+    let bottom = (a.y + (a.height as i32)).min(b.y + (b.height as i32));
+    if right <= left || bottom <= top {
+        return None;
     }
+    Some(WindowBounds {
+        x: left,
+        y: top,
+        width: (right - left) as u32,
+        height: (bottom - top) as u32,
+    })
+}
 
-    let name_buf = match name_buf.split_last() {
-        Some((_, last)) => last,
-        None => {
-            return BOOL::from(true);
+/// Area covered by overlapping rectangles, by splitting the plane into a
+/// grid at every rectangle edge.
+fn union_area(rects: &[WindowBounds]) -> u64 {
+    let mut xs: Vec<i32> = rects
+        .iter()
+        .flat_map(|r| [r.x, r.x + (r.width as i32)])
+        .collect();
+    let mut ys: Vec<i32> = rects
+        .iter()
+        .flat_map(|r| [r.y, r.y + (r.height as i32)])
+        .collect();
+    xs.sort_unstable();
+    xs.dedup();
+    ys.sort_unstable();
+    ys.dedup();
+
+    let mut area = 0;
+    for x in xs.windows(2) {
+        for y in ys.windows(2) {
+            let covered = rects
+                .iter()
+                .any(|r| {
+                    r.x <= x[0] &&
+                        x[1] <= r.x + (r.width as i32) &&
+                        r.y <= y[0] &&
+                        y[1] <= r.y + (r.height as i32)
+                });
+            if covered {
+                area += ((x[1] - x[0]) as u64) * ((y[1] - y[0]) as u64);
+            }
         }
-    };
-
-    let name = String::from_utf16_lossy(name_buf);
-
-    let ignore_names = vec!["Settings"];
-
-    if ignore_names.contains(&name.as_str()) {
-        return BOOL::from(true);
-    }
-    if name.contains("settings.html") {
-        return BOOL::from(true);
-    }
-    let mut rect = RECT::default(); // Use a zero-initialized RECT struct
-    if GetClientRect(hwnd, &mut rect).is_err() {
-        // println!("Failed to get client rectangle");
-        return BOOL::from(true);
     }
-
-    // Now you have the width and height in rect.right and rect.bottom
-    let width = rect.right as isize;
-    let height = rect.bottom as isize;
-
-    // println!("window dimensions: {}x{}", width, height);
-    if width * height < 10000 {
-        // if too low pixels means window not rendered~
-        return BOOL::from(true);
-    }
-
-    let current_process_id = GetCurrentProcessId();
-    let mut process_id: u32 = 0;
-
-    GetWindowThreadProcessId(hwnd, Some(&mut process_id));
-    if current_process_id == process_id {
-        // ignore itself window
-        return BOOL::from(true);
-    }
-    (*vec).push(HwndName {
-        hwnd: hwnd.0 as isize,
-        window_name: name,
-    });
-
-    BOOL::from(true)
+    area
 }
 
-pub fn get_window_list() -> Result<Vec<HwndName>, WLError> {
-    let mut hwnd_name = Vec::new();
-    unsafe {
-        EnumWindows(
-            Some(wl_callback),
-            LPARAM(&mut hwnd_name as *mut Vec<HwndName> as isize)
-        ).map_err(|_| WLError::EnumWindowsError)?;
-    }
-
-    Ok(hwnd_name)
+/// Cuts each window's text so the rendered context fits in `max_chars`.
+/// Windows that fit their share are kept whole and the rest split what's
+/// left, higher ranked windows getting a larger share.
+pub fn fit_to_budget(windows: Vec<CapturedWindow>, max_chars: usize) -> ScreenContext {
+    let requested: Vec<usize> = windows
+        .iter()
+        .map(|captured| window_overhead(&captured.window.info) + captured.text.len())
+        .collect();
+    let weights: Vec<f32> = (0..windows.len()).map(|rank| 1.0 / ((rank + 1) as f32)).collect();
+    let allotted = allocate(max_chars, &requested, &weights);
+
+    let windows = windows
+        .into_iter()
+        .zip(allotted)
+        .filter_map(|(captured, allotted)| {
+            let overhead = window_overhead(&captured.window.info);
+            // Not even room for the title
+            if allotted <= overhead {
+                return None;
+            }
+            let CapturedWindow { window, text, captured_at } = captured;
+            let full_len = text.len();
+            let text = truncate_to_char_boundary(text, allotted - overhead);
+            Some(WindowContext {
+                truncated: text.len() < full_len,
+                text,
+                title: window.info.title,
+                process_name: window.info.process_name,
+                bounds: window.info.bounds,
+                focused: window.info.focused,
+                visible_area: window.visible_area,
+                focus_age_ms: window.focus_age.map(|age| age.as_millis() as u64),
+                captured_at,
+            })
+        })
+        .collect();
+
+    ScreenContext { captured_at: Utc::now(), windows }
 }
 
-#[derive(Debug, Clone)]
-pub struct WindowContent {
-    pub title: String,
-    pub content: String,
+fn truncate_to_char_boundary(mut text: String, max_len: usize) -> String {
+    if text.len() > max_len {
+        let mut end = max_len;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+    }
+    text
 }
 
-async fn extract_text(window_info: HwndName) -> Option<WindowContent> {
-    let hwnd = window_info.hwnd;
-    println!("window: {}, hwnd: {}", window_info.window_name, hwnd);
+async fn extract_text(
+    source: Arc<dyn ScreenSource>,
+    ocr: Arc<dyn OcrEngine>,
+    window_info: WindowInfo
+) -> Option<(String, DateTime<Utc>)> {
+    println!("window: {}, id: {}", window_info.title, window_info.id);
 
     // Attempt to capture the window
-    let buf = match capture_window(hwnd) {
-        Ok(buf) => buf,
+    let captured_at = Utc::now();
+    let image = match source.capture_window(&window_info) {
+        Ok(image) => image,
         Err(e) => {
             println!("Error capturing window: {}", e);
             return None;
         }
     };
 
-    let img = DynamicImage::ImageRgba8(
-        RgbaImage::from_raw(buf.width, buf.height, buf.pixels).unwrap()
-    ).to_rgb8();
-    let mut temp_file = match NamedTempFile::new() {
-        Ok(file) => file,
-        Err(e) => {
-            println!("Failed to create temp file: {}", e);
-            return None;
-        }
-    };
-
-    // Save the image to the temporary file
-    match img.write_to(&mut temp_file, image::ImageFormat::Png) {
-        Ok(_) => (),
-        Err(e) => {
-            println!("Failed to write image to temp file: {}", e);
-            return None;
-        }
-    }
-
-    // Attempt to extract text from the bitmap
-    match ocr(temp_file.path().display().to_string().as_str()) {
-        Ok(text) =>
-            Some(WindowContent {
-                title: window_info.window_name,
-                content: text,
-            }),
+    // Attempt to extract text from the capture
+    match ocr.recognize(&image) {
+        Ok(lines) => Some((lines_to_text(&lines), captured_at)),
         Err(e) => {
             println!("Error extracting text from bitmap: {}", e);
             None
@@ -237,48 +233,259 @@ async fn extract_text(window_info: HwndName) -> Option<WindowContent> {
     }
 }
 
-async fn process_windows() -> Result<Vec<WindowContent>, Error> {
-    let window_list = get_window_list().map_err(|e| {
-        anyhow!("Failed to get window list: {:?}", e)
-    })?;
-
-    let mut tasks = Vec::with_capacity(window_list.len());
+async fn process_windows(
+    source: Arc<dyn ScreenSource>,
+    ocr: Arc<dyn OcrEngine>,
+    windows: Vec<RankedWindow>
+) -> Result<Vec<CapturedWindow>, Error> {
+    let mut tasks = Vec::with_capacity(windows.len());
 
     // Spawn a task for each window and store the join handles in the tasks vector
-    for window_info in window_list {
-        let task = task::spawn(async move { extract_text(window_info).await });
+    for window in &windows {
+        let source = Arc::clone(&source);
+        let ocr = Arc::clone(&ocr);
+        let window_info = window.info.clone();
+        let task = task::spawn(async move { extract_text(source, ocr, window_info).await });
         tasks.push(task);
     }
 
-    // Await all futures concurrently
+    // Await all futures concurrently, results come back in ranking order
     let results = futures::future::try_join_all(tasks).await?;
 
-    Ok(results.into_iter().flatten().collect())
+    Ok(
+        windows
+            .into_iter()
+            .zip(results)
+            .filter_map(|(window, result)| {
+                result.map(|(text, captured_at)| CapturedWindow { window, text, captured_at })
+            })
+            .collect()
+    )
+}
+
+/// OCRs the visible windows and fits their text into `max_chars`. With
+/// `window_filter`, only windows whose title or process name contains it
+/// (case-insensitive) are read, and they get the whole budget.
+pub fn get_screen(
+    max_chars: usize,
+    ocr: Arc<dyn OcrEngine>,
+    window_filter: Option<&str>
+) -> Result<ScreenContext, Error> {
+    let source: Arc<dyn ScreenSource> = Arc::from(default_screen_source()?);
+    read_screen(source, max_chars, ocr, window_filter)
 }
 
-pub fn get_screen(max_chars: usize) -> Result<String, Error> {
+/// `get_screen` on a given source
+fn read_screen(
+    source: Arc<dyn ScreenSource>,
+    max_chars: usize,
+    ocr: Arc<dyn OcrEngine>,
+    window_filter: Option<&str>
+) -> Result<ScreenContext, Error> {
     let start_time = Instant::now();
 
+    let window_list = source.list_windows().map_err(|e| {
+        anyhow!("Failed to get window list: {:?}", e)
+    })?;
+    if let Some(focused) = window_list.iter().find(|window| window.focused) {
+        record_focus(focused.id);
+    }
+    let mut ranked = rank_windows(window_list, &focus_ages());
+    if let Some(filter) = window_filter {
+        // After ranking, so windows left out still count as covering others
+        let filter = filter.to_lowercase();
+        ranked.retain(|window| {
+            window.info.title.to_lowercase().contains(&filter) ||
+                window.info.process_name.to_lowercase().contains(&filter)
+        });
+    }
+
     let rt = Runtime::new()?;
-    let window_contents = rt.block_on(async { process_windows().await })?;
+    let captured = rt.block_on(async { process_windows(source, ocr, ranked).await })?;
 
     let duration = start_time.elapsed();
     println!("Time taken: {:?}", duration);
 
-    let mut combined_content = String::new();
+    Ok(fit_to_budget(captured, max_chars))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use image::RgbaImage;
+
+    use crate::test_support::write_image;
+    use super::super::ocr::OcrLine;
+    use super::super::screen_source::ReplayScreenSource;
+    use super::*;
+
+    fn window(
+        id: WindowId,
+        (x, y, width, height): (i32, i32, u32, u32),
+        focused: bool
+    ) -> WindowInfo {
+        WindowInfo {
+            id,
+            title: format!("Window {}", id),
+            process_id: 0,
+            process_name: String::new(),
+            bounds: WindowBounds { x, y, width, height },
+            focused,
+        }
+    }
+
+    fn ids(ranked: &[RankedWindow]) -> Vec<WindowId> {
+        ranked
+            .iter()
+            .map(|window| window.info.id)
+            .collect()
+    }
+
+    /// `text_len` characters of OCR text for each window, ranked as listed
+    fn captured(text_lens: &[usize]) -> Vec<CapturedWindow> {
+        text_lens
+            .iter()
+            .enumerate()
+            .map(|(i, &text_len)| {
+                let info = window(i as WindowId, (0, 0, 100, 100), i == 0);
+                CapturedWindow {
+                    window: RankedWindow { info, visible_area: 10000, focus_age: None },
+                    text: "x".repeat(text_len),
+                    captured_at: Utc::now(),
+                }
+            })
+            .collect()
+    }
+
+    /// Characters each window takes up once rendered
+    fn rendered_lens(screen: &ScreenContext) -> Vec<usize> {
+        screen.windows
+            .iter()
+            .map(|window| {
+                window_header(&window.title, &window.process_name, window.focused).len() +
+                    TRUNCATION_MARKER.len() +
+                    2 +
+                    window.text.len()
+            })
+            .collect()
+    }
 
-    for window_content in window_contents.into_iter() {
-        // Calculate the length of the next piece of content to be added
-        let next_content = format!("{}:\n{}\n\n", window_content.title, window_content.content);
+    #[test]
+    fn focus_beats_recent_focus_beats_visible_area() {
+        // Topmost first, none overlapping
+        let windows = vec![
+            window(1, (0, 0, 500, 500), false),
+            window(2, (600, 0, 100, 100), true),
+            window(3, (800, 0, 200, 200), false),
+            window(4, (0, 600, 300, 300), false),
+            window(5, (400, 600, 100, 100), false),
+            window(6, (600, 600, 100, 100), false)
+        ];
+        let focus_ages = HashMap::from([
+            (2, Duration::ZERO),
+            (3, Duration::from_secs(60)),
+            (4, Duration::from_secs(5)),
+        ]);
+        let ranked = rank_windows(windows, &focus_ages);
+        // Never focused ones by visible area, ties in z-order
+        assert_eq!(ids(&ranked), [2, 4, 3, 1, 5, 6]);
+        assert_eq!(ranked[1].focus_age, Some(Duration::from_secs(5)));
+        assert_eq!(ranked[3].focus_age, None);
+    }
+
+    #[test]
+    fn occluded_windows_rank_last() {
+        let windows = vec![
+            window(1, (0, 0, 1000, 1000), false),
+            // Hidden under 1
+            window(2, (100, 100, 800, 800), false),
+            // A quarter under 1
+            window(3, (900, 900, 200, 200), false),
+            window(4, (1200, 0, 50, 50), false),
+            // Under 1 and 3, which overlap each other
+            window(5, (950, 950, 100, 100), false)
+        ];
+        let ranked = rank_windows(windows, &HashMap::new());
+        assert_eq!(ids(&ranked), [1, 3, 4, 2, 5]);
+        let visible: Vec<u64> = ranked
+            .iter()
+            .map(|window| window.visible_area)
+            .collect();
+        assert_eq!(visible, [1_000_000, 30_000, 2_500, 0, 0]);
+    }
 
-        // Check if adding this content would exceed the max chars limit
-        if combined_content.len() + next_content.len() > max_chars {
-            break; // Stop adding more content
+    #[test]
+    fn budget_is_split_by_rank() {
+        let screen = fit_to_budget(captured(&[5000, 5000, 5000]), 1100);
+        assert!(screen.windows.iter().all(|window| window.truncated));
+        // Shares of 1, 1/2 and 1/3, give or take rounding
+        let lens = rendered_lens(&screen);
+        for (len, share) in lens.iter().zip([600, 300, 200]) {
+            assert!(len.abs_diff(share) <= 1, "{:?}", lens);
         }
+    }
+
+    #[test]
+    fn unused_share_goes_to_lower_ranks() {
+        let screen = fit_to_budget(captured(&[10, 5000, 5000]), 1100);
+        let lens = rendered_lens(&screen);
+        assert!(!screen.windows[0].truncated);
+        assert!(screen.windows[1].truncated && screen.windows[2].truncated);
+        // What the first didn't need is split 1/2 : 1/3
+        let rest = 1100 - lens[0];
+        assert!(lens[1].abs_diff((rest * 3) / 5) <= 1, "{:?}", lens);
+        assert!(lens[2].abs_diff((rest * 2) / 5) <= 1, "{:?}", lens);
+    }
 
-        // Add the content and update the total character count
-        combined_content.push_str(&next_content);
+    #[test]
+    fn windows_without_room_for_a_title_are_left_out() {
+        let overhead = window_overhead(&captured(&[0])[0].window.info);
+        let screen = fit_to_budget(captured(&[5000, 5000, 5000]), overhead * 2);
+        assert_eq!(screen.windows.len(), 1);
+        assert_eq!(screen.windows[0].title, "Window 0");
     }
 
-    Ok(combined_content)
+    /// Reads each image's size back as its text
+    struct SizeOcr;
+
+    impl OcrEngine for SizeOcr {
+        fn recognize(&self, image: &RgbaImage) -> Result<Vec<OcrLine>> {
+            Ok(
+                vec![OcrLine {
+                    text: format!("{}x{}", image.width(), image.height()),
+                    confidence: None,
+                }]
+            )
+        }
+    }
+
+    #[test]
+    fn read_screen_runs_headless_on_a_replay() {
+        let dir = tempfile::tempdir().unwrap();
+        write_image(&dir.path().join("browser.png"), 200, 100);
+        write_image(&dir.path().join("terminal.png"), 400, 300);
+        let manifest =
+            r#"[
+            { "title": "Browser", "image": "browser.png" },
+            { "title": "Terminal", "image": "terminal.png", "focused": true }
+        ]"#;
+        fs::write(dir.path().join("windows.json"), manifest).unwrap();
+        let source: Arc<dyn ScreenSource> = Arc::new(ReplayScreenSource::open(dir.path()).unwrap());
+
+        let screen = read_screen(source.clone(), 10_000, Arc::new(SizeOcr), None).unwrap();
+        let windows: Vec<(&str, &str, bool)> = screen.windows
+            .iter()
+            .map(|window| (window.title.as_str(), window.text.as_str(), window.focused))
+            .collect();
+        // The focused window comes first though it's below in z-order
+        assert_eq!(windows, [("Terminal", "400x300\n", true), ("Browser", "200x100\n", false)]);
+
+        // Only matching windows are read
+        let screen = read_screen(source, 10_000, Arc::new(SizeOcr), Some("BROWSER")).unwrap();
+        let windows: Vec<(&str, &str)> = screen.windows
+            .iter()
+            .map(|window| (window.title.as_str(), window.text.as_str()))
+            .collect();
+        assert_eq!(windows, [("Browser", "200x100\n")]);
+    }
 }
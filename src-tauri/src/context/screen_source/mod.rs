@@ -0,0 +1,91 @@
+use std::path::PathBuf;
+use std::sync::Mutex as SyncMutex;
+use anyhow::{ anyhow, Result };
+use image::RgbaImage;
+use serde::{ Deserialize, Serialize };
+
+mod replay;
+#[cfg(windows)]
+mod win32;
+#[cfg(target_os = "linux")]
+mod x11;
+
+pub use replay::ReplayScreenSource;
+#[cfg(windows)]
+pub use win32::Win32ScreenSource;
+#[cfg(target_os = "linux")]
+pub use x11::X11ScreenSource;
+
+pub type WindowId = u64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct WindowBounds {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl WindowBounds {
+    pub fn area(&self) -> u64 {
+        (self.width as u64) * (self.height as u64)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WindowInfo {
+    pub id: WindowId,
+    pub title: String,
+    pub process_id: u32,
+    /// Executable name without extension, empty if unknown
+    pub process_name: String,
+    pub bounds: WindowBounds,
+    pub focused: bool,
+}
+
+/// Platform backend for everything `get_screen` needs from the desktop.
+///
+/// `list_windows` returns visible top-level windows of other processes,
+/// topmost first.
+pub trait ScreenSource: Send + Sync {
+    fn list_windows(&self) -> Result<Vec<WindowInfo>>;
+    fn capture_window(&self, window: &WindowInfo) -> Result<RgbaImage>;
+    fn focused_window(&self) -> Result<Option<WindowId>>;
+}
+
+lazy_static! {
+    static ref REPLAY_DIR: SyncMutex<Option<PathBuf>> = SyncMutex::new(None);
+}
+
+/// Makes `default_screen_source` play back `dir` instead of the desktop.
+/// Set once at startup (`--replay-screen <dir>`).
+pub fn set_replay_dir(dir: PathBuf) {
+    if let Ok(mut replay_dir) = REPLAY_DIR.lock() {
+        *replay_dir = Some(dir);
+    }
+}
+
+pub fn default_screen_source() -> Result<Box<dyn ScreenSource>> {
+    let replay_dir = REPLAY_DIR.lock()
+        .map_err(|err| anyhow!("Failed to lock mutex: {}", err))?
+        .clone();
+    if let Some(dir) = replay_dir {
+        return Ok(Box::new(ReplayScreenSource::open(&dir)?));
+    }
+    platform_screen_source()
+}
+
+#[cfg(windows)]
+fn platform_screen_source() -> Result<Box<dyn ScreenSource>> {
+    Ok(Box::new(Win32ScreenSource::new()))
+}
+
+#[cfg(target_os = "linux")]
+fn platform_screen_source() -> Result<Box<dyn ScreenSource>> {
+    Ok(Box::new(X11ScreenSource::connect(None)?))
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+fn platform_screen_source() -> Result<Box<dyn ScreenSource>> {
+    Err(anyhow!("Screen capture is not supported on this platform"))
+}
@@ -0,0 +1,200 @@
+use std::fs;
+use std::path::{ Path, PathBuf };
+use anyhow::{ anyhow, Result };
+use image::RgbaImage;
+use serde::Deserialize;
+
+use super::{ ScreenSource, WindowBounds, WindowId, WindowInfo };
+
+/// One entry of `windows.json` in a replay directory.
+#[derive(Deserialize)]
+struct ReplayWindow {
+    title: String,
+    /// Image file relative to the replay directory
+    image: String,
+    #[serde(default)]
+    process_id: u32,
+    #[serde(default)]
+    process_name: String,
+    #[serde(default)]
+    bounds: Option<WindowBounds>,
+    #[serde(default)]
+    focused: bool,
+}
+
+/// Plays back recorded windows instead of touching the desktop, for running
+/// the context pipeline headless.
+///
+/// The directory holds the window images plus an optional `windows.json`
+/// listing them topmost first. Without a manifest every PNG/JPEG becomes a
+/// window titled after its file stem, in file name order, the first one focused.
+pub struct ReplayScreenSource {
+    dir: PathBuf,
+    windows: Vec<(WindowInfo, PathBuf)>,
+}
+
+impl ReplayScreenSource {
+    pub fn open(dir: &Path) -> Result<Self> {
+        let manifest_path = dir.join("windows.json");
+        let entries: Vec<ReplayWindow> = if manifest_path.exists() {
+            serde_json::from_str(&fs::read_to_string(&manifest_path)?).map_err(|e|
+                anyhow!("Invalid {}: {}", manifest_path.display(), e)
+            )?
+        } else {
+            let mut images: Vec<PathBuf> = fs
+                ::read_dir(dir)?
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| is_image(path))
+                .collect();
+            images.sort();
+            images
+                .iter()
+                .enumerate()
+                .map(|(i, path)| ReplayWindow {
+                    title: path
+                        .file_stem()
+                        .map(|stem| stem.to_string_lossy().into_owned())
+                        .unwrap_or_default(),
+                    image: path
+                        .file_name()
+                        .map(|name| name.to_string_lossy().into_owned())
+                        .unwrap_or_default(),
+                    process_id: 0,
+                    process_name: String::new(),
+                    bounds: None,
+                    focused: i == 0,
+                })
+                .collect()
+        };
+
+        let mut windows = Vec::with_capacity(entries.len());
+        for (i, entry) in entries.into_iter().enumerate() {
+            let image_path = dir.join(&entry.image);
+            let bounds = match entry.bounds {
+                Some(bounds) => bounds,
+                None => {
+                    let (width, height) = image::image_dimensions(&image_path)?;
+                    WindowBounds { x: 0, y: 0, width, height }
+                }
+            };
+            windows.push((
+                WindowInfo {
+                    id: (i + 1) as WindowId,
+                    title: entry.title,
+                    process_id: entry.process_id,
+                    process_name: entry.process_name,
+                    bounds,
+                    focused: entry.focused,
+                },
+                image_path,
+            ));
+        }
+
+        Ok(Self { dir: dir.to_path_buf(), windows })
+    }
+}
+
+fn is_image(path: &Path) -> bool {
+    matches!(
+        path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_lowercase())
+            .as_deref(),
+        Some("png" | "jpg" | "jpeg")
+    )
+}
+
+impl ScreenSource for ReplayScreenSource {
+    fn list_windows(&self) -> Result<Vec<WindowInfo>> {
+        Ok(
+            self.windows
+                .iter()
+                .map(|(window, _)| window.clone())
+                .collect()
+        )
+    }
+
+    fn capture_window(&self, window: &WindowInfo) -> Result<RgbaImage> {
+        let (_, path) = self.windows
+            .iter()
+            .find(|(recorded, _)| recorded.id == window.id)
+            .ok_or_else(|| anyhow!("No window {} in replay {}", window.id, self.dir.display()))?;
+        Ok(image::open(path)?.to_rgba8())
+    }
+
+    fn focused_window(&self) -> Result<Option<WindowId>> {
+        Ok(
+            self.windows
+                .iter()
+                .find(|(window, _)| window.focused)
+                .map(|(window, _)| window.id)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::write_image;
+    use super::*;
+
+    #[test]
+    fn images_without_manifest_are_windows_in_name_order() {
+        let dir = tempfile::tempdir().unwrap();
+        write_image(&dir.path().join("b-editor.png"), 300, 200);
+        write_image(&dir.path().join("a-terminal.png"), 640, 480);
+        fs::write(dir.path().join("notes.txt"), "not a window").unwrap();
+
+        let source = ReplayScreenSource::open(dir.path()).unwrap();
+        let windows = source.list_windows().unwrap();
+        let titles: Vec<&str> = windows
+            .iter()
+            .map(|window| window.title.as_str())
+            .collect();
+        assert_eq!(titles, ["a-terminal", "b-editor"]);
+        assert!(windows[0].focused && !windows[1].focused);
+        assert_eq!(windows[0].bounds, WindowBounds { x: 0, y: 0, width: 640, height: 480 });
+        assert_eq!(source.focused_window().unwrap(), Some(windows[0].id));
+        assert_eq!(source.capture_window(&windows[1]).unwrap().dimensions(), (300, 200));
+    }
+
+    #[test]
+    fn manifest_sets_order_titles_and_bounds() {
+        let dir = tempfile::tempdir().unwrap();
+        write_image(&dir.path().join("editor.png"), 400, 300);
+        write_image(&dir.path().join("browser.png"), 200, 100);
+        let manifest =
+            r#"[
+            {
+                "title": "Browser",
+                "image": "browser.png",
+                "process_name": "firefox",
+                "bounds": { "x": 50, "y": 60, "width": 800, "height": 600 }
+            },
+            { "title": "Editor", "image": "editor.png", "focused": true }
+        ]"#;
+        fs::write(dir.path().join("windows.json"), manifest).unwrap();
+
+        let source = ReplayScreenSource::open(dir.path()).unwrap();
+        let windows = source.list_windows().unwrap();
+        assert_eq!(windows.len(), 2);
+        assert_eq!(windows[0].title, "Browser");
+        assert_eq!(windows[0].process_name, "firefox");
+        assert_eq!(windows[0].bounds, WindowBounds { x: 50, y: 60, width: 800, height: 600 });
+        // Without bounds the image size is used
+        assert_eq!(windows[1].bounds, WindowBounds { x: 0, y: 0, width: 400, height: 300 });
+        assert_eq!(source.focused_window().unwrap(), Some(windows[1].id));
+        // The recorded image is returned as is, whatever the bounds say
+        assert_eq!(source.capture_window(&windows[0]).unwrap().dimensions(), (200, 100));
+    }
+
+    #[test]
+    fn unknown_window_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        write_image(&dir.path().join("only.png"), 100, 100);
+        let source = ReplayScreenSource::open(dir.path()).unwrap();
+        let mut window = source.list_windows().unwrap().remove(0);
+        window.id = 99;
+        assert!(source.capture_window(&window).is_err());
+    }
+}
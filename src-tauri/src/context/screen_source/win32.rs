@@ -0,0 +1,279 @@
+use anyhow::{ anyhow, Result };
+use image::RgbaImage;
+use windows::core::PWSTR;
+use windows::Win32::Foundation::{ CloseHandle, BOOL, HWND, LPARAM, RECT };
+use windows::Win32::Graphics::Gdi::{
+    CreateCompatibleBitmap,
+    CreateCompatibleDC,
+    DeleteDC,
+    DeleteObject,
+    GetDIBits,
+    GetWindowDC,
+    ReleaseDC,
+    SelectObject,
+    BITMAPINFO,
+    BITMAPINFOHEADER,
+    BI_RGB,
+    DIB_RGB_COLORS,
+    HGDIOBJ,
+};
+use windows::Win32::Storage::Xps::{ PrintWindow, PRINT_WINDOW_FLAGS };
+use windows::Win32::UI::WindowsAndMessaging::{
+    EnumWindows,
+    GetClassNameW,
+    GetForegroundWindow,
+    GetWindowTextLengthW,
+    GetWindowTextW,
+    IsWindowVisible,
+    GetClientRect,
+    GetWindowRect,
+    GetWindowThreadProcessId,
+    PW_RENDERFULLCONTENT,
+};
+use windows::Win32::System::Threading::{
+    GetCurrentProcessId,
+    OpenProcess,
+    QueryFullProcessImageNameW,
+    PROCESS_NAME_WIN32,
+    PROCESS_QUERY_LIMITED_INFORMATION,
+};
+
+use super::{ ScreenSource, WindowBounds, WindowId, WindowInfo };
+
+#[derive(Default)]
+pub struct Win32ScreenSource;
+
+impl Win32ScreenSource {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+/// File stem of the process image, e.g. "chrome" for `C:\...\chrome.exe`
+unsafe fn process_name(process_id: u32) -> String {
+    let handle = match OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, BOOL::from(false), process_id) {
+        Ok(handle) => handle,
+        Err(_) => {
+            return String::new();
+        }
+    };
+    let mut buffer: Vec<u16> = vec![0; 1024];
+    let mut length = buffer.len() as u32;
+    let result = QueryFullProcessImageNameW(
+        handle,
+        PROCESS_NAME_WIN32,
+        PWSTR(buffer.as_mut_ptr()),
+        &mut length
+    );
+    let _ = CloseHandle(handle);
+    if result.is_err() {
+        return String::new();
+    }
+    let path = String::from_utf16_lossy(&buffer[..length as usize]);
+    std::path::Path
+        ::new(&path)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Draws `hwnd` into a memory bitmap with `PrintWindow`, so covered windows
+/// come out whole, and reads it back as top-down RGBA
+unsafe fn print_window(hwnd: HWND) -> Result<RgbaImage> {
+    let mut rect = RECT::default();
+    GetWindowRect(hwnd, &mut rect).map_err(|e| anyhow!("GetWindowRect failed: {}", e))?;
+    let width = rect.right - rect.left;
+    let height = rect.bottom - rect.top;
+    if width <= 0 || height <= 0 {
+        return Err(anyhow!("Window has no area"));
+    }
+
+    let window_dc = GetWindowDC(hwnd);
+    if window_dc.is_invalid() {
+        return Err(anyhow!("GetWindowDC failed"));
+    }
+    let memory_dc = CreateCompatibleDC(window_dc);
+    let bitmap = CreateCompatibleBitmap(window_dc, width, height);
+    let previous = SelectObject(memory_dc, HGDIOBJ::from(bitmap));
+
+    let printed = PrintWindow(hwnd, memory_dc, PRINT_WINDOW_FLAGS(PW_RENDERFULLCONTENT)).as_bool();
+    let mut info = BITMAPINFO {
+        bmiHeader: BITMAPINFOHEADER {
+            biSize: std::mem::size_of::<BITMAPINFOHEADER>() as u32,
+            biWidth: width,
+            // Negative for rows top-down
+            biHeight: -height,
+            biPlanes: 1,
+            biBitCount: 32,
+            biCompression: BI_RGB.0,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let mut pixels = vec![0u8; (width as usize) * (height as usize) * 4];
+    let lines = if printed {
+        GetDIBits(
+            memory_dc,
+            bitmap,
+            0,
+            height as u32,
+            Some(pixels.as_mut_ptr().cast()),
+            &mut info,
+            DIB_RGB_COLORS
+        )
+    } else {
+        0
+    };
+
+    SelectObject(memory_dc, previous);
+    let _ = DeleteObject(HGDIOBJ::from(bitmap));
+    let _ = DeleteDC(memory_dc);
+    ReleaseDC(hwnd, window_dc);
+
+    if !printed {
+        return Err(anyhow!("PrintWindow failed"));
+    }
+    if lines != height {
+        return Err(anyhow!("GetDIBits read {} of {} rows", lines, height));
+    }
+    // BGRA to RGBA; the alpha GDI leaves is meaningless
+    for pixel in pixels.chunks_exact_mut(4) {
+        pixel.swap(0, 2);
+        pixel[3] = 255;
+    }
+    RgbaImage::from_raw(width as u32, height as u32, pixels).ok_or_else(||
+        anyhow!("Captured buffer does not match its dimensions")
+    )
+}
+
+unsafe extern "system" fn wl_callback(hwnd: HWND, lparam: LPARAM) -> BOOL {
+    let vec = lparam.0 as *mut Vec<WindowInfo>;
+    if IsWindowVisible(hwnd) == false {
+        return BOOL::from(true);
+    }
+
+    let gwtl = GetWindowTextLengthW(hwnd);
+    if gwtl == 0 {
+        return BOOL::from(true);
+    }
+
+    let max_count = 256; // Example maximum count, adjust based on your needs
+
+    // Allocate memory for the class name buffer
+    let mut class_name_buffer: Vec<u16> = vec![0; max_count as usize];
+
+    // Pass the buffer as a mutable slice
+    let class_name_length = GetClassNameW(hwnd, &mut class_name_buffer[..]);
+    let class_name = String::from_utf16_lossy(&class_name_buffer[..class_name_length as usize])
+        .trim_end_matches('\0')
+        .to_string();
+    let ignore_class_names = ["Progman", "TaskManagerWindow", "Windows.UI.Core.CoreWindow"];
+    if ignore_class_names.contains(&class_name.as_str()) {
+        return BOOL::from(true);
+    }
+    if class_name.contains("HwndWrapper") {
+        return BOOL::from(true);
+    }
+
+    let mut name_buf: Vec<u16> = vec![0; (gwtl + 1) as usize];
+
+    let gwt = GetWindowTextW(hwnd, &mut name_buf);
+    if gwt == 0 {
+        return BOOL::from(true);
+    }
+
+    let name_buf = match name_buf.split_last() {
+        Some((_, last)) => last,
+        None => {
+            return BOOL::from(true);
+        }
+    };
+
+    let name = String::from_utf16_lossy(name_buf);
+
+    let ignore_names = ["Settings"];
+
+    if ignore_names.contains(&name.as_str()) {
+        return BOOL::from(true);
+    }
+    if name.contains("settings.html") {
+        return BOOL::from(true);
+    }
+    let mut rect = RECT::default(); // Use a zero-initialized RECT struct
+    if GetClientRect(hwnd, &mut rect).is_err() {
+        return BOOL::from(true);
+    }
+
+    // Now you have the width and height in rect.right and rect.bottom
+    let width = rect.right as isize;
+    let height = rect.bottom as isize;
+
+    if width * height < 10000 {
+        // if too low pixels means window not rendered~
+        return BOOL::from(true);
+    }
+
+    let current_process_id = GetCurrentProcessId();
+    let mut process_id: u32 = 0;
+
+    GetWindowThreadProcessId(hwnd, Some(&mut process_id));
+    if current_process_id == process_id {
+        // ignore itself window
+        return BOOL::from(true);
+    }
+
+    let mut window_rect = RECT::default();
+    if GetWindowRect(hwnd, &mut window_rect).is_err() {
+        return BOOL::from(true);
+    }
+
+    (*vec).push(WindowInfo {
+        id: hwnd.0 as isize as WindowId,
+        title: name,
+        process_id,
+        process_name: process_name(process_id),
+        bounds: WindowBounds {
+            x: window_rect.left,
+            y: window_rect.top,
+            width: (window_rect.right - window_rect.left).max(0) as u32,
+            height: (window_rect.bottom - window_rect.top).max(0) as u32,
+        },
+        focused: false,
+    });
+
+    BOOL::from(true)
+}
+
+impl ScreenSource for Win32ScreenSource {
+    fn list_windows(&self) -> Result<Vec<WindowInfo>> {
+        let mut windows: Vec<WindowInfo> = Vec::new();
+        unsafe {
+            EnumWindows(
+                Some(wl_callback),
+                LPARAM(&mut windows as *mut Vec<WindowInfo> as isize)
+            ).map_err(|e| anyhow!("EnumWindows failed: {}", e))?;
+        }
+
+        // EnumWindows already walks in z-order, topmost first
+        if let Some(focused) = self.focused_window()? {
+            for window in windows.iter_mut() {
+                window.focused = window.id == focused;
+            }
+        }
+
+        Ok(windows)
+    }
+
+    fn capture_window(&self, window: &WindowInfo) -> Result<RgbaImage> {
+        let hwnd = HWND(window.id as isize as *mut std::ffi::c_void);
+        unsafe { print_window(hwnd) }.map_err(|e| anyhow!("Error capturing window: {}", e))
+    }
+
+    fn focused_window(&self) -> Result<Option<WindowId>> {
+        let hwnd = unsafe { GetForegroundWindow() };
+        if hwnd.0.is_null() {
+            return Ok(None);
+        }
+        Ok(Some(hwnd.0 as isize as WindowId))
+    }
+}
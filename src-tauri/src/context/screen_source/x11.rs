@@ -0,0 +1,316 @@
+use anyhow::{ anyhow, Result };
+use image::RgbaImage;
+use x11rb::connection::Connection;
+use x11rb::protocol::xproto::{ Atom, AtomEnum, ConnectionExt, ImageFormat, MapState, Window };
+use x11rb::rust_connection::RustConnection;
+
+use super::{ ScreenSource, WindowBounds, WindowId, WindowInfo };
+
+struct Atoms {
+    net_client_list_stacking: Atom,
+    net_active_window: Atom,
+    net_wm_name: Atom,
+    net_wm_pid: Atom,
+    utf8_string: Atom,
+}
+
+/// X11 backend. Works against any X server, including Xvfb; with an EWMH
+/// window manager it uses `_NET_CLIENT_LIST_STACKING`, otherwise it falls
+/// back to the mapped children of the root window.
+pub struct X11ScreenSource {
+    conn: RustConnection,
+    root: Window,
+    atoms: Atoms,
+}
+
+impl X11ScreenSource {
+    /// Connects to `display`, or to `$DISPLAY` when `None`.
+    pub fn connect(display: Option<&str>) -> Result<Self> {
+        let (conn, screen_num) = x11rb::connect(display).map_err(|e|
+            anyhow!("Failed to connect to X server: {}", e)
+        )?;
+        let root = conn.setup().roots[screen_num].root;
+
+        let intern = |name: &[u8]| -> Result<Atom> {
+            Ok(conn.intern_atom(false, name)?.reply()?.atom)
+        };
+        let atoms = Atoms {
+            net_client_list_stacking: intern(b"_NET_CLIENT_LIST_STACKING")?,
+            net_active_window: intern(b"_NET_ACTIVE_WINDOW")?,
+            net_wm_name: intern(b"_NET_WM_NAME")?,
+            net_wm_pid: intern(b"_NET_WM_PID")?,
+            utf8_string: intern(b"UTF8_STRING")?,
+        };
+
+        Ok(Self { conn, root, atoms })
+    }
+
+    fn window_property_u32(&self, window: Window, property: Atom, kind: AtomEnum) -> Result<Vec<u32>> {
+        let reply = self.conn.get_property(false, window, property, kind, 0, u32::MAX)?.reply()?;
+        Ok(reply.value32().map(|values| values.collect()).unwrap_or_default())
+    }
+
+    /// Client windows, bottom-most first.
+    fn stacking_order(&self) -> Result<Vec<Window>> {
+        let clients = self.window_property_u32(
+            self.root,
+            self.atoms.net_client_list_stacking,
+            AtomEnum::WINDOW
+        )?;
+        if !clients.is_empty() {
+            return Ok(clients);
+        }
+        // No EWMH window manager (e.g. bare Xvfb): query_tree is bottom-to-top too
+        Ok(self.conn.query_tree(self.root)?.reply()?.children)
+    }
+
+    fn window_title(&self, window: Window) -> Result<String> {
+        let reply = self.conn
+            .get_property(false, window, self.atoms.net_wm_name, self.atoms.utf8_string, 0, u32::MAX)?
+            .reply()?;
+        if !reply.value.is_empty() {
+            return Ok(String::from_utf8_lossy(&reply.value).into_owned());
+        }
+        let reply = self.conn
+            .get_property(false, window, AtomEnum::WM_NAME, AtomEnum::STRING, 0, u32::MAX)?
+            .reply()?;
+        Ok(String::from_utf8_lossy(&reply.value).into_owned())
+    }
+
+    fn window_bounds(&self, window: Window) -> Result<WindowBounds> {
+        let geometry = self.conn.get_geometry(window)?.reply()?;
+        let origin = self.conn.translate_coordinates(window, self.root, 0, 0)?.reply()?;
+        Ok(WindowBounds {
+            x: origin.dst_x as i32,
+            y: origin.dst_y as i32,
+            width: geometry.width as u32,
+            height: geometry.height as u32,
+        })
+    }
+
+    fn screen_bounds(&self) -> Result<WindowBounds> {
+        let geometry = self.conn.get_geometry(self.root)?.reply()?;
+        Ok(WindowBounds {
+            x: 0,
+            y: 0,
+            width: geometry.width as u32,
+            height: geometry.height as u32,
+        })
+    }
+}
+
+impl ScreenSource for X11ScreenSource {
+    fn list_windows(&self) -> Result<Vec<WindowInfo>> {
+        let focused = self.focused_window()?;
+        let current_process_id = std::process::id();
+        let mut windows = Vec::new();
+
+        for window in self.stacking_order()?.into_iter().rev() {
+            let attributes = match self.conn.get_window_attributes(window)?.reply() {
+                Ok(attributes) => attributes,
+                // window went away between listing and querying
+                Err(_) => {
+                    continue;
+                }
+            };
+            if attributes.map_state != MapState::VIEWABLE || attributes.override_redirect {
+                continue;
+            }
+
+            // Windows closed since listing fail with BadWindow from here on
+            let Ok(title) = self.window_title(window) else {
+                continue;
+            };
+            if title.is_empty() || title == "Settings" || title.contains("settings.html") {
+                continue;
+            }
+
+            let Ok(process_ids) = self.window_property_u32(
+                window,
+                self.atoms.net_wm_pid,
+                AtomEnum::CARDINAL
+            ) else {
+                continue;
+            };
+            let process_id = process_ids.first().copied().unwrap_or(0);
+            if process_id == current_process_id {
+                // ignore itself window
+                continue;
+            }
+
+            let Ok(bounds) = self.window_bounds(window) else {
+                continue;
+            };
+            if bounds.area() < 10000 {
+                // if too low pixels means window not rendered~
+                continue;
+            }
+
+            windows.push(WindowInfo {
+                id: window as WindowId,
+                title,
+                process_id,
+                process_name: process_name(process_id),
+                bounds,
+                focused: focused == Some(window as WindowId),
+            });
+        }
+
+        Ok(windows)
+    }
+
+    fn capture_window(&self, window: &WindowInfo) -> Result<RgbaImage> {
+        // GetImage fails with BadMatch for any part outside the screen, so
+        // only request the on-screen portion of the window
+        let screen = self.screen_bounds()?;
+        let bounds = window.bounds;
+        let left = bounds.x.max(screen.x);
+        let top = bounds.y.max(screen.y);
+        let right = (bounds.x + (bounds.width as i32)).min(screen.x + (screen.width as i32));
+        let bottom = (bounds.y + (bounds.height as i32)).min(screen.y + (screen.height as i32));
+        if right <= left || bottom <= top {
+            return Err(anyhow!("Window is entirely off-screen"));
+        }
+        let width = (right - left) as u16;
+        let height = (bottom - top) as u16;
+
+        let reply = self.conn
+            .get_image(
+                ImageFormat::Z_PIXMAP,
+                window.id as Window,
+                (left - bounds.x) as i16,
+                (top - bounds.y) as i16,
+                width,
+                height,
+                u32::MAX
+            )?
+            .reply()?;
+
+        let bits_per_pixel = self.conn
+            .setup()
+            .pixmap_formats.iter()
+            .find(|format| format.depth == reply.depth)
+            .map(|format| format.bits_per_pixel)
+            .unwrap_or(0);
+        if bits_per_pixel != 32 {
+            return Err(
+                anyhow!("Unsupported pixmap format: depth {} at {} bpp", reply.depth, bits_per_pixel)
+            );
+        }
+
+        // 24/32-bit TrueColor visuals are BGRX in memory
+        let mut pixels = reply.data;
+        for pixel in pixels.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+            pixel[3] = 255;
+        }
+
+        RgbaImage::from_raw(width as u32, height as u32, pixels).ok_or_else(||
+            anyhow!("Captured buffer does not match its dimensions")
+        )
+    }
+
+    fn focused_window(&self) -> Result<Option<WindowId>> {
+        let active = self.window_property_u32(
+            self.root,
+            self.atoms.net_active_window,
+            AtomEnum::WINDOW
+        )?;
+        if let Some(&window) = active.first() {
+            if window != 0 {
+                return Ok(Some(window as WindowId));
+            }
+        }
+        // Without a window manager fall back to the core input focus
+        let focus = self.conn.get_input_focus()?.reply()?.focus;
+        if focus == x11rb::NONE || focus == self.root || focus == 1 {
+            // 1 is PointerRoot
+            return Ok(None);
+        }
+        Ok(Some(focus as WindowId))
+    }
+}
+
+fn process_name(process_id: u32) -> String {
+    if process_id == 0 {
+        return String::new();
+    }
+    std::fs
+        ::read_to_string(format!("/proc/{}/comm", process_id))
+        .map(|comm| comm.trim_end().to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use x11rb::protocol::xproto::{ CreateWindowAux, PropMode, WindowClass };
+    use x11rb::wrapper::ConnectionExt as _;
+
+    use super::*;
+
+    /// Maps a `width` x `height` white window titled `title` at 10,10
+    fn open_window(conn: &RustConnection, root: Window, title: &str, width: u16, height: u16) -> Window {
+        let screen = &conn.setup().roots[0];
+        let window = conn.generate_id().unwrap();
+        conn.create_window(
+            x11rb::COPY_DEPTH_FROM_PARENT,
+            window,
+            root,
+            10,
+            10,
+            width,
+            height,
+            0,
+            WindowClass::INPUT_OUTPUT,
+            x11rb::COPY_FROM_PARENT,
+            &CreateWindowAux::new().background_pixel(screen.white_pixel)
+        ).unwrap();
+        conn.change_property8(
+            PropMode::REPLACE,
+            window,
+            AtomEnum::WM_NAME,
+            AtomEnum::STRING,
+            title.as_bytes()
+        ).unwrap();
+        conn.map_window(window).unwrap();
+        conn.sync().unwrap();
+        window
+    }
+
+    // Run with `xvfb-run cargo test -- --ignored x11`
+    #[test]
+    #[ignore = "needs an X server, e.g. Xvfb"]
+    fn x11_lists_and_captures_windows_under_xvfb() {
+        let source = X11ScreenSource::connect(None).unwrap();
+        let (conn, screen_num) = x11rb::connect(None).unwrap();
+        let root = conn.setup().roots[screen_num].root;
+        let window = open_window(&conn, root, "Xvfb check", 200, 100);
+        // Too small to hold text
+        let tiny = open_window(&conn, root, "Tiny", 20, 20);
+        // Closed before it's listed: skipped, not an error
+        let closed = open_window(&conn, root, "Closed", 200, 100);
+        conn.destroy_window(closed).unwrap();
+        conn.sync().unwrap();
+
+        let windows = source.list_windows().unwrap();
+        let listed: Vec<&str> = windows
+            .iter()
+            .map(|window| window.title.as_str())
+            .collect();
+        assert!(listed.contains(&"Xvfb check"), "{:?}", listed);
+        assert!(!listed.contains(&"Tiny") && !listed.contains(&"Closed"), "{:?}", listed);
+
+        let info = windows
+            .iter()
+            .find(|info| info.id == (window as WindowId))
+            .unwrap();
+        assert_eq!((info.bounds.width, info.bounds.height), (200, 100));
+        let image = source.capture_window(info).unwrap();
+        assert_eq!(image.dimensions(), (200, 100));
+        assert_eq!(image.get_pixel(100, 50).0, [255, 255, 255, 255]);
+
+        conn.destroy_window(window).unwrap();
+        conn.destroy_window(tiny).unwrap();
+        conn.sync().unwrap();
+    }
+}
@@ -0,0 +1,696 @@
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Mutex as SyncMutex;
+use anyhow::Result;
+use chrono::{ DateTime, Duration, Utc };
+use rusqlite::types::ToSql;
+use rusqlite::{ params, params_from_iter, Connection, OptionalExtension, Row };
+use serde::{ Deserialize, Serialize };
+use sha2::{ Digest, Sha256 };
+
+use crate::history::fts_query;
+use crate::history::migrations::migrate;
+use super::audio::transcript::TranscriptSegment;
+use super::screen::WindowContext;
+
+pub mod recorder;
+
+pub const TIMELINE_DB_PATH: &str = "./timeline.db";
+
+/// Speech returned around a query's `at`
+const AROUND_AT: Duration = Duration::minutes(2);
+/// Results of each kind when the query doesn't say
+const DEFAULT_LIMIT: usize = 20;
+
+/// Timeline schema changes, see `history::migrations::MIGRATIONS`.
+pub const MIGRATIONS: &[&str] = &[
+    // 1: window text over the time it stayed unchanged, transcript segments,
+    // and when snapshots were taken
+    "CREATE TABLE captures (
+        at TEXT PRIMARY KEY
+    );
+    CREATE TABLE window_snapshots (
+        id INTEGER PRIMARY KEY,
+        title TEXT NOT NULL,
+        process_name TEXT NOT NULL,
+        text TEXT NOT NULL,
+        text_hash TEXT NOT NULL,
+        first_seen TEXT NOT NULL,
+        last_seen TEXT NOT NULL
+    );
+    CREATE INDEX window_snapshots_by_window ON window_snapshots (process_name, title, last_seen);
+    CREATE INDEX window_snapshots_by_time ON window_snapshots (last_seen);
+    CREATE TABLE transcript_segments (
+        id INTEGER PRIMARY KEY,
+        start TEXT NOT NULL,
+        end TEXT NOT NULL,
+        speaker TEXT NOT NULL,
+        voice INTEGER,
+        text TEXT NOT NULL,
+        UNIQUE (start, speaker, text)
+    );
+    CREATE INDEX transcript_segments_by_time ON transcript_segments (end);
+    CREATE VIRTUAL TABLE window_snapshots_fts USING fts5 (
+        title,
+        text,
+        content = 'window_snapshots',
+        content_rowid = 'id'
+    );
+    CREATE TRIGGER window_snapshots_fts_insert AFTER INSERT ON window_snapshots BEGIN
+        INSERT INTO window_snapshots_fts (rowid, title, text)
+            VALUES (new.id, new.title, new.text);
+    END;
+    CREATE TRIGGER window_snapshots_fts_delete AFTER DELETE ON window_snapshots BEGIN
+        INSERT INTO window_snapshots_fts (window_snapshots_fts, rowid, title, text)
+            VALUES ('delete', old.id, old.title, old.text);
+    END;
+    CREATE VIRTUAL TABLE transcript_segments_fts USING fts5 (
+        text,
+        content = 'transcript_segments',
+        content_rowid = 'id'
+    );
+    CREATE TRIGGER transcript_segments_fts_insert AFTER INSERT ON transcript_segments BEGIN
+        INSERT INTO transcript_segments_fts (rowid, text) VALUES (new.id, new.text);
+    END;
+    CREATE TRIGGER transcript_segments_fts_delete AFTER DELETE ON transcript_segments BEGIN
+        INSERT INTO transcript_segments_fts (transcript_segments_fts, rowid, text)
+            VALUES ('delete', old.id, old.text);
+    END;",
+    // 2: ids are never reused after pruning, so they can mark how far the
+    // embedding index got
+    "CREATE TABLE window_snapshots_v2 (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        title TEXT NOT NULL,
+        process_name TEXT NOT NULL,
+        text TEXT NOT NULL,
+        text_hash TEXT NOT NULL,
+        first_seen TEXT NOT NULL,
+        last_seen TEXT NOT NULL
+    );
+    INSERT INTO window_snapshots_v2
+        SELECT id, title, process_name, text, text_hash, first_seen, last_seen
+        FROM window_snapshots;
+    DROP TABLE window_snapshots;
+    ALTER TABLE window_snapshots_v2 RENAME TO window_snapshots;
+    CREATE INDEX window_snapshots_by_window ON window_snapshots (process_name, title, last_seen);
+    CREATE INDEX window_snapshots_by_time ON window_snapshots (last_seen);
+    CREATE TRIGGER window_snapshots_fts_insert AFTER INSERT ON window_snapshots BEGIN
+        INSERT INTO window_snapshots_fts (rowid, title, text)
+            VALUES (new.id, new.title, new.text);
+    END;
+    CREATE TRIGGER window_snapshots_fts_delete AFTER DELETE ON window_snapshots BEGIN
+        INSERT INTO window_snapshots_fts (window_snapshots_fts, rowid, title, text)
+            VALUES ('delete', old.id, old.title, old.text);
+    END;
+    CREATE TABLE transcript_segments_v2 (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        start TEXT NOT NULL,
+        end TEXT NOT NULL,
+        speaker TEXT NOT NULL,
+        voice INTEGER,
+        text TEXT NOT NULL,
+        UNIQUE (start, speaker, text)
+    );
+    INSERT INTO transcript_segments_v2
+        SELECT id, start, end, speaker, voice, text FROM transcript_segments;
+    DROP TABLE transcript_segments;
+    ALTER TABLE transcript_segments_v2 RENAME TO transcript_segments;
+    CREATE INDEX transcript_segments_by_time ON transcript_segments (end);
+    CREATE TRIGGER transcript_segments_fts_insert AFTER INSERT ON transcript_segments BEGIN
+        INSERT INTO transcript_segments_fts (rowid, text) VALUES (new.id, new.text);
+    END;
+    CREATE TRIGGER transcript_segments_fts_delete AFTER DELETE ON transcript_segments BEGIN
+        INSERT INTO transcript_segments_fts (transcript_segments_fts, rowid, text)
+            VALUES ('delete', old.id, old.text);
+    END;",
+];
+
+/// A window's text from when it was first captured like this until it
+/// last was.
+#[derive(Debug, Clone, Serialize)]
+pub struct WindowRecord {
+    pub id: i64,
+    pub title: String,
+    pub process_name: String,
+    pub text: String,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    /// Around the match of a text query, matched terms wrapped in `[` `]`
+    pub snippet: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SegmentRecord {
+    pub id: i64,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    /// "me" or "others"
+    pub speaker: String,
+    pub voice: Option<i64>,
+    pub text: String,
+}
+
+/// What to look up; all given conditions must hold.
+///
+/// "What was in my terminal at 14:05" is `at` with `window: "terminal"`,
+/// "what did they say about the deadline" is `text: "deadline"`.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TimelineQuery {
+    /// Windows as they were at this time and speech within two minutes of it
+    pub at: Option<DateTime<Utc>>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    /// Part of a window title or process name, case-insensitive; only
+    /// windows are returned
+    pub window: Option<String>,
+    /// Words that must all appear (prefixes match too)
+    pub text: Option<String>,
+    /// Results of each kind
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TimelineResult {
+    /// Most recent first, or best match first for a text query
+    pub windows: Vec<WindowRecord>,
+    /// Oldest first, or best match first for a text query
+    pub segments: Vec<SegmentRecord>,
+}
+
+/// What one `record_screen` did.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RecordStats {
+    pub new: usize,
+    pub unchanged: usize,
+}
+
+fn window_from_row(row: &Row) -> rusqlite::Result<WindowRecord> {
+    Ok(WindowRecord {
+        id: row.get(0)?,
+        title: row.get(1)?,
+        process_name: row.get(2)?,
+        text: row.get(3)?,
+        first_seen: row.get(4)?,
+        last_seen: row.get(5)?,
+        snippet: row.get(6)?,
+    })
+}
+
+fn segment_from_row(row: &Row) -> rusqlite::Result<SegmentRecord> {
+    Ok(SegmentRecord {
+        id: row.get(0)?,
+        start: row.get(1)?,
+        end: row.get(2)?,
+        speaker: row.get(3)?,
+        voice: row.get(4)?,
+        text: row.get(5)?,
+    })
+}
+
+/// Screen and audio context recorded over time, in SQLite.
+///
+/// A window whose title and text haven't changed since the previous capture
+/// extends its record instead of adding one, so a mostly static screen
+/// costs little and each record says how long its text was up.
+pub struct TimelineStore {
+    connection: Connection,
+}
+
+impl TimelineStore {
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut connection = Connection::open(path)?;
+        migrate(&mut connection, MIGRATIONS)?;
+        Ok(Self { connection })
+    }
+
+    /// Adds the windows captured `at`, after every earlier capture.
+    pub fn record_screen(
+        &mut self,
+        at: DateTime<Utc>,
+        windows: &[WindowContext]
+    ) -> Result<RecordStats> {
+        let transaction = self.connection.transaction()?;
+        let previous: Option<DateTime<Utc>> = transaction
+            .query_row("SELECT MAX(at) FROM captures", [], |row| row.get(0))
+            .optional()?
+            .flatten();
+        transaction.execute("INSERT OR IGNORE INTO captures (at) VALUES (?1)", [at])?;
+
+        let mut stats = RecordStats::default();
+        let mut seen = HashSet::new();
+        for window in windows {
+            if window.text.trim().is_empty() {
+                continue;
+            }
+            let hash = format!("{:x}", Sha256::digest(window.text.as_bytes()));
+            // The same window twice in one capture is one record
+            if !seen.insert((&window.process_name, &window.title, hash.clone())) {
+                continue;
+            }
+            let extended = match previous {
+                Some(previous) =>
+                    transaction.execute(
+                        "UPDATE window_snapshots SET last_seen = ?1
+                         WHERE process_name = ?2 AND title = ?3 AND text_hash = ?4
+                           AND last_seen = ?5",
+                        params![at, window.process_name, window.title, hash, previous]
+                    )?,
+                None => 0,
+            };
+            if extended > 0 {
+                stats.unchanged += 1;
+                continue;
+            }
+            transaction.execute(
+                "INSERT INTO window_snapshots
+                    (title, process_name, text, text_hash, first_seen, last_seen)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?5)",
+                params![window.title, window.process_name, window.text, hash, at]
+            )?;
+            stats.new += 1;
+        }
+        transaction.commit()?;
+        Ok(stats)
+    }
+
+    /// Adds transcript segments, skipping ones already recorded. Returns
+    /// how many were new.
+    pub fn record_segments(&mut self, segments: &[TranscriptSegment]) -> Result<usize> {
+        let transaction = self.connection.transaction()?;
+        let mut added = 0;
+        for segment in segments {
+            let text = segment.text.trim();
+            if text.is_empty() {
+                continue;
+            }
+            added += transaction.execute(
+                "INSERT OR IGNORE INTO transcript_segments (start, end, speaker, voice, text)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    segment.start,
+                    segment.end,
+                    segment.speaker.label(),
+                    segment.voice.map(|voice| voice as i64),
+                    text
+                ]
+            )?;
+        }
+        transaction.commit()?;
+        Ok(added)
+    }
+
+    /// Forgets everything last seen before `cutoff`.
+    pub fn prune(&self, cutoff: DateTime<Utc>) -> Result<()> {
+        self.connection.execute("DELETE FROM window_snapshots WHERE last_seen < ?1", [cutoff])?;
+        self.connection.execute("DELETE FROM transcript_segments WHERE end < ?1", [cutoff])?;
+        self.connection.execute("DELETE FROM captures WHERE at < ?1", [cutoff])?;
+        Ok(())
+    }
+
+    pub fn query(&self, query: &TimelineQuery) -> Result<TimelineResult> {
+        let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+        let text = query.text.as_deref().and_then(fts_query);
+        let window_filter = query.window.as_deref().map(str::trim).filter(|w| !w.is_empty());
+        let mut result = TimelineResult::default();
+
+        // Windows
+        let mut conditions: Vec<String> = Vec::new();
+        let mut values: Vec<Box<dyn ToSql>> = Vec::new();
+        if let Some(at) = query.at {
+            // The capture in effect at `at` is the last one before it
+            let capture: Option<DateTime<Utc>> = self.connection
+                .query_row("SELECT MAX(at) FROM captures WHERE at <= ?1", [at], |row| row.get(0))
+                .optional()?
+                .flatten();
+            let Some(capture) = capture else {
+                // Nothing was on screen yet
+                if window_filter.is_some() {
+                    return Ok(result);
+                }
+                return self.query_segments(query, text.as_deref(), limit);
+            };
+            let placeholder = bind(&mut values, Box::new(capture));
+            conditions.push(format!("w.first_seen <= {0} AND w.last_seen >= {0}", placeholder));
+        }
+        if let Some(since) = query.since {
+            conditions.push(format!("w.last_seen >= {}", bind(&mut values, Box::new(since))));
+        }
+        if let Some(until) = query.until {
+            conditions.push(format!("w.first_seen <= {}", bind(&mut values, Box::new(until))));
+        }
+        if let Some(window) = window_filter {
+            let placeholder = bind(&mut values, Box::new(format!("%{}%", escape_like(window))));
+            conditions.push(
+                format!(
+                    "(w.title LIKE {0} ESCAPE '\\' OR w.process_name LIKE {0} ESCAPE '\\')",
+                    placeholder
+                )
+            );
+        }
+        let (from, snippet, order) = match &text {
+            Some(text) => {
+                let placeholder = bind(&mut values, Box::new(text.clone()));
+                conditions.push(format!("window_snapshots_fts MATCH {}", placeholder));
+                (
+                    "window_snapshots_fts f JOIN window_snapshots w ON w.id = f.rowid",
+                    "snippet(window_snapshots_fts, 1, '[', ']', '...', 16)",
+                    "f.rank",
+                )
+            }
+            None => ("window_snapshots w", "NULL", "w.last_seen DESC, w.id DESC"),
+        };
+        let limit_placeholder = bind(&mut values, Box::new(limit as i64));
+        let sql = format!(
+            "SELECT w.id, w.title, w.process_name, w.text, w.first_seen, w.last_seen, {}
+             FROM {} {} ORDER BY {} LIMIT {}",
+            snippet,
+            from,
+            where_clause(&conditions),
+            order,
+            limit_placeholder
+        );
+        let mut statement = self.connection.prepare(&sql)?;
+        result.windows = statement
+            .query_map(params_from_iter(values.iter()), window_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        // Speech isn't in any window
+        if window_filter.is_none() {
+            result.segments = self.query_segments(query, text.as_deref(), limit)?.segments;
+        }
+        Ok(result)
+    }
+
+    fn query_segments(
+        &self,
+        query: &TimelineQuery,
+        text: Option<&str>,
+        limit: usize
+    ) -> Result<TimelineResult> {
+        let mut conditions: Vec<String> = Vec::new();
+        let mut values: Vec<Box<dyn ToSql>> = Vec::new();
+        if let Some(at) = query.at {
+            conditions.push(format!("s.end >= {}", bind(&mut values, Box::new(at - AROUND_AT))));
+            conditions.push(format!("s.start <= {}", bind(&mut values, Box::new(at + AROUND_AT))));
+        }
+        if let Some(since) = query.since {
+            conditions.push(format!("s.end >= {}", bind(&mut values, Box::new(since))));
+        }
+        if let Some(until) = query.until {
+            conditions.push(format!("s.start <= {}", bind(&mut values, Box::new(until))));
+        }
+        let (from, order) = match text {
+            Some(text) => {
+                let placeholder = bind(&mut values, Box::new(text.to_string()));
+                conditions.push(format!("transcript_segments_fts MATCH {}", placeholder));
+                ("transcript_segments_fts f JOIN transcript_segments s ON s.id = f.rowid", "f.rank")
+            }
+            None => ("transcript_segments s", "s.start, s.id"),
+        };
+        // Without a text query the newest speech in range is the most useful
+        let newest_first = text.is_none() && query.at.is_none() && query.since.is_none();
+        let limit_placeholder = bind(&mut values, Box::new(limit as i64));
+        let sql = format!(
+            "SELECT s.id, s.start, s.end, s.speaker, s.voice, s.text
+             FROM {} {} ORDER BY {} LIMIT {}",
+            from,
+            where_clause(&conditions),
+            if newest_first { "s.start DESC, s.id DESC" } else { order },
+            limit_placeholder
+        );
+        let mut statement = self.connection.prepare(&sql)?;
+        let mut segments = statement
+            .query_map(params_from_iter(values.iter()), segment_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        if newest_first {
+            segments.reverse();
+        }
+        Ok(TimelineResult { windows: Vec::new(), segments })
+    }
+
+    /// Window records added after record `after`, oldest first. Records
+    /// only ever get a later `last_seen`, so their text is final.
+    pub fn windows_after(&self, after: i64, limit: usize) -> Result<Vec<WindowRecord>> {
+        let mut statement = self.connection.prepare(
+            "SELECT id, title, process_name, text, first_seen, last_seen, NULL
+             FROM window_snapshots WHERE id > ?1 ORDER BY id LIMIT ?2"
+        )?;
+        let windows = statement
+            .query_map(params![after, limit as i64], window_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(windows)
+    }
+
+    /// Transcript segments added after segment `after`, oldest first.
+    pub fn segments_after(&self, after: i64, limit: usize) -> Result<Vec<SegmentRecord>> {
+        let mut statement = self.connection.prepare(
+            "SELECT id, start, end, speaker, voice, text
+             FROM transcript_segments WHERE id > ?1 ORDER BY id LIMIT ?2"
+        )?;
+        let segments = statement
+            .query_map(params![after, limit as i64], segment_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(segments)
+    }
+
+    pub fn window(&self, id: i64) -> Result<Option<WindowRecord>> {
+        Ok(
+            self.connection
+                .query_row(
+                    "SELECT id, title, process_name, text, first_seen, last_seen, NULL
+                     FROM window_snapshots WHERE id = ?1",
+                    [id],
+                    window_from_row
+                )
+                .optional()?
+        )
+    }
+
+    pub fn segment(&self, id: i64) -> Result<Option<SegmentRecord>> {
+        Ok(
+            self.connection
+                .query_row(
+                    "SELECT id, start, end, speaker, voice, text
+                     FROM transcript_segments WHERE id = ?1",
+                    [id],
+                    segment_from_row
+                )
+                .optional()?
+        )
+    }
+
+    /// Ids of every window record still kept
+    pub fn window_ids(&self) -> Result<Vec<i64>> {
+        let mut statement = self.connection.prepare("SELECT id FROM window_snapshots")?;
+        let ids = statement
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(ids)
+    }
+
+    /// Ids of every transcript segment still kept
+    pub fn segment_ids(&self) -> Result<Vec<i64>> {
+        let mut statement = self.connection.prepare("SELECT id FROM transcript_segments")?;
+        let ids = statement
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(ids)
+    }
+}
+
+/// Adds a parameter, returning its placeholder
+fn bind(values: &mut Vec<Box<dyn ToSql>>, value: Box<dyn ToSql>) -> String {
+    values.push(value);
+    format!("?{}", values.len())
+}
+
+fn where_clause(conditions: &[String]) -> String {
+    if conditions.is_empty() {
+        String::new()
+    } else {
+        format!("WHERE {}", conditions.join(" AND "))
+    }
+}
+
+/// `LIKE` wildcards in user text, escaped with `\`
+fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+lazy_static! {
+    static ref TIMELINE_STORE: SyncMutex<Option<TimelineStore>> = SyncMutex::new(None);
+}
+
+/// Runs `f` on the timeline at `TIMELINE_DB_PATH`, opening it on first use.
+pub fn with_timeline<T>(f: impl FnOnce(&mut TimelineStore) -> Result<T>) -> Result<T, String> {
+    let mut store = TIMELINE_STORE.lock().map_err(|err|
+        format!("Failed to lock timeline: {}", err)
+    )?;
+    if store.is_none() {
+        *store = Some(
+            TimelineStore::open(Path::new(TIMELINE_DB_PATH)).map_err(|err|
+                format!("Failed to open timeline: {}", err)
+            )?
+        );
+    }
+    f(store.as_mut().unwrap()).map_err(|err| err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{ DateTime, Duration, TimeZone, Utc };
+
+    use crate::context::audio::transcript::{ Speaker, TranscriptSegment };
+    use crate::test_support::{ window, TempDatabase };
+    use super::*;
+
+    fn segment(start: DateTime<Utc>, speaker: Speaker, text: &str) -> TranscriptSegment {
+        TranscriptSegment {
+            start,
+            end: start + Duration::seconds(4),
+            speaker,
+            voice: (speaker == Speaker::Others).then_some(1),
+            text: text.to_string(),
+            language: Some("en".to_string()),
+        }
+    }
+
+    fn minute(m: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 5, 6, 14, m, 0).unwrap()
+    }
+
+    const BUILD: &str = "$ cargo build\nerror[E0382]: borrow of moved value";
+    const FIXED: &str = "$ cargo build\nFinished dev profile";
+
+    /// Records a terminal and a browser: 14:00 both, 14:01 only the browser
+    /// changed, 14:02 the build is fixed, 14:03 the old error is back. Returns
+    /// (new, unchanged) windows per snapshot.
+    fn record_screens(store: &mut TimelineStore) -> Vec<(usize, usize)> {
+        let snapshots = [
+            (0, BUILD, "Quarterly report draft"),
+            (1, BUILD, "Release checklist"),
+            (2, FIXED, "Release checklist"),
+            (3, BUILD, "Release checklist"),
+        ];
+        snapshots
+            .into_iter()
+            .map(|(m, terminal, page)| {
+                let stats = store
+                    .record_screen(minute(m), &[
+                        window("Terminal", "WindowsTerminal.exe", terminal, minute(m)),
+                        window("Docs - Browser", "firefox", page, minute(m)),
+                        // Listed twice by the window system
+                        window("Docs - Browser", "firefox", page, minute(m)),
+                    ])
+                    .unwrap();
+                (stats.new, stats.unchanged)
+            })
+            .collect()
+    }
+
+    /// Speech around 14:05 and at 14:20
+    fn speech() -> [TranscriptSegment; 3] {
+        [
+            segment(minute(5), Speaker::Others, " The deadline moved to Friday."),
+            segment(minute(5) + Duration::seconds(5), Speaker::Me, " Then we ship Thursday."),
+            segment(minute(20), Speaker::Others, " Lunch?"),
+        ]
+    }
+
+    fn at(time: DateTime<Utc>, window: &str) -> TimelineQuery {
+        TimelineQuery { at: Some(time), window: Some(window.to_string()), ..Default::default() }
+    }
+
+    fn text(text: &str) -> TimelineQuery {
+        TimelineQuery { text: Some(text.to_string()), ..Default::default() }
+    }
+
+    #[test]
+    fn unchanged_windows_extend_their_record() {
+        let database = TempDatabase::new("timeline-dedup");
+        let mut store = TimelineStore::open(database.path()).unwrap();
+        assert_eq!(record_screens(&mut store), [(2, 0), (1, 1), (1, 1), (1, 1)]);
+    }
+
+    #[test]
+    fn window_at_a_time_is_what_was_there() {
+        let database = TempDatabase::new("timeline-at");
+        let mut store = TimelineStore::open(database.path()).unwrap();
+        record_screens(&mut store);
+
+        let result = store.query(&at(minute(1) + Duration::seconds(30), "terminal")).unwrap();
+        assert_eq!(result.windows.len(), 1);
+        assert_eq!(result.windows[0].text, BUILD);
+        assert_eq!(result.windows[0].first_seen, minute(0));
+        assert_eq!(result.windows[0].last_seen, minute(1));
+        assert!(result.segments.is_empty());
+
+        let result = store.query(&at(minute(2) + Duration::seconds(10), "TERMINAL")).unwrap();
+        assert_eq!(result.windows.len(), 1);
+        assert_eq!(result.windows[0].text, FIXED);
+
+        // Text that comes back is a new record
+        let result = store.query(&at(minute(3), "terminal")).unwrap();
+        assert_eq!(result.windows.len(), 1);
+        assert_eq!(result.windows[0].first_seen, minute(3));
+
+        // Before the first capture there are no windows, and a window query
+        // doesn't fall back to speech
+        let early = [segment(minute(0) - Duration::seconds(30), Speaker::Me, " Morning.")];
+        store.record_segments(&early).unwrap();
+        let before = store.query(&at(minute(0) - Duration::seconds(1), "terminal")).unwrap();
+        assert!(before.windows.is_empty() && before.segments.is_empty());
+        let before = store.query(&at(minute(0) - Duration::seconds(1), "")).unwrap();
+        assert_eq!(before.segments.len(), 1);
+        let wildcard = store.query(&at(minute(1), "%")).unwrap();
+        assert!(wildcard.windows.is_empty());
+    }
+
+    #[test]
+    fn speech_recorded_twice_is_kept_once() {
+        let database = TempDatabase::new("timeline-speech");
+        let mut store = TimelineStore::open(database.path()).unwrap();
+        let speech = speech();
+        // Two overlapping snapshots
+        assert_eq!(store.record_segments(&speech[..2]).unwrap(), 2);
+        assert_eq!(store.record_segments(&speech).unwrap(), 1);
+
+        let around = TimelineQuery { at: Some(minute(6)), ..Default::default() };
+        let result = store.query(&around).unwrap();
+        assert_eq!(result.segments.len(), 2, "speech around 14:06");
+        assert!(result.segments[0].start < result.segments[1].start);
+    }
+
+    #[test]
+    fn text_search_covers_speech_and_windows() {
+        let database = TempDatabase::new("timeline-text");
+        let mut store = TimelineStore::open(database.path()).unwrap();
+        record_screens(&mut store);
+        store.record_segments(&speech()).unwrap();
+
+        let result = store.query(&text("deadline")).unwrap();
+        assert_eq!(result.segments.len(), 1);
+        assert_eq!(result.segments[0].text, "The deadline moved to Friday.");
+        assert_eq!(result.segments[0].speaker, "others");
+        assert_eq!(result.segments[0].voice, Some(1));
+        assert!(result.windows.is_empty());
+
+        let result = store.query(&text("borrow")).unwrap();
+        assert_eq!(result.windows.len(), 2);
+        for window in &result.windows {
+            let snippet = window.snippet.as_deref();
+            assert!(snippet.is_some_and(|s| s.contains("[borrow]")), "{:?}", window);
+        }
+    }
+
+    #[test]
+    fn prune_forgets_old_records_and_their_text() {
+        let database = TempDatabase::new("timeline-prune");
+        let mut store = TimelineStore::open(database.path()).unwrap();
+        record_screens(&mut store);
+
+        store.prune(minute(2)).unwrap();
+        assert!(store.query(&text("quarterly")).unwrap().windows.is_empty());
+        assert_eq!(store.query(&at(minute(3), "terminal")).unwrap().windows.len(), 1);
+    }
+}
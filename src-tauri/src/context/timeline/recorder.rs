@@ -0,0 +1,99 @@
+use std::sync::Mutex as SyncMutex;
+use std::thread;
+use std::time::{ Duration, Instant };
+use anyhow::{ anyhow, Result };
+use chrono::Utc;
+use tokio::runtime::Runtime;
+
+use crate::context::audio::AudioManager;
+use crate::context::ocr::engine_from_settings;
+use crate::context::screen::get_screen;
+use crate::settings::Settings;
+use super::with_timeline;
+
+/// How often the recorder checks whether a snapshot is due
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// Window text recorded per snapshot, across all windows
+const MAX_SNAPSHOT_CHARS: usize = 100_000;
+/// Speech is transcribed a while after it's heard, so each snapshot looks
+/// back this much further than the previous one; repeats are skipped
+const TRANSCRIPT_OVERLAP: chrono::Duration = chrono::Duration::minutes(2);
+
+lazy_static! {
+    static ref RECORDER_SETTINGS: SyncMutex<Option<Settings>> = SyncMutex::new(None);
+}
+
+/// Applies settings to the recorder: whether it runs, how often, and what
+/// it captures. Takes effect at its next check.
+pub fn configure_recorder(settings: &Settings) {
+    if let Ok(mut current) = RECORDER_SETTINGS.lock() {
+        *current = Some(settings.clone());
+    }
+}
+
+/// Snapshots screen text and new transcript segments into the timeline
+/// every `timeline_interval_secs` while `timeline_enabled` is on, and
+/// forgets what's older than `timeline_retention_days`.
+pub fn start_timeline_recorder() {
+    thread::spawn(|| {
+        let runtime = match Runtime::new() {
+            Ok(runtime) => runtime,
+            Err(e) => {
+                eprintln!("Timeline recorder failed to start: {}", e);
+                return;
+            }
+        };
+        let mut last_snapshot: Option<Instant> = None;
+        loop {
+            thread::sleep(POLL_INTERVAL);
+            let settings = RECORDER_SETTINGS.lock()
+                .ok()
+                .and_then(|settings| settings.clone());
+            let Some(settings) = settings else {
+                continue;
+            };
+            if !settings.timeline_enabled {
+                continue;
+            }
+            let interval = Duration::from_secs(settings.timeline_interval_secs.max(10));
+            if last_snapshot.is_some_and(|taken| taken.elapsed() < interval) {
+                continue;
+            }
+            last_snapshot = Some(Instant::now());
+            if let Err(e) = take_snapshot(&runtime, &settings, interval) {
+                eprintln!("Timeline snapshot failed: {}", e);
+            }
+        }
+    });
+}
+
+/// Records what's on screen and what was said since the last snapshot,
+/// each only if that context is enabled.
+fn take_snapshot(runtime: &Runtime, settings: &Settings, interval: Duration) -> Result<()> {
+    let now = Utc::now();
+    if settings.screen_context {
+        let ocr = engine_from_settings(settings)?;
+        let screen = get_screen(MAX_SNAPSHOT_CHARS, ocr, None)?;
+        with_timeline(|timeline| {
+            timeline.record_screen(screen.captured_at, &screen.windows)
+        }).map_err(|e| anyhow!(e))?;
+    }
+
+    if settings.audio_context {
+        let manager = AudioManager::get_instance()
+            .lock()
+            .map_err(|e| anyhow!("Failed to lock mutex: {}", e))?
+            .clone();
+        if let Some(manager) = manager {
+            let since = now - chrono::Duration::from_std(interval)? - TRANSCRIPT_OVERLAP;
+            let audio = runtime.block_on(manager.get_transcript(Some(since), None));
+            with_timeline(|timeline| timeline.record_segments(&audio.segments)).map_err(|e|
+                anyhow!(e)
+            )?;
+        }
+    }
+
+    let retention = chrono::Duration::days(settings.timeline_retention_days.max(1).into());
+    with_timeline(|timeline| timeline.prune(now - retention)).map_err(|e| anyhow!(e))?;
+    Ok(())
+}
@@ -0,0 +1,92 @@
+use anyhow::{ anyhow, Result };
+use rusqlite::Connection;
+
+/// Chat history schema changes in order; `PRAGMA user_version` is how many
+/// have been applied. Only ever append, a released migration must not change.
+pub const MIGRATIONS: &[&str] = &[
+    // 1: sessions and their messages, each user turn with the PC context it
+    // was asked in
+    "CREATE TABLE sessions (
+        id INTEGER PRIMARY KEY,
+        title TEXT NOT NULL,
+        created_at TEXT NOT NULL,
+        updated_at TEXT NOT NULL
+    );
+    CREATE TABLE messages (
+        id INTEGER PRIMARY KEY,
+        session_id INTEGER NOT NULL REFERENCES sessions (id) ON DELETE CASCADE,
+        role TEXT NOT NULL,
+        content TEXT NOT NULL,
+        context TEXT,
+        created_at TEXT NOT NULL
+    );
+    CREATE INDEX messages_by_session ON messages (session_id, id);",
+    // 2: full-text search over message content, kept in sync by triggers
+    "CREATE VIRTUAL TABLE messages_fts USING fts5 (
+        content,
+        content = 'messages',
+        content_rowid = 'id'
+    );
+    INSERT INTO messages_fts (rowid, content) SELECT id, content FROM messages;
+    CREATE TRIGGER messages_fts_insert AFTER INSERT ON messages BEGIN
+        INSERT INTO messages_fts (rowid, content) VALUES (new.id, new.content);
+    END;
+    CREATE TRIGGER messages_fts_delete AFTER DELETE ON messages BEGIN
+        INSERT INTO messages_fts (messages_fts, rowid, content)
+            VALUES ('delete', old.id, old.content);
+    END;
+    CREATE TRIGGER messages_fts_update AFTER UPDATE OF content ON messages BEGIN
+        INSERT INTO messages_fts (messages_fts, rowid, content)
+            VALUES ('delete', old.id, old.content);
+        INSERT INTO messages_fts (rowid, content) VALUES (new.id, new.content);
+    END;",
+    // 3: message ids are never reused after a session is deleted, so they
+    // can mark how far the embedding index got
+    "CREATE TABLE messages_v3 (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        session_id INTEGER NOT NULL REFERENCES sessions (id) ON DELETE CASCADE,
+        role TEXT NOT NULL,
+        content TEXT NOT NULL,
+        context TEXT,
+        created_at TEXT NOT NULL
+    );
+    INSERT INTO messages_v3 (id, session_id, role, content, context, created_at)
+        SELECT id, session_id, role, content, context, created_at FROM messages;
+    DROP TABLE messages;
+    ALTER TABLE messages_v3 RENAME TO messages;
+    CREATE INDEX messages_by_session ON messages (session_id, id);
+    CREATE TRIGGER messages_fts_insert AFTER INSERT ON messages BEGIN
+        INSERT INTO messages_fts (rowid, content) VALUES (new.id, new.content);
+    END;
+    CREATE TRIGGER messages_fts_delete AFTER DELETE ON messages BEGIN
+        INSERT INTO messages_fts (messages_fts, rowid, content)
+            VALUES ('delete', old.id, old.content);
+    END;
+    CREATE TRIGGER messages_fts_update AFTER UPDATE OF content ON messages BEGIN
+        INSERT INTO messages_fts (messages_fts, rowid, content)
+            VALUES ('delete', old.id, old.content);
+        INSERT INTO messages_fts (rowid, content) VALUES (new.id, new.content);
+    END;",
+];
+
+/// Brings a database up to the last of `migrations`, each in its own
+/// transaction. Refuses databases written by a newer build.
+pub fn migrate(connection: &mut Connection, migrations: &[&str]) -> Result<()> {
+    let version: usize = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    if version > migrations.len() {
+        return Err(
+            anyhow!(
+                "Database is schema version {}, this version of PC Agent knows up to {}",
+                version,
+                migrations.len()
+            )
+        );
+    }
+    for (index, migration) in migrations.iter().enumerate().skip(version) {
+        let transaction = connection.transaction()?;
+        transaction.execute_batch(migration)?;
+        transaction.pragma_update(None, "user_version", index + 1)?;
+        transaction.commit()?;
+    }
+    Ok(())
+}
@@ -0,0 +1,512 @@
+use std::path::Path;
+use std::sync::Mutex as SyncMutex;
+use anyhow::{ anyhow, Result };
+use chrono::{ DateTime, Utc };
+use rusqlite::{ params, Connection, OptionalExtension, Row };
+use serde::Serialize;
+
+use crate::llm::ChatMessage;
+
+pub mod migrations;
+
+pub const HISTORY_DB_PATH: &str = "./history.db";
+
+/// Characters of the first message used as a new session's title
+const TITLE_CHARS: usize = 60;
+
+/// A past conversation, without its messages.
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionSummary {
+    pub id: i64,
+    pub title: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub message_count: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StoredMessage {
+    pub id: i64,
+    pub role: String,
+    pub content: String,
+    /// The PC context the message was asked in, user messages only
+    pub context: Option<serde_json::Value>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Session {
+    #[serde(flatten)]
+    pub summary: SessionSummary,
+    pub messages: Vec<StoredMessage>,
+}
+
+/// A message matching a search, with the match in context.
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchHit {
+    pub session: SessionSummary,
+    pub message_id: i64,
+    pub role: String,
+    /// Around the match, matched terms wrapped in `[` `]`
+    pub snippet: String,
+}
+
+const SUMMARY_COLUMNS: &str =
+    "s.id, s.title, s.created_at, s.updated_at,
+     (SELECT COUNT(*) FROM messages m WHERE m.session_id = s.id)";
+
+fn summary_from_row(row: &Row) -> rusqlite::Result<SessionSummary> {
+    Ok(SessionSummary {
+        id: row.get(0)?,
+        title: row.get(1)?,
+        created_at: row.get(2)?,
+        updated_at: row.get(3)?,
+        message_count: row.get(4)?,
+    })
+}
+
+/// Conversations kept in SQLite: sessions, their messages and the context
+/// each question was asked in.
+pub struct ChatStore {
+    connection: Connection,
+}
+
+impl ChatStore {
+    /// Opens or creates the database at `path`, migrating it if needed.
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut connection = Connection::open(path)?;
+        connection.pragma_update(None, "foreign_keys", true)?;
+        migrations::migrate(&mut connection, migrations::MIGRATIONS)?;
+        Ok(Self { connection })
+    }
+
+    /// A new, empty session titled after its first message.
+    pub fn create_session(&self, first_message: &str) -> Result<i64> {
+        let now = Utc::now();
+        self.connection.execute(
+            "INSERT INTO sessions (title, created_at, updated_at) VALUES (?1, ?2, ?2)",
+            params![title_from(first_message), now]
+        )?;
+        Ok(self.connection.last_insert_rowid())
+    }
+
+    pub fn add_message(
+        &mut self,
+        session_id: i64,
+        message: &ChatMessage,
+        context: Option<&serde_json::Value>
+    ) -> Result<i64> {
+        let now = Utc::now();
+        let context = context.map(serde_json::Value::to_string);
+        let transaction = self.connection.transaction()?;
+        let updated = transaction.execute(
+            "UPDATE sessions SET updated_at = ?2 WHERE id = ?1",
+            params![session_id, now]
+        )?;
+        if updated == 0 {
+            return Err(anyhow!("No chat session {}", session_id));
+        }
+        transaction.execute(
+            "INSERT INTO messages (session_id, role, content, context, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![session_id, message.role, message.content, context, now]
+        )?;
+        let id = transaction.last_insert_rowid();
+        transaction.commit()?;
+        Ok(id)
+    }
+
+    /// Most recently active first
+    pub fn list_sessions(&self) -> Result<Vec<SessionSummary>> {
+        let mut statement = self.connection.prepare(
+            &format!(
+                "SELECT {} FROM sessions s ORDER BY s.updated_at DESC, s.id DESC",
+                SUMMARY_COLUMNS
+            )
+        )?;
+        let sessions = statement
+            .query_map([], summary_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(sessions)
+    }
+
+    fn summary(&self, session_id: i64) -> Result<SessionSummary> {
+        self.connection
+            .query_row(
+                &format!("SELECT {} FROM sessions s WHERE s.id = ?1", SUMMARY_COLUMNS),
+                [session_id],
+                summary_from_row
+            )
+            .optional()?
+            .ok_or_else(|| anyhow!("No chat session {}", session_id))
+    }
+
+    /// A session with all its messages, oldest first.
+    pub fn session(&self, session_id: i64) -> Result<Session> {
+        let summary = self.summary(session_id)?;
+        let mut statement = self.connection.prepare(
+            "SELECT id, role, content, context, created_at FROM messages
+             WHERE session_id = ?1 ORDER BY id"
+        )?;
+        let messages = statement
+            .query_map([session_id], |row| {
+                let context: Option<String> = row.get(3)?;
+                Ok(StoredMessage {
+                    id: row.get(0)?,
+                    role: row.get(1)?,
+                    content: row.get(2)?,
+                    context: context.and_then(|context| serde_json::from_str(&context).ok()),
+                    created_at: row.get(4)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(Session { summary, messages })
+    }
+
+    /// Messages added after message `after` in any session, oldest first,
+    /// without their context.
+    pub fn messages_after(&self, after: i64, limit: usize) -> Result<Vec<StoredMessage>> {
+        let mut statement = self.connection.prepare(
+            "SELECT id, role, content, created_at FROM messages
+             WHERE id > ?1 ORDER BY id LIMIT ?2"
+        )?;
+        let messages = statement
+            .query_map(params![after, limit as i64], |row| {
+                Ok(StoredMessage {
+                    id: row.get(0)?,
+                    role: row.get(1)?,
+                    content: row.get(2)?,
+                    context: None,
+                    created_at: row.get(3)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(messages)
+    }
+
+    /// A message without its context, with the session it's in.
+    pub fn message(&self, message_id: i64) -> Result<Option<(SessionSummary, StoredMessage)>> {
+        Ok(
+            self.connection
+                .query_row(
+                    &format!(
+                        "SELECT {}, m.id, m.role, m.content, m.created_at
+                         FROM messages m JOIN sessions s ON s.id = m.session_id
+                         WHERE m.id = ?1",
+                        SUMMARY_COLUMNS
+                    ),
+                    [message_id],
+                    |row| {
+                        Ok((
+                            summary_from_row(row)?,
+                            StoredMessage {
+                                id: row.get(5)?,
+                                role: row.get(6)?,
+                                content: row.get(7)?,
+                                context: None,
+                                created_at: row.get(8)?,
+                            },
+                        ))
+                    }
+                )
+                .optional()?
+        )
+    }
+
+    /// Ids of every message in any session
+    pub fn message_ids(&self) -> Result<Vec<i64>> {
+        let mut statement = self.connection.prepare("SELECT id FROM messages")?;
+        let ids = statement
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(ids)
+    }
+
+    /// A session's messages as the chat history sent to the model.
+    pub fn history(&self, session_id: i64) -> Result<Vec<ChatMessage>> {
+        let session = self.session(session_id)?;
+        Ok(
+            session.messages
+                .iter()
+                .map(|message| ChatMessage::new(&message.role, &message.content))
+                .collect()
+        )
+    }
+
+    pub fn rename_session(&self, session_id: i64, title: &str) -> Result<()> {
+        let title = title.trim();
+        if title.is_empty() {
+            return Err(anyhow!("Session title can't be empty"));
+        }
+        let updated = self.connection.execute(
+            "UPDATE sessions SET title = ?2 WHERE id = ?1",
+            params![session_id, title]
+        )?;
+        if updated == 0 {
+            return Err(anyhow!("No chat session {}", session_id));
+        }
+        Ok(())
+    }
+
+    /// Deletes a session and its messages.
+    pub fn delete_session(&self, session_id: i64) -> Result<()> {
+        let deleted = self.connection.execute("DELETE FROM sessions WHERE id = ?1", [session_id])?;
+        if deleted == 0 {
+            return Err(anyhow!("No chat session {}", session_id));
+        }
+        Ok(())
+    }
+
+    /// Messages containing every word of `query` (word prefixes match too),
+    /// best match first.
+    pub fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchHit>> {
+        let Some(query) = fts_query(query) else {
+            return Ok(Vec::new());
+        };
+        let mut statement = self.connection.prepare(
+            &format!(
+                "SELECT {}, f.rowid, m.role,
+                        snippet(messages_fts, 0, '[', ']', '...', 12)
+                 FROM messages_fts f
+                 JOIN messages m ON m.id = f.rowid
+                 JOIN sessions s ON s.id = m.session_id
+                 WHERE messages_fts MATCH ?1
+                 ORDER BY f.rank
+                 LIMIT ?2",
+                SUMMARY_COLUMNS
+            )
+        )?;
+        let hits = statement
+            .query_map(params![query, limit as i64], |row| {
+                Ok(SearchHit {
+                    session: summary_from_row(row)?,
+                    message_id: row.get(5)?,
+                    role: row.get(6)?,
+                    snippet: row.get(7)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(hits)
+    }
+}
+
+/// First line of the message, cut at a word near `TITLE_CHARS`
+fn title_from(message: &str) -> String {
+    let line = message.trim().lines().next().unwrap_or("").trim();
+    if line.is_empty() {
+        return "New chat".to_string();
+    }
+    if line.chars().count() <= TITLE_CHARS {
+        return line.to_string();
+    }
+    let cut: String = line.chars().take(TITLE_CHARS).collect();
+    let cut = match cut.rfind(char::is_whitespace) {
+        Some(index) if index > TITLE_CHARS / 2 => &cut[..index],
+        _ => cut.as_str(),
+    };
+    format!("{}...", cut.trim_end())
+}
+
+/// User text as an FTS5 query: each word quoted, so punctuation and
+/// operators are searched for literally, and matched as a prefix.
+/// `None` if there are no words.
+pub fn fts_query(query: &str) -> Option<String> {
+    let terms: Vec<String> = query
+        .split_whitespace()
+        .map(|term| format!("\"{}\"*", term.replace('"', "\"\"")))
+        .collect();
+    (!terms.is_empty()).then(|| terms.join(" "))
+}
+
+lazy_static! {
+    static ref CHAT_STORE: SyncMutex<Option<ChatStore>> = SyncMutex::new(None);
+}
+
+/// Runs `f` on the chat store at `HISTORY_DB_PATH`, opening it on first use.
+pub fn with_store<T>(f: impl FnOnce(&mut ChatStore) -> Result<T>) -> Result<T, String> {
+    let mut store = CHAT_STORE.lock().map_err(|err| format!("Failed to lock chat store: {}", err))?;
+    if store.is_none() {
+        *store = Some(
+            ChatStore::open(Path::new(HISTORY_DB_PATH)).map_err(|err|
+                format!("Failed to open chat history: {}", err)
+            )?
+        );
+    }
+    f(store.as_mut().unwrap()).map_err(|err| err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use rusqlite::Connection;
+    use serde_json::{ json, Value };
+
+    use crate::test_support::TempDatabase;
+    use super::migrations::MIGRATIONS;
+    use super::*;
+
+    fn schema_version(path: &Path) -> usize {
+        let connection = Connection::open(path).unwrap();
+        connection.query_row("PRAGMA user_version", [], |row| row.get(0)).unwrap()
+    }
+
+    /// A store with two conversations: a question and answer about the
+    /// borrow checker, asked with some screen context, then a Lisbon trip.
+    /// Returns their ids and that context.
+    fn two_sessions(store: &mut ChatStore) -> (i64, i64, Value) {
+        let context = json!({
+            "screen": { "windows": [{ "title": "Terminal", "text": "cargo build" }] }
+        });
+        let title = "How do I fix this borrow checker error?\nsecond line";
+        let first = store.create_session(title).unwrap();
+        let question = ChatMessage::new("user", "How do I fix this borrow checker error?");
+        store.add_message(first, &question, Some(&context)).unwrap();
+        let answer = ChatMessage::new("assistant", "Clone the value before the loop.");
+        store.add_message(first, &answer, None).unwrap();
+        let second = store.create_session("Plan a trip to Lisbon").unwrap();
+        let text = "Plan a trip to Lisbon, C++ style \"quotes\" AND NOT";
+        let question = ChatMessage::new("user", text);
+        store.add_message(second, &question, None).unwrap();
+        (first, second, context)
+    }
+
+    #[test]
+    fn fresh_database_migrates_to_latest() {
+        let database = TempDatabase::new("fresh");
+        ChatStore::open(database.path()).unwrap();
+        // Opening again finds nothing to migrate
+        ChatStore::open(database.path()).unwrap();
+        assert_eq!(schema_version(database.path()), MIGRATIONS.len());
+    }
+
+    #[test]
+    fn first_schema_upgrades_and_indexes_old_messages() {
+        // Written by the first release, before search existed
+        let database = TempDatabase::new("v1");
+        {
+            let connection = Connection::open(database.path()).unwrap();
+            connection.execute_batch(MIGRATIONS[0]).unwrap();
+            connection.pragma_update(None, "user_version", 1).unwrap();
+            connection
+                .execute(
+                    "INSERT INTO sessions (id, title, created_at, updated_at)
+                     VALUES (1, 'Old', '2024-01-01T00:00:00Z', '2024-01-01T00:00:00Z')",
+                    []
+                )
+                .unwrap();
+            connection
+                .execute(
+                    "INSERT INTO messages (session_id, role, content, created_at)
+                     VALUES (1, 'user', 'what is a lighthouse keeper', '2024-01-01T00:00:00Z')",
+                    []
+                )
+                .unwrap();
+        }
+        let store = ChatStore::open(database.path()).unwrap();
+        assert_eq!(schema_version(database.path()), MIGRATIONS.len());
+        assert_eq!(store.search("lighthouse", 10).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn newer_schema_is_refused() {
+        let database = TempDatabase::new("newer");
+        Connection::open(database.path())
+            .unwrap()
+            .pragma_update(None, "user_version", MIGRATIONS.len() + 1)
+            .unwrap();
+        assert!(ChatStore::open(database.path()).is_err());
+    }
+
+    #[test]
+    fn sessions_keep_their_messages_and_context() {
+        let database = TempDatabase::new("sessions");
+        let mut store = ChatStore::open(database.path()).unwrap();
+        let (first, second, context) = two_sessions(&mut store);
+
+        let sessions = store.list_sessions().unwrap();
+        let counts: Vec<(i64, u32)> = sessions
+            .iter()
+            .map(|session| (session.id, session.message_count))
+            .collect();
+        assert_eq!(counts, [(second, 1), (first, 2)], "newest first");
+        assert_eq!(sessions[1].title, "How do I fix this borrow checker error?");
+        drop(store);
+
+        let store = ChatStore::open(database.path()).unwrap();
+        let session = store.session(first).unwrap();
+        assert_eq!(session.messages.len(), 2);
+        assert_eq!(session.messages[0].context.as_ref(), Some(&context));
+        assert!(session.messages[1].context.is_none());
+        assert_eq!(session.messages[1].content, "Clone the value before the loop.");
+        let roles: Vec<String> = store
+            .history(first)
+            .unwrap()
+            .into_iter()
+            .map(|message| message.role)
+            .collect();
+        assert_eq!(roles, ["user", "assistant"]);
+    }
+
+    #[test]
+    fn rename_trims_and_refuses_empty_titles() {
+        let database = TempDatabase::new("rename");
+        let mut store = ChatStore::open(database.path()).unwrap();
+        let (_, second, _) = two_sessions(&mut store);
+
+        store.rename_session(second, "  Lisbon  ").unwrap();
+        assert_eq!(store.session(second).unwrap().summary.title, "Lisbon");
+        assert!(store.rename_session(second, " ").is_err());
+    }
+
+    #[test]
+    fn search_finds_words_and_prefixes() {
+        let database = TempDatabase::new("search");
+        let mut store = ChatStore::open(database.path()).unwrap();
+        let (first, second, _) = two_sessions(&mut store);
+
+        let hits = store.search("borrow", 10).unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].session.id, first);
+        assert!(hits[0].snippet.contains("[borrow]"), "{}", hits[0].snippet);
+        let hits = store.search("lisb", 10).unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].session.id, second);
+        assert!(store.search("  ", 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn search_treats_fts_syntax_as_text() {
+        let database = TempDatabase::new("syntax");
+        let mut store = ChatStore::open(database.path()).unwrap();
+        two_sessions(&mut store);
+
+        for query in ["C++", "(", "*"] {
+            store.search(query, 10).unwrap();
+        }
+        assert_eq!(store.search("\"quotes\"", 10).unwrap().len(), 1);
+        assert_eq!(store.search("AND NOT", 10).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn delete_removes_messages_and_search_index() {
+        let database = TempDatabase::new("delete");
+        let mut store = ChatStore::open(database.path()).unwrap();
+        let (first, _, _) = two_sessions(&mut store);
+
+        store.delete_session(first).unwrap();
+        assert_eq!(store.list_sessions().unwrap().len(), 1);
+        assert!(store.session(first).is_err());
+        let orphans: i64 = Connection::open(database.path())
+            .unwrap()
+            .query_row("SELECT COUNT(*) FROM messages WHERE session_id = ?1", [first], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(orphans, 0);
+        assert!(store.search("borrow", 10).unwrap().is_empty());
+
+        // Messages need an existing session
+        assert!(store.add_message(first, &ChatMessage::new("user", "hello"), None).is_err());
+        assert!(store.delete_session(first).is_err());
+    }
+}
@@ -0,0 +1,298 @@
+use serde::Serialize;
+
+use crate::settings::Settings;
+use super::tokenizer::TokenCounter;
+use super::ChatMessage;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Section {
+    System,
+    Screen,
+    Audio,
+    Recalled,
+    History,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SectionReport {
+    pub section: Section,
+    /// Tokens the section wanted
+    pub requested: usize,
+    /// Tokens it got
+    pub used: usize,
+    pub dropped: usize,
+}
+
+/// What the budgeter kept and cut, sent to the UI as `context-report`.
+#[derive(Debug, Clone, Serialize)]
+pub struct BudgetReport {
+    pub total_budget: usize,
+    pub sections: Vec<SectionReport>,
+    /// Oldest chat messages left out of the request
+    pub dropped_messages: usize,
+}
+
+pub struct PromptParts<'a> {
+    pub system_prompt: &'a str,
+    pub screen: &'a str,
+    pub audio: &'a str,
+    pub recalled: &'a str,
+    pub history: &'a [ChatMessage],
+}
+
+pub struct BudgetedPrompt {
+    pub system_prompt: String,
+    pub screen: String,
+    pub audio: String,
+    pub recalled: String,
+    pub history: Vec<ChatMessage>,
+    pub report: BudgetReport,
+}
+
+/// Splits one token budget between system prompt, screen, audio, recalled
+/// context and chat history by weight. A section that needs less than its
+/// share gives the rest back to the others, so weights only matter when
+/// sections compete.
+pub struct ContextBudget {
+    pub total_tokens: usize,
+    pub weights: [(Section, f32); 5],
+}
+
+impl ContextBudget {
+    pub fn from_settings(settings: &Settings) -> Self {
+        Self {
+            total_tokens: settings.context_budget_tokens,
+            weights: [
+                (Section::System, settings.budget_weight_system),
+                (Section::Screen, settings.budget_weight_screen),
+                (Section::Audio, settings.budget_weight_audio),
+                (Section::Recalled, settings.budget_weight_recalled),
+                (Section::History, settings.budget_weight_history),
+            ],
+        }
+    }
+
+    pub fn apply(&self, counter: &dyn TokenCounter, parts: &PromptParts) -> BudgetedPrompt {
+        let message_tokens: Vec<usize> = parts.history
+            .iter()
+            .map(|message| counter.count(&message.content))
+            .collect();
+        let requested = [
+            counter.count(parts.system_prompt),
+            counter.count(parts.screen),
+            counter.count(parts.audio),
+            counter.count(parts.recalled),
+            message_tokens.iter().sum(),
+        ];
+        let weights = self.weights.map(|(_, weight)| weight.max(0.0));
+        let allotted = allocate(self.total_tokens, &requested, &weights);
+
+        // Prompt, screen and recalled context (best first) keep their
+        // beginning, audio its most recent end, history its most recent whole
+        // messages
+        let system_prompt = counter.truncate_start(parts.system_prompt, allotted[0]);
+        let screen = counter.truncate_start(parts.screen, allotted[1]);
+        let audio = counter.truncate_end(parts.audio, allotted[2]);
+        let recalled = counter.truncate_start(parts.recalled, allotted[3]);
+
+        // The newest message is the one being answered, so it stays even if
+        // it has to be cut to the whole share; older turns go whole
+        let mut history = Vec::new();
+        let mut history_used = 0;
+        let mut dropped_messages = 0;
+        if let Some((newest, older)) = parts.history.split_last() {
+            let mut newest = newest.clone();
+            newest.content = counter.truncate_start(&newest.content, allotted[4]);
+            history_used = counter.count(&newest.content).min(allotted[4]);
+
+            let mut kept = 0;
+            for tokens in message_tokens[..older.len()].iter().rev() {
+                if history_used + tokens > allotted[4] {
+                    break;
+                }
+                history_used += tokens;
+                kept += 1;
+            }
+            history.extend_from_slice(&older[older.len() - kept..]);
+            history.push(newest);
+            dropped_messages = older.len() - kept;
+        }
+
+        let used = [
+            allotted[0].min(requested[0]),
+            allotted[1].min(requested[1]),
+            allotted[2].min(requested[2]),
+            allotted[3].min(requested[3]),
+            history_used,
+        ];
+        let sections = self.weights
+            .iter()
+            .enumerate()
+            .map(|(i, (section, _))| SectionReport {
+                section: *section,
+                requested: requested[i],
+                used: used[i],
+                dropped: requested[i] - used[i],
+            })
+            .collect();
+
+        BudgetedPrompt {
+            system_prompt,
+            screen,
+            audio,
+            recalled,
+            history,
+            report: BudgetReport {
+                total_budget: self.total_tokens,
+                sections,
+                dropped_messages,
+            },
+        }
+    }
+}
+
+/// Weighted water-filling: every round, sections that fit in their share of
+/// what's left are granted in full and leave; once none fit, the remaining
+/// sections split what's left by weight.
+pub(crate) fn allocate(total: usize, requested: &[usize], weights: &[f32]) -> Vec<usize> {
+    let mut allotted = vec![0usize; requested.len()];
+    let mut open: Vec<usize> = (0..requested.len()).filter(|&i| requested[i] > 0).collect();
+    let mut remaining = total;
+
+    loop {
+        let weight_sum: f32 = open
+            .iter()
+            .map(|&i| weights[i])
+            .sum();
+        if open.is_empty() || weight_sum <= 0.0 {
+            break;
+        }
+        let share = |i: usize| (((remaining as f32) * weights[i]) / weight_sum) as usize;
+
+        let satisfied: Vec<usize> = open
+            .iter()
+            .copied()
+            .filter(|&i| requested[i] <= share(i))
+            .collect();
+        if satisfied.is_empty() {
+            for &i in &open {
+                allotted[i] = share(i);
+            }
+            break;
+        }
+        for &i in &satisfied {
+            allotted[i] = requested[i];
+            remaining -= requested[i];
+        }
+        open.retain(|i| !satisfied.contains(i));
+    }
+
+    allotted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tokenizer::TokenCounter;
+    use super::*;
+
+    /// One token per character
+    struct CharCounter;
+
+    impl TokenCounter for CharCounter {
+        fn encode(&self, text: &str) -> Vec<u32> {
+            text.chars()
+                .map(|c| c as u32)
+                .collect()
+        }
+
+        fn decode(&self, tokens: &[u32]) -> String {
+            tokens
+                .iter()
+                .filter_map(|&token| char::from_u32(token))
+                .collect()
+        }
+    }
+
+    fn equal_weights(total_tokens: usize) -> ContextBudget {
+        ContextBudget {
+            total_tokens,
+            weights: [
+                (Section::System, 1.0),
+                (Section::Screen, 1.0),
+                (Section::Audio, 1.0),
+                (Section::Recalled, 1.0),
+                (Section::History, 1.0),
+            ],
+        }
+    }
+
+    fn apply(budget: &ContextBudget, history: &[ChatMessage]) -> BudgetedPrompt {
+        let parts = PromptParts {
+            system_prompt: "",
+            screen: "",
+            audio: "",
+            recalled: "",
+            history,
+        };
+        budget.apply(&CharCounter, &parts)
+    }
+
+    fn contents(history: &[ChatMessage]) -> Vec<&str> {
+        history
+            .iter()
+            .map(|message| message.content.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn oldest_turns_are_dropped_first() {
+        let history = [
+            ChatMessage::new("user", "aaaa"),
+            ChatMessage::new("assistant", "bbbb"),
+            ChatMessage::new("user", "cc"),
+        ];
+        let budgeted = apply(&equal_weights(7), &history);
+        assert_eq!(contents(&budgeted.history), ["bbbb", "cc"]);
+        assert_eq!(budgeted.report.dropped_messages, 1);
+        assert_eq!(budgeted.report.sections[4].used, 6);
+    }
+
+    #[test]
+    fn newest_message_is_cut_rather_than_dropped() {
+        let history = [
+            ChatMessage::new("assistant", "bbbb"),
+            ChatMessage::new("user", "0123456789"),
+        ];
+        let budgeted = apply(&equal_weights(6), &history);
+        assert_eq!(contents(&budgeted.history), ["012345"]);
+        assert_eq!(budgeted.history[0].role, "user");
+        assert_eq!(budgeted.report.dropped_messages, 1);
+        let report = &budgeted.report.sections[4];
+        assert_eq!((report.requested, report.used, report.dropped), (14, 6, 8));
+    }
+
+    #[test]
+    fn sections_share_what_is_left() {
+        let budget = equal_weights(10);
+        let parts = PromptParts {
+            system_prompt: "sys",
+            screen: "screen text that is long",
+            audio: "",
+            recalled: "",
+            history: &[ChatMessage::new("user", "hi")],
+        };
+        let budgeted = budget.apply(&CharCounter, &parts);
+        assert_eq!(budgeted.system_prompt, "sys");
+        assert_eq!(contents(&budgeted.history), ["hi"]);
+        // What the others didn't need goes to the screen
+        assert_eq!(budgeted.screen, "scree");
+    }
+
+    #[test]
+    fn allocate_gives_unused_share_to_the_rest() {
+        assert_eq!(allocate(100, &[10, 200, 200], &[1.0, 1.0, 1.0]), [10, 45, 45]);
+        assert_eq!(allocate(100, &[10, 200, 200], &[1.0, 3.0, 1.0]), [10, 67, 22]);
+        assert_eq!(allocate(100, &[10, 20, 0], &[1.0, 1.0, 1.0]), [10, 20, 0]);
+    }
+}
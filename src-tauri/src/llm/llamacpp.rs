@@ -0,0 +1,162 @@
+use anyhow::{ anyhow, Result };
+use futures::future::BoxFuture;
+use serde_json::{ json, Value };
+
+use super::{ post_json, read_sse, ChatReply, ChatRequest, LlmProvider, TokenCallback };
+
+/// llama.cpp's `llama-server` native API: the chat is rendered with the
+/// model's own template via `/apply-template`, then streamed from
+/// `/completion`. `base_url` is the server root, e.g. `http://localhost:8080`.
+/// The model is whatever the server was started with. Tools aren't offered
+/// on this API; use the OpenAI-compatible provider with `--jinja` for them.
+pub struct LlamaCppProvider {
+    http: reqwest::Client,
+    base_url: String,
+    api_key: String,
+}
+
+impl LlamaCppProvider {
+    pub fn new(base_url: &str, api_key: &str) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key: api_key.to_string(),
+        }
+    }
+}
+
+impl LlmProvider for LlamaCppProvider {
+    fn stream_chat<'a>(
+        &'a self,
+        request: &'a ChatRequest<'a>,
+        on_token: TokenCallback<'a>
+    ) -> BoxFuture<'a, Result<ChatReply>> {
+        Box::pin(async move {
+            let template: Value = post_json(
+                &self.http,
+                format!("{}/apply-template", self.base_url),
+                &self.api_key,
+                &json!({ "messages": request.messages })
+            ).await?.json().await?;
+            let prompt = template["prompt"]
+                .as_str()
+                .ok_or_else(|| anyhow!("apply-template returned no prompt: {}", template))?;
+
+            let payload =
+                json!({
+                "prompt": prompt,
+                "stream": true,
+                "temperature": request.temperature,
+                "top_p": request.top_p,
+                "n_predict": request.max_tokens,
+                "cache_prompt": true,
+            });
+            let response = post_json(
+                &self.http,
+                format!("{}/completion", self.base_url),
+                &self.api_key,
+                &payload
+            ).await?;
+
+            let mut full_message = String::new();
+            read_sse(response, |event| {
+                let data = event.data.trim();
+                let parsed: Value = serde_json
+                    ::from_str(data)
+                    .map_err(|err| anyhow!("Invalid stream chunk {}: {}", data, err))?;
+                if let Some(error) = parsed.get("error") {
+                    return Err(anyhow!("llama.cpp error: {}", error));
+                }
+                if let Some(content) = parsed["content"].as_str() {
+                    if !content.is_empty() {
+                        full_message.push_str(content);
+                        on_token(content);
+                    }
+                }
+                Ok(!parsed["stop"].as_bool().unwrap_or(false))
+            }).await?;
+
+            Ok(ChatReply { content: full_message, tool_calls: Vec::new() })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::llm::ChatMessage;
+    use crate::test_support::{ mock_server, MockResponse };
+    use super::*;
+
+    fn event(data: Value) -> String {
+        format!("data: {}\n\n", data)
+    }
+
+    async fn chat(base_url: &str) -> (Result<ChatReply>, Vec<String>) {
+        let provider = LlamaCppProvider::new(base_url, "");
+        let messages = [ChatMessage::new("user", "Hi")];
+        let request = ChatRequest {
+            model: "",
+            messages: &messages,
+            temperature: 0.5,
+            top_p: 1.0,
+            max_tokens: 64,
+            tools: &[],
+        };
+        let mut tokens = Vec::new();
+        let mut on_token = |token: &str| tokens.push(token.to_string());
+        let reply = provider.stream_chat(&request, &mut on_token).await;
+        (reply, tokens)
+    }
+
+    #[tokio::test]
+    async fn renders_the_template_then_streams_deltas() {
+        let template = json!({ "prompt": "<|user|>Hi<|assistant|>" }).to_string();
+        let completion = [
+            event(json!({ "content": "Hello", "stop": false })),
+            event(json!({ "content": " there", "stop": false })),
+            event(json!({ "content": "", "stop": true })),
+        ];
+        let (base_url, server) = mock_server(
+            vec![MockResponse::ok(&[template]), MockResponse::ok(&completion)]
+        ).await;
+
+        let (reply, tokens) = chat(&base_url).await;
+        assert_eq!(tokens, ["Hello", " there"]);
+        let reply = reply.unwrap();
+        assert_eq!(reply.content, "Hello there");
+        assert!(reply.tool_calls.is_empty());
+
+        let requests = server.await.unwrap();
+        assert_eq!(requests[0].path(), "/apply-template");
+        assert_eq!(requests[0].body["messages"][0]["content"], "Hi");
+        assert_eq!(requests[1].path(), "/completion");
+        assert_eq!(requests[1].body["prompt"], "<|user|>Hi<|assistant|>");
+        assert_eq!(requests[1].body["n_predict"], 64);
+        assert!(requests[1].header("authorization").is_none());
+    }
+
+    #[tokio::test]
+    async fn fails_with_the_error_body() {
+        let response = MockResponse::error(503, r#"{"error":"Loading model"}"#);
+        let (base_url, _server) = mock_server(vec![response]).await;
+
+        let err = chat(&base_url).await.0.unwrap_err().to_string();
+        assert!(err.contains("503") && err.contains("Loading model"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn fails_on_an_error_event() {
+        let template = json!({ "prompt": "Hi" }).to_string();
+        let completion = [
+            event(json!({ "content": "Hel", "stop": false })),
+            event(json!({ "error": { "message": "context full" } })),
+        ];
+        let (base_url, _server) = mock_server(
+            vec![MockResponse::ok(&[template]), MockResponse::ok(&completion)]
+        ).await;
+
+        let (reply, tokens) = chat(&base_url).await;
+        assert_eq!(tokens, ["Hel"]);
+        assert!(reply.unwrap_err().to_string().contains("context full"));
+    }
+}
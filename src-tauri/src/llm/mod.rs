@@ -0,0 +1,172 @@
+use anyhow::{ anyhow, Result };
+use futures::future::BoxFuture;
+use serde::{ Deserialize, Serialize };
+
+use crate::settings::{ LlmBackend, Settings };
+
+mod openai;
+mod ollama;
+mod llamacpp;
+pub mod sse;
+pub mod tokenizer;
+pub mod budget;
+pub mod tools;
+
+pub use openai::OpenAiCompatibleProvider;
+pub use ollama::OllamaProvider;
+pub use llamacpp::LlamaCppProvider;
+
+use sse::{ SseDecoder, SseEvent };
+use tools::{ ToolCall, ToolDefinition };
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChatMessage {
+    pub role: String,
+    pub content: String,
+    /// Tools an assistant message asked to call
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCall>>,
+    /// The call a "tool" message is the result of
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+}
+
+impl ChatMessage {
+    pub fn new(role: &str, content: &str) -> Self {
+        Self {
+            role: role.to_string(),
+            content: content.to_string(),
+            tool_calls: None,
+            tool_call_id: None,
+        }
+    }
+
+    /// An assistant turn that calls tools, with whatever it said first
+    pub fn tool_calls(content: &str, calls: Vec<ToolCall>) -> Self {
+        Self { tool_calls: Some(calls), ..Self::new("assistant", content) }
+    }
+
+    /// What a tool call returned
+    pub fn tool_result(call_id: &str, content: &str) -> Self {
+        Self { tool_call_id: Some(call_id.to_string()), ..Self::new("tool", content) }
+    }
+}
+
+pub struct ChatRequest<'a> {
+    pub model: &'a str,
+    pub messages: &'a [ChatMessage],
+    pub temperature: f32,
+    pub top_p: f32,
+    pub max_tokens: u32,
+    /// Tools the model may call, none by default
+    pub tools: &'a [ToolDefinition],
+}
+
+impl<'a> ChatRequest<'a> {
+    /// Model and sampling parameters as configured in `settings`
+    pub fn from_settings(settings: &'a Settings, messages: &'a [ChatMessage]) -> Self {
+        Self {
+            model: &settings.llm_model,
+            messages,
+            temperature: settings.llm_temperature,
+            top_p: settings.llm_top_p,
+            max_tokens: settings.llm_max_tokens,
+            tools: &[],
+        }
+    }
+}
+
+/// A finished reply: its text, and the tools it asks to call, if any.
+#[derive(Debug, Clone, Default)]
+pub struct ChatReply {
+    pub content: String,
+    pub tool_calls: Vec<ToolCall>,
+}
+
+pub type TokenCallback<'a> = &'a mut (dyn FnMut(&str) + Send);
+
+/// A chat backend. Implementations stream the reply, calling `on_token` for
+/// every piece of content as it arrives, and resolve with the full reply.
+/// Backends that can't call tools ignore `request.tools`.
+pub trait LlmProvider: Send + Sync {
+    fn stream_chat<'a>(
+        &'a self,
+        request: &'a ChatRequest<'a>,
+        on_token: TokenCallback<'a>
+    ) -> BoxFuture<'a, Result<ChatReply>>;
+}
+
+pub fn provider_from_settings(settings: &Settings) -> Box<dyn LlmProvider> {
+    let base_url = settings.llm_base_url.as_str();
+    let api_key = settings.api_key.as_str();
+    match settings.llm_provider {
+        LlmBackend::OpenAi => Box::new(OpenAiCompatibleProvider::new(base_url, api_key)),
+        LlmBackend::Ollama => Box::new(OllamaProvider::new(base_url)),
+        LlmBackend::LlamaCpp => Box::new(LlamaCppProvider::new(base_url, api_key)),
+    }
+}
+
+/// POSTs `payload` and fails with the response body on a non-2xx status.
+async fn post_json(
+    http: &reqwest::Client,
+    url: String,
+    api_key: &str,
+    payload: &serde_json::Value
+) -> Result<reqwest::Response> {
+    let mut request = http.post(url).json(payload);
+    if !api_key.is_empty() {
+        request = request.bearer_auth(api_key);
+    }
+    let response = request.send().await?;
+
+    if !response.status().is_success() {
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        return Err(anyhow!("Request failed with {}: {}", status, body));
+    }
+    Ok(response)
+}
+
+/// Calls `on_line` for every line of a streamed body until it returns
+/// `Ok(false)` or the body ends. A chunk can end anywhere, even inside a
+/// UTF-8 sequence, so incomplete lines are held back.
+async fn read_lines<F>(mut response: reqwest::Response, mut on_line: F) -> Result<()>
+    where F: FnMut(&str) -> Result<bool>
+{
+    let mut pending: Vec<u8> = Vec::new();
+
+    while let Some(chunk) = response.chunk().await? {
+        pending.extend_from_slice(&chunk);
+
+        while let Some(newline) = pending.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = pending.drain(..=newline).collect();
+            if !on_line(String::from_utf8_lossy(&line).trim())? {
+                return Ok(());
+            }
+        }
+    }
+    if !pending.is_empty() {
+        on_line(String::from_utf8_lossy(&pending).trim())?;
+    }
+    Ok(())
+}
+
+/// Calls `on_event` for every Server-Sent Event of a streamed body until it
+/// returns `Ok(false)` or the body ends.
+async fn read_sse<F>(mut response: reqwest::Response, mut on_event: F) -> Result<()>
+    where F: FnMut(&SseEvent) -> Result<bool>
+{
+    let mut decoder = SseDecoder::new();
+
+    while let Some(chunk) = response.chunk().await? {
+        for event in decoder.feed(&chunk) {
+            if !on_event(&event)? {
+                return Ok(());
+            }
+        }
+    }
+    if let Some(event) = decoder.finish() {
+        on_event(&event)?;
+    }
+    Ok(())
+}
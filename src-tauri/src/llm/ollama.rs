@@ -0,0 +1,233 @@
+use anyhow::{ anyhow, Result };
+use futures::future::BoxFuture;
+use serde_json::{ json, Value };
+
+use super::tools::ToolCall;
+use super::{
+    post_json,
+    read_lines,
+    ChatMessage,
+    ChatReply,
+    ChatRequest,
+    LlmProvider,
+    TokenCallback,
+};
+
+/// Ollama's native `/api/chat`, which streams newline-delimited JSON.
+/// `base_url` is the server root, e.g. `http://localhost:11434`.
+pub struct OllamaProvider {
+    http: reqwest::Client,
+    base_url: String,
+}
+
+impl OllamaProvider {
+    pub fn new(base_url: &str) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+        }
+    }
+}
+
+impl LlmProvider for OllamaProvider {
+    fn stream_chat<'a>(
+        &'a self,
+        request: &'a ChatRequest<'a>,
+        on_token: TokenCallback<'a>
+    ) -> BoxFuture<'a, Result<ChatReply>> {
+        Box::pin(async move {
+            let mut payload =
+                json!({
+                "model": request.model,
+                "messages": ollama_messages(request.messages),
+                "stream": true,
+                "options": {
+                    "temperature": request.temperature,
+                    "top_p": request.top_p,
+                    "num_predict": request.max_tokens,
+                },
+            });
+            // Models without tool support refuse any list, even an empty one
+            if !request.tools.is_empty() {
+                payload["tools"] = json!(request.tools);
+            }
+            // Ollama sends calls without ids: they are numbered on from the
+            // calls earlier in the conversation, so results of every round
+            // stay paired with their call
+            let earlier_calls: usize = request.messages
+                .iter()
+                .filter_map(|message| message.tool_calls.as_ref())
+                .map(Vec::len)
+                .sum();
+            let response = post_json(
+                &self.http,
+                format!("{}/api/chat", self.base_url),
+                "",
+                &payload
+            ).await?;
+
+            let mut full_message = String::new();
+            let mut tool_calls = Vec::new();
+            read_lines(response, |line| {
+                if line.is_empty() {
+                    return Ok(true);
+                }
+                let parsed: Value = serde_json
+                    ::from_str(line)
+                    .map_err(|err| anyhow!("Invalid stream chunk {}: {}", line, err))?;
+                if let Some(error) = parsed["error"].as_str() {
+                    return Err(anyhow!("Ollama error: {}", error));
+                }
+                if let Some(content) = parsed["message"]["content"].as_str() {
+                    if !content.is_empty() {
+                        full_message.push_str(content);
+                        on_token(content);
+                    }
+                }
+                // Whole calls, without ids
+                for call in parsed["message"]["tool_calls"].as_array().into_iter().flatten() {
+                    if let Some(name) = call["function"]["name"].as_str() {
+                        let id = format!("call_{}", earlier_calls + tool_calls.len());
+                        let arguments = call["function"]["arguments"].to_string();
+                        tool_calls.push(ToolCall::new(&id, name, &arguments));
+                    }
+                }
+                Ok(!parsed["done"].as_bool().unwrap_or(false))
+            }).await?;
+
+            Ok(ChatReply { content: full_message, tool_calls })
+        })
+    }
+}
+
+/// Messages as Ollama takes them: tool call arguments are objects, not
+/// JSON text
+fn ollama_messages(messages: &[ChatMessage]) -> Vec<Value> {
+    messages
+        .iter()
+        .map(|message| {
+            let mut value = json!({ "role": message.role, "content": message.content });
+            if let Some(calls) = &message.tool_calls {
+                value["tool_calls"] = calls
+                    .iter()
+                    .map(|call| {
+                        let arguments: Value = serde_json
+                            ::from_str(&call.function.arguments)
+                            .unwrap_or_else(|_| json!({}));
+                        json!({
+                            "function": { "name": call.function.name, "arguments": arguments },
+                        })
+                    })
+                    .collect();
+            }
+            value
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::llm::ChatMessage;
+    use crate::test_support::{ mock_server, MockResponse };
+    use super::*;
+
+    fn line(chunk: Value) -> String {
+        format!("{}\n", chunk)
+    }
+
+    async fn chat(base_url: &str, messages: &[ChatMessage]) -> (Result<ChatReply>, Vec<String>) {
+        let provider = OllamaProvider::new(&format!("{}/", base_url));
+        let request = ChatRequest {
+            model: "llama3.1",
+            messages,
+            temperature: 0.5,
+            top_p: 1.0,
+            max_tokens: 64,
+            tools: &[],
+        };
+        let mut tokens = Vec::new();
+        let mut on_token = |token: &str| tokens.push(token.to_string());
+        let reply = provider.stream_chat(&request, &mut on_token).await;
+        (reply, tokens)
+    }
+
+    #[tokio::test]
+    async fn streams_deltas_and_tool_calls() {
+        let split = line(json!({ "message": { "content": "lo" }, "done": false }));
+        let (split_start, split_end) = split.split_at(10);
+        let chunks = [
+            line(json!({ "message": { "content": "Hel" }, "done": false })) + split_start,
+            split_end.to_string(),
+            line(
+                json!({
+                "message": {
+                    "content": "",
+                    "tool_calls": [
+                        { "function": { "name": "get_time", "arguments": { "zone": "UTC" } } },
+                        { "function": { "name": "list_windows", "arguments": {} } },
+                    ],
+                },
+                "done": false,
+            })
+            ),
+            line(json!({ "message": { "content": "" }, "done": true })),
+            // Past `done`, never read
+            line(json!({ "message": { "content": "ignored" }, "done": false })),
+        ];
+        let (base_url, server) = mock_server(vec![MockResponse::ok(&chunks)]).await;
+        let earlier_call = ToolCall::new("call_0", "get_time", r#"{"zone":"UTC"}"#);
+        let messages = [
+            ChatMessage::new("user", "What time is it?"),
+            ChatMessage::tool_calls("", vec![earlier_call]),
+            ChatMessage::tool_result("call_0", "12:00"),
+        ];
+
+        let (reply, tokens) = chat(&base_url, &messages).await;
+        let reply = reply.unwrap();
+        assert_eq!(tokens, ["Hel", "lo"]);
+        assert_eq!(reply.content, "Hello");
+        let calls: Vec<(&str, &str)> = reply.tool_calls
+            .iter()
+            .map(|call| (call.function.name.as_str(), call.function.arguments.as_str()))
+            .collect();
+        assert_eq!(calls, [("get_time", r#"{"zone":"UTC"}"#), ("list_windows", "{}")]);
+        // Numbered on from the call already answered
+        let ids: Vec<&str> = reply.tool_calls
+            .iter()
+            .map(|call| call.id.as_str())
+            .collect();
+        assert_eq!(ids, ["call_1", "call_2"]);
+
+        let requests = server.await.unwrap();
+        assert_eq!(requests[0].path(), "/api/chat");
+        assert_eq!(requests[0].body["options"]["num_predict"], 64);
+        assert!(requests[0].body.get("tools").is_none());
+        // Arguments go back as an object
+        assert_eq!(
+            requests[0].body["messages"][1]["tool_calls"][0]["function"]["arguments"],
+            json!({ "zone": "UTC" })
+        );
+    }
+
+    #[tokio::test]
+    async fn fails_with_the_error_body() {
+        let response = MockResponse::error(404, r#"{"error":"model 'llama3.1' not found"}"#);
+        let (base_url, _server) = mock_server(vec![response]).await;
+
+        let err = chat(&base_url, &[]).await.0.unwrap_err().to_string();
+        assert!(err.contains("404") && err.contains("not found"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn fails_on_an_error_line() {
+        let chunks = [
+            line(json!({ "message": { "content": "Hi" }, "done": false })),
+            line(json!({ "error": "out of memory" })),
+        ];
+        let (base_url, _server) = mock_server(vec![MockResponse::ok(&chunks)]).await;
+
+        let (reply, tokens) = chat(&base_url, &[]).await;
+        assert_eq!(tokens, ["Hi"]);
+        assert!(reply.unwrap_err().to_string().contains("out of memory"));
+    }
+}
@@ -0,0 +1,199 @@
+use anyhow::{ anyhow, Result };
+use futures::future::BoxFuture;
+use serde_json::json;
+
+use super::sse::{ parse_chat_event, tool_call_deltas, StreamEvent };
+use super::tools::ToolCallAccumulator;
+use super::{ post_json, read_sse, ChatReply, ChatRequest, LlmProvider, TokenCallback };
+
+/// Any server speaking OpenAI's `/chat/completions` (Groq, OpenAI, vLLM,
+/// LM Studio, ...). `base_url` is everything before `/chat/completions`,
+/// e.g. `https://api.groq.com/openai/v1`. Tool calls are streamed in
+/// pieces and put back together.
+pub struct OpenAiCompatibleProvider {
+    http: reqwest::Client,
+    base_url: String,
+    api_key: String,
+}
+
+impl OpenAiCompatibleProvider {
+    pub fn new(base_url: &str, api_key: &str) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key: api_key.to_string(),
+        }
+    }
+}
+
+impl LlmProvider for OpenAiCompatibleProvider {
+    fn stream_chat<'a>(
+        &'a self,
+        request: &'a ChatRequest<'a>,
+        on_token: TokenCallback<'a>
+    ) -> BoxFuture<'a, Result<ChatReply>> {
+        Box::pin(async move {
+            let mut payload =
+                json!({
+                "messages": request.messages,
+                "model": request.model,
+                "temperature": request.temperature,
+                "max_tokens": request.max_tokens,
+                "top_p": request.top_p,
+                "stream": true,
+                "stop": null,
+            });
+            // Some servers refuse an empty list
+            if !request.tools.is_empty() {
+                payload["tools"] = json!(request.tools);
+            }
+            let response = post_json(
+                &self.http,
+                format!("{}/chat/completions", self.base_url),
+                &self.api_key,
+                &payload
+            ).await?;
+
+            let mut full_message = String::new();
+            let mut tool_calls = ToolCallAccumulator::default();
+            read_sse(response, |event| {
+                if let Some(deltas) = tool_call_deltas(event) {
+                    tool_calls.feed(&deltas);
+                }
+                match parse_chat_event(event)? {
+                    Some(StreamEvent::Delta { content }) => {
+                        full_message.push_str(&content);
+                        on_token(&content);
+                        Ok(true)
+                    }
+                    Some(StreamEvent::Done) => Ok(false),
+                    Some(StreamEvent::Error { message }) => {
+                        Err(anyhow!("Chat completion error: {}", message))
+                    }
+                    _ => Ok(true),
+                }
+            }).await?;
+
+            Ok(ChatReply { content: full_message, tool_calls: tool_calls.finish() })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{ json, Value };
+
+    use crate::llm::tools::{ FunctionDefinition, ToolCall, ToolDefinition };
+    use crate::llm::ChatMessage;
+    use crate::test_support::{ mock_server, MockResponse };
+    use super::*;
+
+    fn event(data: Value) -> String {
+        format!("data: {}\n\n", data)
+    }
+
+    fn delta(delta: Value) -> String {
+        event(json!({ "choices": [{ "delta": delta }] }))
+    }
+
+    async fn chat(base_url: &str, tools: &[ToolDefinition]) -> (Result<ChatReply>, Vec<String>) {
+        let provider = OpenAiCompatibleProvider::new(&format!("{}/v1/", base_url), "secret");
+        let messages = [ChatMessage::new("user", "What time is it?")];
+        let request = ChatRequest {
+            model: "test-model",
+            messages: &messages,
+            temperature: 0.5,
+            top_p: 1.0,
+            max_tokens: 64,
+            tools,
+        };
+        let mut tokens = Vec::new();
+        let mut on_token = |token: &str| tokens.push(token.to_string());
+        let reply = provider.stream_chat(&request, &mut on_token).await;
+        (reply, tokens)
+    }
+
+    #[tokio::test]
+    async fn streams_deltas_and_tool_calls() {
+        // One event split across two chunks, with CRLF line endings
+        let split = delta(json!({ "content": "check" })).replace('\n', "\r\n");
+        let (split_start, split_end) = split.split_at(20);
+        let chunks = [
+            format!(": keep-alive\n\n{}", delta(json!({ "role": "assistant" }))),
+            delta(json!({ "content": "Let me " })) + split_start,
+            split_end.to_string(),
+            delta(
+                json!({
+                "tool_calls": [{
+                    "index": 0,
+                    "id": "call_7",
+                    "function": { "name": "get_time", "arguments": "{\"zone\":" },
+                }],
+            })
+            ),
+            delta(
+                json!({
+                "tool_calls": [{ "index": 0, "function": { "arguments": "\"UTC\"}" } }],
+            })
+            ),
+            "data: [DONE]\n\n".to_string(),
+        ];
+        let (base_url, server) = mock_server(vec![MockResponse::ok(&chunks)]).await;
+        let tools = [
+            ToolDefinition {
+                kind: "function".to_string(),
+                function: FunctionDefinition {
+                    name: "get_time".to_string(),
+                    description: "Current time".to_string(),
+                    parameters: json!({ "type": "object" }),
+                },
+            },
+        ];
+
+        let (reply, tokens) = chat(&base_url, &tools).await;
+        let reply = reply.unwrap();
+        assert_eq!(tokens, ["Let me ", "check"]);
+        assert_eq!(reply.content, "Let me check");
+        assert_eq!(reply.tool_calls, [ToolCall::new("call_7", "get_time", r#"{"zone":"UTC"}"#)]);
+
+        let requests = server.await.unwrap();
+        assert_eq!(requests[0].path(), "/v1/chat/completions");
+        assert_eq!(requests[0].header("authorization"), Some("Bearer secret"));
+        assert_eq!(requests[0].body["model"], "test-model");
+        assert_eq!(requests[0].body["stream"], true);
+        assert_eq!(requests[0].body["tools"][0]["function"]["name"], "get_time");
+    }
+
+    #[tokio::test]
+    async fn leaves_out_an_empty_tool_list() {
+        // No [DONE], the stream just ends
+        let chunks = [delta(json!({ "content": "Hi" }))];
+        let (base_url, server) = mock_server(vec![MockResponse::ok(&chunks)]).await;
+
+        let (reply, _) = chat(&base_url, &[]).await;
+        assert_eq!(reply.unwrap().content, "Hi");
+        assert!(server.await.unwrap()[0].body.get("tools").is_none());
+    }
+
+    #[tokio::test]
+    async fn fails_with_the_error_body() {
+        let response = MockResponse::error(401, r#"{"error":"invalid api key"}"#);
+        let (base_url, _server) = mock_server(vec![response]).await;
+
+        let err = chat(&base_url, &[]).await.0.unwrap_err().to_string();
+        assert!(err.contains("401") && err.contains("invalid api key"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn fails_on_an_error_event() {
+        let chunks = [
+            delta(json!({ "content": "Hi" })),
+            event(json!({ "error": { "message": "overloaded" } })),
+        ];
+        let (base_url, _server) = mock_server(vec![MockResponse::ok(&chunks)]).await;
+
+        let (reply, tokens) = chat(&base_url, &[]).await;
+        assert_eq!(tokens, ["Hi"]);
+        assert!(reply.unwrap_err().to_string().contains("overloaded"));
+    }
+}
@@ -0,0 +1,334 @@
+use anyhow::{ anyhow, Result };
+use serde::Serialize;
+use serde_json::Value;
+
+/// One dispatched Server-Sent Event.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SseEvent {
+    /// `event:` field, `None` for the default "message" type
+    pub event: Option<String>,
+    /// All `data:` lines of the event joined with `\n`
+    pub data: String,
+    pub id: Option<String>,
+    pub retry: Option<u64>,
+}
+
+/// Incremental `text/event-stream` decoder following the WHATWG framing
+/// rules: `\n`, `\r\n` and `\r` line endings, multi-line `data:`, comment
+/// lines (`:` keep-alives), and events dispatched on a blank line.
+///
+/// Chunks may be split anywhere, including inside a line ending or a UTF-8
+/// sequence; bytes are held until their line is complete.
+pub struct SseDecoder {
+    line: Vec<u8>,
+    /// Last byte was `\r`, so a following `\n` belongs to the same line ending
+    after_cr: bool,
+    /// Nothing decoded yet, a leading BOM is still possible
+    at_start: bool,
+    event: Option<String>,
+    data: String,
+    has_data: bool,
+    id: Option<String>,
+    retry: Option<u64>,
+}
+
+impl Default for SseDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SseDecoder {
+    pub fn new() -> Self {
+        Self {
+            line: Vec::new(),
+            after_cr: false,
+            at_start: true,
+            event: None,
+            data: String::new(),
+            has_data: false,
+            id: None,
+            retry: None,
+        }
+    }
+
+    /// Decodes `chunk` and returns every event it completed.
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        let mut events = Vec::new();
+        for &byte in chunk {
+            if self.after_cr {
+                self.after_cr = false;
+                if byte == b'\n' {
+                    continue;
+                }
+            }
+            match byte {
+                b'\n' => self.end_line(&mut events),
+                b'\r' => {
+                    self.end_line(&mut events);
+                    self.after_cr = true;
+                }
+                _ => self.line.push(byte),
+            }
+        }
+        events
+    }
+
+    /// Flushes at end of stream. The spec drops an event that was never
+    /// terminated by a blank line, but servers that close right after their
+    /// last `data:` line are common enough to be lenient.
+    pub fn finish(&mut self) -> Option<SseEvent> {
+        let mut events = Vec::new();
+        if !self.line.is_empty() {
+            self.end_line(&mut events);
+        }
+        self.end_line(&mut events);
+        events.pop()
+    }
+
+    fn end_line(&mut self, events: &mut Vec<SseEvent>) {
+        let mut bytes = std::mem::take(&mut self.line);
+        if self.at_start {
+            self.at_start = false;
+            if bytes.starts_with(&[0xef, 0xbb, 0xbf]) {
+                bytes.drain(..3);
+            }
+        }
+        let line = String::from_utf8_lossy(&bytes);
+
+        if line.is_empty() {
+            if let Some(event) = self.dispatch() {
+                events.push(event);
+            }
+            return;
+        }
+        if line.starts_with(':') {
+            // comment / keep-alive
+            return;
+        }
+
+        let (field, value) = match line.find(':') {
+            Some(colon) => {
+                let value = &line[colon + 1..];
+                (&line[..colon], value.strip_prefix(' ').unwrap_or(value))
+            }
+            None => (line.as_ref(), ""),
+        };
+        match field {
+            "event" => {
+                self.event = Some(value.to_string());
+            }
+            "data" => {
+                if self.has_data {
+                    self.data.push('\n');
+                }
+                self.data.push_str(value);
+                self.has_data = true;
+            }
+            "id" if !value.contains('\0') => {
+                self.id = Some(value.to_string());
+            }
+            "retry" => {
+                if let Ok(retry) = value.parse() {
+                    self.retry = Some(retry);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn dispatch(&mut self) -> Option<SseEvent> {
+        let event = self.event.take();
+        let retry = self.retry.take();
+        if !self.has_data {
+            return None;
+        }
+        self.has_data = false;
+        Some(SseEvent {
+            event,
+            data: std::mem::take(&mut self.data),
+            // id persists across events per the spec
+            id: self.id.clone(),
+            retry,
+        })
+    }
+}
+
+/// What a chat stream event means to the UI. Sent to the webview as the
+/// payload of `llm-stream` events.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum StreamEvent {
+    Delta {
+        content: String,
+    },
+    Done,
+    Error {
+        message: String,
+    },
+    /// The model called a tool; `arguments` is its JSON text
+    #[serde(rename = "tool_call")]
+    ToolCall {
+        id: String,
+        name: String,
+        arguments: String,
+    },
+    /// A tool call finished, with `error` if it failed
+    #[serde(rename = "tool_result")]
+    ToolResult {
+        id: String,
+        name: String,
+        error: Option<String>,
+    },
+}
+
+/// Interprets an OpenAI-style `chat.completion.chunk` event. `Ok(None)` for
+/// events without content (role-only deltas, usage, finish reasons).
+pub fn parse_chat_event(event: &SseEvent) -> Result<Option<StreamEvent>> {
+    let data = event.data.trim();
+    if data == "[DONE]" {
+        return Ok(Some(StreamEvent::Done));
+    }
+    if event.event.as_deref() == Some("error") {
+        return Ok(Some(StreamEvent::Error { message: data.to_string() }));
+    }
+
+    let parsed: Value = serde_json
+        ::from_str(data)
+        .map_err(|err| anyhow!("Invalid stream chunk {}: {}", data, err))?;
+    if let Some(error) = parsed.get("error") {
+        let message = error["message"]
+            .as_str()
+            .map(str::to_string)
+            .unwrap_or_else(|| error.to_string());
+        return Ok(Some(StreamEvent::Error { message }));
+    }
+    match parsed["choices"][0]["delta"]["content"].as_str() {
+        Some(content) if !content.is_empty() =>
+            Ok(Some(StreamEvent::Delta { content: content.to_string() })),
+        _ => Ok(None),
+    }
+}
+
+/// The `delta.tool_calls` pieces of an OpenAI-style chunk, `None` if it has
+/// none. See `tools::ToolCallAccumulator`.
+pub fn tool_call_deltas(event: &SseEvent) -> Option<Value> {
+    let parsed: Value = serde_json::from_str(event.data.trim()).ok()?;
+    let deltas = parsed["choices"][0]["delta"]["tool_calls"].clone();
+    deltas.is_array().then_some(deltas)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Events of `input` fed whole, one byte at a time, and split in two at
+    /// every position; fails unless all of them agree.
+    fn decode(input: &[u8]) -> Vec<SseEvent> {
+        let feed = |chunks: &[&[u8]]| {
+            let mut decoder = SseDecoder::default();
+            let mut events: Vec<SseEvent> = chunks
+                .iter()
+                .flat_map(|chunk| decoder.feed(chunk))
+                .collect();
+            events.extend(decoder.finish());
+            events
+        };
+        let whole = feed(&[input]);
+        let bytes: Vec<&[u8]> = input.chunks(1).collect();
+        assert_eq!(feed(&bytes), whole, "fed byte by byte");
+        for split in 0..=input.len() {
+            let (head, tail) = input.split_at(split);
+            assert_eq!(feed(&[head, tail]), whole, "split at byte {}", split);
+        }
+        whole
+    }
+
+    fn data(data: &str) -> SseEvent {
+        SseEvent { data: data.to_string(), ..SseEvent::default() }
+    }
+
+    #[test]
+    fn line_endings_can_be_split() {
+        let events = decode(b"data: a\r\n\r\ndata: b\r\rdata: c\n\n");
+        assert_eq!(events, [data("a"), data("b"), data("c")]);
+    }
+
+    #[test]
+    fn data_lines_are_joined() {
+        let events = decode(b"data: first\ndata:second\ndata\ndata:  indented\n\n");
+        assert_eq!(events, [data("first\nsecond\n\n indented")]);
+    }
+
+    #[test]
+    fn leading_bom_is_stripped() {
+        assert_eq!(decode(b"\xef\xbb\xbfdata: a\n\n"), [data("a")]);
+        // Only at the very start
+        let events = decode(b"data: a\n\n\xef\xbb\xbfdata: b\n\n");
+        assert_eq!(events, [data("a")]);
+    }
+
+    #[test]
+    fn utf8_sequences_can_be_split() {
+        assert_eq!(decode("data: héllo ✓\n\n".as_bytes()), [data("héllo ✓")]);
+    }
+
+    #[test]
+    fn comments_and_empty_events_are_skipped() {
+        let events = decode(b": keep-alive\n\nevent: ping\n\n:\ndata: a\n\n");
+        assert_eq!(events, [data("a")]);
+    }
+
+    #[test]
+    fn fields_are_kept_per_spec() {
+        let events = decode(b"event: error\nid: 7\nretry: 500\ndata: a\n\ndata: b\n\n");
+        let first = SseEvent {
+            event: Some("error".to_string()),
+            data: "a".to_string(),
+            id: Some("7".to_string()),
+            retry: Some(500),
+        };
+        // The id carries over, the type and retry don't
+        let second = SseEvent { id: Some("7".to_string()), ..data("b") };
+        assert_eq!(events, [first, second]);
+    }
+
+    #[test]
+    fn unterminated_last_event_is_flushed() {
+        assert_eq!(decode(b"data: a\n\ndata: b"), [data("a"), data("b")]);
+        assert_eq!(decode(b"data: a\n\ndata: b\n"), [data("a"), data("b")]);
+        assert_eq!(decode(b"data: a\n\n: bye"), [data("a")]);
+    }
+
+    #[test]
+    fn chat_events_are_interpreted() {
+        let delta = data(r#"{"choices":[{"delta":{"content":"Hi"}}]}"#);
+        assert_eq!(
+            parse_chat_event(&delta).unwrap(),
+            Some(StreamEvent::Delta { content: "Hi".to_string() })
+        );
+        let role_only = data(r#"{"choices":[{"delta":{"role":"assistant"}}]}"#);
+        assert_eq!(parse_chat_event(&role_only).unwrap(), None);
+        assert_eq!(parse_chat_event(&data(" [DONE] ")).unwrap(), Some(StreamEvent::Done));
+
+        let error = data(r#"{"error":{"message":"rate limited"}}"#);
+        assert_eq!(
+            parse_chat_event(&error).unwrap(),
+            Some(StreamEvent::Error { message: "rate limited".to_string() })
+        );
+        let error_event = SseEvent { event: Some("error".to_string()), ..data("overloaded") };
+        assert_eq!(
+            parse_chat_event(&error_event).unwrap(),
+            Some(StreamEvent::Error { message: "overloaded".to_string() })
+        );
+        assert!(parse_chat_event(&data("{not json")).is_err());
+    }
+
+    #[test]
+    fn tool_call_deltas_are_found() {
+        let event = data(r#"{"choices":[{"delta":{"tool_calls":[{"index":0}]}}]}"#);
+        assert_eq!(tool_call_deltas(&event), Some(serde_json::json!([{ "index": 0 }])));
+        assert_eq!(tool_call_deltas(&data(r#"{"choices":[{"delta":{}}]}"#)), None);
+        assert_eq!(tool_call_deltas(&data("[DONE]")), None);
+    }
+}
@@ -0,0 +1,129 @@
+use std::sync::{ Arc, Mutex as SyncMutex };
+use anyhow::{ anyhow, Result };
+use tiktoken_rs::CoreBPE;
+
+use crate::settings::Settings;
+
+/// Counts and truncates text in the configured model's tokens.
+pub trait TokenCounter: Send + Sync {
+    fn encode(&self, text: &str) -> Vec<u32>;
+    fn decode(&self, tokens: &[u32]) -> String;
+
+    fn count(&self, text: &str) -> usize {
+        self.encode(text).len()
+    }
+
+    /// First `max_tokens` tokens of `text`
+    fn truncate_start(&self, text: &str, max_tokens: usize) -> String {
+        let tokens = self.encode(text);
+        if tokens.len() <= max_tokens {
+            return text.to_string();
+        }
+        self.decode(&tokens[..max_tokens])
+    }
+
+    /// Last `max_tokens` tokens of `text`
+    fn truncate_end(&self, text: &str, max_tokens: usize) -> String {
+        let tokens = self.encode(text);
+        if tokens.len() <= max_tokens {
+            return text.to_string();
+        }
+        self.decode(&tokens[tokens.len() - max_tokens..])
+    }
+}
+
+/// OpenAI BPE vocabularies, picked from the model name (cl100k/o200k)
+pub struct TiktokenCounter {
+    bpe: CoreBPE,
+}
+
+impl TiktokenCounter {
+    /// Known OpenAI models get their own vocabulary, anything else falls
+    /// back to cl100k as an approximation.
+    pub fn for_model(model: &str) -> Result<Self> {
+        let bpe = match tiktoken_rs::get_bpe_from_model(model) {
+            Ok(bpe) => bpe,
+            Err(_) => tiktoken_rs::cl100k_base()?,
+        };
+        Ok(Self { bpe })
+    }
+}
+
+impl TokenCounter for TiktokenCounter {
+    fn encode(&self, text: &str) -> Vec<u32> {
+        self.bpe.encode_ordinary(text)
+    }
+
+    fn decode(&self, tokens: &[u32]) -> String {
+        // A cut can land inside a multi-byte character, so decode lossily
+        let bytes: Vec<u8> = self.bpe._decode_native_and_split(tokens.to_vec()).flatten().collect();
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+}
+
+/// Hugging Face `tokenizer.json`, for open models (Llama, Mistral, Qwen, ...)
+/// whose vocabulary tiktoken doesn't know.
+pub struct HfTokenCounter {
+    tokenizer: tokenizers::Tokenizer,
+}
+
+impl HfTokenCounter {
+    pub fn from_file(path: &str) -> Result<Self> {
+        let tokenizer = tokenizers::Tokenizer
+            ::from_file(path)
+            .map_err(|err| anyhow!("Failed to load tokenizer {}: {}", path, err))?;
+        Ok(Self { tokenizer })
+    }
+}
+
+impl TokenCounter for HfTokenCounter {
+    fn encode(&self, text: &str) -> Vec<u32> {
+        match self.tokenizer.encode(text, false) {
+            Ok(encoding) => encoding.get_ids().to_vec(),
+            Err(err) => {
+                eprintln!("Failed to tokenize: {}", err);
+                Vec::new()
+            }
+        }
+    }
+
+    fn decode(&self, tokens: &[u32]) -> String {
+        self.tokenizer.decode(tokens, true).unwrap_or_default()
+    }
+}
+
+#[derive(Clone, PartialEq)]
+struct CounterKey {
+    model: String,
+    tokenizer_path: String,
+}
+
+lazy_static! {
+    static ref TOKEN_COUNTER: SyncMutex<Option<(CounterKey, Arc<dyn TokenCounter>)>> = SyncMutex::new(
+        None
+    );
+}
+
+/// `tokenizer_path` when set, otherwise tiktoken for `llm_model`. Loading a
+/// vocabulary is slow, so the counter is reused until those settings change.
+pub fn counter_from_settings(settings: &Settings) -> Result<Arc<dyn TokenCounter>> {
+    let key = CounterKey {
+        model: settings.llm_model.clone(),
+        tokenizer_path: settings.tokenizer_path.clone(),
+    };
+
+    let mut cached = TOKEN_COUNTER.lock().map_err(|err| anyhow!("Failed to lock mutex: {}", err))?;
+    if let Some((cached_key, counter)) = cached.as_ref() {
+        if *cached_key == key {
+            return Ok(Arc::clone(counter));
+        }
+    }
+
+    let counter: Arc<dyn TokenCounter> = if key.tokenizer_path.is_empty() {
+        Arc::new(TiktokenCounter::for_model(&key.model)?)
+    } else {
+        Arc::new(HfTokenCounter::from_file(&key.tokenizer_path)?)
+    };
+    *cached = Some((key, Arc::clone(&counter)));
+    Ok(counter)
+}
@@ -0,0 +1,106 @@
+use serde::{ Deserialize, Serialize };
+use serde_json::Value;
+
+/// A tool offered to the model, as sent in a request's `tools`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ToolDefinition {
+    /// Always "function"
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub function: FunctionDefinition,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FunctionDefinition {
+    pub name: String,
+    pub description: String,
+    /// JSON Schema of the arguments object
+    pub parameters: Value,
+}
+
+/// A call the model asked for, in OpenAI's wire format: `arguments` is the
+/// JSON text of the arguments object.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub id: String,
+    /// Always "function"
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub function: FunctionCall,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FunctionCall {
+    pub name: String,
+    pub arguments: String,
+}
+
+impl ToolCall {
+    pub fn new(id: &str, name: &str, arguments: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            kind: "function".to_string(),
+            function: FunctionCall {
+                name: name.to_string(),
+                arguments: arguments.to_string(),
+            },
+        }
+    }
+}
+
+/// Puts streamed tool calls back together. Each call arrives in pieces
+/// under `delta.tool_calls`: the first with its `index`, `id` and name,
+/// the rest adding to `arguments`.
+#[derive(Debug, Default)]
+pub struct ToolCallAccumulator {
+    calls: Vec<ToolCall>,
+}
+
+impl ToolCallAccumulator {
+    /// Adds the pieces of one chunk's `tool_calls` array.
+    pub fn feed(&mut self, deltas: &Value) {
+        let Some(deltas) = deltas.as_array() else {
+            return;
+        };
+        for delta in deltas {
+            // Servers that leave out `index` start a call with its id
+            let index = match delta["index"].as_u64() {
+                Some(index) => index as usize,
+                None if delta["id"].is_string() => self.calls.len(),
+                None => self.calls.len().saturating_sub(1),
+            };
+            // A call starts at the next index; anything further out skipped
+            // one and can't be placed
+            if index > self.calls.len() {
+                continue;
+            }
+            if index == self.calls.len() {
+                self.calls.push(ToolCall::new("", "", ""));
+            }
+            let call = &mut self.calls[index];
+            if let Some(id) = delta["id"].as_str() {
+                call.id = id.to_string();
+            }
+            if let Some(name) = delta["function"]["name"].as_str() {
+                call.function.name.push_str(name);
+            }
+            match &delta["function"]["arguments"] {
+                Value::String(arguments) => call.function.arguments.push_str(arguments),
+                // Some servers send the object itself
+                Value::Object(_) => {
+                    call.function.arguments = delta["function"]["arguments"].to_string();
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// The calls that got both an id and a name; a result can't be sent
+    /// back for the others.
+    pub fn finish(self) -> Vec<ToolCall> {
+        self.calls
+            .into_iter()
+            .filter(|call| !call.id.is_empty() && !call.function.name.is_empty())
+            .collect()
+    }
+}
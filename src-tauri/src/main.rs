@@ -1,9 +1,8 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-use std::fs;
 use tauri::{ Manager, SystemTray, Window, WindowBuilder, WindowUrl };
-use tauri::{ CustomMenuItem, SystemTrayMenu, SystemTrayEvent, Position };
+use tauri::{ CustomMenuItem, SystemTrayMenu, SystemTrayEvent };
 use global_hotkey::{
     hotkey::{ Code, HotKey, Modifiers },
     GlobalHotKeyEvent,
@@ -12,56 +11,62 @@ use global_hotkey::{
 };
 use std::time::Duration;
 use std::env;
+use std::path::PathBuf;
 mod context;
+mod settings;
+mod llm;
+mod history;
+mod agent;
+#[cfg(test)]
+mod test_support;
 use context::screen::get_screen;
-use context::audio::AudioManager;
-use single_instance::SingleInstance;
+use context::focus::start_focus_tracker;
+use context::render::{ render_audio, render_recalled, render_screen, render_system_message };
+use context::PcContext;
+use context::ocr::engine_from_settings;
+use settings::{ load_settings, save_settings, Settings };
+use llm::{ provider_from_settings, ChatMessage };
+use llm::budget::{ BudgetReport, BudgetedPrompt, ContextBudget, PromptParts };
+use llm::tokenizer::counter_from_settings;
+use llm::sse::StreamEvent;
+use context::audio::{ AudioManager, TranscriptionOptions };
+use context::audio::model::{ list_models, InstalledModel, ModelStatus };
+use context::audio::source::{
+    list_devices,
+    AudioDeviceInfo,
+    AudioSourceKind,
+    CaptureSource,
+    DeviceChoice,
+};
+use context::audio::transcript::{ AudioContext, Speaker };
+use context::screen_source::set_replay_dir;
+use named_lock::NamedLock;
+use context::timeline::{ with_timeline, TimelineQuery, TimelineResult };
+use context::timeline::recorder::{ configure_recorder, start_timeline_recorder };
+use context::retrieval::{ recall, Present, SharedStores };
+use context::retrieval::embedder::embedder_from_settings;
+use context::retrieval::indexer::{ configure_indexer, start_indexer };
+use history::{ with_store, SearchHit, Session, SessionSummary };
+use agent::{ run_agent, ToolRegistry };
+use agent::builtin::builtin_tools;
+use agent::command::{ answer_approval, register_command_tool, CommandApproval, COMMAND_LOG_PATH };
 use std::sync::Mutex as SyncMutex;
 use auto_launch::*;
 use anyhow::Result;
-use serde::{ Deserialize, Serialize };
-use std::path::PathBuf;
+use serde::Serialize;
+use chrono::{ DateTime, Utc };
 
-#[derive(Serialize, Deserialize, Debug)]
-struct Settings {
-    groq_api_key: String,
-    screen_context: bool,
-    audio_context: bool,
-}
-pub const SETTINGS_FILE_PATH: &str = "./settings.json";
+/// Sent to the webview in place of the real API key, which never leaves Rust
+const API_KEY_MASK: &str = "********";
 
 // Learn more about Tauri commands at https://tauri.app/v1/guides/features/command
 #[tauri::command]
 fn get_settings() -> Result<Settings, String> {
-    let file_path = PathBuf::from(SETTINGS_FILE_PATH);
-
-    let default_settings = Settings {
-        groq_api_key: "".to_string(),
-        screen_context: true,
-        audio_context: true,
-    };
-
-    if !file_path.exists() {
-        let serialized = serde_json
-            ::to_string(&default_settings)
-            .map_err(|err| format!("Failed to serialize default settings: {}", err))?;
-        fs
-            ::write(file_path, serialized)
-            .map_err(|err| format!("Failed to write default settings file: {}", err))?;
-        Ok(default_settings)
-    } else {
-        // Read the file
-        let contents = fs
-            ::read_to_string(file_path)
-            .map_err(|err| format!("Failed to read settings file: {}", err))?;
-
-        // Deserialize the JSON string into our Settings struct
-        let settings: Settings = serde_json
-            ::from_str(&contents)
-            .map_err(|err| format!("Failed to deserialize settings: {}", err))?;
-
-        Ok(settings)
+    let mut settings = load_settings()?;
+    if !settings.api_key.is_empty() {
+        settings.api_key = API_KEY_MASK.to_string();
     }
+    Ok(settings)
 }
 
 #[tauri::command]
@@ -82,7 +87,13 @@ fn new_chat() -> Result<(), String> {
 
 // Function to update settings in local storage
 #[tauri::command]
-fn update_settings(settings: Settings) -> Result<(), String> {
+fn update_settings(mut settings: Settings) -> Result<(), String> {
+    // The mask comes from a key field left alone, an empty key from one
+    // cleared for retyping; both keep the saved key
+    if settings.api_key.is_empty() || settings.api_key == API_KEY_MASK {
+        settings.api_key = load_settings()?.api_key;
+    }
+
     // let rt = tokio::runtime::Runtime
     //     ::new()
     //     .map_err(|err| format!("Failed to create runtime: {}", err))?;
@@ -94,20 +105,102 @@ fn update_settings(settings: Settings) -> Result<(), String> {
             .as_ref()
     {
         manager.set_enabled(settings.audio_context);
+        manager.set_sources(capture_sources(&settings, None, None));
+        manager.set_model(PathBuf::from(&settings.models_dir), settings.whisper_model);
+        manager.set_options(TranscriptionOptions::from_settings(&settings));
     }
-    let file_path = PathBuf::from(SETTINGS_FILE_PATH);
+    configure_recorder(&settings);
+    configure_indexer(&settings);
+    save_settings(&settings)
+}
 
-    // Serialize the Settings struct into a JSON string
-    let serialized = serde_json
-        ::to_string(&settings)
-        .map_err(|err| format!("Failed to serialize settings: {}", err))?;
+/// Transcript segments overlapping `[since, until]` (RFC 3339), either end
+/// open if omitted.
+#[tauri::command]
+async fn get_transcript(
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>
+) -> Result<AudioContext, String> {
+    // Don't hold the std mutex across the await
+    let manager = AudioManager::get_instance()
+        .lock()
+        .map_err(|err| format!("Failed to lock mutex: {}", err))?
+        .clone()
+        .ok_or("Audio is not running")?;
+    Ok(manager.get_transcript(since, until).await)
+}
 
-    // Write the JSON string to the file
-    fs
-        ::write(file_path, serialized)
-        .map_err(|err| format!("Failed to write settings file: {}", err))?;
+/// Whisper model lookup/load state, `error` with a message when there is no
+/// usable model.
+#[tauri::command]
+fn get_model_status() -> Result<ModelStatus, String> {
+    let manager = AudioManager::get_instance()
+        .lock()
+        .map_err(|err| format!("Failed to lock mutex: {}", err))?;
+    match manager.as_ref() {
+        Some(manager) => Ok(manager.model_status()),
+        None => Err("Audio is not running".to_string()),
+    }
+}
 
-    Ok(())
+/// Models in the configured models directory with their checksum status
+#[tauri::command]
+async fn list_whisper_models() -> Result<Vec<InstalledModel>, String> {
+    let settings = load_settings()?;
+    // Hashing is slow the first time, keep it off the async workers
+    tokio::task
+        ::spawn_blocking(move || list_models(&PathBuf::from(&settings.models_dir)))
+        .await
+        .map_err(|err| err.to_string())?
+        .map_err(|err| err.to_string())
+}
+
+/// Answers a `command-approval` event: runs the command or declines it
+#[tauri::command]
+fn approve_command(id: u64, approved: bool) -> Result<(), String> {
+    answer_approval(id, approved).map_err(|err| err.to_string())
+}
+
+/// Search results returned at most
+const MAX_SEARCH_HITS: usize = 50;
+
+/// Past chat sessions, most recently active first
+#[tauri::command]
+fn list_sessions() -> Result<Vec<SessionSummary>, String> {
+    with_store(|store| store.list_sessions())
+}
+
+/// A past session with its messages, to continue it
+#[tauri::command]
+fn open_session(session_id: i64) -> Result<Session, String> {
+    with_store(|store| store.session(session_id))
+}
+
+#[tauri::command]
+fn rename_session(session_id: i64, title: String) -> Result<(), String> {
+    with_store(|store| store.rename_session(session_id, &title))
+}
+
+#[tauri::command]
+fn delete_session(session_id: i64) -> Result<(), String> {
+    with_store(|store| store.delete_session(session_id))
+}
+
+/// Messages of past sessions matching `query`, best match first
+#[tauri::command]
+fn search_sessions(query: String) -> Result<Vec<SearchHit>, String> {
+    with_store(|store| store.search(&query, MAX_SEARCH_HITS))
+}
+
+/// Recorded screen text and speech matching `query`, see `TimelineQuery`
+#[tauri::command]
+fn query_timeline(query: TimelineQuery) -> Result<TimelineResult, String> {
+    with_timeline(|timeline| timeline.query(&query))
+}
+
+#[tauri::command]
+fn list_audio_devices() -> Result<Vec<AudioDeviceInfo>, String> {
+    list_devices().map_err(|err| format!("Failed to list audio devices: {}", err))
 }
 
 #[macro_use]
@@ -116,10 +209,10 @@ lazy_static! {
     static ref AUDIO_MANAGER: SyncMutex<Option<AudioManager>> = SyncMutex::new(None);
 }
 impl AudioManager {
-    pub fn init(model_path: &str, max_chars: usize) {
+    pub fn init(sources: Vec<CaptureSource>) {
         let mut manager = AUDIO_MANAGER.lock().unwrap();
-        *manager = Some(AudioManager::new(model_path, max_chars).unwrap());
-        manager.as_ref().unwrap().set_enabled(get_settings().unwrap().audio_context)
+        *manager = Some(AudioManager::new(sources));
+        manager.as_ref().unwrap().set_enabled(load_settings().unwrap().audio_context)
     }
 
     pub fn get_instance() -> &'static SyncMutex<Option<AudioManager>> {
@@ -127,61 +220,230 @@ impl AudioManager {
     }
 }
 
-#[derive(Serialize, Deserialize)]
+/// `context` is the structured form, `content` the system message rendered
+/// from it after budgeting.
+#[derive(Serialize)]
 struct AIContext {
+    context: PcContext,
     content: String,
-    api_key: String,
+    report: BudgetReport,
 }
 
+/// The context a question would be asked in; `query` stands in for the
+/// question when recalling past context.
 #[tauri::command]
-fn get_context() -> Result<AIContext, String> {
-    let max_screen_chars = 4000; // 1000 tokens~
-
-    let mut context = String::new();
-    let settings = get_settings()?;
-    println!("{:?}", settings);
-    if !settings.screen_context && !settings.audio_context {
-        return Ok(AIContext {
-            content: context,
-            api_key: settings.groq_api_key,
-        });
-    }
-    context.push_str("PC CONTEXT\n");
+fn get_context(query: Option<String>) -> Result<AIContext, String> {
+    let settings = load_settings()?;
+    let context = gather_context(&settings, query.as_deref(), None)?;
+    let counter = counter_from_settings(&settings).map_err(|err|
+        format!("Failed to load tokenizer: {}", err)
+    )?;
+    let screen = context.screen.as_ref().map(render_screen).unwrap_or_default();
+    let audio = context.audio.as_ref().map(render_audio).unwrap_or_default();
+    let recalled = context.recalled.as_deref().map(render_recalled).unwrap_or_default();
+    let budgeted = ContextBudget::from_settings(&settings).apply(counter.as_ref(), &(PromptParts {
+        system_prompt: &settings.system_prompt,
+        screen: &screen,
+        audio: &audio,
+        recalled: &recalled,
+        history: &[],
+    }));
+    Ok(AIContext {
+        context,
+        content: format_system_message(&budgeted),
+        report: budgeted.report,
+    })
+}
+
+/// What's on screen and was said just now, and with retrieval on, what's
+/// relevant to `question` from before, leaving out chat `session_id`.
+fn gather_context(
+    settings: &Settings,
+    question: Option<&str>,
+    session_id: Option<i64>
+) -> Result<PcContext, String> {
+    // ~4 chars per token: windows are cut to fit here, by rank, so the token
+    // budget only ever trims the least relevant tail
+    let max_screen_chars = settings.context_budget_tokens * 4;
+
+    let mut context = PcContext::default();
 
     if settings.screen_context {
-        let screen_context = get_screen(max_screen_chars).map_err(|err|
-            format!("Failed to get screen: {}", err)
+        let ocr = engine_from_settings(settings).map_err(|err|
+            format!("Failed to create OCR engine: {}", err)
         )?;
-        if !screen_context.is_empty() {
-            context.push_str("SCREEN:\n");
-            context.push_str(&screen_context);
-        }
+        context.screen = Some(
+            get_screen(max_screen_chars, ocr, None).map_err(|err|
+                format!("Failed to get screen: {}", err)
+            )?
+        );
     }
     if settings.audio_context {
         let rt = tokio::runtime::Runtime
             ::new()
             .map_err(|err| format!("Failed to create runtime: {}", err))?;
 
-        if
-            let Some(manager) = AudioManager::get_instance()
-                .lock()
-                .map_err(|err| format!("Failed to lock mutex: {}", err))?
-                .as_ref()
-        {
-            // Block on the async operation using the Tokio runtime
-            let audio_context = rt.block_on(manager.get_full_transcription());
-            if !audio_context.is_empty() {
-                context.push_str("AUDIO:\n");
-                context.push_str(&audio_context);
-            }
+        // Cloned out so the lock isn't held while whisper runs
+        let manager = AudioManager::get_instance()
+            .lock()
+            .map_err(|err| format!("Failed to lock mutex: {}", err))?
+            .clone();
+        if let Some(manager) = manager {
+            let since =
+                chrono::Utc::now() -
+                chrono::Duration::seconds(settings.audio_context_window_secs as i64);
+            // Block on the async operation using the Tokio runtime; speech
+            // still going on is transcribed up to now first
+            context.audio = Some(
+                rt.block_on(async {
+                    manager.flush_live().await;
+                    manager.get_transcript(Some(since), None).await
+                })
+            );
         }
     }
-    Ok(AIContext {
-        content: context,
-        api_key: settings.groq_api_key,
-    })
+    if let (true, Some(question)) = (settings.retrieval_enabled, question) {
+        let now = chrono::Utc::now();
+        let present = Present {
+            session_id,
+            // The recorder keeps extending what's still on screen
+            screen_since: settings.screen_context.then(|| {
+                now - chrono::Duration::seconds((settings.timeline_interval_secs * 2) as i64)
+            }),
+            audio_since: settings.audio_context.then(|| {
+                now - chrono::Duration::seconds(settings.audio_context_window_secs as i64)
+            }),
+        };
+        let embedder = embedder_from_settings(settings).map_err(|err|
+            format!("Failed to load embedding model: {}", err)
+        )?;
+        context.recalled = Some(
+            recall(&embedder, &SharedStores, question, settings.retrieval_top_k, &present).map_err(
+                |err| format!("Failed to recall context: {}", err)
+            )?
+        );
+    }
+    Ok(context)
+}
+
+fn format_system_message(budgeted: &BudgetedPrompt) -> String {
+    render_system_message(
+        &budgeted.system_prompt,
+        &budgeted.screen,
+        &budgeted.audio,
+        &budgeted.recalled
+    )
+}
 
-    //format!("PC Context\nscreen:\n{}\naudio:\n{}", screen_context, audio_context)
+/// Answers the chat: gathers PC context, fits it and the history into the
+/// token budget, calls the configured provider and streams the reply to
+/// `window` as typed `llm-stream` events: `delta`s, then `done` or `error`.
+/// With agent tools on, the model may call them first; each call is sent as
+/// a `tool_call` then a `tool_result`. Commands it wants to run are shown
+/// as `command-approval` events first, answered by `approve_command`. What
+/// the budget cut is reported first as a `context-report` event. Resolves
+/// with the full reply once the stream ends.
+///
+/// The question, with the context it was asked in, and the reply are saved
+/// to `session_id`, whose earlier messages are the history. Without one a
+/// new session is started and its id sent as a `chat-session` event.
+#[tauri::command]
+async fn ask(window: Window, session_id: Option<i64>, content: String) -> Result<String, String> {
+    let settings = load_settings()?;
+    // get_screen and the audio manager block on their own runtimes
+    let gather_settings = settings.clone();
+    let question = content.clone();
+    let context = tauri::async_runtime
+        ::spawn_blocking(move || {
+            gather_context(&gather_settings, Some(&question), session_id)
+        }).await
+        .map_err(|err| format!("Failed to get context: {}", err))??;
+    let screen = context.screen.as_ref().map(render_screen).unwrap_or_default();
+    let audio = context.audio.as_ref().map(render_audio).unwrap_or_default();
+    let recalled = context.recalled.as_deref().map(render_recalled).unwrap_or_default();
+
+    // Saved before the model is called, so the question survives a failed reply
+    let question = ChatMessage::new("user", &content);
+    let snapshot = serde_json::to_value(&context).ok();
+    let (session_id, mut messages) = with_store(|store| {
+        let session_id = match session_id {
+            Some(session_id) => session_id,
+            None => store.create_session(&content)?,
+        };
+        let history = store.history(session_id)?;
+        store.add_message(session_id, &question, snapshot.as_ref())?;
+        Ok((session_id, history))
+    })?;
+    if let Err(err) = window.emit("chat-session", session_id) {
+        eprintln!("Failed to emit chat session: {}", err);
+    }
+    messages.push(question);
+
+    let counter = counter_from_settings(&settings).map_err(|err|
+        format!("Failed to load tokenizer: {}", err)
+    )?;
+    let budgeted = ContextBudget::from_settings(&settings).apply(counter.as_ref(), &(PromptParts {
+        system_prompt: &settings.system_prompt,
+        screen: &screen,
+        audio: &audio,
+        recalled: &recalled,
+        history: &messages,
+    }));
+    if let Err(err) = window.emit("context-report", &budgeted.report) {
+        eprintln!("Failed to emit context report: {}", err);
+    }
+
+    let mut request_messages = vec![ChatMessage::new("system", &format_system_message(&budgeted))];
+    request_messages.extend(budgeted.history.iter().cloned());
+
+    let provider = provider_from_settings(&settings);
+    let mut tools = if settings.agent_tools {
+        builtin_tools(&settings)
+    } else {
+        ToolRegistry::new()
+    };
+    if settings.agent_tools && settings.run_commands {
+        let approval_window = window.clone();
+        register_command_tool(
+            &mut tools,
+            &settings,
+            std::sync::Arc::new(move |approval: &CommandApproval| {
+                approval_window
+                    .emit("command-approval", approval)
+                    .map_err(|err| anyhow::anyhow!("Failed to ask for approval: {}", err))
+            }),
+            PathBuf::from(COMMAND_LOG_PATH)
+        );
+    }
+    let mut emit = |event: StreamEvent| {
+        if let Err(err) = window.emit("llm-stream", event) {
+            eprintln!("Failed to emit stream event: {}", err);
+        }
+    };
+    let result = run_agent(
+        provider.as_ref(),
+        &settings,
+        &tools,
+        counter.as_ref(),
+        request_messages,
+        &mut emit
+    ).await;
+
+    match result {
+        Ok(full_message) => {
+            let reply = ChatMessage::new("assistant", &full_message);
+            if let Err(err) = with_store(|store| store.add_message(session_id, &reply, None)) {
+                eprintln!("Failed to save reply: {}", err);
+            }
+            emit(StreamEvent::Done);
+            Ok(full_message)
+        }
+        Err(err) => {
+            let message = format!("Failed to call AI: {}", err);
+            emit(StreamEvent::Error { message: message.clone() });
+            Err(message)
+        }
+    }
 }
 
 #[tauri::command]
@@ -198,10 +460,76 @@ fn toggle_settings_window(app_handle: tauri::AppHandle) {
     }
 }
 
+/// Audio sources from settings; replay directories stand in for the system
+/// audio and the microphone in headless runs.
+fn capture_sources(
+    settings: &Settings,
+    replay_system: Option<std::path::PathBuf>,
+    replay_mic: Option<std::path::PathBuf>
+) -> Vec<CaptureSource> {
+    if replay_system.is_some() || replay_mic.is_some() {
+        let others = replay_system.map(|dir| CaptureSource {
+            kind: AudioSourceKind::Replay(dir),
+            speaker: Speaker::Others,
+        });
+        let me = replay_mic.map(|dir| CaptureSource {
+            kind: AudioSourceKind::Replay(dir),
+            speaker: Speaker::Me,
+        });
+        return others.into_iter().chain(me).collect();
+    }
+
+    let non_empty = |value: &str| Some(value.to_string()).filter(|value| !value.is_empty());
+    let choice = |device: &str| DeviceChoice {
+        host: non_empty(&settings.audio_host),
+        device: non_empty(device),
+    };
+
+    let mut sources = Vec::new();
+    if settings.audio_capture_system {
+        sources.push(CaptureSource {
+            kind: AudioSourceKind::system(choice(&settings.audio_output_device)),
+            speaker: Speaker::Others,
+        });
+    }
+    if settings.audio_capture_microphone {
+        sources.push(CaptureSource {
+            kind: AudioSourceKind::Microphone(choice(&settings.audio_microphone_device)),
+            speaker: Speaker::Me,
+        });
+    }
+    sources
+}
+
 fn main() {
-    let max_audio_chars = 2000; // 500 tokens~
-    AudioManager::init("./assets/ggml-tiny-q5_1.bin", max_audio_chars);
+    let args: Vec<String> = env::args().collect();
+    // Headless runs: feed the context pipeline from recorded windows / WAV files
+    let arg_value = |flag: &str| {
+        args.iter()
+            .position(|arg| arg == flag)
+            .and_then(|index| args.get(index + 1))
+            .map(std::path::PathBuf::from)
+    };
+    if let Some(dir) = arg_value("--replay-screen") {
+        println!("replaying screen from {}", dir.display());
+        set_replay_dir(dir);
+    }
+    start_focus_tracker(Duration::from_millis(500));
+    let settings = load_settings().unwrap();
+    configure_recorder(&settings);
+    start_timeline_recorder();
+    configure_indexer(&settings);
+    start_indexer();
+    let audio_sources = capture_sources(
+        &settings,
+        arg_value("--replay-audio"),
+        arg_value("--replay-mic")
+    );
+
+    AudioManager::init(audio_sources);
     if let Some(manager) = AudioManager::get_instance().lock().unwrap().as_ref() {
+        manager.set_model(PathBuf::from(&settings.models_dir), settings.whisper_model);
+        manager.set_options(TranscriptionOptions::from_settings(&settings));
         manager.start_audio_capture().unwrap();
     }
     let auto = AutoLaunchBuilder::new()
@@ -215,12 +543,12 @@ fn main() {
         auto.enable().unwrap();
     }
 
-    let instance = SingleInstance::new("pc-assistant").unwrap();
-    println!("instance a is single: {}", instance.is_single());
-
-    if !instance.is_single() {
-        std::process::exit(0);
-    }
+    // Held until exit; a second instance can't take it and quits
+    let instance = NamedLock::create("pc-assistant").unwrap();
+    let _instance_guard = match instance.try_lock() {
+        Ok(guard) => guard,
+        Err(_) => std::process::exit(0),
+    };
 
     let hotkey_manager = GlobalHotKeyManager::new().unwrap();
     let hotkey = HotKey::new(Some(Modifiers::ALT), Code::KeyQ);
@@ -292,14 +620,8 @@ fn main() {
                     window.show().unwrap();
                     window.set_focus().unwrap();
                 }
-                SystemTrayEvent::MenuItemClick { id, .. } => {
-                    match id.as_str() {
-                        "quit" => {
-                            std::process::exit(0);
-                        }
-
-                        _ => {}
-                    }
+                SystemTrayEvent::MenuItemClick { id, .. } if id == "quit" => {
+                    std::process::exit(0);
                 }
                 _ => {}
             }
@@ -310,17 +632,26 @@ fn main() {
                 get_settings,
                 update_settings,
                 get_context,
-                new_chat
+                ask,
+                new_chat,
+                list_audio_devices,
+                get_transcript,
+                get_model_status,
+                list_whisper_models,
+                list_sessions,
+                open_session,
+                rename_session,
+                delete_session,
+                search_sessions,
+                query_timeline,
+                approve_command
             ]
         )
         .build(tauri::generate_context!())
         .expect("error while building tauri application")
         .run(|_app_handle, event| {
-            match event {
-                tauri::RunEvent::ExitRequested { api, .. } => {
-                    api.prevent_exit();
-                }
-                _ => {}
+            if let tauri::RunEvent::ExitRequested { api, .. } = event {
+                api.prevent_exit();
             }
         });
 }
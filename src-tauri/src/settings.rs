@@ -0,0 +1,249 @@
+use std::fs;
+use std::path::PathBuf;
+use serde::{ Deserialize, Serialize };
+
+pub const SETTINGS_FILE_PATH: &str = "./settings.json";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OcrBackend {
+    Windows,
+    Tesseract,
+}
+
+impl Default for OcrBackend {
+    fn default() -> Self {
+        if cfg!(windows) { OcrBackend::Windows } else { OcrBackend::Tesseract }
+    }
+}
+
+/// whisper.cpp model sizes offered, larger is slower and more accurate
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum WhisperModelSize {
+    #[default]
+    Tiny,
+    Base,
+    Small,
+    Medium,
+}
+
+impl WhisperModelSize {
+    pub fn name(self) -> &'static str {
+        match self {
+            WhisperModelSize::Tiny => "tiny",
+            WhisperModelSize::Base => "base",
+            WhisperModelSize::Small => "small",
+            WhisperModelSize::Medium => "medium",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum WhisperDecoding {
+    #[default]
+    Greedy,
+    /// Slower, fewer misheard words
+    BeamSearch,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LlmBackend {
+    /// Any OpenAI-compatible `/chat/completions` endpoint
+    #[default]
+    OpenAi,
+    Ollama,
+    LlamaCpp,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Settings {
+    #[serde(alias = "groq_api_key")]
+    pub api_key: String,
+    pub llm_provider: LlmBackend,
+    pub llm_base_url: String,
+    pub llm_model: String,
+    pub llm_temperature: f32,
+    pub llm_top_p: f32,
+    pub llm_max_tokens: u32,
+    /// Hugging Face `tokenizer.json` for the model, empty to use tiktoken
+    pub tokenizer_path: String,
+    pub system_prompt: String,
+    /// Tokens shared by system prompt, screen, audio, recalled context and
+    /// chat history
+    pub context_budget_tokens: usize,
+    pub budget_weight_system: f32,
+    pub budget_weight_screen: f32,
+    pub budget_weight_audio: f32,
+    pub budget_weight_history: f32,
+    pub budget_weight_recalled: f32,
+    pub screen_context: bool,
+    pub audio_context: bool,
+    /// Transcribe speech while it's still going on instead of at pauses only
+    pub audio_streaming: bool,
+    /// New speech between streaming runs
+    pub audio_streaming_step_ms: u32,
+    /// Captured audio waiting for the transcriber is capped at this, the
+    /// oldest is dropped beyond it
+    pub audio_buffer_secs: u32,
+    /// How far back the transcript given to the model reaches
+    pub audio_context_window_secs: u64,
+    /// Transcribe what the PC plays (WASAPI loopback / Pulse monitor) as "others"
+    pub audio_capture_system: bool,
+    /// Transcribe the microphone as "me". Off until the user opts in
+    pub audio_capture_microphone: bool,
+    /// cpal host ("WASAPI", "ALSA", ...), empty for the platform default
+    pub audio_host: String,
+    /// Input device name, empty to follow the default microphone
+    pub audio_microphone_device: String,
+    /// Output device to loop back, empty to follow the default output
+    pub audio_output_device: String,
+    /// Tell the voices in system audio apart as "Speaker 1", "Speaker 2", ...
+    pub audio_diarization: bool,
+    /// Names for the voices told apart, in order: the first is "Speaker 1"
+    pub speaker_names: Vec<String>,
+    pub whisper_model: WhisperModelSize,
+    /// Where `ggml-<size>*.bin` models and their `SHA256SUMS` are
+    pub models_dir: String,
+    /// Spoken language as an ISO 639-1 code ("en", "de"), "auto" to detect
+    /// it per chunk
+    pub whisper_language: String,
+    /// Translate speech to English instead of transcribing it as spoken
+    pub whisper_translate: bool,
+    pub whisper_threads: u32,
+    pub whisper_decoding: WhisperDecoding,
+    /// Beams kept with `BeamSearch`
+    pub whisper_beam_size: u32,
+    /// Text whisper treats as what came before: names, jargon, spelling
+    pub whisper_initial_prompt: String,
+    /// Keep a searchable history of screen text and speech in the background
+    pub timeline_enabled: bool,
+    /// Time between timeline snapshots
+    pub timeline_interval_secs: u64,
+    /// Timeline records older than this are deleted
+    pub timeline_retention_days: u32,
+    /// Index the timeline and chat history with a local embedding model and
+    /// add what's relevant to each question to its context
+    pub retrieval_enabled: bool,
+    /// Where the embedding model's `model.onnx` and `tokenizer.json` are
+    pub embedding_model_dir: String,
+    /// Past snippets recalled per question
+    pub retrieval_top_k: usize,
+    /// Let the model call tools to look at the screen, transcript and
+    /// timeline itself
+    pub agent_tools: bool,
+    /// Rounds of tool calls per answer before the model has to reply
+    pub agent_max_steps: u32,
+    /// Offer a tool that runs shell commands, each shown for approval first
+    pub run_commands: bool,
+    /// Commands run without asking: an entry matches the command itself or
+    /// the command followed by arguments, never with shell operators
+    pub command_allowlist: Vec<String>,
+    /// Commands still running after this are killed
+    pub command_timeout_secs: u64,
+    /// Output kept per stream, stdout and stderr, in bytes
+    pub command_output_bytes: usize,
+    /// Where commands run unless the model names a directory, empty for the
+    /// home directory
+    pub command_working_dir: String,
+    pub ocr_engine: OcrBackend,
+    /// Passed to the OCR engine as-is: BCP-47 tags ("en-US") for Windows,
+    /// traineddata names ("eng") for Tesseract. Empty means engine default.
+    pub ocr_languages: Vec<String>,
+    /// Tesseract data directory, empty to use `TESSDATA_PREFIX`
+    pub tessdata_dir: String,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            api_key: "".to_string(),
+            llm_provider: LlmBackend::default(),
+            llm_base_url: "https://api.groq.com/openai/v1".to_string(),
+            llm_model: "llama-3.3-70b-versatile".to_string(),
+            llm_temperature: 0.5,
+            llm_top_p: 1.0,
+            llm_max_tokens: 800,
+            tokenizer_path: "".to_string(),
+            system_prompt: "You are PC Agent, an assistant running on the user's computer. Use the PC context of their screen and audio when it helps to answer.".to_string(),
+            context_budget_tokens: 6000,
+            budget_weight_system: 0.1,
+            budget_weight_screen: 0.4,
+            budget_weight_audio: 0.2,
+            budget_weight_history: 0.3,
+            budget_weight_recalled: 0.15,
+            screen_context: true,
+            audio_context: true,
+            audio_streaming: true,
+            audio_streaming_step_ms: 1000,
+            audio_buffer_secs: 10,
+            audio_context_window_secs: 300,
+            audio_capture_system: true,
+            audio_capture_microphone: false,
+            audio_host: "".to_string(),
+            audio_microphone_device: "".to_string(),
+            audio_output_device: "".to_string(),
+            audio_diarization: true,
+            speaker_names: Vec::new(),
+            whisper_model: WhisperModelSize::default(),
+            models_dir: "./assets".to_string(),
+            whisper_language: "auto".to_string(),
+            whisper_translate: false,
+            whisper_threads: 4,
+            whisper_decoding: WhisperDecoding::default(),
+            whisper_beam_size: 5,
+            whisper_initial_prompt: "".to_string(),
+            timeline_enabled: false,
+            timeline_interval_secs: 60,
+            timeline_retention_days: 7,
+            retrieval_enabled: false,
+            embedding_model_dir: "./assets/embedding".to_string(),
+            retrieval_top_k: 5,
+            agent_tools: false,
+            agent_max_steps: 5,
+            run_commands: false,
+            command_allowlist: Vec::new(),
+            command_timeout_secs: 30,
+            command_output_bytes: 8000,
+            command_working_dir: "".to_string(),
+            ocr_engine: OcrBackend::default(),
+            ocr_languages: Vec::new(),
+            tessdata_dir: "".to_string(),
+        }
+    }
+}
+
+pub fn load_settings() -> Result<Settings, String> {
+    let file_path = PathBuf::from(SETTINGS_FILE_PATH);
+
+    if !file_path.exists() {
+        let default_settings = Settings::default();
+        save_settings(&default_settings)?;
+        return Ok(default_settings);
+    }
+
+    // Read the file
+    let contents = fs
+        ::read_to_string(file_path)
+        .map_err(|err| format!("Failed to read settings file: {}", err))?;
+
+    // Deserialize the JSON string into our Settings struct, missing fields take their defaults
+    serde_json::from_str(&contents).map_err(|err| format!("Failed to deserialize settings: {}", err))
+}
+
+pub fn save_settings(settings: &Settings) -> Result<(), String> {
+    let file_path = PathBuf::from(SETTINGS_FILE_PATH);
+
+    // Serialize the Settings struct into a JSON string
+    let serialized = serde_json
+        ::to_string(settings)
+        .map_err(|err| format!("Failed to serialize settings: {}", err))?;
+
+    // Write the JSON string to the file
+    fs
+        ::write(file_path, serialized)
+        .map_err(|err| format!("Failed to write settings file: {}", err))
+}
@@ -0,0 +1,266 @@
+//! Fixtures shared by the unit tests.
+
+use std::fs;
+use std::path::{ Path, PathBuf };
+use std::time::Duration;
+use anyhow::{ anyhow, Result };
+use chrono::{ DateTime, Utc };
+use image::{ Rgba, RgbaImage };
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use tempfile::TempDir;
+use tokio::io::{ AsyncReadExt, AsyncWriteExt };
+use tokio::net::{ TcpListener, TcpStream };
+use tokio::task::JoinHandle;
+
+use crate::context::audio::resample::{ channels_to_mono, StreamResampler, WHISPER_SAMPLE_RATE };
+use crate::context::audio::source::read_wav;
+use crate::context::screen::WindowContext;
+use crate::context::screen_source::WindowBounds;
+
+/// A database file in its own temp directory, deleted with its journal on
+/// drop.
+pub struct TempDatabase {
+    path: PathBuf,
+    _dir: TempDir,
+}
+
+impl TempDatabase {
+    pub fn new(name: &str) -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        Self { path: dir.path().join(format!("{}.db", name)), _dir: dir }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// An unfocused 800x600 window captured `at`.
+pub fn window(title: &str, process_name: &str, text: &str, at: DateTime<Utc>) -> WindowContext {
+    WindowContext {
+        title: title.to_string(),
+        process_name: process_name.to_string(),
+        bounds: WindowBounds { x: 0, y: 0, width: 800, height: 600 },
+        focused: false,
+        visible_area: 800 * 600,
+        focus_age_ms: None,
+        text: text.to_string(),
+        truncated: false,
+        captured_at: at,
+    }
+}
+
+/// Writes a white `width` x `height` image to `path`, encoded as its
+/// extension says.
+pub fn write_image(path: &Path, width: u32, height: u32) {
+    RgbaImage::from_pixel(width, height, Rgba([255, 255, 255, 255]))
+        .save(path)
+        .unwrap();
+}
+
+/// Writes interleaved `samples` to `path` as a 32-bit float WAV.
+pub fn write_wav(path: &Path, sample_rate: u32, channels: u16, samples: &[f32]) {
+    let spec = hound::WavSpec {
+        channels,
+        sample_rate,
+        bits_per_sample: 32,
+        sample_format: hound::SampleFormat::Float,
+    };
+    let mut writer = hound::WavWriter::create(path, spec).unwrap();
+    for &sample in samples {
+        writer.write_sample(sample).unwrap();
+    }
+    writer.finalize().unwrap();
+}
+
+/// `secs` of a `frequency` Hz sine wave, mono at `sample_rate`.
+pub fn tone(frequency: f32, amplitude: f32, secs: f32, sample_rate: u32) -> Vec<f32> {
+    let len = (secs * (sample_rate as f32)) as usize;
+    (0..len)
+        .map(|i| {
+            let t = (i as f32) / (sample_rate as f32);
+            amplitude * (std::f32::consts::TAU * frequency * t).sin()
+        })
+        .collect()
+}
+
+/// `secs` of white noise within `amplitude`, mono at `sample_rate`. The
+/// same on every call.
+pub fn noise(amplitude: f32, secs: f32, sample_rate: u32) -> Vec<f32> {
+    let len = (secs * (sample_rate as f32)) as usize;
+    let mut state: u32 = 0x9e37_79b9;
+    (0..len)
+        .map(|_| {
+            // xorshift32
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            amplitude * ((state as f32) / (u32::MAX as f32) * 2.0 - 1.0)
+        })
+        .collect()
+}
+
+/// One-shot conversion of a whole interleaved buffer to Whisper's format,
+/// for recorded fixtures.
+pub fn to_whisper_format(audio_data: Vec<f32>, sample_rate: u32, channels: u16) -> Result<Vec<f32>> {
+    let mono = channels_to_mono(audio_data, channels.into());
+    let mut resampler = StreamResampler::new(sample_rate, WHISPER_SAMPLE_RATE)?;
+    let mut output = resampler.process(&mono)?;
+    output.extend(resampler.flush()?);
+    Ok(output)
+}
+
+/// A labeled recording, as the audio evals read them.
+pub struct WavFixture<L> {
+    /// File name of the WAV
+    pub name: String,
+    /// Mono at `WHISPER_SAMPLE_RATE`, the way capture hands audio on
+    pub samples: Vec<f32>,
+    pub labels: L,
+}
+
+/// Loads every `<name>.wav` in `dir`, in name order, with its labels from
+/// `<name>.json`.
+pub fn load_wav_fixtures<L: DeserializeOwned>(dir: &Path) -> Result<Vec<WavFixture<L>>> {
+    let mut paths: Vec<PathBuf> = fs
+        ::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("wav")))
+        .collect();
+    paths.sort();
+    if paths.is_empty() {
+        return Err(anyhow!("No WAV fixtures found in {}", dir.display()));
+    }
+
+    paths
+        .iter()
+        .map(|path| {
+            let labels_path = path.with_extension("json");
+            let labels = serde_json
+                ::from_str(&fs::read_to_string(&labels_path)?)
+                .map_err(|e| anyhow!("Invalid {}: {}", labels_path.display(), e))?;
+            let spec = hound::WavReader::open(path)?.spec();
+            let samples = to_whisper_format(read_wav(path)?, spec.sample_rate, spec.channels)?;
+            let name = path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            Ok(WavFixture { name, samples, labels })
+        })
+        .collect()
+}
+
+/// A canned HTTP response. The body goes out as one HTTP chunk per entry
+/// of `chunks`, each flushed on its own, so a client sees them split.
+/// Chunks must not be empty, that would end the body.
+pub struct MockResponse {
+    pub status: u16,
+    pub chunks: Vec<String>,
+}
+
+impl MockResponse {
+    pub fn ok<S: AsRef<str>>(chunks: &[S]) -> Self {
+        Self {
+            status: 200,
+            chunks: chunks
+                .iter()
+                .map(|chunk| chunk.as_ref().to_string())
+                .collect(),
+        }
+    }
+
+    pub fn error(status: u16, body: &str) -> Self {
+        Self { status, chunks: vec![body.to_string()] }
+    }
+}
+
+/// A request `mock_server` answered.
+#[derive(Debug)]
+pub struct MockRequest {
+    /// Request line and headers
+    pub head: String,
+    pub body: Value,
+}
+
+impl MockRequest {
+    pub fn path(&self) -> &str {
+        self.head.split(' ').nth(1).unwrap_or_default()
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.head.lines().find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            key.eq_ignore_ascii_case(name).then(|| value.trim())
+        })
+    }
+}
+
+/// Serves `responses` on a local port, in order, one per connection.
+/// Returns the server's base URL and a handle resolving to the requests
+/// it got once every response was sent.
+pub async fn mock_server(
+    responses: Vec<MockResponse>
+) -> (String, JoinHandle<Vec<MockRequest>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+    let server = tokio::spawn(async move {
+        let mut requests = Vec::new();
+        for response in responses {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            requests.push(read_request(&mut stream).await);
+            // Clients may hang up before the end, e.g. once they've seen `done`
+            let _ = write_response(&mut stream, &response).await;
+        }
+        requests
+    });
+    (base_url, server)
+}
+
+async fn read_request(stream: &mut TcpStream) -> MockRequest {
+    let mut received = Vec::new();
+    let mut buffer = [0u8; 4096];
+    let head_end = loop {
+        if let Some(end) = received.windows(4).position(|bytes| bytes == b"\r\n\r\n") {
+            break end;
+        }
+        let read = stream.read(&mut buffer).await.unwrap();
+        assert!(read > 0, "connection closed mid-request");
+        received.extend_from_slice(&buffer[..read]);
+    };
+    let head = String::from_utf8_lossy(&received[..head_end]).into_owned();
+    let mut body = received.split_off(head_end + 4);
+
+    let mut request = MockRequest { head, body: Value::Null };
+    let length: usize = request
+        .header("content-length")
+        .and_then(|length| length.parse().ok())
+        .unwrap_or(0);
+    while body.len() < length {
+        let read = stream.read(&mut buffer).await.unwrap();
+        assert!(read > 0, "connection closed mid-body");
+        body.extend_from_slice(&buffer[..read]);
+    }
+    request.body = serde_json::from_slice(&body).unwrap_or(Value::Null);
+    request
+}
+
+async fn write_response(stream: &mut TcpStream, response: &MockResponse) -> std::io::Result<()> {
+    let head = format!(
+        "HTTP/1.1 {} Mock\r\n\
+        Content-Type: text/plain\r\n\
+        Transfer-Encoding: chunked\r\n\
+        Connection: close\r\n\r\n",
+        response.status
+    );
+    stream.write_all(head.as_bytes()).await?;
+    for chunk in &response.chunks {
+        let framed = format!("{:x}\r\n{}\r\n", chunk.len(), chunk);
+        stream.write_all(framed.as_bytes()).await?;
+        stream.flush().await?;
+        // Keeps the client from reading several chunks at once
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+    stream.write_all(b"0\r\n\r\n").await?;
+    stream.shutdown().await
+}
@@ -1,148 +1,387 @@
 const { invoke } = window.__TAURI__.tauri;
+const { listen } = window.__TAURI__.event;
 
-let messages = [];
+// Session the chat is saved to, null until the first message of a new chat
+let sessionId = null;
 let sendLocked = false;
 let isAtBottom = true;
 function endStream() {
   sendLocked = false;
 }
 
-async function call_ai(messagesContainer, messageDiv) {
-  let ai_context = await get_context();
-
-  const url = "https://api.groq.com/openai/v1/chat/completions";
-
-  let filteredMessages = [];
-  let currentLength = 0;
-  let max_chars = 10000;
-  for (let i = messages.length - 1; i >= 0 && currentLength < max_chars; i--) {
-    const messageContentLength = messages[i].content.length;
-    if (currentLength + messageContentLength <= max_chars) {
-      filteredMessages.unshift(messages[i]); // Add to the beginning of the array
-      currentLength += messageContentLength;
-    } else {
-      break; // Stop adding more messages
-    }
+function describeReport(report) {
+  const parts = report.sections
+    .filter((section) => section.dropped > 0)
+    .map((section) => `${section.section} -${section.dropped}`);
+  if (report.dropped_messages > 0) {
+    parts.push(`${report.dropped_messages} older messages`);
   }
-  console.log("len before messages:", filteredMessages.length);
-
-  console.log("len messages:", filteredMessages.length);
-
-  const payload = {
-    messages: [{ role: "system", content: ai_context.content }, ...messages],
-    model: "llama-3.2-90b-text-preview",
-    temperature: 0.5,
-    max_tokens: 800,
-    top_p: 1,
-    stream: true,
-    stop: null,
-  };
-
-  try {
-    const response = await fetch(url, {
-      method: "POST",
-      headers: {
-        "Content-Type": "application/json",
-        Authorization: `Bearer ${ai_context.api_key}`,
-      },
-      body: JSON.stringify(payload),
-    });
-
-    const reader = response.body.getReader();
-    const decoder = new TextDecoder();
-
-    let lastBuffer = "";
-
-    let fullMessage = "";
+  if (parts.length === 0) {
+    return "";
+  }
+  return `Trimmed to ${report.total_budget} tokens: ${parts.join(", ")}`;
+}
 
-    while (true) {
-      const { done, value } = await reader.read();
-      if (done) {
-        endStream();
-        return;
+async function call_ai(messagesContainer, messageDiv, content) {
+  // The backend gathers context, calls the model and streams tokens back
+  let fullMessage = "";
+  const unlistenReport = await listen("context-report", (event) => {
+    const text = describeReport(event.payload);
+    if (text !== "") {
+      const reportDiv = document.createElement("div");
+      reportDiv.className = "context-report";
+      reportDiv.textContent = text;
+      messagesContainer.insertBefore(reportDiv, messageDiv);
+    }
+  });
+  const unlistenSession = await listen("chat-session", (event) => {
+    sessionId = event.payload;
+  });
+  // Commands the model wants to run wait for Run or Don't run
+  const unlistenApproval = await listen("command-approval", (event) => {
+    const approval = event.payload;
+    const approvalDiv = document.createElement("div");
+    approvalDiv.className = "command-approval";
+    const prompt = document.createElement("div");
+    prompt.textContent = `Run in ${approval.working_dir}?`;
+    const command = document.createElement("code");
+    command.textContent = approval.command;
+    const runButton = document.createElement("button");
+    runButton.textContent = "Run";
+    const denyButton = document.createElement("button");
+    denyButton.textContent = "Don't run";
+    const answer = async (approved) => {
+      runButton.remove();
+      denyButton.remove();
+      prompt.textContent = approved ? "Approved" : "Declined";
+      try {
+        await invoke("approve_command", { id: approval.id, approved });
+      } catch (error) {
+        prompt.textContent = error;
       }
+    };
+    runButton.addEventListener("click", () => answer(true));
+    denyButton.addEventListener("click", () => answer(false));
+    approvalDiv.append(prompt, command, runButton, denyButton);
+    messagesContainer.insertBefore(approvalDiv, messageDiv);
+    scrollToMax(messagesContainer);
+  });
+  // Tool calls show above the reply, each updated when its result is in
+  const toolDivs = new Map();
+  const unlisten = await listen("llm-stream", (event) => {
+    const streamEvent = event.payload;
+    if (streamEvent.type === "error") {
+      console.error(streamEvent.message);
+      return;
+    }
+    if (streamEvent.type === "tool_call") {
+      const toolDiv = document.createElement("div");
+      toolDiv.className = "tool-call";
+      toolDiv.textContent = `Using ${streamEvent.name}…`;
+      toolDiv.title = streamEvent.arguments;
+      toolDivs.set(streamEvent.id, toolDiv);
+      messagesContainer.insertBefore(toolDiv, messageDiv);
+      return;
+    }
+    if (streamEvent.type === "tool_result") {
+      const toolDiv = toolDivs.get(streamEvent.id);
+      if (toolDiv) {
+        toolDiv.textContent =
+          streamEvent.error === null
+            ? `Used ${streamEvent.name}`
+            : `${streamEvent.name} failed: ${streamEvent.error}`;
+      }
+      return;
+    }
+    if (streamEvent.type !== "delta") {
+      return;
+    }
+    const out = streamEvent.content;
+    fullMessage += out;
 
-      let chunk = decoder.decode(value, { stream: true });
-      chunk = lastBuffer + chunk;
-      lastBuffer = "";
-      console.log(chunk);
-
-      const pieces = chunk
-        .split("data: ")
-        .filter((piece) => piece.trim() !== ""); // Filter out any empty strings resulting from split
-      pieces.forEach((piece) => {
-        if (piece.trim().endsWith("[DONE]")) {
-          endStream();
+    renderMarkdown(messageDiv, fullMessage);
 
-          return;
-        }
-        if (piece.startsWith("data: ")) {
-          piece = piece.substring(6).trim().split("\n")[0];
-        }
-        try {
-          const parsedChunk = JSON.parse(piece);
-
-          if (
-            parsedChunk.choices &&
-            parsedChunk.choices.length > 0 &&
-            parsedChunk.choices[0].delta
-          ) {
-            const out = parsedChunk.choices[0].delta.content || "";
-            fullMessage += out;
-
-            messageDiv.innerHTML = marked.parse(fullMessage);
-            messageDiv.querySelectorAll("pre code").forEach((el) => {
-              hljs.highlightElement(el);
-            });
-
-            console.log(out);
-            messages[messages.length - 1].content += out;
-            if (isAtBottom) {
-              scrollToMax(messagesContainer);
-            }
-          } else {
-            console.log(
-              "Received chunk does not contain expected data:",
-              parsedChunk
-            );
-          }
-        } catch (error) {
-          console.error("NON JSON:", error);
-          lastBuffer = piece;
-        }
-      });
+    if (isAtBottom) {
+      scrollToMax(messagesContainer);
     }
+  });
+
+  try {
+    // The backend keeps the history of the session
+    await invoke("ask", { sessionId, content });
   } catch (error) {
-    console.error("Failed to fetch:", error);
+    console.error("Failed to call AI:", error);
+  } finally {
+    unlisten();
+    unlistenReport();
+    unlistenSession();
+    unlistenApproval();
     endStream();
   }
 }
 function convertBRnewLines(str) {
   return str.replace(/\n/g, "<br>");
 }
-async function get_context() {
-  let text = await invoke("get_context", {});
-  console.log(text);
-  return text;
-}
 function scrollToMax(element) {
   console.log("SCROLLING MAX");
   element.scrollTo({ top: element.scrollHeight - element.clientHeight });
   isAtBottom = true;
 }
+// Markdown from the model is untrusted: only the tags marked produces for
+// formatting are kept, and only attributes that can't run anything
+const ALLOWED_TAGS = new Set([
+  "A", "B", "BLOCKQUOTE", "BR", "CODE", "DEL", "EM", "H1", "H2", "H3", "H4",
+  "H5", "H6", "HR", "I", "LI", "OL", "P", "PRE", "STRONG", "TABLE", "TBODY",
+  "TD", "TH", "THEAD", "TR", "UL",
+]);
+const ALLOWED_ATTRIBUTES = {
+  A: ["href", "title"],
+  CODE: ["class"],
+  OL: ["start"],
+  TD: ["align"],
+  TH: ["align"],
+};
+// Removed along with everything in them, other unknown tags are unwrapped
+const DROPPED_TAGS = new Set([
+  "EMBED", "IFRAME", "MATH", "NOSCRIPT", "OBJECT", "SCRIPT", "SELECT", "STYLE",
+  "SVG", "TEMPLATE", "TEXTAREA", "TITLE",
+]);
+const SAFE_URL = /^(https?:|mailto:|#)/i;
+
+function sanitize(parent) {
+  for (const node of [...parent.childNodes]) {
+    if (node.nodeType === Node.TEXT_NODE) {
+      continue;
+    }
+    const tag = node.nodeName.toUpperCase();
+    if (node.nodeType !== Node.ELEMENT_NODE || DROPPED_TAGS.has(tag)) {
+      node.remove();
+      continue;
+    }
+    sanitize(node);
+    if (tag === "IMG") {
+      node.replaceWith(node.getAttribute("alt") ?? "");
+    } else if (!ALLOWED_TAGS.has(tag)) {
+      node.replaceWith(...node.childNodes);
+    } else {
+      const allowed = ALLOWED_ATTRIBUTES[tag] ?? [];
+      for (const { name, value } of [...node.attributes]) {
+        if (
+          !allowed.includes(name) ||
+          (name === "href" && !SAFE_URL.test(value.trim()))
+        ) {
+          node.removeAttribute(name);
+        }
+      }
+    }
+  }
+}
+
+function renderMarkdown(element, markdown) {
+  // A template's content is inert, nothing in it loads or runs
+  const template = document.createElement("template");
+  template.innerHTML = marked.parse(markdown);
+  sanitize(template.content);
+  element.replaceChildren(template.content);
+  element.querySelectorAll("pre code").forEach((el) => {
+    hljs.highlightElement(el);
+  });
+}
+function renderMessage(messagesContainer, role, content) {
+  const messageDiv = document.createElement("div");
+  if (role === "user") {
+    messageDiv.className = "user-message";
+    messageDiv.textContent = content;
+  } else {
+    messageDiv.className = "ai-message";
+    renderMarkdown(messageDiv, content);
+  }
+  messagesContainer.appendChild(messageDiv);
+}
 window.newChat = async function () {
-  messages = [];
+  sessionId = null;
+  showHistory(false);
   sendLocked = false;
   isAtBottom = true;
   const messagesContainer = document.getElementById("messages-container");
   messagesContainer.innerHTML = "";
   await invoke("new_chat", {});
 };
+
+function showHistory(visible) {
+  document.getElementById("history-panel").style.display = visible
+    ? "block"
+    : "none";
+  document.getElementById("messages-container").style.display = visible
+    ? "none"
+    : "block";
+}
+window.toggleHistory = async function () {
+  const panel = document.getElementById("history-panel");
+  const visible = panel.style.display !== "block";
+  showHistory(visible);
+  if (visible) {
+    const search = document.getElementById("history-search");
+    search.value = "";
+    search.focus();
+    await refreshHistory();
+  }
+};
+
+async function openSession(id) {
+  if (sendLocked) {
+    return;
+  }
+  let session;
+  try {
+    session = await invoke("open_session", { sessionId: id });
+  } catch (error) {
+    console.error("Failed to open session:", error);
+    return;
+  }
+  sessionId = session.id;
+  isAtBottom = true;
+  const messagesContainer = document.getElementById("messages-container");
+  messagesContainer.innerHTML = "";
+  session.messages.forEach((message) => {
+    renderMessage(messagesContainer, message.role, message.content);
+  });
+  showHistory(false);
+  scrollToMax(messagesContainer);
+}
+
+function historyItem(session, detail, detailClass) {
+  const item = document.createElement("div");
+  item.className = "history-item";
+  const text = document.createElement("div");
+  text.className = "history-text";
+  const title = document.createElement("div");
+  title.className = "history-title";
+  title.textContent = session.title;
+  const info = document.createElement("div");
+  info.className = detailClass;
+  info.textContent = detail;
+  text.append(title, info);
+  item.appendChild(text);
+  item.addEventListener("click", () => openSession(session.id));
+
+  const rename = document.createElement("span");
+  rename.className = "history-action";
+  rename.textContent = "rename";
+  rename.addEventListener("click", (event) => {
+    event.stopPropagation();
+    const input = document.createElement("input");
+    input.type = "text";
+    input.value = session.title;
+    input.addEventListener("click", (event) => event.stopPropagation());
+    input.addEventListener("keydown", async (event) => {
+      if (event.key === "Enter") {
+        try {
+          await invoke("rename_session", {
+            sessionId: session.id,
+            title: input.value,
+          });
+        } catch (error) {
+          console.error("Failed to rename session:", error);
+        }
+        await refreshHistory();
+      } else if (event.key === "Escape") {
+        await refreshHistory();
+      }
+    });
+    title.replaceWith(input);
+    input.focus();
+  });
+
+  // Two clicks, so a stray one doesn't lose a conversation
+  const remove = document.createElement("span");
+  remove.className = "history-action";
+  remove.textContent = "delete";
+  remove.addEventListener("click", async (event) => {
+    event.stopPropagation();
+    if (remove.textContent !== "sure?") {
+      remove.textContent = "sure?";
+      return;
+    }
+    try {
+      await invoke("delete_session", { sessionId: session.id });
+    } catch (error) {
+      console.error("Failed to delete session:", error);
+    }
+    if (sessionId === session.id) {
+      await window.newChat();
+      showHistory(true);
+    }
+    await refreshHistory();
+  });
+  item.append(rename, remove);
+  return item;
+}
+
+// All sessions, or the messages matching the search box
+async function refreshHistory() {
+  const list = document.getElementById("history-list");
+  const query = document.getElementById("history-search").value.trim();
+  let items = [];
+  try {
+    if (query === "") {
+      const sessions = await invoke("list_sessions");
+      items = sessions.map((session) =>
+        historyItem(
+          session,
+          `${new Date(session.updated_at).toLocaleString()} · ${session.message_count} messages`,
+          "history-date"
+        )
+      );
+    } else {
+      const hits = await invoke("search_sessions", { query });
+      items = hits.map((hit) =>
+        historyItem(hit.session, hit.snippet, "history-snippet")
+      );
+    }
+  } catch (error) {
+    console.error("Failed to load chat history:", error);
+  }
+  list.replaceChildren(...items);
+}
+
 window.settings = async function () {
   await invoke("toggle_settings_window", {});
 };
 
+// Without a model nothing is transcribed; say so above the chat
+async function showModelError() {
+  const element = document.getElementById("model-status");
+  let text = "";
+  try {
+    const status = await invoke("get_model_status");
+    if (status.state === "error") {
+      text = `Audio context unavailable: ${status.message}`;
+    }
+  } catch (error) {
+    console.error(error);
+  }
+  element.textContent = text;
+  element.style.display = text === "" ? "none" : "block";
+}
+
 document.addEventListener("DOMContentLoaded", async () => {
+  // Handlers are attached here, the CSP blocks inline ones
+  document
+    .querySelector(".refresh-button")
+    .addEventListener("click", window.newChat);
+  document
+    .querySelector(".history-button")
+    .addEventListener("click", window.toggleHistory);
+  document
+    .querySelector(".settings-button")
+    .addEventListener("click", window.settings);
+  await showModelError();
+  window.addEventListener("focus", showModelError);
+  document
+    .getElementById("history-search")
+    .addEventListener("input", refreshHistory);
+
   const inputField = document.getElementById("message-input");
   const messagesContainer = document.getElementById("messages-container");
 
@@ -176,15 +415,12 @@ document.addEventListener("DOMContentLoaded", async () => {
       event.preventDefault();
 
       console.log(inputField.value);
-      messages = [
-        ...messages,
-        { role: "user", content: inputField.value },
-        { role: "assistant", content: "" },
-      ];
+      const content = inputField.value;
+      showHistory(false);
 
       const messageDiv = document.createElement("div");
       messageDiv.className = "user-message";
-      messageDiv.innerHTML = inputField.value;
+      messageDiv.textContent = content;
       // let divider = document.createElement("hr");
       // let divider2 = document.createElement("hr");
 
@@ -203,7 +439,7 @@ document.addEventListener("DOMContentLoaded", async () => {
       if (isAtBottom) {
         scrollToMax(messagesContainer);
       }
-      await call_ai(messagesContainer, aiMessageDiv);
+      await call_ai(messagesContainer, aiMessageDiv, content);
     }
   });
 });
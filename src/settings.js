@@ -1,37 +1,242 @@
 const { invoke } = window.__TAURI__.tauri;
 
+// element id -> [settings field, kind]
+const fields = {
+  apiKey: ["api_key", "text"],
+  llmProvider: ["llm_provider", "text"],
+  llmBaseUrl: ["llm_base_url", "text"],
+  llmModel: ["llm_model", "text"],
+  llmTemperature: ["llm_temperature", "number"],
+  llmTopP: ["llm_top_p", "number"],
+  llmMaxTokens: ["llm_max_tokens", "number"],
+  screenContext: ["screen_context", "checkbox"],
+  audioContext: ["audio_context", "checkbox"],
+  audioStreaming: ["audio_streaming", "checkbox"],
+  audioContextWindowSecs: ["audio_context_window_secs", "number"],
+  audioCaptureSystem: ["audio_capture_system", "checkbox"],
+  audioCaptureMicrophone: ["audio_capture_microphone", "checkbox"],
+  audioHost: ["audio_host", "text"],
+  audioMicrophoneDevice: ["audio_microphone_device", "text"],
+  audioOutputDevice: ["audio_output_device", "text"],
+  audioDiarization: ["audio_diarization", "checkbox"],
+  speakerNames: ["speaker_names", "list"],
+  whisperModel: ["whisper_model", "text"],
+  modelsDir: ["models_dir", "text"],
+  whisperLanguage: ["whisper_language", "text"],
+  whisperTranslate: ["whisper_translate", "checkbox"],
+  whisperDecoding: ["whisper_decoding", "text"],
+  whisperBeamSize: ["whisper_beam_size", "number"],
+  whisperThreads: ["whisper_threads", "number"],
+  whisperInitialPrompt: ["whisper_initial_prompt", "text"],
+  timelineEnabled: ["timeline_enabled", "checkbox"],
+  timelineIntervalSecs: ["timeline_interval_secs", "number"],
+  timelineRetentionDays: ["timeline_retention_days", "number"],
+  retrievalEnabled: ["retrieval_enabled", "checkbox"],
+  embeddingModelDir: ["embedding_model_dir", "text"],
+  retrievalTopK: ["retrieval_top_k", "number"],
+  agentTools: ["agent_tools", "checkbox"],
+  agentMaxSteps: ["agent_max_steps", "number"],
+  runCommands: ["run_commands", "checkbox"],
+  commandAllowlist: ["command_allowlist", "list"],
+  commandTimeoutSecs: ["command_timeout_secs", "number"],
+  commandOutputBytes: ["command_output_bytes", "number"],
+  commandWorkingDir: ["command_working_dir", "text"],
+  ocrEngine: ["ocr_engine", "text"],
+  ocrLanguages: ["ocr_languages", "list"],
+};
+
+// Placeholder base URLs when switching provider
+const defaultBaseUrls = {
+  openai: "https://api.groq.com/openai/v1",
+  ollama: "http://localhost:11434",
+  llamacpp: "http://localhost:8080",
+};
+
+function readField(element, kind) {
+  switch (kind) {
+    case "checkbox":
+      return element.checked;
+    case "number":
+      return Number(element.value);
+    case "list":
+      return element.value
+        .split(",")
+        .map((item) => item.trim())
+        .filter((item) => item !== "");
+    default:
+      return element.value;
+  }
+}
+
+function writeField(element, kind, value) {
+  switch (kind) {
+    case "checkbox":
+      element.checked = value;
+      break;
+    case "list":
+      element.value = value.join(", ");
+      break;
+    default:
+      element.value = value;
+  }
+}
+
+// Fills a device <select>; the empty value follows the system default and a
+// pinned device that is currently unplugged stays selectable
+function fillSelect(element, names, defaultLabel, current) {
+  element.replaceChildren();
+  for (const name of ["", ...new Set(names)]) {
+    const option = document.createElement("option");
+    option.value = name;
+    option.textContent = name === "" ? defaultLabel : name;
+    element.appendChild(option);
+  }
+  if (current !== "" && !names.includes(current)) {
+    const option = document.createElement("option");
+    option.value = current;
+    option.textContent = `${current} (not connected)`;
+    element.appendChild(option);
+  }
+}
+
+async function fillAudioDevices(settings) {
+  let devices = [];
+  try {
+    devices = await invoke("list_audio_devices");
+  } catch (error) {
+    console.error(error);
+  }
+  const host = settings.audio_host;
+  const onHost = devices.filter((device) => host === "" || device.host === host);
+  const names = (direction) =>
+    onHost.filter((device) => device.direction === direction).map((device) => device.name);
+
+  fillSelect(
+    document.getElementById("audioHost"),
+    devices.map((device) => device.host),
+    "Default host",
+    settings.audio_host
+  );
+  fillSelect(
+    document.getElementById("audioMicrophoneDevice"),
+    names("input"),
+    "Default microphone",
+    settings.audio_microphone_device
+  );
+  fillSelect(
+    document.getElementById("audioOutputDevice"),
+    names("output"),
+    "Default output",
+    settings.audio_output_device
+  );
+}
+
+function describeModelStatus(status) {
+  switch (status.state) {
+    case "checking":
+      return "Checking model...";
+    case "found":
+    case "loading":
+    case "ready": {
+      const model = status.model;
+      const check = model.verification === "verified" ? "verified" : "no checksum";
+      const state = status.state === "found" ? "loads on first speech" : status.state;
+      return `${model.name} (${check}), ${state}`;
+    }
+    default:
+      return status.message;
+  }
+}
+
+async function refreshModelStatus() {
+  const element = document.getElementById("modelStatus");
+  try {
+    const status = await invoke("get_model_status");
+    element.textContent = describeModelStatus(status);
+    element.classList.toggle("model-error", status.state === "error");
+  } catch (error) {
+    element.textContent = error;
+    element.classList.add("model-error");
+  }
+}
+
 document.addEventListener("DOMContentLoaded", async () => {
-  const savedSettings = await invoke("get_settings");
-  console.log(savedSettings);
+  // Keep the whole object so fields without a control here survive a save
+  let settings = await invoke("get_settings");
+  console.log(settings);
+  await fillAudioDevices(settings);
 
-  document.getElementById("groqApiKey").value = savedSettings.groq_api_key;
-  document.getElementById("screenContext").checked =
-    savedSettings.screen_context;
-  document.getElementById("audioContext").checked = savedSettings.audio_context;
+  for (const [id, [key, kind]] of Object.entries(fields)) {
+    writeField(document.getElementById(id), kind, settings[key]);
+  }
 
   const updateAllSettings = async () => {
-    const groqApiKey = document.getElementById("groqApiKey").value;
-    const screenContext = document.getElementById("screenContext").checked;
-    const audioContext = document.getElementById("audioContext").checked;
-
-    await invoke("update_settings", {
-      settings: {
-        // Adjusted to match the expected argument structure
-        groq_api_key: groqApiKey,
-        screen_context: screenContext,
-        audio_context: audioContext,
-      },
-    });
+    settings = { ...settings };
+    for (const [id, [key, kind]] of Object.entries(fields)) {
+      settings[key] = readField(document.getElementById(id), kind);
+    }
+
+    await invoke("update_settings", { settings });
   };
 
+  // The backend only ever sends a mask for a saved key and keeps the saved
+  // one when sent an empty key; start fresh on edit, and put the mask back
+  // if nothing was typed
+  const apiKeyMask = "********";
+  const apiKeyInput = document.getElementById("apiKey");
+  let hasSavedKey = apiKeyInput.value === apiKeyMask;
+  apiKeyInput.addEventListener("focus", () => {
+    if (apiKeyInput.value === apiKeyMask) {
+      apiKeyInput.value = "";
+    }
+  });
+  apiKeyInput.addEventListener("input", () => {
+    hasSavedKey ||= apiKeyInput.value !== "";
+  });
+  apiKeyInput.addEventListener("blur", () => {
+    if (apiKeyInput.value === "" && hasSavedKey) {
+      apiKeyInput.value = apiKeyMask;
+    }
+  });
+  document.getElementById("llmProvider").addEventListener("change", (event) => {
+    document.getElementById("llmBaseUrl").value =
+      defaultBaseUrls[event.target.value];
+  });
+
+  const refreshAudioDevices = async () => {
+    await fillAudioDevices(settings);
+    for (const id of ["audioHost", "audioMicrophoneDevice", "audioOutputDevice"]) {
+      const [key, kind] = fields[id];
+      writeField(document.getElementById(id), kind, settings[key]);
+    }
+  };
+  // Switching host offers that host's devices, starting from its defaults
+  document.getElementById("audioHost").addEventListener("change", async (event) => {
+    settings = {
+      ...settings,
+      audio_host: event.target.value,
+      audio_microphone_device: "",
+      audio_output_device: "",
+    };
+    await refreshAudioDevices();
+    await updateAllSettings();
+  });
+  // Re-list on open so freshly plugged devices show up
+  window.addEventListener("focus", refreshAudioDevices);
+
+  // Lookup and loading happen in the background, keep the line current
+  await refreshModelStatus();
+  setInterval(refreshModelStatus, 1000);
+
   // Attach the updateAllSettings function to each input and checkbox
-  document
-    .getElementById("groqApiKey")
-    .addEventListener("input", updateAllSettings);
-  document
-    .getElementById("screenContext")
-    .addEventListener("change", updateAllSettings);
-  document
-    .getElementById("audioContext")
-    .addEventListener("change", updateAllSettings);
+  for (const [id, [, kind]] of Object.entries(fields)) {
+    if (id === "audioHost") {
+      continue;
+    }
+    const eventName =
+      kind === "checkbox" || kind === "list" || document.getElementById(id).tagName === "SELECT"
+        ? "change"
+        : "input";
+    document.getElementById(id).addEventListener(eventName, updateAllSettings);
+  }
 });